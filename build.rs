@@ -0,0 +1,33 @@
+fn main() {
+    // protobuf code of the grpc service, protoc is vendored so no system install is needed
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/lcd.proto");
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+        tonic_prost_build::compile_protos("proto/lcd.proto").unwrap();
+    }
+
+    // c header of the ffi module, into OUT_DIR. the source tree is only written when
+    // LCD_HEADER_DIR asks for it, e.g. LCD_HEADER_DIR=include to refresh the checked in one
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-env-changed=LCD_HEADER_DIR");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let bindings = cbindgen::Builder::new()
+            .with_crate(&dir)
+            .with_config(cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap())
+            .generate()
+            .unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        bindings.write_to_file(format!("{}/lcd_core.h", out_dir));
+        if let Ok(header_dir) = std::env::var("LCD_HEADER_DIR") {
+            bindings.write_to_file(
+                std::path::Path::new(&dir)
+                    .join(header_dir)
+                    .join("lcd_core.h"),
+            );
+        }
+    }
+}
@@ -0,0 +1,160 @@
+#ifndef LCD_CORE_H
+#define LCD_CORE_H
+
+/* generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * engine handle, from lcd_new to lcd_free
+ */
+typedef struct LcdHandle LcdHandle;
+
+/**
+ * completion of an operation, user_data as given to the call
+ */
+typedef void (*LcdCallback)(void *user_data, const char *reply);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * new engine handle of the json settings, null when it fails, the error is logged
+ *
+ * # Safety
+ * config is a NUL terminated string or null for the defaults
+ */
+struct LcdHandle *lcd_new(const char *config);
+
+/**
+ * release the handle, operations still running keep it until they complete
+ *
+ * # Safety
+ * handle comes from lcd_new and is not used after
+ */
+void lcd_free(struct LcdHandle *handle);
+
+/**
+ * release a reply returned by a query
+ *
+ * # Safety
+ * reply comes from a query of this library and is not used after
+ */
+void lcd_string_free(char *reply);
+
+/**
+ * {"ip":"192.168.1.1","offset":1,"count":254,"timeout_seconds":5}, data is the machines
+ *
+ * # Safety
+ * handle from lcd_new, request a NUL terminated string, the callback may run on any thread
+ */
+void lcd_scan(const struct LcdHandle *handle,
+              const char *request,
+              LcdCallback callback,
+              void *user_data);
+
+/**
+ * as lcd_scan, data is the machines plus the new, gone and changed ones against the last
+ * scan of the range
+ *
+ * # Safety
+ * as lcd_scan
+ */
+void lcd_scan_diff(const struct LcdHandle *handle,
+                   const char *request,
+                   LcdCallback callback,
+                   void *user_data);
+
+/**
+ * {"target":{"Ips":["192.168.1.10"]},"timeout_seconds":5}, data is the machines
+ *
+ * # Safety
+ * as lcd_scan
+ */
+void lcd_watching(const struct LcdHandle *handle,
+                  const char *request,
+                  LcdCallback callback,
+                  void *user_data);
+
+/**
+ * {"target":..,"timeout_seconds":5}
+ *
+ * # Safety
+ * as lcd_scan
+ */
+void lcd_reboot(const struct LcdHandle *handle,
+                const char *request,
+                LcdCallback callback,
+                void *user_data);
+
+/**
+ * {"target":..,"pools":[..],"run_mode":"高功","timeout_seconds":5}, data is the job id
+ *
+ * # Safety
+ * as lcd_scan
+ */
+void lcd_config(const struct LcdHandle *handle,
+                const char *request,
+                LcdCallback callback,
+                void *user_data);
+
+/**
+ * {"target":..,"account":{..},"timeout_seconds":5}, switch the account whatever the sheets
+ *
+ * # Safety
+ * as lcd_scan
+ */
+void lcd_force_switch(const struct LcdHandle *handle,
+                      const char *request,
+                      LcdCallback callback,
+                      void *user_data);
+
+/**
+ * {"excel":..,"sheets":[..],"account_time_sheet":..,"perf_time_sheet":..,"pool_sheet":..},
+ * data is the switch report
+ *
+ * # Safety
+ * as lcd_scan
+ */
+void lcd_switch_if_need(const struct LcdHandle *handle,
+                        const char *request,
+                        LcdCallback callback,
+                        void *user_data);
+
+/**
+ * stop the tasks of the handle and wait up to the timeout for running batches
+ *
+ * # Safety
+ * as lcd_scan
+ */
+void lcd_shutdown(const struct LcdHandle *handle,
+                  uint64_t timeout_seconds,
+                  LcdCallback callback,
+                  void *user_data);
+
+/**
+ * {"ip":"192.168.1.10","start_time":..,"end_time":..,"limit":100}, the paging fields as
+ * RecordQuery. reply freed with lcd_string_free
+ *
+ * # Safety
+ * handle from lcd_new, request a NUL terminated string
+ */
+char *lcd_query_machine_records(const struct LcdHandle *handle, const char *request);
+
+/**
+ * engine status, reply freed with lcd_string_free
+ *
+ * # Safety
+ * handle from lcd_new
+ */
+char *lcd_status(const struct LcdHandle *handle);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LCD_CORE_H */
@@ -0,0 +1,263 @@
+/// who may run what: every operation needs a role, read only, operator or admin. the
+/// mutating entry points check the role of the caller and log the call with the caller in
+/// the audit log, refused calls too. the http and grpc surfaces run each request as the
+/// user of its token, calls of the embedding app and the tasks run as the system caller
+use std::collections::HashMap;
+use std::future::Future;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::secret::{self, SecretString};
+use crate::store::db;
+
+static CONFIG: Local<AccessConfig> = Local::new(AccessConfig::default);
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    ReadOnly,
+    Operator,
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Scan,
+    Watch,
+    ExportFleet,
+    PlanFleet,
+    Reboot,
+    /// pools and run mode
+    Config,
+    /// scheduled, forced and sheet driven switches
+    Switch,
+    Reconcile,
+    ApplyProfile,
+    Maintenance,
+    Curtail,
+    AckAlert,
+    ResolveAlert,
+    EditProfile,
+    ImportFleet,
+    ApplyFleetPlan,
+    EditSheet,
+    /// worker names and notify sinks
+    Settings,
+    Secrets,
+    /// backups and deleting records
+    Database,
+    Shutdown,
+}
+
+impl Operation {
+    /// role needed unless configured otherwise
+    pub fn default_role(&self) -> Role {
+        match self {
+            Operation::Scan | Operation::Watch | Operation::ExportFleet | Operation::PlanFleet => {
+                Role::ReadOnly
+            }
+            Operation::Reboot
+            | Operation::Config
+            | Operation::Switch
+            | Operation::Reconcile
+            | Operation::ApplyProfile
+            | Operation::Maintenance
+            | Operation::Curtail
+            | Operation::AckAlert
+            | Operation::ResolveAlert => Role::Operator,
+            Operation::EditProfile
+            | Operation::ImportFleet
+            | Operation::ApplyFleetPlan
+            | Operation::EditSheet
+            | Operation::Settings
+            | Operation::Secrets
+            | Operation::Database
+            | Operation::Shutdown => Role::Admin,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessConfig {
+    /// operations needing another role than their default
+    #[serde(default)]
+    pub roles: HashMap<Operation, Role>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Caller {
+    /// the embedding app and the tasks it started
+    pub fn system() -> Self {
+        Caller {
+            name: "system".to_string(),
+            role: Role::Admin,
+        }
+    }
+}
+
+/// a user of the http and grpc surfaces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiUser {
+    pub name: String,
+    /// bearer token, "secret:<name>" reads it from the secrets
+    pub token: SecretString,
+    pub role: Role,
+}
+
+pub fn set_config(config: AccessConfig) {
+    CONFIG.set(config);
+}
+
+pub fn required_role(operation: Operation) -> Role {
+    CONFIG
+        .with(|config| config.roles.get(&operation).copied())
+        .unwrap_or_else(|| operation.default_role())
+}
+
+/// caller of the running call, the system outside of a scope
+pub fn current() -> Caller {
+    CALLER
+        .try_with(|caller| caller.clone())
+        .unwrap_or_else(|_| Caller::system())
+}
+
+/// run the future as the caller
+pub async fn scope<F: Future>(caller: Caller, future: F) -> F::Output {
+    CALLER.scope(caller, future).await
+}
+
+/// same time for every mismatch position, an empty token never matches
+pub fn token_matches(presented: &str, token: &str) -> bool {
+    !token.is_empty()
+        && presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// users with their secret tokens read, a token that cannot be read stays empty and matches
+/// no request
+pub fn resolve_users(users: &[ApiUser]) -> Vec<ApiUser> {
+    users
+        .iter()
+        .map(|user| ApiUser {
+            token: secret::resolve(user.token.expose())
+                .map(SecretString::from)
+                .unwrap_or_else(|e| {
+                    error!("resolve token of {} error: {:?}", user.name, e);
+                    SecretString::default()
+                }),
+            ..user.clone()
+        })
+        .collect()
+}
+
+/// the user of a presented token
+pub fn caller_of(users: &[ApiUser], presented: &str) -> Option<Caller> {
+    users
+        .iter()
+        .find(|user| token_matches(presented, user.token.expose()))
+        .map(|user| Caller {
+            name: user.name.clone(),
+            role: user.role,
+        })
+}
+
+/// targets for the audit log, the first ip and how many more
+pub fn describe(ips: &[String]) -> String {
+    match ips {
+        [] => "".to_string(),
+        [ip] => ip.clone(),
+        [ip, rest @ ..] => format!("{} +{}", ip, rest.len()),
+    }
+}
+
+// read only operations pass unlogged, the rest and every refusal are audited
+fn decide(caller: &Caller, operation: Operation, target: &str) -> Result<(), MinerError> {
+    let required = required_role(operation);
+    let allowed = caller.role >= required;
+    if required > Role::ReadOnly || !allowed {
+        let detail = format!(
+            "{} ({:?}) {:?} {}{}",
+            caller.name,
+            caller.role,
+            operation,
+            target,
+            if allowed { "" } else { " denied" }
+        );
+        if let Err(e) = db::insert_event(db::EVENT_OPERATION, "", detail.trim_end()) {
+            error!("audit {} error: {:?}", detail, e);
+        }
+    }
+    if allowed {
+        Ok(())
+    } else {
+        Err(MinerError::PermissionDeniedError(format!(
+            "{:?} needs {:?}, {} is {:?}",
+            operation, required, caller.name, caller.role
+        )))
+    }
+}
+
+/// fail unless the caller has the role the operation needs, the call is audited
+pub fn check(operation: Operation, target: &str) -> Result<(), MinerError> {
+    decide(&current(), operation, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roles() {
+        let users = vec![
+            ApiUser {
+                name: "viewer".to_string(),
+                token: "view".into(),
+                role: Role::ReadOnly,
+            },
+            ApiUser {
+                name: "ops".to_string(),
+                token: "ops-token".into(),
+                role: Role::Operator,
+            },
+        ];
+        assert_eq!(caller_of(&users, "ops-token").unwrap().role, Role::Operator);
+        assert_eq!(caller_of(&users, "ops-tokeN"), None);
+        assert!(!format!("{:?}", users).contains("ops-token"));
+        assert!(!token_matches("", ""));
+
+        let viewer = caller_of(&users, "view").unwrap();
+        assert!(decide(&viewer, Operation::Scan, "").is_ok());
+        match decide(&viewer, Operation::Reboot, "10.0.0.1") {
+            Err(MinerError::PermissionDeniedError(e)) => assert!(e.contains("viewer")),
+            other => panic!("reboot of a viewer not refused: {:?}", other),
+        }
+        let ops = caller_of(&users, "ops-token").unwrap();
+        assert!(decide(&ops, Operation::Reboot, "").is_ok());
+        assert!(decide(&ops, Operation::ImportFleet, "").is_err());
+
+        // the system outside of a scope, the caller inside
+        assert_eq!(current(), Caller::system());
+        assert_eq!(scope(ops.clone(), async { current() }).await, ops);
+        assert_eq!(
+            describe(&["10.0.0.1".to_string(), "10.0.0.2".to_string()]),
+            "10.0.0.1 +1"
+        );
+    }
+}
@@ -0,0 +1,1017 @@
+/// the free functions of the engine, re-exported at the crate root
+use std::collections::HashMap;
+
+use log::{error, info};
+
+pub use crate::access::{AccessConfig, ApiUser, Caller, Operation, Role};
+pub use crate::engine::{LcdCore, LcdCoreBuilder};
+use crate::error::MinerError;
+pub use crate::miner::anomaly::{Anomaly, AnomalyConfig};
+pub use crate::miner::catalog::ModelSpec;
+pub use crate::miner::curtail::{
+    CurtailAction, CurtailOrder, CurtailResult, CurtailStep, CurtailStrategy,
+};
+pub use crate::miner::desired::{
+    DesiredMachine, DesiredState, Drift, DriftField, FanPolicy, StateReport,
+};
+pub use crate::miner::discovery::{DiscoveredMachine, DiscoveryConfig, DiscoveryReport, OuiVendor};
+use crate::miner::entry::*;
+pub use crate::miner::entry::{RowError, SwitchReport, SwitchState};
+pub use crate::miner::fleet::{
+    FleetDocument, FleetImport, FleetMachine, FleetSheet, FleetTable, FLEET_VERSION,
+};
+pub use crate::miner::group::{GroupConfig, GroupSelector};
+pub use crate::miner::inventory::{ChangedMachine, InventoryEntry, ScanDiff, ScannedMachine};
+pub use crate::miner::mode::{RunMode, RunModeAliases};
+pub use crate::miner::plan::{
+    ChangeKind, FieldChange, FleetApply, FleetPlan, MachineChange, SettingChange,
+};
+pub use crate::miner::position::{Position, PositionCell};
+pub use crate::miner::power::{MachinePowerUsage, PowerPoint, PowerUsage};
+pub use crate::miner::profile::ConfigProfile;
+pub use crate::miner::reach::{ReachConfig, ReachMethod, Reachability};
+pub use crate::miner::restart::{CrashLoopAction, CrashLoopConfig};
+pub use crate::miner::route::{RouteConfig, RouteRule, SshTunnel, Transport};
+pub use crate::miner::schedule::{SwitchRun, SwitchScheduleConfig};
+use crate::miner::sheet::{SheetColumns, StatusAliases};
+#[cfg(feature = "ssh")]
+pub use crate::miner::ssh::{SshConfig, SshProfile};
+pub use crate::miner::stagger::StaggerConfig;
+pub use crate::miner::subnet::ScanQueueConfig;
+pub use crate::miner::tag::TagExpr;
+pub use crate::miner::thermal::ThermalConfig;
+pub use crate::miner::validate::{ConfigIssue, IssueKind, ValidationReport};
+pub use crate::miner::window::{OverlapPolicy, WindowConfig};
+pub use crate::miner::zone::{ZoneLevel, ZoneTemp, ZoneTempConfig};
+pub use crate::notify::alerts::{
+    AlertAnalytics, AlertEntry, AlertQuery, AlertState, AlertStats, AlertStoreConfig,
+};
+pub use crate::notify::dingtalk::DingTalkNotifier;
+#[cfg(feature = "email")]
+pub use crate::notify::email::{EmailNotifier, EmailTls};
+pub use crate::notify::escalation::{
+    EscalationAction, EscalationConfig, EscalationRule, EscalationStep, Incident,
+};
+#[cfg(feature = "feishu")]
+pub use crate::notify::feishu::FeishuNotifier;
+pub use crate::notify::notifier::{NotifierType, NotifySink};
+pub use crate::notify::slack::SlackNotifier;
+pub use crate::notify::telegram::TelegramNotifier;
+pub use crate::notify::template::{Locale, TemplateConfig};
+pub use crate::notify::throttle::ThrottleConfig;
+pub use crate::notify::webhook::WebhookNotifier;
+pub use crate::notify::wecom::WeComNotifier;
+pub use crate::notify::{Alert, AlertMachine, Severity, SheetBackend};
+pub use crate::pools::health::{PoolEndpointStatus, PoolHealthConfig};
+pub use crate::pools::pool::PoolQueryStatus;
+pub use crate::pools::proxy::{ProxyStatus, ProxyUpstream, StratumProxyConfig};
+pub use crate::pools::reconcile::HashReconcile;
+pub use crate::pools::stale::StaleWorkerConfig;
+pub use crate::pools::summary::{AccountSummary, DisappearedWorker, HashPoint, PoolSummary};
+pub use crate::profitability::{
+    Market, MarketSource, ModelProfit, Profitability, ProfitabilityConfig,
+};
+pub use crate::secret::SecretConfig;
+pub use crate::status::{EngineStatus, TaskStatus};
+pub use crate::store::db::RecordQuery;
+pub use crate::store::retention::RetentionConfig;
+pub use crate::tariff::{TariffConfig, TariffPeriod, TariffPolicy};
+#[cfg(feature = "pools")]
+use crate::PoolAccountConfig;
+use crate::{
+    access, clock, context, http, miner, notify, pools, profitability, report, secret, tariff,
+    PoolEarning,
+};
+
+use crate::store::{db, retention};
+
+#[derive(Default)]
+pub struct MinersLibConfig {
+    pub app_path: String,
+    /// outbound proxy for feishu, pools and notify channels, empty for direct
+    pub proxy: String,
+    pub feishu_app_id: String,
+    /// plain or "secret:<name>", as every password, secret and token of the config
+    pub feishu_app_secret: String,
+    pub feishu_bot: String,
+    pub feishu_oncall: Vec<String>,
+    pub notify_sinks: Vec<NotifySink>,
+    pub notify_throttle: ThrottleConfig,
+    /// language and custom texts of the alerts
+    pub notify_templates: TemplateConfig,
+    /// steps for alerts left unacknowledged, run by start_escalation_task
+    pub escalation: EscalationConfig,
+    /// raised alerts kept with their state, needs the db
+    pub alert_store: AlertStoreConfig,
+    pub pool_stale: StaleWorkerConfig,
+    pub pool_health: PoolHealthConfig,
+    /// ip to pool worker, "acc.rack3-07" or the suffix "rack3-07", for machines not named
+    /// "<ip3>x<ip4>" on the pool
+    pub worker_names: HashMap<String, String>,
+    /// time-of-use prices driving run mode, replaces the perf time sheet when set
+    pub tariff: Option<TariffConfig>,
+    /// models besides or replacing the built in ones of the catalog, looked up first
+    pub model_specs: Vec<ModelSpec>,
+    /// run mode per model by revenue against power cost, replaces the tariff policy and the
+    /// perf time sheet when set
+    pub profitability: Option<ProfitabilityConfig>,
+    /// step hot machines down on watching, off by default
+    pub thermal: ThermalConfig,
+    /// average board temperature of racks and rooms on watching, from the sheet positions
+    pub zone_temp: ZoneTempConfig,
+    /// machines falling below their own hashrate baseline on watching
+    pub anomaly: AnomalyConfig,
+    /// machines restarting over and over on watching
+    pub crash_loop: CrashLoopConfig,
+    /// per site/zone concurrency and notify sinks
+    pub groups: Vec<GroupConfig>,
+    /// switches and configs start in waves of machines, groups by priority
+    pub stagger: StaggerConfig,
+    /// parallelism and probe delay of scans per /24, slowed while timeouts spike
+    pub scan_queue: ScanQueueConfig,
+    /// mac oui blocks of the miner vendors for arp discovery
+    pub discovery: DiscoveryConfig,
+    /// seconds a detected miner type is reused by batch operations, 0 to probe every time
+    pub detect_cache_seconds: u64,
+    /// seconds an idle cgminer api connection is kept for reuse, 0 to connect every command
+    pub cgminer_idle_seconds: u64,
+    /// reachability check of machines an operation failed on
+    pub reach: ReachConfig,
+    /// socks5 proxy or ssh tunnel per subnet for remote sites, others are reached directly
+    pub route: RouteConfig,
+    /// credentials per miner model for the shell commands
+    #[cfg(feature = "ssh")]
+    pub ssh: SshConfig,
+    /// raw miner replies are saved here as fixtures, empty to disable
+    pub capture_dir: String,
+    /// the vendor replies of each query are kept in MachineInfo.raw
+    pub keep_raw: bool,
+    /// iana time zone of the site, e.g. "Asia/Shanghai", empty for the host time zone
+    pub timezone: String,
+    /// overlap and gap handling of the account/perf time sheets
+    pub time_window: WindowConfig,
+    /// more labels of the run modes in the sheets and api calls, "high" and "normal" are
+    /// always taken
+    pub run_mode_aliases: RunModeAliases,
+    pub is_need_db: bool,
+    /// sqlite file, "<app_path>/db/lcd.sqlite" when empty, ":memory:" to keep it in memory
+    pub db_path: String,
+    pub db_keep_days: i64,
+    /// days kept per table, every table db_keep_days when None
+    pub retention: Option<RetentionConfig>,
+    pub sheet_columns: SheetColumns,
+    /// fields of the position column, e.g. "{room}-{row}-{rack}-{slot}" for "A-03-12-4",
+    /// that format when empty
+    pub position_format: String,
+    /// more labels of the status column, unknown labels are reported as row errors
+    pub sheet_status: StatusAliases,
+    pub sheet_backend: SheetBackend,
+    /// keychain service and encrypted file the "secret:<name>" values are read from
+    pub secrets: SecretConfig,
+    /// roles of the operations other than their defaults
+    pub access: AccessConfig,
+}
+
+/// log through log4rs configured by the yaml or json file, once per process, before init
+#[cfg(feature = "logging")]
+pub fn init_logging(config_file: &str) -> Result<(), MinerError> {
+    log4rs::init_file(config_file, Default::default())
+        .map_err(|e| MinerError::LogConfigError(e.to_string()))
+}
+
+/// init lcd
+pub fn init(config: &MinersLibConfig) {
+    http::set_proxy(&config.proxy);
+    secret::set_config(config.secrets.clone());
+    access::set_config(config.access.clone());
+    if let Err(e) = clock::set_timezone(&config.timezone) {
+        error!("set timezone error, use host time zone: {:?}", e);
+    }
+
+    retention::set_config(
+        config
+            .retention
+            .clone()
+            .unwrap_or_else(|| RetentionConfig::days(config.db_keep_days)),
+    );
+
+    // init sqlite db and try to clear old data
+    if config.is_need_db {
+        let db_path = db::get_db_path(&config.app_path, &config.db_path);
+        match db::init(&db_path) {
+            Ok(()) => {
+                // flags and profiles read before the db was opened
+                miner::maintenance::reload();
+                miner::profile::reload();
+                tariff::reload();
+                miner::curtail::reload();
+                if let Err(e) = retention::run_now() {
+                    error!("clear old data error: {:?}", e);
+                }
+                // batches cut short by the last exit, needs the caller's runtime
+                match tokio::runtime::Handle::try_current() {
+                    Ok(runtime) => {
+                        context::spawn(&runtime, resume_pending_jobs(runtime.clone()));
+                    }
+                    Err(_) => info!("no tokio runtime, pending jobs wait for resume_pending_jobs"),
+                }
+            }
+            Err(e) => error!("init db {} error: {:?}", db_path, e),
+        }
+    }
+
+    #[cfg(feature = "feishu")]
+    let sinks = init_feishu(config);
+    #[cfg(not(feature = "feishu"))]
+    let sinks = config.notify_sinks.clone();
+    notify::notifier::set_sinks(sinks);
+    notify::throttle::set_config(config.notify_throttle.clone());
+    notify::template::set_config(config.notify_templates.clone());
+    notify::escalation::set_config(config.escalation.clone());
+    notify::alerts::set_config(config.alert_store.clone());
+    pools::stale::set_config(config.pool_stale.clone());
+    pools::health::set_config(config.pool_health.clone());
+    pools::pool::set_worker_names(config.worker_names.clone());
+    tariff::set_config(config.tariff.clone());
+    profitability::set_config(config.profitability.clone());
+    miner::catalog::set_specs(config.model_specs.clone());
+    miner::thermal::set_config(config.thermal.clone());
+    miner::zone::set_config(config.zone_temp.clone());
+    miner::anomaly::set_config(config.anomaly.clone());
+    miner::restart::set_config(config.crash_loop.clone());
+    miner::group::set_groups(config.groups.clone());
+    miner::stagger::set_config(config.stagger.clone());
+    miner::subnet::set_config(config.scan_queue.clone());
+    miner::discovery::set_config(config.discovery.clone());
+    miner::detection::set_ttl(config.detect_cache_seconds);
+    miner::conn::set_idle_seconds(config.cgminer_idle_seconds);
+    miner::reach::set_config(config.reach.clone());
+    if let Err(e) = miner::route::set_config(config.route.clone()) {
+        error!("set route error, keep the previous routes: {:?}", e);
+    }
+    #[cfg(feature = "ssh")]
+    miner::ssh::set_config(miner::ssh::SshConfig {
+        known_hosts: miner::ssh::get_known_hosts_path(&config.app_path, &config.ssh.known_hosts),
+        ..config.ssh.clone()
+    });
+    miner::capture::set_dir(&config.capture_dir);
+    miner::capture::set_keep_raw(config.keep_raw);
+    miner::window::set_config(config.time_window.clone());
+    miner::mode::set_aliases(config.run_mode_aliases.clone());
+
+    miner::sheet::set_columns(config.sheet_columns.clone());
+    if let Err(e) = miner::position::set_format(&config.position_format) {
+        error!(
+            "set position format error, keep the previous format: {:?}",
+            e
+        );
+    }
+    miner::sheet::set_status_aliases(config.sheet_status.clone());
+
+    if let Err(e) = notify::init_sheet_backend(&config.sheet_backend) {
+        error!("init sheet backend error: {:?}", e);
+    }
+
+    info!("lcd initialized.");
+}
+
+/// feishu app and on-call users, returns the notify sinks with the feishu bot first
+#[cfg(feature = "feishu")]
+fn init_feishu(config: &MinersLibConfig) -> Vec<NotifySink> {
+    let feishu_app_secret = secret::resolve(&config.feishu_app_secret).unwrap_or_else(|e| {
+        error!("resolve feishu app secret error: {:?}", e);
+        String::new()
+    });
+    notify::feishu::init(
+        &config.feishu_app_id,
+        &feishu_app_secret,
+        &config.feishu_bot,
+    );
+    notify::feishu::set_oncall(config.feishu_oncall.clone());
+
+    // feishu bot keeps receiving every alert, other sinks as configured
+    let mut sinks = config.notify_sinks.clone();
+    if !config.feishu_bot.is_empty() {
+        sinks.insert(
+            0,
+            NotifySink {
+                notifier: NotifierType::Feishu(FeishuNotifier {
+                    bot: config.feishu_bot.clone(),
+                }),
+                min_severity: Severity::Info,
+            },
+        );
+    }
+    sinks
+}
+
+/// switch miner config as config, fails while another switch is running
+pub async fn switch_if_need(
+    runtime: tokio::runtime::Handle,
+    excel: &str,
+    sheets: Vec<&str>,
+    account_time_sheet: &str,
+    perf_time_sheet: &str,
+    pool_sheet: &str,
+) -> Result<SwitchReport, MinerError> {
+    access::check(Operation::Switch, "")?;
+    miner::entry::switch_if_need(
+        runtime,
+        excel,
+        sheets,
+        account_time_sheet,
+        perf_time_sheet,
+        pool_sheet,
+        &GroupSelector::All,
+    )
+    .await
+}
+
+/// run switch_if_need on a cron schedule, e.g. "0 */10 * * * *", runs never overlap
+pub fn start_switch_scheduler(
+    runtime: tokio::runtime::Handle,
+    config: SwitchScheduleConfig,
+    cron_expr: &str,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    access::check(Operation::Switch, cron_expr)?;
+    miner::schedule::start(runtime, config, cron_expr)
+}
+
+/// run the scheduled switch now, fails while a run is going
+pub async fn trigger_switch_now(runtime: tokio::runtime::Handle) -> Result<SwitchRun, MinerError> {
+    access::check(Operation::Switch, "")?;
+    miner::schedule::trigger_now(runtime).await
+}
+
+/// last applied account per machine as stored in the db, every machine when ips is empty
+pub fn query_switch_state(ips: Vec<String>) -> Result<Vec<SwitchState>, MinerError> {
+    miner::entry::query_switch_state(&ips)
+}
+
+/// status of the last scheduled or manual switch run
+pub fn last_switch_run() -> Option<SwitchRun> {
+    miner::schedule::last_run()
+}
+
+/// switch only the selected machines of the sheets
+pub async fn switch_group_if_need(
+    runtime: tokio::runtime::Handle,
+    excel: &str,
+    sheets: Vec<&str>,
+    account_time_sheet: &str,
+    perf_time_sheet: &str,
+    pool_sheet: &str,
+    selector: GroupSelector,
+) -> Result<SwitchReport, MinerError> {
+    access::check(Operation::Switch, &access::describe(&selector.resolve()))?;
+    miner::entry::switch_if_need(
+        runtime,
+        excel,
+        sheets,
+        account_time_sheet,
+        perf_time_sheet,
+        pool_sheet,
+        &selector,
+    )
+    .await
+}
+
+/// check the sheets the switch reads, reports problems without switching anything
+pub async fn validate_config(
+    excel: &str,
+    sheets: Vec<&str>,
+    account_time_sheet: &str,
+    perf_time_sheet: &str,
+    pool_sheet: &str,
+) -> Result<ValidationReport, MinerError> {
+    miner::validate::validate(
+        excel,
+        sheets,
+        account_time_sheet,
+        perf_time_sheet,
+        pool_sheet,
+    )
+    .await
+}
+
+/// the machine, account and pool configuration of the sheets the switch reads as one
+/// versioned document, FleetDocument::to_json for backups and review in git
+pub async fn export_fleet(
+    excel: &str,
+    sheets: Vec<&str>,
+    account_time_sheet: &str,
+    perf_time_sheet: &str,
+    pool_sheet: &str,
+) -> Result<FleetDocument, MinerError> {
+    access::check(Operation::ExportFleet, excel)?;
+    miner::fleet::export(
+        excel,
+        sheets,
+        account_time_sheet,
+        perf_time_sheet,
+        pool_sheet,
+    )
+    .await
+}
+
+/// restore an exported fleet onto the sheets it names, e.g. of a new controller, json from
+/// FleetDocument::from_json
+pub async fn import_fleet(
+    excel: &str,
+    document: &FleetDocument,
+) -> Result<FleetImport, MinerError> {
+    access::check(Operation::ImportFleet, excel)?;
+    miner::fleet::import(excel, document).await
+}
+
+/// what the document changes against the fleet of the sheets it names, FleetPlan::render
+/// for review before apply_fleet_plan
+pub async fn plan_fleet(excel: &str, document: &FleetDocument) -> Result<FleetPlan, MinerError> {
+    access::check(Operation::PlanFleet, excel)?;
+    miner::plan::plan(excel, document).await
+}
+
+/// write a reviewed plan onto the sheets and switch the machines it changed, refused when the
+/// sheets changed since the plan
+pub async fn apply_fleet_plan(
+    runtime: tokio::runtime::Handle,
+    excel: &str,
+    plan: &FleetPlan,
+) -> Result<FleetApply, MinerError> {
+    access::check(Operation::ApplyFleetPlan, excel)?;
+    miner::plan::apply(runtime, excel, plan).await
+}
+
+/// scan
+pub async fn scan(
+    runtime: tokio::runtime::Handle,
+    ip: &str,
+    offset: i32,
+    count: i32,
+    timeout_seconds: i64,
+) -> Result<Vec<MachineInfo>, MinerError> {
+    info!("scan ip: {}", ip);
+    access::check(Operation::Scan, ip)?;
+    miner::entry::scan(runtime, ip, offset, count, timeout_seconds).await
+}
+
+/// scan and compare against the last scan of the same range: new machines, machines gone and
+/// machines whose vendor, model or worker changed
+pub async fn scan_diff(
+    runtime: tokio::runtime::Handle,
+    ip: &str,
+    offset: i32,
+    count: i32,
+    timeout_seconds: i64,
+) -> Result<ScanDiff, MinerError> {
+    info!("scan diff ip: {}", ip);
+    access::check(Operation::Scan, ip)?;
+    miner::entry::scan_diff(runtime, ip, offset, count, timeout_seconds).await
+}
+
+/// scan plus arp neighbor table, machines alive but with a dead web ui are reported apart
+pub async fn discover(
+    runtime: tokio::runtime::Handle,
+    ip: &str,
+    offset: i32,
+    count: i32,
+    timeout_seconds: i64,
+) -> DiscoveryReport {
+    info!("discover ip: {}", ip);
+    miner::discovery::discover(runtime, ip, offset, count, timeout_seconds).await
+}
+
+/// forget detected miner types, every cached ip when ips is empty
+pub fn invalidate_detection(ips: Vec<String>) -> Result<(), MinerError> {
+    access::check(Operation::Config, &access::describe(&ips))?;
+    if ips.is_empty() {
+        miner::detection::clear();
+    }
+    for ip in ips.iter() {
+        miner::detection::invalidate(ip);
+    }
+    Ok(())
+}
+
+/// batch reboot, ips or a group selector. failed machines come back as MinerError::BatchError
+pub async fn reboot(
+    runtime: tokio::runtime::Handle,
+    ips: impl Into<GroupSelector>,
+    timeout_seconds: i64,
+) -> Result<(), MinerError> {
+    let ips = ips.into().resolve();
+    info!("reboot ips: {:?}", ips);
+    access::check(Operation::Reboot, &access::describe(&ips))?;
+    miner::entry::reboot_batch(runtime, ips, timeout_seconds).await
+}
+
+/// push the account to ips or a group selector right away, even when already on it.
+/// failed machines come back as MinerError::BatchError
+pub async fn force_switch(
+    runtime: tokio::runtime::Handle,
+    ips: impl Into<GroupSelector>,
+    mut account: Account,
+    timeout_seconds: i64,
+) -> Result<(), MinerError> {
+    account.run_mode = miner::mode::normalize(&account.run_mode);
+    let ips = ips.into().resolve();
+    info!("force switch {} ips: {:?}", account.name, ips);
+    access::check(Operation::Switch, &access::describe(&ips))?;
+    miner::entry::force_switch_batch(runtime, ips, account, timeout_seconds).await
+}
+
+/// batch config, ips or a group selector, run_mode a label as the sheets take, empty to keep
+/// it. failed machines come back as MinerError::BatchError
+pub async fn config(
+    runtime: tokio::runtime::Handle,
+    ips: impl Into<GroupSelector>,
+    account: Vec<PoolConfig>,
+    run_mode: String,
+    timeout_seconds: i64,
+) -> Result<i64, MinerError> {
+    //info!("config ips: {:?}", ips);
+    let ips = ips.into().resolve();
+    access::check(Operation::Config, &access::describe(&ips))?;
+    miner::entry::config_batch(
+        runtime,
+        ips,
+        account,
+        miner::mode::normalize(&run_mode),
+        timeout_seconds,
+    )
+    .await
+}
+
+/// watching, ips or a group selector
+pub async fn watching(
+    runtime: tokio::runtime::Handle,
+    ips: impl Into<GroupSelector>,
+    timeout_seconds: i64,
+) -> Result<Vec<MachineInfo>, MinerError> {
+    let ips = ips.into().resolve();
+    access::check(Operation::Watch, &access::describe(&ips))?;
+    miner::entry::watching(runtime, ips, timeout_seconds).await
+}
+
+/// watching, and write status back to the configured sheet columns
+pub async fn watching_with_sheet_status(
+    runtime: tokio::runtime::Handle,
+    ips: impl Into<GroupSelector>,
+    timeout_seconds: i64,
+    excel: &str,
+    sheets: Vec<&str>,
+) -> Result<Vec<MachineInfo>, MinerError> {
+    let ips = ips.into().resolve();
+    access::check(Operation::Watch, &access::describe(&ips))?;
+    miner::entry::watching_with_sheet_status(runtime, ips, timeout_seconds, excel, sheets).await
+}
+
+/// reload site/zone members from the machine sheets, members by group
+pub async fn load_groups(
+    excel: &str,
+    sheets: Vec<&str>,
+) -> Result<HashMap<String, Vec<String>>, MinerError> {
+    access::check(Operation::Settings, "groups")?;
+    miner::group::load_from_sheets(excel, &sheets).await
+}
+
+/// members by group as last loaded
+pub fn groups() -> HashMap<String, Vec<String>> {
+    miner::group::members()
+}
+
+/// diff desired state against live miners, apply the drift unless dry run
+pub async fn reconcile_state(
+    runtime: tokio::runtime::Handle,
+    state: DesiredState,
+    dry_run: bool,
+) -> Result<StateReport, MinerError> {
+    access::check(reconcile_operation(dry_run), "")?;
+    Ok(miner::desired::reconcile(&runtime, &state, !dry_run).await)
+}
+
+// a dry run only reads the machines
+fn reconcile_operation(dry_run: bool) -> Operation {
+    if dry_run {
+        Operation::Watch
+    } else {
+        Operation::Reconcile
+    }
+}
+
+/// reconcile desired state periodically, drift is notified
+pub fn start_desired_state_task(
+    runtime: tokio::runtime::Handle,
+    state: DesiredState,
+    interval_seconds: u64,
+    dry_run: bool,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    access::check(reconcile_operation(dry_run), "task")?;
+    Ok(miner::desired::schedule_task(
+        runtime,
+        state,
+        interval_seconds,
+        !dry_run,
+    ))
+}
+
+/// store a named config profile
+pub fn save_profile(profile: ConfigProfile) -> Result<(), MinerError> {
+    access::check(Operation::EditProfile, &profile.name)?;
+    miner::profile::save(profile)
+}
+
+pub fn delete_profile(name: &str) -> Result<(), MinerError> {
+    access::check(Operation::EditProfile, name)?;
+    miner::profile::delete(name)
+}
+
+pub fn profiles() -> Vec<ConfigProfile> {
+    miner::profile::list()
+}
+
+/// load and store the profiles of a sheet
+pub async fn load_profiles_from_sheet(
+    excel: &str,
+    sheet: &str,
+) -> Result<Vec<ConfigProfile>, MinerError> {
+    access::check(Operation::EditProfile, sheet)?;
+    miner::profile::load_from_sheet(excel, sheet).await
+}
+
+/// apply a named profile to ips or a group selector
+pub async fn apply_profile(
+    runtime: tokio::runtime::Handle,
+    ips: impl Into<GroupSelector>,
+    profile_name: &str,
+) -> Result<StateReport, MinerError> {
+    let selector = ips.into();
+    let target = format!("{} {}", profile_name, access::describe(&selector.resolve()));
+    access::check(Operation::ApplyProfile, &target)?;
+    miner::profile::apply(&runtime, &selector, profile_name).await
+}
+
+/// flag machines under maintenance until the time, switch, throttling and alerts skip them
+pub fn set_maintenance(ips: Vec<String>, until: i64) -> Result<(), MinerError> {
+    access::check(Operation::Maintenance, &access::describe(&ips))?;
+    miner::maintenance::set(&ips, until)
+}
+
+/// end maintenance of the machines
+pub fn clear_maintenance(ips: Vec<String>) -> Result<(), MinerError> {
+    access::check(Operation::Maintenance, &access::describe(&ips))?;
+    miner::maintenance::clear(&ips)
+}
+
+/// machines under maintenance and their end time
+pub fn maintenance_list() -> Vec<(String, i64)> {
+    miner::maintenance::list()
+}
+
+/// tags of a machine, from the sheet and the last watching
+pub fn machine_tags(ip: &str) -> Vec<String> {
+    miner::tag::tags_of(ip)
+}
+
+/// write rows of text into a sheet range, e.g. "ftMgRx!A2:C3"
+pub async fn update_sheet_range(
+    excel: &str,
+    range: &str,
+    values: Vec<Vec<String>>,
+) -> Result<(), MinerError> {
+    access::check(Operation::EditSheet, range)?;
+    let values = values
+        .into_iter()
+        .map(|row| row.into_iter().map(serde_json::Value::String).collect())
+        .collect();
+    notify::update_sheet_range(excel, range, values).await
+}
+
+/// send plain text message through chat bot
+#[cfg(feature = "feishu")]
+pub async fn notify_text(msg: &str) {
+    notify::feishu::notify(msg).await
+}
+
+/// send alert to all notify sinks routed for its severity
+pub async fn notify_alert(alert: &Alert) {
+    notify::notifier::send_alert(alert).await
+}
+
+/// replace the ip to pool worker mapping, e.g. after the inventory sheet changed, kept for
+/// callers not allowed to change settings
+pub fn set_worker_names(names: HashMap<String, String>) {
+    if let Err(e) = access::check(Operation::Settings, "worker names") {
+        error!("set worker names error: {:?}", e);
+        return;
+    }
+    pools::pool::set_worker_names(names)
+}
+
+/// add a notify sink at runtime, skipped for callers not allowed to change settings
+pub fn add_notify_sink(sink: NotifySink) {
+    if let Err(e) = access::check(Operation::Settings, "notify sink") {
+        error!("add notify sink error: {:?}", e);
+        return;
+    }
+    notify::notifier::add_sink(sink)
+}
+
+/// query machine records
+pub fn query_machine_records_by_time(
+    ip: String,
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<MachineRecord>, String> {
+    match db::query_records_by_time(ip, start_time, end_time) {
+        Ok(records) => Ok(records),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// machine records of one ip, or of every machine when ip is empty, a page at a time
+pub fn query_machine_records(
+    ip: &str,
+    start_time: i64,
+    end_time: i64,
+    query: &RecordQuery,
+) -> Result<Vec<MachineRecord>, MinerError> {
+    db::query_machine_records(ip, start_time, end_time, query)
+}
+
+/// clear records before time
+pub fn clear_records_before_time(time: i64) -> Result<(), String> {
+    access::check(Operation::Database, "clear records").map_err(|e| e.to_string())?;
+    match db::clear_records_before_time(time) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// through watching url to query pool workers data
+// pub async fn query_pool_workers(url: String) -> Result<Vec<PoolWorker>, MinerError> {
+//     pools::pool::query_pool_workers(&url).await
+// }
+
+/// start pool record update task, every account is queried each cycle
+#[cfg(feature = "pools")]
+pub fn start_pool_record_update_task(
+    runtime: tokio::runtime::Handle,
+    proxy: String,
+    accounts: Vec<PoolAccountConfig>,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    access::check(Operation::Settings, "pool record task")?;
+    Ok(pools::pool::schedule_query_task(runtime, proxy, accounts))
+}
+
+/// local against pool hashrate of the ips, from latest records in db
+pub fn reconcile(ips: Vec<String>) -> Result<Vec<HashReconcile>, MinerError> {
+    access::check(Operation::Watch, &access::describe(&ips))?;
+    pools::reconcile::reconcile(&ips)
+}
+
+/// health of stratum endpoints probed on switch
+pub fn pool_health_status() -> Vec<PoolEndpointStatus> {
+    pools::health::status()
+}
+
+/// query status of local stratum proxies now
+pub async fn query_proxy_status(proxies: Vec<StratumProxyConfig>) -> Vec<ProxyStatus> {
+    pools::proxy::query_all(&proxies).await
+}
+
+/// recorded status of a stratum proxy in time range
+pub fn query_proxy_records(
+    name: &str,
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<ProxyStatus>, MinerError> {
+    db::query_proxy_records_by_time(name, start_time, end_time)
+}
+
+/// online copy of the db to a file, safe while the tasks keep writing
+pub fn backup_db(to_path: &str) -> Result<(), MinerError> {
+    access::check(Operation::Database, to_path)?;
+    db::backup(to_path)
+}
+
+/// start the db VACUUM/ANALYZE task, e.g. once a day
+pub fn start_db_maintenance_task(
+    runtime: tokio::runtime::Handle,
+    interval_seconds: u64,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    access::check(Operation::Database, "maintenance task")?;
+    Ok(db::schedule_maintenance_task(runtime, interval_seconds))
+}
+
+/// health of the engine: task liveness, last pool query and switch run, db size and
+/// notifier errors
+pub fn status() -> EngineStatus {
+    crate::status::status()
+}
+
+/// stop the internal tasks, wait up to the timeout for the batch operations in flight and
+/// close the db. later batches fail with ShuttingDownError
+pub async fn shutdown(timeout_seconds: u64) -> Result<(), MinerError> {
+    access::check(Operation::Shutdown, "")?;
+    crate::shutdown::shutdown(std::time::Duration::from_secs(timeout_seconds)).await
+}
+
+/// verify and finish the switches and configs a crash or restart cut short, returns the
+/// machines resumed. init runs it when called inside a tokio runtime
+pub async fn resume_pending_jobs(runtime: tokio::runtime::Handle) -> Result<usize, MinerError> {
+    access::check(Operation::Switch, "pending jobs")?;
+    miner::job::resume_pending(runtime).await.inspect_err(|e| {
+        error!("resume pending jobs error: {:?}", e);
+    })
+}
+
+/// clear the data older than the retention windows now, returns the deleted rows
+pub fn run_retention_now() -> Result<usize, MinerError> {
+    access::check(Operation::Database, "retention")?;
+    retention::run_now()
+}
+
+/// start the retention task clearing old data every interval, e.g. every hour
+pub fn start_retention_task(
+    runtime: tokio::runtime::Handle,
+    interval_seconds: u64,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    access::check(Operation::Database, "retention task")?;
+    Ok(retention::schedule_task(runtime, interval_seconds))
+}
+
+/// start the task running the due escalation steps every interval, e.g. every minute
+pub fn start_escalation_task(
+    runtime: tokio::runtime::Handle,
+    interval_seconds: u64,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    access::check(Operation::Settings, "escalation task")?;
+    Ok(notify::escalation::schedule_task(runtime, interval_seconds))
+}
+
+/// open incidents of the escalation rules, acknowledged ones included
+pub fn incidents() -> Vec<Incident> {
+    notify::escalation::incidents()
+}
+
+/// stop the escalation of an incident, e.g. when someone is on it
+pub fn ack_incident(id: u64, by: &str) -> Result<(), MinerError> {
+    access::check(Operation::AckAlert, &format!("incident {}", id))?;
+    notify::escalation::ack(id, by)
+}
+
+/// stored alerts, open, acknowledged or resolved
+pub fn query_alerts(query: &AlertQuery) -> Result<Vec<AlertEntry>, MinerError> {
+    notify::alerts::query(query)
+}
+
+/// mark a stored alert acknowledged, the escalation of its incident stops too
+pub fn ack_alert(id: i64, by: &str) -> Result<(), MinerError> {
+    access::check(Operation::AckAlert, &format!("alert {}", id))?;
+    notify::alerts::ack(id, by)
+}
+
+/// alerts per kind and the top machines by alerts raised in the range, e.g. for an rma
+pub fn alert_analytics(
+    start_time: i64,
+    end_time: i64,
+    top: usize,
+) -> Result<AlertAnalytics, MinerError> {
+    notify::alerts::analytics(start_time, end_time, top)
+}
+
+/// close a stored alert, it opens again as a new one when raised again
+pub fn resolve_alert(id: i64) -> Result<(), MinerError> {
+    access::check(Operation::ResolveAlert, &format!("alert {}", id))?;
+    notify::alerts::resolve(id)
+}
+
+/// start stratum proxy status task, records status and alerts when a proxy is down
+pub fn start_proxy_status_task(
+    runtime: tokio::runtime::Handle,
+    proxies: Vec<StratumProxyConfig>,
+    interval_seconds: u64,
+) -> tokio::task::JoinHandle<()> {
+    pools::proxy::schedule_status_task(runtime, proxies, interval_seconds)
+}
+
+/// current electricity price and the run mode it maps to, None without tariff
+pub async fn current_tariff() -> Result<Option<(f64, String)>, MinerError> {
+    Ok(tariff::current()
+        .await?
+        .map(|(price, mode)| (price, mode.to_string())))
+}
+
+/// market, power price and the run mode of each model seen, None without profitability
+pub async fn current_profitability() -> Result<Option<Profitability>, MinerError> {
+    profitability::current().await
+}
+
+/// shed load, sleep or downclock machines until estimated fleet power is below the target
+pub async fn curtail(
+    runtime: tokio::runtime::Handle,
+    target_power_kw: f64,
+    strategy: CurtailStrategy,
+) -> Result<CurtailResult, MinerError> {
+    access::check(Operation::Curtail, &format!("{} kW", target_power_kw))?;
+    miner::curtail::curtail(runtime, target_power_kw, strategy).await
+}
+
+/// undo curtailment, returns ips failed to restore, every curtailed one for callers not
+/// allowed to
+pub async fn restore(runtime: tokio::runtime::Handle) -> Vec<String> {
+    if let Err(e) = access::check(Operation::Curtail, "restore") {
+        error!("restore error: {:?}", e);
+        return miner::curtail::curtailed()
+            .into_iter()
+            .map(|s| s.ip)
+            .collect();
+    }
+    miner::curtail::restore(runtime).await
+}
+
+/// machines currently curtailed
+pub fn curtailed() -> Vec<CurtailStep> {
+    miner::curtail::curtailed()
+}
+
+/// hourly kWh per machine and fleet totals, estimated for miners not reporting power
+pub fn query_power_usage(start_time: i64, end_time: i64) -> Result<PowerUsage, MinerError> {
+    miner::power::query_power_usage(start_time, end_time)
+}
+
+/// machines below their own hashrate baseline at the end of the range of stored records
+pub fn query_hashrate_anomalies(
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<Anomaly>, MinerError> {
+    miner::anomaly::query(start_time, end_time)
+}
+
+/// machines seen with a mac, the ip each had last, so dhcp moves can be followed
+pub fn query_inventory() -> Result<Vec<InventoryEntry>, MinerError> {
+    miner::inventory::query()
+}
+
+/// machines of the sheets by position with their latest record of the range, for heatmaps of
+/// the room, every room when empty
+pub fn query_position_heatmap(
+    start_time: i64,
+    end_time: i64,
+    room: &str,
+) -> Result<Vec<PositionCell>, MinerError> {
+    miner::position::heatmap(start_time, end_time, room)
+}
+
+/// query pool revenue and payouts, dates are YYYY-MM-DD and inclusive
+pub fn query_pool_earnings(
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<PoolEarning>, MinerError> {
+    db::query_pool_earnings(start_date, end_date)
+}
+
+/// pool hashrate per account and for the fleet over the time range, with the workers
+/// that stopped reporting in it
+pub fn query_pool_summary(start_time: i64, end_time: i64) -> Result<PoolSummary, MinerError> {
+    pools::summary::query_pool_summary(start_time, end_time)
+}
+
+/// generate summary report of time range from db
+pub fn generate_report(start_time: i64, end_time: i64) -> Result<report::DailyReport, MinerError> {
+    report::generate_report(start_time, end_time)
+}
+
+/// machines best first by efficiency, temperature or downtime over the time range
+pub fn rank_machines(
+    metric: report::RankMetric,
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<report::MachineSummary>, MinerError> {
+    report::rank_machines(metric, start_time, end_time)
+}
+
+/// start report task, push and/or write report on cron schedule
+pub fn start_report_task(
+    runtime: tokio::runtime::Handle,
+    config: report::ReportConfig,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    report::start_report_task(runtime, config)
+}
+
+/// store a secret into the encrypted secrets file, referenced as "secret:<name>"
+pub fn set_secret(name: &str, value: &str) -> Result<(), MinerError> {
+    access::check(Operation::Secrets, name)?;
+    secret::set(name, value)
+}
+
+pub fn remove_secret(name: &str) -> Result<(), MinerError> {
+    access::check(Operation::Secrets, name)?;
+    secret::remove(name)
+}
+
+/// names of the stored secrets, values are never returned
+pub fn secret_names() -> Result<Vec<String>, MinerError> {
+    secret::names()
+}
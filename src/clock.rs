@@ -0,0 +1,51 @@
+/// site time zone, used for time windows, schedules and human readable timestamps
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use chrono_tz::Tz;
+
+use crate::context;
+use crate::error::MinerError;
+
+static TIMEZONE: context::Local<Option<Tz>> = context::Local::new(Option::default);
+
+/// iana name, e.g. "Asia/Shanghai", empty for the host time zone
+pub fn set_timezone(name: &str) -> Result<(), MinerError> {
+    let tz = if name.trim().is_empty() {
+        None
+    } else {
+        Some(
+            name.trim()
+                .parse::<Tz>()
+                .map_err(|e| MinerError::TimezoneError(e.to_string()))?,
+        )
+    };
+    TIMEZONE.set(tz);
+    Ok(())
+}
+
+/// the instant in the site time zone
+pub fn at(t: DateTime<Utc>) -> DateTime<FixedOffset> {
+    match TIMEZONE.get() {
+        Some(tz) => t.with_timezone(&tz).fixed_offset(),
+        None => t.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+pub fn now() -> DateTime<FixedOffset> {
+    at(Utc::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    #[test]
+    fn test_site_timezone() {
+        let t = DateTime::from_timestamp(1_700_000_000, 0).unwrap(); // 22:13:20 utc
+        set_timezone("Asia/Shanghai").unwrap();
+        assert_eq!(at(t).hour(), 6);
+        assert!(set_timezone("Mars/Olympus").is_err());
+        set_timezone("").unwrap();
+        assert_eq!(at(t), t.with_timezone(&Local).fixed_offset());
+    }
+}
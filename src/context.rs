@@ -0,0 +1,144 @@
+/// state of one managed site: the db, the feishu app, the notify sinks and the settings and
+/// caches declared as Local. the free functions work on the default context, an LcdCore runs
+/// its calls and every task they spawn in its own, so several sites can be managed from one
+/// process. the network settings, the connection routes and the shutdown stay process wide
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+#[cfg(feature = "feishu")]
+use crate::notify::feishu::FeishuApp;
+use crate::notify::notifier::NotifySink;
+#[cfg(feature = "sqlite")]
+use crate::store::db::DB;
+
+lazy_static! {
+    static ref DEFAULT: Arc<Context> = Arc::new(Context::default());
+}
+
+tokio::task_local! {
+    static CURRENT: Arc<Context>;
+}
+
+#[derive(Default)]
+pub struct Context {
+    #[cfg(feature = "sqlite")]
+    pub(crate) db: Mutex<Option<DB>>,
+    #[cfg(feature = "feishu")]
+    pub(crate) feishu: FeishuApp,
+    pub(crate) sinks: Mutex<Vec<NotifySink>>,
+    // values of the Local statics, by the address of the static
+    values: Mutex<HashMap<usize, Box<dyn Any + Send + Sync>>>,
+}
+
+/// a value each context keeps for itself, declared as a static of the module instead of a
+/// lazy_static mutex. the first use in a context starts from init
+pub struct Local<T> {
+    init: fn() -> T,
+}
+
+impl<T: Send + 'static> Local<T> {
+    pub const fn new(init: fn() -> T) -> Self {
+        Local { init }
+    }
+
+    // the lock of the map is released before the value is used, so a value may use others
+    fn value(&'static self) -> Arc<Mutex<T>> {
+        let context = current();
+        let mut values = context.values.lock().unwrap();
+        values
+            .entry(self as *const Self as usize)
+            .or_insert_with(|| Box::new(Arc::new(Mutex::new((self.init)()))))
+            .downcast_ref::<Arc<Mutex<T>>>()
+            .expect("one type per static")
+            .clone()
+    }
+
+    pub fn with<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.value().lock().unwrap())
+    }
+
+    pub fn set(&'static self, value: T) {
+        self.with(|current| *current = value);
+    }
+}
+
+impl<T: Clone + Send + 'static> Local<T> {
+    pub fn get(&'static self) -> T {
+        self.with(|value| value.clone())
+    }
+}
+
+/// context of the running call, the default one outside of an LcdCore
+pub fn current() -> Arc<Context> {
+    CURRENT
+        .try_with(|context| context.clone())
+        .unwrap_or_else(|_| DEFAULT.clone())
+}
+
+/// run the future in the context
+pub async fn scope<F: Future>(context: Arc<Context>, future: F) -> F::Output {
+    CURRENT.scope(context, future).await
+}
+
+/// run the closure in the context
+pub fn enter<R>(context: Arc<Context>, f: impl FnOnce() -> R) -> R {
+    CURRENT.sync_scope(context, f)
+}
+
+/// spawn keeping the context of the caller
+pub fn spawn<F>(runtime: &Handle, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    runtime.spawn(CURRENT.scope(current(), future))
+}
+
+/// spawn_blocking keeping the context of the caller
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let context = current();
+    tokio::task::spawn_blocking(move || CURRENT.sync_scope(context, f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "sqlite")]
+    use crate::store::db;
+
+    #[tokio::test]
+    async fn test_separate_contexts() {
+        let site = Arc::new(Context::default());
+        #[cfg(feature = "sqlite")]
+        {
+            enter(site.clone(), || db::init(db::MEMORY)).unwrap();
+            assert!(site.db.lock().unwrap().is_some());
+        }
+        assert!(!Arc::ptr_eq(&current(), &site));
+
+        let runtime = Handle::current();
+        let (spawned, blocking) = scope(site.clone(), async {
+            let spawned = spawn(&runtime, async { current() }).await.unwrap();
+            let blocking = spawn_blocking(current).await.unwrap();
+            (spawned, blocking)
+        })
+        .await;
+        assert!(Arc::ptr_eq(&spawned, &site));
+        assert!(Arc::ptr_eq(&blocking, &site));
+
+        static COUNT: Local<u32> = Local::new(u32::default);
+        COUNT.set(1);
+        enter(site.clone(), || COUNT.with(|count| *count += 5));
+        assert_eq!(COUNT.get(), 1);
+        assert_eq!(enter(site, || COUNT.get()), 5);
+    }
+}
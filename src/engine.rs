@@ -0,0 +1,437 @@
+/// builder and handle of the engine, only the components asked for are set up. the free
+/// functions of the crate stay as they are, the handle calls them with its own runtime and
+/// context, so each handle has its own db, feishu app, notify sinks and settings
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use log::info;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::context::{self, Context};
+use crate::error::MinerError;
+use crate::miner::entry::{MachineInfo, MachineRecord, PoolConfig};
+#[cfg(feature = "pools")]
+use crate::PoolAccountConfig;
+use crate::{
+    Account, EngineStatus, GroupSelector, MinersLibConfig, NotifySink, PoolHealthConfig,
+    PoolSummary, RecordQuery, RetentionConfig, ScanDiff, StaleWorkerConfig, SwitchReport,
+    SwitchRun, SwitchScheduleConfig,
+};
+
+pub struct LcdCoreBuilder {
+    config: MinersLibConfig,
+    runtime: Option<Handle>,
+    // proxy, accounts
+    #[cfg(feature = "pools")]
+    pools: Option<(String, Vec<PoolAccountConfig>)>,
+    // config, cron
+    scheduler: Option<(SwitchScheduleConfig, String)>,
+    db_maintenance_seconds: u64,
+    retention_seconds: u64,
+}
+
+impl LcdCoreBuilder {
+    fn new() -> Self {
+        LcdCoreBuilder {
+            config: MinersLibConfig::default(),
+            runtime: None,
+            #[cfg(feature = "pools")]
+            pools: None,
+            scheduler: None,
+            db_maintenance_seconds: 0,
+            retention_seconds: 0,
+        }
+    }
+
+    /// the remaining settings, for the fields without a builder method
+    pub fn config(mut self, config: MinersLibConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn app_path(mut self, app_path: &str) -> Self {
+        self.config.app_path = app_path.to_string();
+        self
+    }
+
+    /// outbound proxy for feishu, pools and notify channels
+    pub fn proxy(mut self, proxy: &str) -> Self {
+        self.config.proxy = proxy.to_string();
+        self
+    }
+
+    /// runtime of the tasks and batches, the current one when not set
+    pub fn runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// iana time zone of the site, e.g. "Asia/Shanghai"
+    pub fn timezone(mut self, timezone: &str) -> Self {
+        self.config.timezone = timezone.to_string();
+        self
+    }
+
+    /// sqlite db, empty path for "<app_path>/db/lcd.sqlite", ":memory:" to keep it in memory
+    pub fn db(mut self, db_path: &str) -> Self {
+        self.config.is_need_db = true;
+        self.config.db_path = db_path.to_string();
+        self
+    }
+
+    /// days kept per table, cleared every interval when it is not 0
+    pub fn retention(mut self, retention: RetentionConfig, interval_seconds: u64) -> Self {
+        self.config.retention = Some(retention);
+        self.retention_seconds = interval_seconds;
+        self
+    }
+
+    /// VACUUM/ANALYZE the db every interval
+    pub fn db_maintenance(mut self, interval_seconds: u64) -> Self {
+        self.db_maintenance_seconds = interval_seconds;
+        self
+    }
+
+    /// feishu app for the sheets, the bot receives every alert when not empty
+    #[cfg(feature = "feishu")]
+    pub fn feishu(mut self, app_id: &str, app_secret: &str, bot: &str) -> Self {
+        self.config.feishu_app_id = app_id.to_string();
+        self.config.feishu_app_secret = app_secret.to_string();
+        self.config.feishu_bot = bot.to_string();
+        self
+    }
+
+    pub fn notify_sink(mut self, sink: NotifySink) -> Self {
+        self.config.notify_sinks.push(sink);
+        self
+    }
+
+    /// query the pool accounts every cycle
+    #[cfg(feature = "pools")]
+    pub fn pools(mut self, proxy: &str, accounts: Vec<PoolAccountConfig>) -> Self {
+        self.pools = Some((proxy.to_string(), accounts));
+        self
+    }
+
+    pub fn pool_stale(mut self, config: StaleWorkerConfig) -> Self {
+        self.config.pool_stale = config;
+        self
+    }
+
+    pub fn pool_health(mut self, config: PoolHealthConfig) -> Self {
+        self.config.pool_health = config;
+        self
+    }
+
+    /// ip to pool worker, "acc.rack3-07" or the suffix "rack3-07"
+    pub fn worker_names(mut self, worker_names: HashMap<String, String>) -> Self {
+        self.config.worker_names = worker_names;
+        self
+    }
+
+    /// run the switch on a cron schedule, e.g. "0 */10 * * * *"
+    pub fn scheduler(mut self, config: SwitchScheduleConfig, cron_expr: &str) -> Self {
+        self.scheduler = Some((config, cron_expr.to_string()));
+        self
+    }
+
+    /// init the engine and start the tasks of the components set
+    pub fn build(self) -> Result<LcdCore, MinerError> {
+        let runtime = match self.runtime.clone() {
+            Some(runtime) => runtime,
+            None => Handle::try_current().map_err(|_| MinerError::NoRuntimeError)?,
+        };
+        let context = Arc::new(Context::default());
+        // init resumes pending jobs on the current runtime
+        let tasks = context::enter(context.clone(), || {
+            let _guard = runtime.enter();
+            crate::init(&self.config);
+            self.start_tasks(&runtime)
+        })?;
+        info!("lcd core built, {} tasks", tasks.len());
+
+        Ok(LcdCore {
+            runtime,
+            context,
+            tasks: Mutex::new(tasks),
+        })
+    }
+
+    fn start_tasks(self, runtime: &Handle) -> Result<Vec<JoinHandle<()>>, MinerError> {
+        let mut tasks = vec![];
+        #[cfg(feature = "pools")]
+        if let Some((proxy, accounts)) = self.pools {
+            tasks.push(crate::start_pool_record_update_task(
+                runtime.clone(),
+                proxy,
+                accounts,
+            )?);
+        }
+        if let Some((config, cron_expr)) = self.scheduler {
+            tasks.push(crate::start_switch_scheduler(
+                runtime.clone(),
+                config,
+                &cron_expr,
+            )?);
+        }
+        if self.config.is_need_db && self.retention_seconds > 0 {
+            tasks.push(crate::start_retention_task(
+                runtime.clone(),
+                self.retention_seconds,
+            )?);
+        }
+        if self.config.is_need_db && self.db_maintenance_seconds > 0 {
+            tasks.push(crate::start_db_maintenance_task(
+                runtime.clone(),
+                self.db_maintenance_seconds,
+            )?);
+        }
+        Ok(tasks)
+    }
+}
+
+/// handle of an initialized engine
+pub struct LcdCore {
+    runtime: Handle,
+    context: Arc<Context>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl LcdCore {
+    pub fn builder() -> LcdCoreBuilder {
+        LcdCoreBuilder::new()
+    }
+
+    pub fn runtime(&self) -> &Handle {
+        &self.runtime
+    }
+
+    /// run a free function of the crate in the context of the handle
+    async fn scope<F: Future>(&self, future: F) -> F::Output {
+        context::scope(self.context.clone(), future).await
+    }
+
+    fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        context::enter(self.context.clone(), f)
+    }
+
+    pub async fn scan(
+        &self,
+        ip: &str,
+        offset: i32,
+        count: i32,
+        timeout_seconds: i64,
+    ) -> Result<Vec<MachineInfo>, MinerError> {
+        self.scope(crate::scan(
+            self.runtime.clone(),
+            ip,
+            offset,
+            count,
+            timeout_seconds,
+        ))
+        .await
+    }
+
+    pub async fn scan_diff(
+        &self,
+        ip: &str,
+        offset: i32,
+        count: i32,
+        timeout_seconds: i64,
+    ) -> Result<ScanDiff, MinerError> {
+        self.scope(crate::scan_diff(
+            self.runtime.clone(),
+            ip,
+            offset,
+            count,
+            timeout_seconds,
+        ))
+        .await
+    }
+
+    pub async fn watching(
+        &self,
+        ips: impl Into<GroupSelector>,
+        timeout_seconds: i64,
+    ) -> Result<Vec<MachineInfo>, MinerError> {
+        self.scope(crate::watching(self.runtime.clone(), ips, timeout_seconds))
+            .await
+    }
+
+    pub async fn reboot(
+        &self,
+        ips: impl Into<GroupSelector>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        self.scope(crate::reboot(self.runtime.clone(), ips, timeout_seconds))
+            .await
+    }
+
+    pub async fn config(
+        &self,
+        ips: impl Into<GroupSelector>,
+        account: Vec<PoolConfig>,
+        run_mode: String,
+        timeout_seconds: i64,
+    ) -> Result<i64, MinerError> {
+        self.scope(crate::config(
+            self.runtime.clone(),
+            ips,
+            account,
+            run_mode,
+            timeout_seconds,
+        ))
+        .await
+    }
+
+    pub async fn force_switch(
+        &self,
+        ips: impl Into<GroupSelector>,
+        account: Account,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        self.scope(crate::force_switch(
+            self.runtime.clone(),
+            ips,
+            account,
+            timeout_seconds,
+        ))
+        .await
+    }
+
+    pub async fn switch_if_need(
+        &self,
+        excel: &str,
+        sheets: Vec<&str>,
+        account_time_sheet: &str,
+        perf_time_sheet: &str,
+        pool_sheet: &str,
+    ) -> Result<SwitchReport, MinerError> {
+        self.scope(crate::switch_if_need(
+            self.runtime.clone(),
+            excel,
+            sheets,
+            account_time_sheet,
+            perf_time_sheet,
+            pool_sheet,
+        ))
+        .await
+    }
+
+    /// run the scheduled switch now, fails while a run is going
+    pub async fn trigger_switch_now(&self) -> Result<SwitchRun, MinerError> {
+        self.scope(crate::trigger_switch_now(self.runtime.clone()))
+            .await
+    }
+
+    pub fn query_machine_records(
+        &self,
+        ip: &str,
+        start_time: i64,
+        end_time: i64,
+        query: &RecordQuery,
+    ) -> Result<Vec<MachineRecord>, MinerError> {
+        self.enter(|| crate::query_machine_records(ip, start_time, end_time, query))
+    }
+
+    pub fn query_pool_summary(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<PoolSummary, MinerError> {
+        self.enter(|| crate::query_pool_summary(start_time, end_time))
+    }
+
+    pub fn generate_report(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<crate::report::DailyReport, MinerError> {
+        self.enter(|| crate::generate_report(start_time, end_time))
+    }
+
+    pub fn status(&self) -> EngineStatus {
+        self.enter(crate::status)
+    }
+
+    /// stop the tasks started by the builder and close the db of the handle. the shutdown
+    /// signal itself is process wide
+    pub async fn shutdown(&self, timeout_seconds: u64) -> Result<(), MinerError> {
+        let result = self.scope(crate::shutdown(timeout_seconds)).await;
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let builder = LcdCore::builder()
+            .app_path("/tmp/lcd")
+            .db(":memory:")
+            .timezone("Asia/Shanghai")
+            .scheduler(SwitchScheduleConfig::default(), "0 */10 * * * *");
+        assert!(builder.config.is_need_db);
+        assert_eq!(builder.config.db_path, ":memory:");
+        assert_eq!(builder.config.timezone, "Asia/Shanghai");
+        assert!(builder.scheduler.is_some());
+
+        // no runtime to run on
+        assert!(matches!(
+            LcdCore::builder().build(),
+            Err(MinerError::NoRuntimeError)
+        ));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sites_isolated() {
+        let schedule = SwitchScheduleConfig {
+            excel: "site-a".to_string(),
+            ..Default::default()
+        };
+        let a = LcdCore::builder()
+            .db(crate::store::db::MEMORY)
+            .timezone("Asia/Shanghai")
+            .scheduler(schedule, "0 0 0 1 1 *")
+            .build()
+            .unwrap();
+        // building another site keeps the settings of the first
+        let b = LcdCore::builder()
+            .db(crate::store::db::MEMORY)
+            .timezone("UTC")
+            .build()
+            .unwrap();
+        assert_eq!(
+            a.enter(crate::clock::now).offset().local_minus_utc(),
+            8 * 3600
+        );
+        assert_eq!(b.enter(crate::clock::now).offset().local_minus_utc(), 0);
+
+        let ips = vec!["10.9.1.1".to_string()];
+        let until = chrono::Local::now().timestamp() + 3600;
+        a.enter(|| crate::set_maintenance(ips.clone(), until))
+            .unwrap();
+        assert_eq!(a.enter(crate::maintenance_list).len(), 1);
+        assert!(b.enter(crate::maintenance_list).is_empty());
+
+        // the run of a finds its sheets and its own running switch, b has no scheduler
+        let running = a.enter(|| crate::miner::entry::SWITCH_RUNNING.get());
+        let _running = running.lock_owned().await;
+        assert!(matches!(
+            a.trigger_switch_now().await,
+            Err(MinerError::SwitchRunningError)
+        ));
+        assert!(matches!(
+            b.trigger_switch_now().await,
+            Err(MinerError::SwitchScheduleNotStartedError)
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 /// General error define
+use serde::{Serialize, Serializer};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -31,21 +32,130 @@ pub enum MinerError {
     #[error("Websocket Error: {0}")]
     WebSocketError(String),
 
+    #[error("SSH Error: {0}")]
+    SshError(String),
+
     #[error("Poolin Api Regex Error")]
     PoolinApiRegexError,
 
     #[error("Poolin Api Request Error")]
     PoolinApiRequestError,
 
+    #[error("Antpool Api Regex Error")]
+    AntpoolApiRegexError,
+
+    #[error("Antpool Api Request Error")]
+    AntpoolApiRequestError,
+
+    #[error("ViaBTC Api Regex Error")]
+    ViaBtcApiRegexError,
+
+    #[error("ViaBTC Api Request Error")]
+    ViaBtcApiRequestError,
+
     #[error("Pool Type Not Detected")]
     PoolTypeNotDetected,
 
+    #[error("Google Auth Error")]
+    GoogleAuthError,
+
+    #[error("Sheet Columns Missing: {0}")]
+    SheetColumnMissingError(String),
+
+    #[error("Tag Expression Error: {0}")]
+    TagExprError(String),
+
+    #[error("Profile Not Found: {0}")]
+    ProfileNotFoundError(String),
+
+    #[error("Incident Not Found: {0}")]
+    IncidentNotFoundError(u64),
+
+    #[error("Alert Not Found: {0}")]
+    AlertNotFoundError(i64),
+
+    #[error("Log Config Error: {0}")]
+    LogConfigError(String),
+
+    #[error("Market Feed Error: {0}")]
+    MarketFeedError(String),
+
+    #[error("Time Window Overlap: {0}")]
+    TimeWindowOverlapError(String),
+
+    #[error("Position Format Error: {0}")]
+    PositionFormatError(String),
+
+    #[error("Fleet Document Error: {0}")]
+    FleetDocumentError(String),
+
+    #[error("Timezone Error: {0}")]
+    TimezoneError(String),
+
+    #[error("Switch Already Running")]
+    SwitchRunningError,
+
+    #[error("Switch Scheduler Not Started")]
+    SwitchScheduleNotStartedError,
+
+    #[error("Unknown Record Column: {0}")]
+    UnknownColumnError(String),
+
+    #[error("Invalid Ip: {0}")]
+    InvalidIpError(String),
+
+    #[error("Database Not Initialized")]
+    DbNotInitError,
+
+    #[error("Shutting Down")]
+    ShuttingDownError,
+
+    #[error("Shutdown Timeout, {0} operations still running")]
+    ShutdownTimeoutError(usize),
+
+    #[error("No Tokio Runtime")]
+    NoRuntimeError,
+
+    #[error("Feature Disabled: {0}")]
+    FeatureDisabledError(String),
+
+    #[error("FFI Argument Error: {0}")]
+    FfiArgumentError(String),
+
+    #[error("Permission Denied: {0}")]
+    PermissionDeniedError(String),
+
+    #[error("Secret Error: {0}")]
+    SecretError(String),
+
+    #[error("Secret Not Found: {0}")]
+    SecretNotFoundError(String),
+
+    #[error("Grpc Error: {0}")]
+    GrpcError(String),
+
+    #[error(transparent)]
+    BatchError(#[from] BatchError),
+
+    /// which machine, operation and miner command failed
+    #[error("{op} {ip}{}: {source}", .command.as_ref().map(|c| format!(" [{}]", c)).unwrap_or_default())]
+    Context {
+        ip: String,
+        op: String,
+        command: Option<String>,
+        #[source]
+        source: Box<MinerError>,
+    },
+
+    #[cfg(feature = "sqlite")]
     #[error(transparent)]
     SQLiteError(#[from] rusqlite::Error),
 
+    #[cfg(any(feature = "engine", feature = "pools"))]
     #[error(transparent)]
     ReqwestError(#[from] reqwest::Error),
 
+    #[cfg(any(feature = "engine", feature = "pools"))]
     #[error(transparent)]
     ToStrError(#[from] reqwest::header::ToStrError),
 
@@ -55,9 +165,6 @@ pub enum MinerError {
     #[error(transparent)]
     JsonParseError(#[from] serde_json::Error),
 
-    #[error(transparent)]
-    CurlError(#[from] curl::Error),
-
     #[error(transparent)]
     FromUtf8Error(#[from] std::string::FromUtf8Error),
 
@@ -67,9 +174,281 @@ pub enum MinerError {
     #[error(transparent)]
     TimeParserError(#[from] chrono::ParseError),
 
+    #[cfg(feature = "engine")]
     #[error(transparent)]
     JoinError(#[from] tokio::task::JoinError),
 
     #[error(transparent)]
     StdIoError(#[from] std::io::Error),
+
+    #[cfg(feature = "gsheets")]
+    #[error(transparent)]
+    JwtError(#[from] jsonwebtoken::errors::Error),
+
+    #[cfg(feature = "engine")]
+    #[error(transparent)]
+    CronError(#[from] cron::error::Error),
+
+    #[cfg(feature = "email")]
+    #[error(transparent)]
+    EmailAddressError(#[from] lettre::address::AddressError),
+
+    #[cfg(feature = "email")]
+    #[error(transparent)]
+    EmailError(#[from] lettre::error::Error),
+
+    #[cfg(feature = "email")]
+    #[error(transparent)]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+
+    // robot answering 200 with a non zero code, e.g. a wrong keyword or a rate limit
+    #[error("Webhook Error: {0}")]
+    WebhookError(String),
+}
+
+/// per machine errors of a batch operation
+#[derive(Error, Debug)]
+#[error("{} machines failed, {} succeeded", .failed.len(), .succeeded)]
+pub struct BatchError {
+    pub succeeded: usize,
+    pub failed: Vec<(String, MinerError)>,
+}
+
+impl MinerError {
+    /// connect, read or request timed out
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            MinerError::StdIoError(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+            ),
+            #[cfg(any(feature = "engine", feature = "pools"))]
+            MinerError::ReqwestError(e) => e.is_timeout(),
+            MinerError::Context { source, .. } => source.is_timeout(),
+            _ => false,
+        }
+    }
+
+    /// wrap with the machine and operation, e.g. ("192.168.1.10", "reboot")
+    pub fn context(self, ip: &str, op: &str) -> MinerError {
+        MinerError::Context {
+            ip: ip.to_string(),
+            op: op.to_string(),
+            command: None,
+            source: Box::new(self),
+        }
+    }
+
+    /// wrap with the machine, operation and the miner api command
+    pub fn command_context(self, ip: &str, op: &str, command: &str) -> MinerError {
+        MinerError::Context {
+            ip: ip.to_string(),
+            op: op.to_string(),
+            command: Some(command.to_string()),
+            source: Box::new(self),
+        }
+    }
+
+    /// innermost error without context
+    pub fn root(&self) -> &MinerError {
+        match self {
+            MinerError::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// stable code, the thousands group the cause:
+    /// 1 device, 2 network, 3 data, 4 sheet, 5 pool, 6 config, 7 storage, 8 notify, 9 service
+    pub fn code(&self) -> u32 {
+        if self.is_timeout() {
+            return 2001;
+        }
+        match self.root() {
+            MinerError::MinerNotSupportError => 1001,
+            MinerError::AuthError => 1002,
+            MinerError::ReadAvalonConfigError => 1003,
+            MinerError::TcpReadError => 1004,
+            MinerError::SshError(_) => 1005,
+            MinerError::PingFiledError => 2002,
+            MinerError::HttpError => 2003,
+            #[cfg(any(feature = "engine", feature = "pools"))]
+            MinerError::ReqwestError(_) => 2005,
+            MinerError::StdIoError(_) => 2006,
+            MinerError::WebSocketError(_) => 2007,
+            MinerError::UriError(_) => 2008,
+            MinerError::JsonParseError(_) => 3001,
+            MinerError::FromUtf8Error(_) => 3002,
+            #[cfg(any(feature = "engine", feature = "pools"))]
+            MinerError::ToStrError(_) => 3003,
+            MinerError::SerdeUrlEncodedError(_) => 3004,
+            MinerError::TimeParserError(_) => 3005,
+            MinerError::UnknownColumnError(_) => 3006,
+            MinerError::InvalidIpError(_) => 3007,
+            MinerError::MarketFeedError(_) => 3008,
+            MinerError::FeishuParserJsonError => 4001,
+            MinerError::ReadTimeConfigError => 4002,
+            MinerError::SheetColumnMissingError(_) => 4003,
+            MinerError::GoogleAuthError => 4004,
+            MinerError::TimeWindowOverlapError(_) => 4005,
+            MinerError::PositionFormatError(_) => 4006,
+            MinerError::FleetDocumentError(_) => 4007,
+            MinerError::PoolinApiRegexError => 5001,
+            MinerError::PoolinApiRequestError => 5002,
+            MinerError::AntpoolApiRegexError => 5003,
+            MinerError::AntpoolApiRequestError => 5004,
+            MinerError::ViaBtcApiRegexError => 5005,
+            MinerError::ViaBtcApiRequestError => 5006,
+            MinerError::PoolTypeNotDetected => 5007,
+            MinerError::TagExprError(_) => 6001,
+            MinerError::ProfileNotFoundError(_) => 6002,
+            #[cfg(feature = "engine")]
+            MinerError::CronError(_) => 6003,
+            MinerError::TimezoneError(_) => 6004,
+            MinerError::SwitchScheduleNotStartedError => 6005,
+            MinerError::SecretError(_) => 6006,
+            MinerError::SecretNotFoundError(_) => 6007,
+            MinerError::IncidentNotFoundError(_) => 6008,
+            MinerError::AlertNotFoundError(_) => 6009,
+            MinerError::LogConfigError(_) => 6010,
+            #[cfg(feature = "sqlite")]
+            MinerError::SQLiteError(_) => 7001,
+            MinerError::DbNotInitError => 7002,
+            #[cfg(feature = "gsheets")]
+            MinerError::JwtError(_) => 8001,
+            #[cfg(feature = "email")]
+            MinerError::EmailAddressError(_) => 8002,
+            #[cfg(feature = "email")]
+            MinerError::EmailError(_) => 8003,
+            #[cfg(feature = "email")]
+            MinerError::SmtpError(_) => 8004,
+            MinerError::WebhookError(_) => 8005,
+            #[cfg(feature = "engine")]
+            MinerError::JoinError(_) => 9001,
+            MinerError::GrpcError(_) => 9002,
+            MinerError::BatchError(_) => 9003,
+            MinerError::SwitchRunningError => 9004,
+            MinerError::ShuttingDownError => 9005,
+            MinerError::ShutdownTimeoutError(_) => 9006,
+            MinerError::NoRuntimeError => 9007,
+            MinerError::FeatureDisabledError(_) => 9008,
+            MinerError::FfiArgumentError(_) => 9009,
+            MinerError::PermissionDeniedError(_) => 9010,
+            MinerError::Context { .. } => 9000,
+        }
+    }
+
+    /// json friendly form for frontends, message is for logs, code is for localizing
+    pub fn info(&self) -> ErrorInfo {
+        let (mut ip, mut op, mut command) = (None, None, None);
+        let mut e = self;
+        while let MinerError::Context {
+            ip: context_ip,
+            op: context_op,
+            command: context_command,
+            source,
+        } = e
+        {
+            ip = ip.or(Some(context_ip.clone()));
+            op = op.or(Some(context_op.clone()));
+            command = context_command.clone().or(command);
+            e = source;
+        }
+        let failed = match e {
+            MinerError::BatchError(batch) => batch
+                .failed
+                .iter()
+                .map(|(failed_ip, failed)| {
+                    let mut info = failed.info();
+                    info.ip = info.ip.or(Some(failed_ip.clone()));
+                    info
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ErrorInfo {
+            code: self.code(),
+            message: self.to_string(),
+            ip,
+            op,
+            command,
+            failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorInfo {
+    pub code: u32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub op: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// per machine errors of a batch
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<ErrorInfo>,
+}
+
+impl Serialize for MinerError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.info().serialize(serializer)
+    }
+}
+
+impl BatchError {
+    /// Ok when nothing failed
+    pub fn check(succeeded: usize, failed: Vec<(String, MinerError)>) -> Result<(), BatchError> {
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(BatchError { succeeded, failed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_error() {
+        assert!(BatchError::check(2, vec![]).is_ok());
+        let timeout = std::io::Error::new(std::io::ErrorKind::TimedOut, "timeout");
+        let err = BatchError::check(
+            1,
+            vec![
+                ("192.168.188.41".to_string(), timeout.into()),
+                (
+                    "192.168.188.42".to_string(),
+                    MinerError::MinerNotSupportError,
+                ),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "2 machines failed, 1 succeeded");
+        assert!(err.failed[0].1.is_timeout());
+        assert!(!err.failed[1].1.is_timeout());
+
+        let err = MinerError::from(err);
+        let info = serde_json::to_value(&err).unwrap();
+        assert_eq!(info["code"], 9003);
+        assert_eq!(info["failed"][0]["code"], 2001);
+        assert_eq!(info["failed"][1]["ip"], "192.168.188.42");
+    }
+
+    #[test]
+    fn test_context() {
+        let err = MinerError::TcpReadError
+            .command_context("192.168.188.41", "tcp", "estats")
+            .context("192.168.188.41", "watching");
+        assert_eq!(err.code(), 1004);
+        assert_eq!(
+            err.to_string(),
+            "watching 192.168.188.41: tcp 192.168.188.41 [estats]: TCP Read Error"
+        );
+        let info = err.info();
+        assert_eq!(info.op.as_deref(), Some("watching"));
+        assert_eq!(info.command.as_deref(), Some("estats"));
+    }
 }
@@ -0,0 +1,527 @@
+/// c abi over LcdCore for embedding, e.g. dart:ffi of a flutter desktop app, header in
+/// include/lcd_core.h and the library built with cargo rustc --crate-type cdylib. requests
+/// and replies are json, a reply is {"ok":true,"data":..} or {"ok":false,"error":{"code":..}}
+/// like the http server. the operations on machines return at once and call the callback
+/// from a runtime thread when done, the reply string is only valid during the callback. the
+/// query replies are freed with lcd_string_free
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::future::Future;
+use std::sync::Arc;
+
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::runtime::Runtime;
+
+use crate::error::MinerError;
+use crate::miner::entry::PoolConfig;
+use crate::{
+    Account, AlertStoreConfig, AnomalyConfig, CrashLoopConfig, EscalationConfig, GroupConfig,
+    GroupSelector, LcdCore, MinersLibConfig, ModelSpec, NotifySink, ReachConfig, RecordQuery,
+    RouteConfig, RunModeAliases, ScanQueueConfig, StaggerConfig, TemplateConfig, ThermalConfig,
+    ZoneTempConfig,
+};
+
+lazy_static! {
+    // the embedding app has no tokio runtime, every handle runs on this one
+    static ref RUNTIME: Runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+}
+
+/// completion of an operation, user_data as given to the call
+pub type LcdCallback = extern "C" fn(user_data: *mut c_void, reply: *const c_char);
+
+/// engine handle, from lcd_new to lcd_free
+pub struct LcdHandle {
+    core: Arc<LcdCore>,
+}
+
+// only handed back to the callback
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+impl UserData {
+    fn call(self, callback: LcdCallback, reply: &CStr) {
+        callback(self.0, reply.as_ptr());
+    }
+}
+
+/// settings of lcd_new, the missing fields take their defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HandleConfig {
+    app_path: String,
+    proxy: String,
+    timezone: String,
+    /// sqlite db as LcdCoreBuilder::db, no db when missing
+    db_path: Option<String>,
+    db_keep_days: i64,
+    #[cfg(feature = "feishu")]
+    feishu_app_id: String,
+    #[cfg(feature = "feishu")]
+    feishu_app_secret: String,
+    #[cfg(feature = "feishu")]
+    feishu_bot: String,
+    notify_sinks: Vec<NotifySink>,
+    notify_templates: TemplateConfig,
+    escalation: EscalationConfig,
+    alert_store: AlertStoreConfig,
+    worker_names: HashMap<String, String>,
+    groups: Vec<GroupConfig>,
+    stagger: StaggerConfig,
+    scan_queue: ScanQueueConfig,
+    thermal: ThermalConfig,
+    zone_temp: ZoneTempConfig,
+    anomaly: AnomalyConfig,
+    crash_loop: CrashLoopConfig,
+    reach: ReachConfig,
+    route: RouteConfig,
+    #[cfg(feature = "ssh")]
+    ssh: crate::SshConfig,
+    run_mode_aliases: RunModeAliases,
+    model_specs: Vec<ModelSpec>,
+    detect_cache_seconds: u64,
+}
+
+impl HandleConfig {
+    fn into_config(self) -> MinersLibConfig {
+        MinersLibConfig {
+            app_path: self.app_path,
+            proxy: self.proxy,
+            timezone: self.timezone,
+            is_need_db: self.db_path.is_some(),
+            db_path: self.db_path.unwrap_or_default(),
+            db_keep_days: self.db_keep_days,
+            #[cfg(feature = "feishu")]
+            feishu_app_id: self.feishu_app_id,
+            #[cfg(feature = "feishu")]
+            feishu_app_secret: self.feishu_app_secret,
+            #[cfg(feature = "feishu")]
+            feishu_bot: self.feishu_bot,
+            notify_sinks: self.notify_sinks,
+            notify_templates: self.notify_templates,
+            escalation: self.escalation,
+            alert_store: self.alert_store,
+            worker_names: self.worker_names,
+            groups: self.groups,
+            stagger: self.stagger,
+            scan_queue: self.scan_queue,
+            thermal: self.thermal,
+            zone_temp: self.zone_temp,
+            anomaly: self.anomaly,
+            crash_loop: self.crash_loop,
+            reach: self.reach,
+            route: self.route,
+            #[cfg(feature = "ssh")]
+            ssh: self.ssh,
+            run_mode_aliases: self.run_mode_aliases,
+            model_specs: self.model_specs,
+            detect_cache_seconds: self.detect_cache_seconds,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ScanRequest {
+    ip: String,
+    offset: i32,
+    count: i32,
+    timeout_seconds: i64,
+}
+
+/// watching and reboot
+#[derive(Debug, Deserialize)]
+struct TargetRequest {
+    target: GroupSelector,
+    timeout_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfigRequest {
+    target: GroupSelector,
+    pools: Vec<PoolConfig>,
+    run_mode: String,
+    timeout_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SwitchRequest {
+    target: GroupSelector,
+    account: Account,
+    timeout_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SwitchIfNeedRequest {
+    excel: String,
+    sheets: Vec<String>,
+    account_time_sheet: String,
+    perf_time_sheet: String,
+    pool_sheet: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordsRequest {
+    #[serde(default)]
+    ip: String,
+    start_time: i64,
+    end_time: i64,
+    #[serde(flatten)]
+    query: RecordQuery,
+}
+
+// MinerError serializes to its code, message and context
+fn reply<T: Serialize>(result: Result<T, MinerError>) -> CString {
+    let json = match result {
+        Ok(data) => json!({"ok": true, "data": data}),
+        Err(e) => json!({"ok": false, "error": e}),
+    };
+    // json escapes the control characters, there is no NUL in the text
+    CString::new(json.to_string()).unwrap()
+}
+
+unsafe fn parse<T: DeserializeOwned>(request: *const c_char) -> Result<T, MinerError> {
+    if request.is_null() {
+        return Err(MinerError::FfiArgumentError("null request".to_string()));
+    }
+    let text = CStr::from_ptr(request)
+        .to_str()
+        .map_err(|_| MinerError::FfiArgumentError("request is not utf-8".to_string()))?;
+    Ok(serde_json::from_str(text)?)
+}
+
+unsafe fn core(handle: *const LcdHandle) -> Result<Arc<LcdCore>, MinerError> {
+    handle
+        .as_ref()
+        .map(|handle| handle.core.clone())
+        .ok_or_else(|| MinerError::FfiArgumentError("null handle".to_string()))
+}
+
+// run the operation on the runtime and answer through the callback, a bad handle or
+// request is answered the same way
+unsafe fn complete<R, T, F, Fut>(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+    operation: F,
+) where
+    R: DeserializeOwned + Send + 'static,
+    T: Serialize,
+    F: FnOnce(Arc<LcdCore>, R) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, MinerError>> + Send + 'static,
+{
+    let user_data = UserData(user_data);
+    let args = core(handle).and_then(|core| Ok((core, parse::<R>(request)?)));
+    RUNTIME.spawn(async move {
+        let result = match args {
+            Ok((core, request)) => operation(core, request).await,
+            Err(e) => Err(e),
+        };
+        user_data.call(callback, &reply(result));
+    });
+}
+
+/// new engine handle of the json settings, null when it fails, the error is logged
+///
+/// # Safety
+/// config is a NUL terminated string or null for the defaults
+#[no_mangle]
+pub unsafe extern "C" fn lcd_new(config: *const c_char) -> *mut LcdHandle {
+    let config = if config.is_null() {
+        Ok(HandleConfig::default())
+    } else {
+        parse::<HandleConfig>(config)
+    };
+    let core = config.and_then(|config| {
+        LcdCore::builder()
+            .config(config.into_config())
+            .runtime(RUNTIME.handle().clone())
+            .build()
+    });
+    match core {
+        Ok(core) => Box::into_raw(Box::new(LcdHandle {
+            core: Arc::new(core),
+        })),
+        Err(e) => {
+            error!("lcd_new error: {:?}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// release the handle, operations still running keep it until they complete
+///
+/// # Safety
+/// handle comes from lcd_new and is not used after
+#[no_mangle]
+pub unsafe extern "C" fn lcd_free(handle: *mut LcdHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// release a reply returned by a query
+///
+/// # Safety
+/// reply comes from a query of this library and is not used after
+#[no_mangle]
+pub unsafe extern "C" fn lcd_string_free(reply: *mut c_char) {
+    if !reply.is_null() {
+        drop(CString::from_raw(reply));
+    }
+}
+
+/// {"ip":"192.168.1.1","offset":1,"count":254,"timeout_seconds":5}, data is the machines
+///
+/// # Safety
+/// handle from lcd_new, request a NUL terminated string, the callback may run on any thread
+#[no_mangle]
+pub unsafe extern "C" fn lcd_scan(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        request,
+        callback,
+        user_data,
+        |core, req: ScanRequest| async move {
+            core.scan(&req.ip, req.offset, req.count, req.timeout_seconds)
+                .await
+        },
+    );
+}
+
+/// as lcd_scan, data is the machines plus the new, gone and changed ones against the last
+/// scan of the range
+///
+/// # Safety
+/// as lcd_scan
+#[no_mangle]
+pub unsafe extern "C" fn lcd_scan_diff(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        request,
+        callback,
+        user_data,
+        |core, req: ScanRequest| async move {
+            core.scan_diff(&req.ip, req.offset, req.count, req.timeout_seconds)
+                .await
+        },
+    );
+}
+
+/// {"target":{"Ips":["192.168.1.10"]},"timeout_seconds":5}, data is the machines
+///
+/// # Safety
+/// as lcd_scan
+#[no_mangle]
+pub unsafe extern "C" fn lcd_watching(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        request,
+        callback,
+        user_data,
+        |core, req: TargetRequest| async move { core.watching(req.target, req.timeout_seconds).await },
+    );
+}
+
+/// {"target":..,"timeout_seconds":5}
+///
+/// # Safety
+/// as lcd_scan
+#[no_mangle]
+pub unsafe extern "C" fn lcd_reboot(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        request,
+        callback,
+        user_data,
+        |core, req: TargetRequest| async move { core.reboot(req.target, req.timeout_seconds).await },
+    );
+}
+
+/// {"target":..,"pools":[..],"run_mode":"高功","timeout_seconds":5}, data is the job id
+///
+/// # Safety
+/// as lcd_scan
+#[no_mangle]
+pub unsafe extern "C" fn lcd_config(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        request,
+        callback,
+        user_data,
+        |core, req: ConfigRequest| async move {
+            core.config(req.target, req.pools, req.run_mode, req.timeout_seconds)
+                .await
+        },
+    );
+}
+
+/// {"target":..,"account":{..},"timeout_seconds":5}, switch the account whatever the sheets
+///
+/// # Safety
+/// as lcd_scan
+#[no_mangle]
+pub unsafe extern "C" fn lcd_force_switch(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        request,
+        callback,
+        user_data,
+        |core, req: SwitchRequest| async move {
+            core.force_switch(req.target, req.account, req.timeout_seconds)
+                .await
+        },
+    );
+}
+
+/// {"excel":..,"sheets":[..],"account_time_sheet":..,"perf_time_sheet":..,"pool_sheet":..},
+/// data is the switch report
+///
+/// # Safety
+/// as lcd_scan
+#[no_mangle]
+pub unsafe extern "C" fn lcd_switch_if_need(
+    handle: *const LcdHandle,
+    request: *const c_char,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        request,
+        callback,
+        user_data,
+        |core, req: SwitchIfNeedRequest| async move {
+            core.switch_if_need(
+                &req.excel,
+                req.sheets.iter().map(|s| s.as_str()).collect(),
+                &req.account_time_sheet,
+                &req.perf_time_sheet,
+                &req.pool_sheet,
+            )
+            .await
+        },
+    );
+}
+
+/// stop the tasks of the handle and wait up to the timeout for running batches
+///
+/// # Safety
+/// as lcd_scan
+#[no_mangle]
+pub unsafe extern "C" fn lcd_shutdown(
+    handle: *const LcdHandle,
+    timeout_seconds: u64,
+    callback: LcdCallback,
+    user_data: *mut c_void,
+) {
+    complete(
+        handle,
+        c"{}".as_ptr(),
+        callback,
+        user_data,
+        move |core, _: serde_json::Value| async move { core.shutdown(timeout_seconds).await },
+    );
+}
+
+/// {"ip":"192.168.1.10","start_time":..,"end_time":..,"limit":100}, the paging fields as
+/// RecordQuery. reply freed with lcd_string_free
+///
+/// # Safety
+/// handle from lcd_new, request a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn lcd_query_machine_records(
+    handle: *const LcdHandle,
+    request: *const c_char,
+) -> *mut c_char {
+    let result = core(handle).and_then(|core| {
+        let req = parse::<RecordsRequest>(request)?;
+        core.query_machine_records(&req.ip, req.start_time, req.end_time, &req.query)
+    });
+    reply(result).into_raw()
+}
+
+/// engine status, reply freed with lcd_string_free
+///
+/// # Safety
+/// handle from lcd_new
+#[no_mangle]
+pub unsafe extern "C" fn lcd_status(handle: *const LcdHandle) -> *mut c_char {
+    reply(core(handle).map(|core| core.status())).into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    // takes back the sender boxed for the call
+    extern "C" fn on_reply(user_data: *mut c_void, reply: *const c_char) {
+        let sender = unsafe { Box::from_raw(user_data as *mut Sender<String>) };
+        let reply = unsafe { CStr::from_ptr(reply) };
+        sender.send(reply.to_str().unwrap().to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        let reply = unsafe { lcd_status(std::ptr::null()) };
+        let json: serde_json::Value =
+            serde_json::from_str(unsafe { CStr::from_ptr(reply) }.to_str().unwrap()).unwrap();
+        unsafe { lcd_string_free(reply) };
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["error"]["code"], 9009);
+
+        // errors come through the callback as well
+        let (sender, receiver) = std::sync::mpsc::channel::<String>();
+        unsafe {
+            lcd_scan(
+                std::ptr::null(),
+                c"{}".as_ptr(),
+                on_reply,
+                Box::into_raw(Box::new(sender)) as *mut c_void,
+            )
+        };
+        let json: serde_json::Value = serde_json::from_str(&receiver.recv().unwrap()).unwrap();
+        assert_eq!(json["error"]["code"], 9009);
+
+        let request = unsafe { parse::<ScanRequest>(c"{\"ip\":1}".as_ptr()) };
+        assert_eq!(request.unwrap_err().code(), 3001);
+    }
+}
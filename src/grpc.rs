@@ -0,0 +1,348 @@
+/// tonic grpc service of the fleet operations, messages mirror MachineInfo and MachineRecord.
+/// every call needs the bearer token of a user and runs with its role, without users every
+/// call is refused
+use std::pin::Pin;
+
+use futures::Stream;
+use log::info;
+use tonic::{Request, Response, Status};
+
+use crate::access::{self, ApiUser, Caller};
+use crate::error::MinerError;
+use crate::miner::entry::{self, PoolConfig};
+use crate::miner::group::GroupSelector;
+
+pub mod proto {
+    tonic::include_proto!("lcd");
+}
+
+use proto::fleet_server::{Fleet, FleetServer};
+
+impl From<entry::MachineRecord> for proto::MachineRecord {
+    fn from(r: entry::MachineRecord) -> Self {
+        proto::MachineRecord {
+            id: r.id,
+            ip: r.ip,
+            machine_type: r.machine_type,
+            work_mode: r.work_mode,
+            hash_real: r.hash_real,
+            hash_avg: r.hash_avg,
+            temp_0: r.temp_0,
+            temp_1: r.temp_1,
+            temp_2: r.temp_2,
+            power: r.power,
+            create_time: r.create_time,
+            hw_errors: r.hw_errors,
+            accepted_pct: r.accepted_pct,
+            rejected_pct: r.rejected_pct,
+            stale_pct: r.stale_pct,
+            elapsed: r.elapsed,
+        }
+    }
+}
+
+impl From<entry::MachineInfo> for proto::MachineInfo {
+    fn from(m: entry::MachineInfo) -> Self {
+        proto::MachineInfo {
+            ip: m.ip,
+            machine_type: m.machine_type,
+            hash_real: m.hash_real,
+            hash_avg: m.hash_avg,
+            pool_hash_real: m.pool_hash_real,
+            pool_hash_avg: m.pool_hash_avg,
+            temp: m.temp,
+            fan: m.fan,
+            elapsed: m.elapsed,
+            mode: m.mode,
+            pool1: m.pool1,
+            worker1: m.worker1,
+            pool2: m.pool2,
+            worker2: m.worker2,
+            record: Some(m.record.into()),
+            warnings: m.warnings,
+            raw: m.raw,
+            boards_expected: m.boards_expected,
+            boards_active: m.boards_active,
+            mac: m.mac,
+            serial: m.serial,
+        }
+    }
+}
+
+/// ips first, then groups, then the tag expression, every machine when empty
+pub fn selector(target: Option<proto::Target>) -> Result<GroupSelector, Status> {
+    let target = target.unwrap_or_default();
+    if !target.ips.is_empty() {
+        return Ok(GroupSelector::Ips(target.ips));
+    }
+    if !target.groups.is_empty() {
+        return Ok(GroupSelector::Groups(target.groups));
+    }
+    if !target.tags.trim().is_empty() {
+        return GroupSelector::tags(&target.tags)
+            .map_err(|e| Status::invalid_argument(e.to_string()));
+    }
+    Ok(GroupSelector::All)
+}
+
+// per machine timeout, proto3 sends 0 when unset
+fn timeout(seconds: i64) -> i64 {
+    if seconds > 0 {
+        seconds
+    } else {
+        3
+    }
+}
+
+fn internal(e: MinerError) -> Status {
+    match e {
+        MinerError::PermissionDeniedError(_) => Status::permission_denied(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FleetService {
+    /// every call is refused when empty
+    users: Vec<ApiUser>,
+}
+
+impl FleetService {
+    pub fn new(users: &[ApiUser]) -> Self {
+        FleetService {
+            users: access::resolve_users(users),
+        }
+    }
+
+    // the user of the bearer token in the metadata
+    fn caller<T>(&self, request: &Request<T>) -> Result<Caller, Status> {
+        let presented = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or("");
+        access::caller_of(&self.users, presented)
+            .ok_or_else(|| Status::unauthenticated("unauthorized"))
+    }
+}
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<proto::MachineInfo, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Fleet for FleetService {
+    async fn scan(
+        &self,
+        request: Request<proto::ScanRequest>,
+    ) -> Result<Response<proto::MachineInfoList>, Status> {
+        let caller = self.caller(&request)?;
+        let req = request.into_inner();
+        let runtime = tokio::runtime::Handle::current();
+        let machines = access::scope(
+            caller,
+            crate::scan(
+                runtime,
+                &req.ip,
+                req.offset,
+                req.count,
+                timeout(req.timeout_seconds),
+            ),
+        )
+        .await
+        .map_err(internal)?;
+        Ok(Response::new(proto::MachineInfoList {
+            machines: machines.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    type WatchStream = WatchStream;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let caller = self.caller(&request)?;
+        let req = request.into_inner();
+        let selector = selector(req.target)?;
+        let interval = if req.interval_seconds == 0 {
+            60
+        } else {
+            req.interval_seconds
+        };
+        let timeout_seconds = timeout(req.timeout_seconds);
+
+        // one watching round per item batch, the first round starts right away
+        let rounds = futures::stream::unfold(true, move |first| {
+            let selector = selector.clone();
+            let caller = caller.clone();
+            async move {
+                let wait = tokio::time::Duration::from_secs(interval);
+                if !first && !crate::shutdown::sleep(wait).await {
+                    return None;
+                }
+                let runtime = tokio::runtime::Handle::current();
+                let items: Vec<Result<proto::MachineInfo, Status>> = match access::scope(
+                    caller,
+                    crate::watching(runtime, selector, timeout_seconds),
+                )
+                .await
+                {
+                    Ok(machines) => machines.into_iter().map(|m| Ok(m.into())).collect(),
+                    Err(e) => vec![Err(internal(e))],
+                };
+                Some((futures::stream::iter(items), false))
+            }
+        });
+        Ok(Response::new(Box::pin(futures::StreamExt::flatten(rounds))))
+    }
+
+    async fn reboot(
+        &self,
+        request: Request<proto::TargetRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let caller = self.caller(&request)?;
+        let req = request.into_inner();
+        let selector = selector(req.target)?;
+        let runtime = tokio::runtime::Handle::current();
+        let reboot = crate::reboot(runtime, selector, timeout(req.timeout_seconds));
+        access::scope(caller, reboot).await.map_err(internal)?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn config_pools(
+        &self,
+        request: Request<proto::ConfigPoolsRequest>,
+    ) -> Result<Response<proto::ConfigPoolsReply>, Status> {
+        let caller = self.caller(&request)?;
+        let req = request.into_inner();
+        let selector = selector(req.target)?;
+        let pools = req
+            .pools
+            .into_iter()
+            .map(|p| PoolConfig {
+                url: p.url,
+                user: p.user,
+                password: p.password.into(),
+            })
+            .collect();
+        let runtime = tokio::runtime::Handle::current();
+        let config = crate::config(
+            runtime,
+            selector,
+            pools,
+            req.run_mode,
+            timeout(req.timeout_seconds),
+        );
+        let count = access::scope(caller, config).await.map_err(internal)?;
+        Ok(Response::new(proto::ConfigPoolsReply { count }))
+    }
+
+    async fn switch_now(
+        &self,
+        request: Request<proto::SwitchRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let caller = self.caller(&request)?;
+        let req = request.into_inner();
+        let selector = selector(req.target)?;
+        let runtime = tokio::runtime::Handle::current();
+        let switch = crate::switch_group_if_need(
+            runtime,
+            &req.excel,
+            req.sheets.iter().map(|s| s.as_str()).collect(),
+            &req.account_time_sheet,
+            &req.perf_time_sheet,
+            &req.pool_sheet,
+            selector,
+        );
+        access::scope(caller, switch).await.map_err(internal)?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn query_records(
+        &self,
+        request: Request<proto::RecordsRequest>,
+    ) -> Result<Response<proto::MachineRecordList>, Status> {
+        self.caller(&request)?;
+        let req = request.into_inner();
+        let query = crate::RecordQuery {
+            limit: req.limit,
+            descending: req.descending,
+            after_time: req.after_time,
+            after_id: req.after_id,
+            columns: req.columns,
+        };
+        let records = crate::query_machine_records(&req.ip, req.start_time, req.end_time, &query)
+            .map_err(internal)?;
+        Ok(Response::new(proto::MachineRecordList {
+            records: records.into_iter().map(Into::into).collect(),
+        }))
+    }
+}
+
+/// serve the fleet service on addr, e.g. "0.0.0.0:8421", to the users, each call with the
+/// role of its token
+pub async fn serve_with_users(addr: &str, users: &[ApiUser]) -> Result<(), MinerError> {
+    let addr = addr
+        .parse()
+        .map_err(|e: std::net::AddrParseError| MinerError::GrpcError(e.to_string()))?;
+    if users.is_empty() {
+        return Err(MinerError::GrpcError("no users to serve".to_string()));
+    }
+    info!("lcd grpc listening on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(FleetServer::new(FleetService::new(users)))
+        .serve(addr)
+        .await
+        .map_err(|e| MinerError::GrpcError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector() {
+        assert!(matches!(selector(None), Ok(GroupSelector::All)));
+        let target = proto::Target {
+            ips: vec![],
+            groups: vec!["A区".to_string()],
+            tags: "rack:A7".to_string(),
+        };
+        assert!(matches!(
+            selector(Some(target)),
+            Ok(GroupSelector::Groups(_))
+        ));
+        let target = proto::Target {
+            tags: "rack:A7 AND".to_string(),
+            ..Default::default()
+        };
+        assert!(selector(Some(target)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_untokened_refused() {
+        let reboot = |service: FleetService, token: &str| {
+            let mut request = Request::new(proto::TargetRequest::default());
+            if !token.is_empty() {
+                let value = format!("Bearer {}", token).parse().unwrap();
+                request.metadata_mut().insert("authorization", value);
+            }
+            async move { service.reboot(request).await }
+        };
+        let code = |r: Result<Response<proto::Empty>, Status>| r.unwrap_err().code();
+
+        // no users is no one, not the system
+        let refused = reboot(FleetService::new(&[]), "").await;
+        assert_eq!(code(refused), tonic::Code::Unauthenticated);
+
+        let users = [ApiUser {
+            name: "viewer".to_string(),
+            token: "view".into(),
+            role: access::Role::ReadOnly,
+        }];
+        let refused = reboot(FleetService::new(&users), "").await;
+        assert_eq!(code(refused), tonic::Code::Unauthenticated);
+        let refused = reboot(FleetService::new(&users), "view").await;
+        assert_eq!(code(refused), tonic::Code::PermissionDenied);
+    }
+}
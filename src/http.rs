@@ -0,0 +1,141 @@
+/// outbound http clients, all honor the global proxy. the miner web uis are reached with a
+/// blocking client without it, the drivers run synchronously
+use std::sync::Mutex;
+#[cfg(feature = "ant-http")]
+use std::time::Duration;
+
+#[cfg(feature = "ant-http")]
+use reqwest::blocking;
+#[cfg(feature = "ant-http")]
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use reqwest::Client;
+#[cfg(feature = "ant-http")]
+use reqwest::{Method, StatusCode};
+
+use crate::error::MinerError;
+
+lazy_static! {
+    static ref PROXY: Mutex<String> = Mutex::new(String::new());
+}
+
+#[cfg(feature = "ant-http")]
+lazy_static! {
+    // miners are on the lan, neither the global proxy nor the environment one applies
+    static ref LAN: blocking::Client = blocking::Client::builder().no_proxy().build().unwrap();
+}
+
+/// global outbound proxy, e.g. "http://10.0.0.1:3128", empty for direct
+#[cfg_attr(not(feature = "engine"), allow(dead_code))]
+pub fn set_proxy(proxy: &str) {
+    *PROXY.lock().unwrap() = proxy.to_string();
+}
+
+/// client through the given proxy, falls back to the global one when empty
+pub fn client(proxy: &str) -> Result<Client, MinerError> {
+    let proxy = if proxy.is_empty() {
+        PROXY.lock().unwrap().clone()
+    } else {
+        proxy.to_string()
+    };
+
+    if proxy.is_empty() {
+        return Ok(Client::new());
+    }
+    with_proxy(proxy)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn with_proxy(proxy: String) -> Result<Client, MinerError> {
+    // if proxy not start with http, add it
+    let proxy = if proxy.starts_with("http") {
+        proxy
+    } else {
+        format!("http://{}", proxy)
+    };
+    Ok(Client::builder()
+        .proxy(reqwest::Proxy::all(proxy)?)
+        .build()?)
+}
+
+// the fetch api of the browser has no proxy setting
+#[cfg(target_arch = "wasm32")]
+fn with_proxy(_proxy: String) -> Result<Client, MinerError> {
+    Ok(Client::new())
+}
+
+/// client through the global proxy
+#[cfg_attr(not(feature = "engine"), allow(dead_code))]
+pub fn default_client() -> Result<Client, MinerError> {
+    client("")
+}
+
+/// run the blocking calls to the miner web uis. the blocking client panics on a runtime
+/// thread and the drivers are also called from async code, there it runs on its own thread
+#[cfg(feature = "ant-http")]
+pub fn lan<R: Send>(f: impl FnOnce(&blocking::Client) -> R + Send) -> R {
+    if tokio::runtime::Handle::try_current().is_err() {
+        return f(&LAN);
+    }
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| f(&LAN))
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    })
+}
+
+/// blocking request to a miner web ui, a digest challenge is answered with the credentials
+#[cfg(feature = "ant-http")]
+pub fn digest_request(
+    client: &blocking::Client,
+    method: Method,
+    url: &str,
+    user: &str,
+    password: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<blocking::Response, MinerError> {
+    let send = |authorization: Option<String>| {
+        let mut request = client.request(method.clone(), url).timeout(timeout);
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, "text/plain;charset=UTF-8")
+                .body(body.to_string());
+        }
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        request.send()
+    };
+
+    let response = send(None)?;
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    let challenge = response
+        .headers()
+        .get(WWW_AUTHENTICATE)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(MinerError::AuthError)?;
+    let mut prompt = digest_auth::parse(challenge).map_err(|_| MinerError::AuthError)?;
+    let uri = match response.url().query() {
+        Some(query) => format!("{}?{}", response.url().path(), query),
+        None => response.url().path().to_string(),
+    };
+    let context = digest_auth::AuthContext::new_with_method(
+        user,
+        password,
+        uri,
+        body.map(str::as_bytes),
+        digest_auth::HttpMethod::from(method.as_str()),
+    );
+    let answer = prompt
+        .respond(&context)
+        .map_err(|_| MinerError::AuthError)?;
+
+    let response = send(Some(answer.to_header_string()))?;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        return Err(MinerError::AuthError);
+    }
+    Ok(response)
+}
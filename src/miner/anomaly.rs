@@ -0,0 +1,278 @@
+/// hashrate anomalies against the own baseline of each machine. a slow ewma is the baseline,
+/// a fast one the current rate, a machine is flagged when the fast one falls below the
+/// baseline by more than the z score and the share set, catching the 10% degradation a fixed
+/// threshold misses. samples that low stay out of the baseline so a lasting drop is not learnt
+/// as normal, a run mode change starts a new baseline. samples without hashrate are left
+/// out, downtime is watched elsewhere
+use std::collections::{BTreeMap, HashMap};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::entry::MachineRecord;
+use crate::clock;
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+use crate::store::db;
+
+static ANOMALY: Local<AnomalyWatch> = Local::new(|| AnomalyWatch::new(AnomalyConfig::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnomalyConfig {
+    /// alerts on watching, off by default
+    pub enabled: bool,
+    /// weight of a sample in the baseline
+    pub baseline_alpha: f64,
+    /// weight of a sample in the current rate
+    pub current_alpha: f64,
+    /// standard deviations of the baseline the current rate has to fall below it
+    pub z_threshold: f64,
+    /// and the share of the baseline, percent
+    pub min_drop_pct: f64,
+    /// samples before a machine is judged
+    pub min_samples: u32,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        AnomalyConfig {
+            enabled: false,
+            baseline_alpha: 0.02,
+            current_alpha: 0.3,
+            z_threshold: 3.0,
+            min_drop_pct: 5.0,
+            min_samples: 24,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Anomaly {
+    pub ip: String,
+    pub machine_type: String,
+    /// TH/s
+    pub baseline: f64,
+    /// TH/s
+    pub current: f64,
+    pub drop_pct: f64,
+    pub z: f64,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Ewma {
+    baseline: f64,
+    variance: f64,
+    current: f64,
+    samples: u32,
+    work_mode: i32,
+    flagged: bool,
+}
+
+impl Ewma {
+    fn update(&mut self, config: &AnomalyConfig, hash: f64, work_mode: i32) {
+        if self.samples == 0 || work_mode != self.work_mode {
+            *self = Ewma {
+                baseline: hash,
+                current: hash,
+                work_mode,
+                flagged: self.flagged,
+                ..Default::default()
+            };
+        } else if self.samples >= config.min_samples && self.is_low(config, hash) {
+            self.current += config.current_alpha * (hash - self.current);
+        } else {
+            let diff = hash - self.baseline;
+            self.baseline += config.baseline_alpha * diff;
+            self.variance = (1.0 - config.baseline_alpha)
+                * (self.variance + config.baseline_alpha * diff * diff);
+            self.current += config.current_alpha * (hash - self.current);
+        }
+        self.samples += 1;
+    }
+
+    fn is_low(&self, config: &AnomalyConfig, hash: f64) -> bool {
+        self.baseline - hash > config.z_threshold * self.variance.sqrt()
+    }
+
+    // drop percent and z score when the current rate is anomalous
+    fn check(&self, config: &AnomalyConfig) -> Option<(f64, f64)> {
+        if self.samples < config.min_samples || self.baseline <= 0.0 {
+            return None;
+        }
+        let drop = self.baseline - self.current;
+        let drop_pct = drop / self.baseline * 100.0;
+        let deviation = self.variance.sqrt();
+        let z = if deviation > 0.0 {
+            drop / deviation
+        } else {
+            f64::INFINITY
+        };
+        (drop_pct >= config.min_drop_pct && z >= config.z_threshold).then_some((drop_pct, z))
+    }
+}
+
+#[derive(Debug)]
+pub struct AnomalyWatch {
+    config: AnomalyConfig,
+    machines: HashMap<String, Ewma>,
+}
+
+impl AnomalyWatch {
+    pub fn new(config: AnomalyConfig) -> Self {
+        AnomalyWatch {
+            config,
+            machines: HashMap::new(),
+        }
+    }
+
+    /// record one poll, the anomaly when the machine just became anomalous
+    pub fn check(&mut self, record: &MachineRecord) -> Option<Anomaly> {
+        if record.hash_avg <= 0.0 {
+            return None;
+        }
+        let ewma = self.machines.entry(record.ip.clone()).or_default();
+        ewma.update(&self.config, record.hash_avg, record.work_mode);
+        let found = ewma.check(&self.config);
+        let was_flagged = std::mem::replace(&mut ewma.flagged, found.is_some());
+        let (drop_pct, z) = found.filter(|_| !was_flagged)?;
+        Some(anomaly(record, ewma, drop_pct, z))
+    }
+}
+
+fn anomaly(record: &MachineRecord, ewma: &Ewma, drop_pct: f64, z: f64) -> Anomaly {
+    Anomaly {
+        ip: record.ip.clone(),
+        machine_type: record.machine_type.clone(),
+        baseline: ewma.baseline / 1000.0,
+        current: ewma.current / 1000.0,
+        drop_pct,
+        z,
+    }
+}
+
+pub fn set_config(config: AnomalyConfig) {
+    ANOMALY.set(AnomalyWatch::new(config));
+}
+
+/// machines anomalous at the end of the records, records of one ip ordered by time
+pub fn detect(records: &[MachineRecord], config: &AnomalyConfig) -> Vec<Anomaly> {
+    let mut by_ip: BTreeMap<&str, Vec<&MachineRecord>> = BTreeMap::new();
+    for record in records.iter().filter(|r| r.hash_avg > 0.0) {
+        by_ip.entry(record.ip.as_str()).or_default().push(record);
+    }
+
+    let mut anomalies = vec![];
+    for records in by_ip.values() {
+        let mut ewma = Ewma::default();
+        for record in records.iter() {
+            ewma.update(config, record.hash_avg, record.work_mode);
+        }
+        let last = records[records.len() - 1];
+        if let Some((drop_pct, z)) = ewma.check(config) {
+            anomalies.push(anomaly(last, &ewma, drop_pct, z));
+        }
+    }
+    anomalies.sort_by(|a, b| b.drop_pct.total_cmp(&a.drop_pct));
+    anomalies
+}
+
+/// machines anomalous at the end of the time range of the stored records
+pub fn query(start_time: i64, end_time: i64) -> Result<Vec<Anomaly>, MinerError> {
+    let mut records = db::query_all_records_by_time(start_time, end_time)?;
+    records.sort_by_key(|r| r.create_time);
+    let config = ANOMALY.with(|watch| watch.config.clone());
+    Ok(detect(&records, &config))
+}
+
+/// check polled records and notify the machines that just fell below their baseline
+pub async fn apply(records: &[MachineRecord]) {
+    let anomalies: Vec<Anomaly> = ANOMALY.with(|watch| {
+        if !watch.config.enabled {
+            return vec![];
+        }
+        records.iter().filter_map(|r| watch.check(r)).collect()
+    });
+    if anomalies.is_empty() {
+        return;
+    }
+    info!("hashrate anomalies on {} machines", anomalies.len());
+
+    notifier::send_alert(&Alert {
+        kind: "hash_anomaly".to_string(),
+        title: template::render(
+            "anomaly.title",
+            &[
+                ("time", &clock::now().format("%H:%M:%S")),
+                ("count", &anomalies.len()),
+            ],
+        ),
+        severity: Severity::Warning,
+        content: "".to_string(),
+        machines: anomalies
+            .iter()
+            .map(|a| AlertMachine {
+                ip: a.ip.clone(),
+                detail: template::render(
+                    "anomaly.detail",
+                    &[
+                        ("model", &a.machine_type),
+                        ("current", &format!("{:.2}", a.current)),
+                        ("baseline", &format!("{:.2}", a.baseline)),
+                        ("drop", &format!("{:.1}", a.drop_pct)),
+                    ],
+                ),
+            })
+            .collect(),
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hash: f64, time: i64) -> MachineRecord {
+        MachineRecord {
+            ip: "10.0.0.1".to_string(),
+            machine_type: "S19".to_string(),
+            hash_avg: hash,
+            create_time: time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hashrate_anomaly() {
+        let config = AnomalyConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        // noisy around 95 TH/s, then 10% down
+        let mut records: Vec<MachineRecord> = (0..48)
+            .map(|i| record(95000.0 + if i % 2 == 0 { 500.0 } else { -500.0 }, i * 300))
+            .collect();
+        assert!(detect(&records, &config).is_empty());
+        records.extend((48..56).map(|i| record(85500.0, i * 300)));
+        let anomalies = detect(&records, &config);
+        assert_eq!(anomalies.len(), 1);
+        assert!(anomalies[0].drop_pct > 5.0 && anomalies[0].drop_pct < 10.0);
+
+        // another run mode is another baseline
+        let mut switched = records.clone();
+        switched.push(MachineRecord {
+            work_mode: 1,
+            ..record(105000.0, 56 * 300)
+        });
+        assert!(detect(&switched, &config).is_empty());
+
+        // the watch alerts once, offline samples are left out
+        let mut watch = AnomalyWatch::new(config);
+        let flagged: Vec<Anomaly> = records
+            .iter()
+            .chain([record(0.0, 56 * 300)].iter())
+            .filter_map(|r| watch.check(r))
+            .collect();
+        assert_eq!(flagged.len(), 1);
+    }
+}
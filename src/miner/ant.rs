@@ -1,12 +1,34 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
 use std::time::Duration;
 
+use super::capture;
+use super::endpoint;
 use super::entry::*;
+use super::inventory;
+use super::mode::RunMode;
+use super::power;
 use crate::error::MinerError;
-use curl::easy::{Easy, List};
-//use log::info;
+use crate::http;
+use crate::pools::health;
+use crate::store::db;
+use crate::tariff;
+use log::info;
+use reqwest::Method;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+lazy_static! {
+    static ref SYSTEMS: Mutex<HashMap<String, AntSystem>> = Mutex::new(HashMap::new());
+}
 
 // some const str define
+const STATS_PATH: &str = "/cgi-bin/stats.cgi";
+const SUMMARY_PATH: &str = "/cgi-bin/summary.cgi";
+const SYSTEM_INFO_PATH: &str = "/cgi-bin/get_system_info.cgi";
+const POOLS_PATH: &str = "/cgi-bin/pools.cgi";
+// every supported antminer model has three hash boards
+const BOARDS: u32 = 3;
 const CONF_URL: &str = "http://{}/cgi-bin/get_miner_conf.cgi";
 const UPDATE_URL: &str = "http://{}/cgi-bin/set_miner_conf.cgi";
 
@@ -45,7 +67,7 @@ const UPDATE_URL: &str = "http://{}/cgi-bin/set_miner_conf.cgi";
 //     "bitmain-freq-level" : "100"
 //     }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct Pool {
     url: String,
     user: String,
@@ -67,20 +89,19 @@ struct AntConfig {
     bitmain_fan_ctrl: bool,
     #[serde(rename = "bitmain-fan-pwm")]
     bitmain_fan_pwm: String,
-    #[serde(rename = "bitmain-use-vil")]
-    bitmain_use_vil: bool,
-    #[serde(rename = "bitmain-freq")]
-    bitmain_freq: String,
-    #[serde(rename = "bitmain-voltage")]
-    bitmain_voltage: String,
-    #[serde(rename = "bitmain-ccdelay")]
-    bitmain_ccdelay: String,
-    #[serde(rename = "bitmain-pwth")]
-    bitmain_pwth: String,
-    #[serde(rename = "bitmain-work-mode")]
-    bitmain_work_mode: String,
-    #[serde(rename = "bitmain-freq-level")]
-    bitmain_freq_level: String,
+    // chip tuning and work mode fields of the older firmwares, absent since 2023
+    #[serde(rename = "bitmain-freq", skip_serializing_if = "Option::is_none")]
+    bitmain_freq: Option<String>,
+    #[serde(rename = "bitmain-voltage", skip_serializing_if = "Option::is_none")]
+    bitmain_voltage: Option<String>,
+    #[serde(rename = "bitmain-work-mode", skip_serializing_if = "Option::is_none")]
+    bitmain_work_mode: Option<String>,
+    /// 0 normal, 1 sleep, 3 low power, firmwares since 2023
+    #[serde(rename = "miner-mode", skip_serializing_if = "Option::is_none")]
+    miner_mode: Option<i64>,
+    /// other fields are written back as read
+    #[serde(flatten)]
+    other: serde_json::Map<String, Value>,
 }
 
 impl AntConfig {
@@ -89,33 +110,359 @@ impl AntConfig {
         let worker1_splited: Vec<&str> = self.pools[0].user.split('.').collect();
         let account_name_splited: Vec<&str> = account.name.split('.').collect();
         worker1_splited[0] == account_name_splited[0]
+            && health::same_endpoint(&self.pools[0].url, &account.pool1)
     }
 
     pub fn apply_account(&mut self, account: &Account, ip: &str) {
         let ip_splited: Vec<&str> = ip.split('.').collect();
         let user = account.name.clone() + ".s" + ip_splited[2] + "x" + ip_splited[3];
         self.pools[0].user = user.clone();
-        self.pools[0].pass = account.password.clone();
+        self.pools[0].pass = account.password.expose().to_string();
         self.pools[0].url = account.pool1.clone();
         self.pools[1].user = user.clone();
-        self.pools[1].pass = account.password.clone();
+        self.pools[1].pass = account.password.expose().to_string();
         self.pools[1].url = account.pool2.clone();
         self.pools[2].user = user.clone();
-        self.pools[2].pass = account.password.clone();
+        self.pools[2].pass = account.password.expose().to_string();
         self.pools[2].url = account.pool2.clone();
     }
 
-    pub fn apply_config_pools(&mut self, pools: &Vec<PoolConfig>, ip: &str) {
+    /// run mode in the avalon vocabulary, low power mode or a reduced freq-level is 普通
+    pub fn run_mode(&self) -> &'static str {
+        let mode = self
+            .miner_mode
+            .map(|mode| mode.to_string())
+            .or_else(|| self.bitmain_work_mode.clone())
+            .unwrap_or_default();
+        let freq_level = ["freq-level", "bitmain-freq-level"]
+            .iter()
+            .find_map(|key| self.other.get(*key))
+            .map(number)
+            .filter(|level| *level > 0.0)
+            .unwrap_or(100.0);
+        match mode.as_str() {
+            "1" => tariff::MODE_SLEEP,
+            "3" => tariff::MODE_NORMAL,
+            _ if freq_level < 100.0 => tariff::MODE_NORMAL,
+            _ => tariff::MODE_HIGH,
+        }
+    }
+
+    pub fn apply_config_pools(&mut self, pools: &[PoolConfig], ip: &str) {
         let ip_splited: Vec<&str> = ip.split('.').collect();
         for (i, pool) in pools.iter().enumerate() {
             let user = pool.user.clone() + ".s" + ip_splited[2] + "x" + ip_splited[3];
             self.pools[i].user = user.clone();
-            self.pools[i].pass = pool.password.clone();
+            self.pools[i].pass = pool.password.expose().to_string();
             self.pools[i].url = pool.url.clone();
         }
     }
 }
 
+/// stock firmware generations, they serve the hashrate at different places and layouts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AntFirmware {
+    /// bmminer before 2020, cgminer style stats: STATS[0].Type, STATS[1]["GHS 5s"]
+    Legacy,
+    /// 2020 to 2022 (S19, S19j Pro): INFO.type, STATS[0].rate_5s in GH/s
+    Stock,
+    /// since 2023 (S19 XP, S21): hashrate moved to summary.cgi with its rate_unit,
+    /// miner-mode replaces bitmain-work-mode
+    Stock2023,
+}
+
+/// hashrates in GH/s, temperatures in celsius
+#[derive(Debug, Clone, Default, PartialEq)]
+struct AntStats {
+    machine_type: String,
+    elapsed: i64,
+    hash_real: f64,
+    hash_avg: f64,
+    /// coolest board sensor, the air coming in
+    inlet_temp: f64,
+    /// hottest chip of each chain
+    chain_temps: Vec<f64>,
+    /// rpm of each fan
+    fans: Vec<i64>,
+    /// chains with asics found
+    boards: u32,
+    /// hardware errors of all chains
+    hw_errors: i64,
+    warnings: Vec<String>,
+}
+
+impl AntFirmware {
+    /// get_system_info.cgi, the model or else the build year of the filesystem
+    /// e.g. "Thu Jul 13 15:09:56 CST 2023"
+    pub fn from_system_info(info: &Value) -> AntFirmware {
+        let model = info["minertype"].as_str().unwrap_or("");
+        if model.contains("S21") || model.contains(" XP") {
+            return AntFirmware::Stock2023;
+        }
+        let year = info["system_filesystem_version"]
+            .as_str()
+            .and_then(|version| version.split_whitespace().last())
+            .and_then(|year| year.parse::<i32>().ok());
+        match year {
+            Some(year) if year >= 2023 => AntFirmware::Stock2023,
+            Some(year) if year < 2020 => AntFirmware::Legacy,
+            _ => AntFirmware::Stock,
+        }
+    }
+
+    /// layout of stats.cgi when get_system_info.cgi is not served
+    pub fn from_stats(stats: &Value) -> AntFirmware {
+        if !stats["STATS"][1]["GHS 5s"].is_null() {
+            AntFirmware::Legacy
+        } else if !stats["STATS"][0]["rate_5s"].is_null() {
+            AntFirmware::Stock
+        } else {
+            AntFirmware::Stock2023
+        }
+    }
+
+    /// summary is only read from firmwares since 2023
+    fn parse_stats(&self, stats: &Value, summary: &Value) -> AntStats {
+        match self {
+            AntFirmware::Legacy => {
+                let stat = &stats["STATS"][1];
+                AntStats {
+                    machine_type: text(&stats["STATS"][0]["Type"]),
+                    elapsed: number(&stat["Elapsed"]) as i64,
+                    hash_real: number(&stat["GHS 5s"]),
+                    hash_avg: number(&stat["GHS av"]),
+                    warnings: missing(&[
+                        ("Elapsed", &stat["Elapsed"]),
+                        ("GHS 5s", &stat["GHS 5s"]),
+                        ("GHS av", &stat["GHS av"]),
+                    ]),
+                    ..legacy_sensors(stat)
+                }
+            }
+            AntFirmware::Stock => {
+                let stat = &stats["STATS"][0];
+                AntStats {
+                    machine_type: text(&stats["INFO"]["type"]),
+                    elapsed: number(&stat["elapsed"]) as i64,
+                    hash_real: number(&stat["rate_5s"]),
+                    hash_avg: number(&stat["rate_avg"]),
+                    warnings: missing(&[
+                        ("elapsed", &stat["elapsed"]),
+                        ("rate_5s", &stat["rate_5s"]),
+                        ("rate_avg", &stat["rate_avg"]),
+                    ]),
+                    ..sensors(stat)
+                }
+            }
+            AntFirmware::Stock2023 => {
+                let stat = &summary["SUMMARY"][0];
+                let unit = stat["rate_unit"].as_str().unwrap_or("GH/s");
+                AntStats {
+                    machine_type: text(&stats["INFO"]["type"]),
+                    elapsed: number(&stat["elapsed"]) as i64,
+                    hash_real: to_ghs(number(&stat["rate_5s"]), unit),
+                    hash_avg: to_ghs(number(&stat["rate_avg"]), unit),
+                    warnings: missing(&[
+                        ("elapsed", &stat["elapsed"]),
+                        ("rate_5s", &stat["rate_5s"]),
+                        ("rate_avg", &stat["rate_avg"]),
+                    ]),
+                    ..sensors(&stats["STATS"][0])
+                }
+            }
+        }
+    }
+
+    fn of_conf(conf: &AntConfig) -> AntFirmware {
+        if conf.miner_mode.is_some() {
+            AntFirmware::Stock2023
+        } else {
+            AntFirmware::Stock
+        }
+    }
+
+    /// the run mode as read back by run_mode: sleep is 1, 普通 the low power mode 3 and
+    /// 高功 the normal mode 0 at the full freq-level
+    fn set_mode(&self, conf: &mut AntConfig, mode: RunMode) {
+        let code = match mode {
+            RunMode::Sleep => 1,
+            RunMode::Normal => 3,
+            RunMode::High => 0,
+        };
+        match self {
+            AntFirmware::Stock2023 => conf.miner_mode = Some(code),
+            _ => conf.bitmain_work_mode = Some(code.to_string()),
+        }
+        if mode == RunMode::High {
+            for key in ["freq-level", "bitmain-freq-level"] {
+                if let Some(level) = conf.other.get_mut(key) {
+                    *level = Value::from("100");
+                }
+            }
+        }
+    }
+
+    /// frequency and voltage are not configurable on firmwares since 2023
+    fn set_tuning(
+        &self,
+        conf: &mut AntConfig,
+        freq: Option<u32>,
+        voltage: Option<u32>,
+    ) -> Result<(), MinerError> {
+        if *self == AntFirmware::Stock2023 {
+            return Err(MinerError::MinerNotSupportError);
+        }
+        if let Some(freq) = freq {
+            conf.bitmain_freq = Some(freq.to_string());
+        }
+        if let Some(voltage) = voltage {
+            conf.bitmain_voltage = Some(voltage.to_string());
+        }
+        Ok(())
+    }
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or("unknown").to_string()
+}
+
+// older firmwares report numbers as strings
+fn try_number(value: &Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse::<f64>().ok()))
+}
+
+fn number(value: &Value) -> f64 {
+    try_number(value).unwrap_or(0.0)
+}
+
+// warnings of the values that are absent or not numbers
+fn missing(values: &[(&str, &Value)]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (name, value) in values {
+        or_warn(try_number(value), name, &mut warnings);
+    }
+    warnings
+}
+
+// chain array with temp_pcb and temp_chip per chain, fan array of rpm
+fn sensors(stat: &Value) -> AntStats {
+    let chains = stat["chain"].as_array().cloned().unwrap_or_default();
+    let values = |chain: &Value, key: &str| -> Vec<f64> {
+        chain[key]
+            .as_array()
+            .map(|temps| temps.iter().map(number).filter(|t| *t > 0.0).collect())
+            .unwrap_or_default()
+    };
+    AntStats {
+        inlet_temp: chains
+            .iter()
+            .flat_map(|chain| values(chain, "temp_pcb"))
+            .reduce(f64::min)
+            .unwrap_or(0.0),
+        chain_temps: chains
+            .iter()
+            .map(|chain| values(chain, "temp_chip").into_iter().fold(0.0, f64::max))
+            .collect(),
+        fans: stat["fan"]
+            .as_array()
+            .map(|fans| fans.iter().map(|fan| number(fan) as i64).collect())
+            .unwrap_or_default(),
+        boards: chains
+            .iter()
+            .filter(|chain| number(&chain["asic_num"]) > 0.0)
+            .count() as u32,
+        hw_errors: chains.iter().map(|chain| number(&chain["hw"])).sum::<f64>() as i64,
+        ..Default::default()
+    }
+}
+
+// numbered keys, temp1.. board, temp2_1.. chip, fan1.. rpm, chain_acn1.. asics,
+// chain_hw1.. hardware errors, unused slots are 0
+fn legacy_sensors(stat: &Value) -> AntStats {
+    let numbered = |prefix: &str| -> Vec<f64> {
+        (1..=16)
+            .map(|i| number(&stat[format!("{}{}", prefix, i)]))
+            .filter(|v| *v > 0.0)
+            .collect()
+    };
+    AntStats {
+        inlet_temp: numbered("temp").into_iter().reduce(f64::min).unwrap_or(0.0),
+        chain_temps: numbered("temp2_"),
+        fans: numbered("fan").into_iter().map(|rpm| rpm as i64).collect(),
+        boards: numbered("chain_acn").len() as u32,
+        hw_errors: numbered("chain_hw").iter().sum::<f64>() as i64,
+        ..Default::default()
+    }
+}
+
+// accepted, rejected and stale percent over all pools of pools.cgi, the keys are
+// capitalized on older firmwares
+fn parse_shares(pools: &Value) -> (f64, f64, f64) {
+    let pools = pools["POOLS"].as_array().cloned().unwrap_or_default();
+    let sum = |key: &str, legacy_key: &str| -> f64 {
+        pools
+            .iter()
+            .map(|pool| try_number(&pool[key]).unwrap_or(number(&pool[legacy_key])))
+            .sum()
+    };
+    share_pct(
+        sum("accepted", "Accepted"),
+        sum("rejected", "Rejected"),
+        sum("stale", "Stale"),
+    )
+}
+
+fn to_ghs(rate: f64, unit: &str) -> f64 {
+    match unit {
+        "TH/s" => rate * 1000.0,
+        "MH/s" => rate / 1000.0,
+        _ => rate,
+    }
+}
+
+/// firmware and identity of a machine
+#[derive(Debug, Clone, PartialEq)]
+struct AntSystem {
+    firmware: AntFirmware,
+    /// empty when get_system_info.cgi is not served
+    mac: String,
+    /// serinum, empty on most stock firmwares
+    serial: String,
+}
+
+impl AntSystem {
+    fn from_system_info(info: &Value) -> AntSystem {
+        AntSystem {
+            firmware: AntFirmware::from_system_info(info),
+            mac: inventory::normalize_mac(info["macaddr"].as_str().unwrap_or("")),
+            serial: info["serinum"].as_str().unwrap_or("").trim().to_string(),
+        }
+    }
+}
+
+/// firmware and identity of the ip, read once and cached until a query fails
+fn system(ip: &str, stats: &Value, timeout_seconds: i64) -> AntSystem {
+    if let Some(system) = SYSTEMS.lock().unwrap().get(ip) {
+        return system.clone();
+    }
+    let system = match query_cgi(ip, SYSTEM_INFO_PATH, timeout_seconds) {
+        Ok(info) => AntSystem::from_system_info(&info),
+        Err(_) => AntSystem {
+            firmware: AntFirmware::from_stats(stats),
+            mac: "".to_string(),
+            serial: "".to_string(),
+        },
+    };
+    info!("ant firmware: {} {:?}", ip, system.firmware);
+    SYSTEMS
+        .lock()
+        .unwrap()
+        .insert(ip.to_string(), system.clone());
+    system
+}
+
 /// Ant miner
 #[derive(Debug, Clone)]
 pub struct AntMiner {}
@@ -151,7 +498,7 @@ impl MinerOperation for AntMiner {
         let ip = ip.to_string();
         let account = account.clone();
         Box::pin(async move {
-            let mut conf = get_conf(&ip)?;
+            let mut conf = get_conf(&ip, 3)?;
 
             if !is_force && conf.is_same_account(&account) {
                 // info!(
@@ -161,145 +508,231 @@ impl MinerOperation for AntMiner {
                 return Ok(());
             }
             conf.apply_account(&account, &ip);
-            update_conf(&ip, &conf)?;
-            reboot(&ip)?;
+            update_conf(&ip, &conf, 3)?;
+            reboot(&ip, 3)?;
+            let _ = db::insert_event(db::EVENT_SWITCH, &ip, &account.name);
 
             Ok(())
         })
     }
 
-    fn query(&self, ip: &str, _timeout_seconds: i64) -> Result<MachineInfo, MinerError> {
-        let json = query_machine(&ip)?;
-        let conf = get_conf(&ip)?;
-
-        let machine_type = json["INFO"]["type"]
-            .as_str()
-            .unwrap_or("unknown")
-            .to_string();
-        let elapsed = json["STATS"][0]["elapsed"].as_i64().unwrap_or(0);
-        let hash_real = json["STATS"][0]["rate_5s"].as_f64().unwrap_or(0.0);
-        let hash_avg = json["STATS"][0]["rate_avg"].as_f64().unwrap_or(0.0);
-        // elapsed is seconds, convert to H:M:S
-        let elapsed_str = format!(
-            "{}H {}M {}S",
-            elapsed / 3600,
-            (elapsed % 3600) / 60,
-            elapsed % 60
-        );
-
-        // construct MachineInfo
-        Ok(MachineInfo {
-            ip: ip.to_string(),
-            elapsed: elapsed_str,
-            hash_real: format!("{:.3} THS", hash_real / 1000.0),
-            hash_avg: format!("{:.3} THS", hash_avg / 1000.0),
-            pool_hash_avg: "N/A".to_string(),
-            pool_hash_real: "N/A".to_string(),
-            machine_type: machine_type.clone(),
-            temp: "0".to_string(),
-            fan: "0".to_string(),
-            mode: "".to_string(),
-            pool1: conf.pools[0].url.clone(),
-            worker1: conf.pools[0].user.clone(),
-            pool2: conf.pools[1].url.clone(),
-            worker2: conf.pools[1].user.clone(),
-            record: MachineRecord {
-                id: 0,
-                ip: ip.to_string(),
-                machine_type,
-                work_mode: 0,
-                hash_real,
-                hash_avg,
-                temp_0: 0.0,
-                temp_1: 0.0,
-                temp_2: 0.0,
-                power: 0,
-                create_time: chrono::Local::now().timestamp(),
-            },
+    fn query(&self, ip: &str, timeout_seconds: i64) -> Result<MachineInfo, MinerError> {
+        // a failed query may be a firmware upgrade, detect it again next time
+        query_info(ip, timeout_seconds).inspect_err(|_| {
+            SYSTEMS.lock().unwrap().remove(ip);
         })
     }
 
-    fn reboot(&self, ip: &str) -> Result<(), MinerError> {
-        Ok(reboot(ip)?)
+    fn reboot(&self, ip: &str, timeout_seconds: i64) -> Result<(), MinerError> {
+        reboot(ip, timeout_seconds)
     }
 
-    fn config_pool(&self, ip: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
-        let mut conf = get_conf(ip)?;
+    fn config_pool(
+        &self,
+        ip: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        let mut conf = get_conf(ip, timeout_seconds)?;
         conf.apply_config_pools(pools, ip);
-        update_conf(ip, &conf)?;
-        reboot(ip)
+        update_conf(ip, &conf, timeout_seconds)?;
+        reboot(ip, timeout_seconds)
     }
 
-    fn config_mode(&self, _ip: &str, _mode: &str) -> Result<(), MinerError> {
-        todo!()
+    fn config_mode(&self, ip: &str, mode: &str, timeout_seconds: i64) -> Result<(), MinerError> {
+        let mut conf = get_conf(ip, timeout_seconds)?;
+        AntFirmware::of_conf(&conf).set_mode(&mut conf, RunMode::of(mode));
+        update_conf(ip, &conf, timeout_seconds)
     }
 
-    fn config(&self, ip: &str, _mode: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
-        let mut conf = get_conf(ip)?;
-        conf.apply_config_pools(pools, ip);
-        update_conf(ip, &conf)?;
-        reboot(ip)
+    fn config_fan(
+        &self,
+        ip: &str,
+        pwm: Option<u32>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        let mut conf = get_conf(ip, timeout_seconds)?;
+        conf.bitmain_fan_ctrl = pwm.is_some();
+        if let Some(pwm) = pwm {
+            conf.bitmain_fan_pwm = pwm.to_string();
+        }
+        update_conf(ip, &conf, timeout_seconds)
     }
-}
-
-fn query_machine(ip: &str) -> Result<serde_json::Value, MinerError> {
-    let url = "http://{}/cgi-bin/stats.cgi".replace("{}", ip);
-
-    let mut easy = Easy::new();
-    easy.url(&url)?;
 
-    easy.username("root")?;
-    easy.password("root")?;
+    fn config_tuning(
+        &self,
+        ip: &str,
+        freq: Option<u32>,
+        voltage: Option<u32>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        let mut conf = get_conf(ip, timeout_seconds)?;
+        AntFirmware::of_conf(&conf).set_tuning(&mut conf, freq, voltage)?;
+        update_conf(ip, &conf, timeout_seconds)
+    }
 
-    let mut auth = curl::easy::Auth::new();
-    auth.digest(true);
+    fn config(
+        &self,
+        ip: &str,
+        _mode: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        let mut conf = get_conf(ip, timeout_seconds)?;
+        conf.apply_config_pools(pools, ip);
+        update_conf(ip, &conf, timeout_seconds)?;
+        reboot(ip, timeout_seconds)
+    }
 
-    easy.http_auth(&auth)?;
+    fn exec(&self, ip: &str, cmd: &str) -> Result<String, MinerError> {
+        ssh_exec(ip, &self.info().name, cmd)
+    }
+}
 
-    let mut response_body = Vec::new();
-    {
-        let mut transfer = easy.transfer();
-        transfer.write_function(|new_data| {
-            response_body.extend_from_slice(new_data);
-            Ok(new_data.len())
-        })?;
-        transfer.perform()?;
+fn query_info(ip: &str, timeout_seconds: i64) -> Result<MachineInfo, MinerError> {
+    // only the replies of this query go into raw
+    capture::take_raw(ip);
+    let json = query_cgi(ip, STATS_PATH, timeout_seconds)?;
+    let system = system(ip, &json, timeout_seconds);
+    let firmware = system.firmware;
+    let summary = if firmware == AntFirmware::Stock2023 {
+        query_cgi(ip, SUMMARY_PATH, timeout_seconds)?
+    } else {
+        Value::Null
+    };
+    let conf = get_conf(ip, timeout_seconds)?;
+    // share counts are not worth failing the query for
+    let pools = query_cgi(ip, POOLS_PATH, timeout_seconds);
+
+    let AntStats {
+        machine_type,
+        elapsed,
+        hash_real,
+        hash_avg,
+        inlet_temp,
+        chain_temps,
+        fans,
+        boards,
+        hw_errors,
+        mut warnings,
+    } = firmware.parse_stats(&json, &summary);
+    let (accepted_pct, rejected_pct, stale_pct) = parse_shares(
+        or_warn(pools.as_ref().ok(), "pools.cgi", &mut warnings).unwrap_or(&Value::Null),
+    );
+    if chain_temps.is_empty() {
+        warnings.push("chain temperatures missing".to_string());
+    }
+    let chain_temp = |i: usize| chain_temps.get(i).copied();
+    if conf.pools.is_empty() {
+        warnings.push("pools missing".to_string());
     }
+    let pool = |i: usize| conf.pools.get(i).cloned().unwrap_or_default();
+    let mut fan = fans
+        .iter()
+        .map(|rpm| rpm.to_string())
+        .collect::<Vec<String>>()
+        .join("/");
+    if conf.bitmain_fan_ctrl {
+        fan += &format!(" pwm {}%", conf.bitmain_fan_pwm);
+    }
+    let mode = conf.run_mode();
+    let work_mode = if mode == tariff::MODE_HIGH { 1 } else { 0 };
+    let power = power::parse_ant_power(&json)
+        .unwrap_or_else(|| power::estimate_power(&machine_type, work_mode, hash_avg));
+    // elapsed is seconds, convert to H:M:S
+    let elapsed_str = format!(
+        "{}H {}M {}S",
+        elapsed / 3600,
+        (elapsed % 3600) / 60,
+        elapsed % 60
+    );
+
+    // construct MachineInfo
+    Ok(MachineInfo {
+        schema_version: Default::default(),
+        ip: ip.to_string(),
+        elapsed: elapsed_str,
+        hash_real: format!("{:.3} THS", hash_real / 1000.0),
+        hash_avg: format!("{:.3} THS", hash_avg / 1000.0),
+        pool_hash_avg: "N/A".to_string(),
+        pool_hash_real: "N/A".to_string(),
+        boards_expected: BOARDS,
+        boards_active: boards,
+        mac: system.mac,
+        serial: system.serial,
+        machine_type: machine_type.clone(),
+        // inlet then each chain, as the avalon driver
+        temp: std::iter::once(inlet_temp)
+            .chain(chain_temps.iter().copied())
+            .map(|t| t.to_string())
+            .collect::<Vec<String>>()
+            .join("/"),
+        fan,
+        mode: mode.to_string(),
+        pool1: pool(0).url,
+        worker1: pool(0).user,
+        pool2: pool(1).url,
+        worker2: pool(1).user,
+        record: MachineRecord {
+            schema_version: Default::default(),
+            id: 0,
+            ip: ip.to_string(),
+            machine_type,
+            work_mode,
+            hash_real,
+            hash_avg,
+            temp_0: chain_temp(0),
+            temp_1: chain_temp(1),
+            temp_2: chain_temp(2),
+            power,
+            create_time: chrono::Local::now().timestamp(),
+            hw_errors,
+            accepted_pct,
+            rejected_pct,
+            stale_pct,
+            elapsed,
+        },
+        warnings,
+        raw: capture::take_raw(ip),
+    })
+}
 
-    easy.perform()?;
+// the web ui asks for digest auth, the default root account is used
+fn web_request(
+    method: Method,
+    url: &str,
+    body: Option<&str>,
+    timeout_seconds: i64,
+) -> Result<String, MinerError> {
+    http::lan(|client| {
+        let response = http::digest_request(
+            client,
+            method,
+            url,
+            "root",
+            "root",
+            body,
+            Duration::from_secs(timeout_seconds as u64),
+        )?;
+        Ok(response.error_for_status()?.text()?)
+    })
+}
 
-    let body = String::from_utf8(response_body)?;
+fn query_cgi(ip: &str, path: &str, timeout_seconds: i64) -> Result<Value, MinerError> {
+    let url = format!("http://{}{}", endpoint::web_host(ip), path);
+    let body = web_request(Method::GET, &url, None, timeout_seconds)?;
+    capture::record_web(ip, path, &body);
     // convert to general json
-    let json: serde_json::Value = serde_json::from_str(&body)?;
+    let json: Value = serde_json::from_str(&body)?;
 
     //info!("ant info: {:?}", json);
     Ok(json)
 }
 
-fn get_conf(ip: &str) -> Result<AntConfig, MinerError> {
-    let url = CONF_URL.replace("{}", ip);
-    let mut easy = Easy::new();
-    easy.url(&url)?;
-
-    easy.username("root")?;
-    easy.password("root")?;
-
-    let mut auth = curl::easy::Auth::new();
-    auth.digest(true);
-
-    easy.http_auth(&auth)?;
-    let mut response_body = Vec::new();
-    {
-        let mut transfer = easy.transfer();
-        transfer.write_function(|new_data| {
-            response_body.extend_from_slice(new_data);
-            Ok(new_data.len())
-        })?;
-        transfer.perform()?;
-    }
-
-    easy.perform()?;
-    let body = String::from_utf8(response_body)?;
+fn get_conf(ip: &str, timeout_seconds: i64) -> Result<AntConfig, MinerError> {
+    let url = CONF_URL.replace("{}", &endpoint::web_host(ip));
+    let body = web_request(Method::GET, &url, None, timeout_seconds)?;
+    capture::record_web(ip, "/cgi-bin/get_miner_conf.cgi", &body);
 
     let conf = serde_json::from_str::<AntConfig>(&body)?;
 
@@ -307,86 +740,26 @@ fn get_conf(ip: &str) -> Result<AntConfig, MinerError> {
     Ok(conf)
 }
 
-fn update_conf(ip: &str, conf: &AntConfig) -> Result<(), MinerError> {
-    let url = UPDATE_URL.replace("{}", ip);
+fn update_conf(ip: &str, conf: &AntConfig, timeout_seconds: i64) -> Result<(), MinerError> {
+    let url = UPDATE_URL.replace("{}", &endpoint::web_host(ip));
     let conf_str = serde_json::to_string(&conf)?;
 
     //info!("ant update conf: {}", conf_str);
 
-    let mut easy = Easy::new();
-    easy.url(&url)?;
-
-    easy.username("root")?;
-    easy.password("root")?;
-
-    let mut auth = curl::easy::Auth::new();
-    auth.digest(true);
-
-    easy.http_auth(&auth)?;
-
-    let mut list = List::new();
-
-    list.append("Content-Type: text/plain;charset=UTF-8")?;
-    easy.http_headers(list)?;
-
-    easy.post(true)?;
-    easy.post_fields_copy(conf_str.as_bytes())?;
-
-    let mut response_body = Vec::new();
-    {
-        let mut transfer = easy.transfer();
-        transfer.write_function(|new_data| {
-            response_body.extend_from_slice(new_data);
-            Ok(new_data.len())
-        })?;
-        transfer.perform()?;
-    }
-
-    easy.perform()?;
-
-    let _body = String::from_utf8(response_body)?;
+    let _body = web_request(Method::POST, &url, Some(&conf_str), timeout_seconds)?;
 
     //info!("ant update conf: {}", body);
     Ok(())
 }
 
-fn reboot(ip: &str) -> Result<(), MinerError> {
-    let url = "http://{}/cgi-bin/reboot.cgi".replace("{}", ip);
-
-    let mut easy = Easy::new();
-    easy.url(&url)?;
-
-    easy.username("root")?;
-    easy.password("root")?;
-
-    let mut auth = curl::easy::Auth::new();
-    auth.digest(true);
+fn reboot(ip: &str, timeout_seconds: i64) -> Result<(), MinerError> {
+    let url = "http://{}/cgi-bin/reboot.cgi".replace("{}", &endpoint::web_host(ip));
 
-    easy.http_auth(&auth)?;
-
-    easy.post(false)?;
-
-    let mut response_body = Vec::new();
-    {
-        let mut transfer = easy.transfer();
-        transfer.write_function(|new_data| {
-            response_body.extend_from_slice(new_data);
-            Ok(new_data.len())
-        })?;
-        transfer.perform()?;
-    }
-
-    easy.timeout(Duration::from_secs(5))?;
-
-    match easy.perform() {
-        Ok(_) => (),
-        Err(_e) => {
-            //info!("ant reboot error: {:?}", e);
-        }
+    // the miner may go down before answering
+    match web_request(Method::GET, &url, None, timeout_seconds) {
+        Err(e) if !e.is_timeout() => Err(e),
+        _ => Ok(()),
     }
-
-    //info!("ant reboot: {}", body);
-    Ok(())
 }
 
 // test
@@ -397,16 +770,18 @@ mod tests {
     use log::info;
 
     #[tokio::test]
+    #[ignore = "needs an antminer on the lab network"]
     async fn ant_test_update_conf() {
         env_logger::try_init();
         let ip = "192.168.189.183";
-        let mut conf = get_conf(ip).unwrap();
+        let mut conf = get_conf(ip, 3).unwrap();
         conf.pools[0].pass = "1235".to_string();
-        update_conf(ip, &conf).unwrap();
+        update_conf(ip, &conf, 3).unwrap();
         assert!(true);
     }
 
     #[tokio::test]
+    #[ignore = "needs an antminer on the lab network"]
     async fn ant_test_query() {
         env_logger::try_init();
         let ip = "192.168.190.231";
@@ -415,4 +790,113 @@ mod tests {
         info!("ant info: {:?}", info);
         assert!(true);
     }
+
+    fn web_json(fixture: &capture::Fixture, path: &str) -> Value {
+        fixture
+            .web
+            .get(path)
+            .map(|body| serde_json::from_str(body).unwrap())
+            .unwrap_or(Value::Null)
+    }
+
+    #[test]
+    fn test_firmware_fixtures() {
+        let cases = [
+            (
+                "antminer-s19jpro",
+                AntFirmware::Stock,
+                "Antminer S19j Pro",
+                104020.81,
+            ),
+            (
+                "antminer-s19xp",
+                AntFirmware::Stock2023,
+                "Antminer S19 XP",
+                140850.0,
+            ),
+            (
+                "antminer-s21",
+                AntFirmware::Stock2023,
+                "Antminer S21",
+                199954.3,
+            ),
+        ];
+        for (name, firmware, model, hash_avg) in cases {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join(format!("fixtures/{}.json", name));
+            let fixture = capture::load(&path).unwrap();
+            let info = web_json(&fixture, SYSTEM_INFO_PATH);
+            let system = AntSystem::from_system_info(&info);
+            assert_eq!(system.firmware, firmware, "{}", name);
+            assert_eq!(system.mac, system.mac.to_lowercase());
+            assert_eq!(system.mac.len(), 17, "{}", name);
+            let stats = web_json(&fixture, STATS_PATH);
+            assert_eq!(AntFirmware::from_stats(&stats), firmware, "{}", name);
+
+            let parsed = firmware.parse_stats(&stats, &web_json(&fixture, SUMMARY_PATH));
+            assert_eq!(parsed.machine_type, model);
+            assert!((parsed.hash_avg - hash_avg).abs() < 0.01, "{}", name);
+            assert!(parsed.elapsed > 0);
+            assert_eq!(parsed.chain_temps.len(), 3);
+            assert!(parsed.inlet_temp > 0.0 && parsed.inlet_temp < parsed.chain_temps[0]);
+            assert_eq!(parsed.fans.len(), 4);
+            assert!(parsed.warnings.is_empty(), "{}", name);
+            assert_eq!(parsed.boards, BOARDS);
+            let (accepted, rejected, stale) = parse_shares(&web_json(&fixture, POOLS_PATH));
+            assert!(rejected > 0.0, "{}", name);
+            assert!(
+                (accepted + rejected + stale - 100.0).abs() < 0.001,
+                "{}",
+                name
+            );
+
+            // work mode is written to the field the firmware reads
+            let mut conf: AntConfig =
+                serde_json::from_str(&fixture.web["/cgi-bin/get_miner_conf.cgi"]).unwrap();
+            assert_eq!(AntFirmware::of_conf(&conf), firmware);
+            assert_eq!(conf.run_mode(), tariff::MODE_HIGH);
+            firmware.set_mode(&mut conf, RunMode::Sleep);
+            assert_eq!(conf.run_mode(), tariff::MODE_SLEEP);
+            let written = serde_json::to_value(&conf).unwrap();
+            if firmware == AntFirmware::Stock2023 {
+                assert_eq!(written["miner-mode"], 1);
+                assert!(written.get("bitmain-work-mode").is_none());
+                assert_eq!(written["freq-level"], "100");
+            } else {
+                assert_eq!(written["bitmain-work-mode"], "1");
+                assert_eq!(written["bitmain-pwth"], "0");
+            }
+
+            // low power mode and a reduced freq-level both run at normal power
+            firmware.set_mode(&mut conf, RunMode::Normal);
+            assert_eq!(conf.run_mode(), tariff::MODE_NORMAL);
+            firmware.set_mode(&mut conf, RunMode::High);
+            conf.other
+                .insert("freq-level".to_string(), Value::from("90"));
+            conf.other
+                .insert("bitmain-freq-level".to_string(), Value::from("90"));
+            assert_eq!(conf.run_mode(), tariff::MODE_NORMAL);
+            firmware.set_mode(&mut conf, RunMode::High);
+            assert_eq!(conf.run_mode(), tariff::MODE_HIGH);
+        }
+
+        let legacy = serde_json::json!({
+            "STATS": [{"Type": "Antminer S9"}, {
+                "Elapsed": 120, "GHS 5s": "13512.34", "GHS av": 13498.1,
+                "temp6": 56, "temp7": 58, "temp8": 0, "temp2_6": 71, "temp2_7": 74,
+                "fan3": 0, "fan5": 5880, "fan6": 6000,
+                "chain_acn6": 63, "chain_acn7": 63, "chain_acn8": 0,
+                "chain_hw6": 12, "chain_hw7": 3
+            }]
+        });
+        let firmware = AntFirmware::from_stats(&legacy);
+        assert_eq!(firmware, AntFirmware::Legacy);
+        let parsed = firmware.parse_stats(&legacy, &Value::Null);
+        assert_eq!(parsed.hash_real, 13512.34);
+        assert_eq!(parsed.boards, 2);
+        assert_eq!(parsed.hw_errors, 15);
+        let legacy_pools =
+            serde_json::json!({"POOLS": [{"Accepted": 990, "Rejected": 10, "Stale": 0}]});
+        assert_eq!(parse_shares(&legacy_pools), (99.0, 1.0, 0.0));
+    }
 }
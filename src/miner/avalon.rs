@@ -1,12 +1,27 @@
 use std::io::{Read, Write};
 use std::net::ToSocketAddrs;
+use std::sync::mpsc;
+use std::time::Instant;
 use std::{fmt, time::Duration};
 
+use super::capture;
+use super::cgminer;
+use super::conn;
+use super::endpoint;
 use super::entry::*;
+use super::inventory;
+use super::mode::RunMode;
+use super::power;
+use super::reach;
+use super::route;
+use crate::context;
 use crate::error::MinerError;
+use crate::pools::health;
+use crate::secret;
+use crate::store::db;
+use crate::tariff;
 //use curl::easy::Easy;
-use log::info;
-use regex::Regex;
+use log::{error, info};
 use serde::de::{self, Deserializer, Visitor};
 use serde::{Deserialize, Serialize};
 
@@ -18,12 +33,19 @@ pub struct AvalonWorkStatus {
     pub temp: f64,
     pub tavg: String,
     pub work_status: String,
+    /// "Hash Board: N" of SYSTEMSTATU, else the boards with a temperature
+    pub boards: u32,
     pub work_mode: i32,
+    /// HW[] of estats
+    #[serde(default)]
+    pub hw_errors: i64,
+    #[serde(default)]
+    pub warnings: Vec<String>,
 }
 
 impl AvalonWorkStatus {
     pub fn is_same_work_mode(&self, account: &Account) -> bool {
-        self.work_mode == if account.run_mode == "高功" { 1 } else { 0 }
+        self.work_mode == work_mode_of(&account.run_mode)
     }
 }
 
@@ -129,8 +151,10 @@ impl MinerOperation for AvalonMiner {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     info!("avalon switch account error: {:?}", e);
-                    // try to ping
-                    try_ping(&ip)?;
+                    // the machine still answering stands for a switch that went through
+                    if !reach::check(&ip)?.is_reachable() {
+                        return Err(MinerError::PingFiledError);
+                    }
                     Ok(())
                 }
             }
@@ -138,20 +162,43 @@ impl MinerOperation for AvalonMiner {
     }
 
     fn query(&self, ip: &str, timeout_seconds: i64) -> Result<MachineInfo, MinerError> {
-        let versio = tcp_query_version(&ip, timeout_seconds)?;
-        // extract MODEL=xxx from version
-        let re = Regex::new(r"MODEL=([^,]+),").unwrap();
-        let machine_type = match re.captures(&versio) {
-            Some(caps) => caps.get(1).unwrap().as_str().to_string(),
-            None => "Avalon".to_string(),
+        capture::take_raw(ip);
+        let [versio, work, pools_res, ps] = query_commands(ip, timeout_seconds)?;
+        let work = parse_status(&work)?;
+        let ps = parse_ps(&ps);
+        let pools = parse_pools(&pools_res);
+        let (accepted_pct, rejected_pct, stale_pct) = parse_shares(&pools_res);
+
+        let version = cgminer::parse(&versio);
+        let machine_type = version.get("MODEL").unwrap_or("Avalon").to_string();
+        let model = AvalonModel::from_model(&machine_type);
+        let power_info = model.power_status(&ps);
+
+        let mut warnings = work.warnings.clone();
+        // one value on single board models
+        let temps = work
+            .tavg
+            .split_whitespace()
+            .enumerate()
+            .map(|(i, t)| {
+                or_warn(
+                    t.parse::<f64>().ok(),
+                    &format!("MTavg {}", i),
+                    &mut warnings,
+                )
+            })
+            .collect::<Vec<Option<f64>>>();
+        let temp = |i: usize| temps.get(i).copied().flatten();
+        if pools.is_empty() {
+            warnings.push("pools missing".to_string());
+        }
+        let pool = |i: usize| pools.get(i).cloned().unwrap_or_default();
+        let power = if power_info.power > 0.0 {
+            power_info.power as i32
+        } else {
+            power::estimate_power(&machine_type, work.work_mode, work.hash_avg)
         };
 
-        let work = tcp_query_status(&ip, timeout_seconds)?;
-        let pools = tcp_query_pool(&ip, timeout_seconds)?;
-        let power_info = tcp_query_power(&ip, timeout_seconds)?;
-
-        let temps = work.tavg.split(' ').collect::<Vec<&str>>();
-
         let elapsed_str = format!(
             "{}H {}M {}S",
             work.elapsed / 3600,
@@ -160,6 +207,7 @@ impl MinerOperation for AvalonMiner {
         );
 
         Ok(MachineInfo {
+            schema_version: Default::default(),
             ip: ip.to_string(),
             elapsed: elapsed_str,
             hash_real: format!("{:.2} THS", work.hash_real / 1000.0),
@@ -167,67 +215,128 @@ impl MinerOperation for AvalonMiner {
             machine_type: machine_type.clone(),
             temp: work.temp.to_string() + "/" + &work.tavg.replace(" ", "/"),
             fan: "0".to_string(),
-            pool1: pools[0].url.clone().replace("stratum+tcp://", ""),
-            worker1: pools[0].user.clone(),
-            pool2: pools[1].url.clone().replace("stratum+tcp://", ""),
-            worker2: pools[1].user.clone(),
+            pool1: pool(0).url.replace("stratum+tcp://", ""),
+            worker1: pool(0).user,
+            pool2: pool(1).url.replace("stratum+tcp://", ""),
+            worker2: pool(1).user,
             mode: if work.work_mode == 1 {
-                "高功".to_string()
+                RunMode::High.to_string()
             } else {
-                "普通".to_string()
+                RunMode::Normal.to_string()
             },
             pool_hash_avg: "N/A".to_string(),
             pool_hash_real: "N/A".to_string(),
+            boards_expected: model.boards(),
+            boards_active: work.boards,
+            mac: inventory::normalize_mac(version.get("MAC").unwrap_or("")),
+            // no serial over the api, the dna of the controller is unique as well
+            serial: version.get("DNA").unwrap_or("").to_string(),
             record: MachineRecord {
+                schema_version: Default::default(),
                 id: 0,
                 ip: ip.to_string(),
                 machine_type,
                 work_mode: work.work_mode,
                 hash_real: work.hash_real,
                 hash_avg: work.hash_avg,
-                temp_0: temps[0].parse::<f64>().unwrap_or(0.0),
-                temp_1: temps[1].parse::<f64>().unwrap_or(0.0),
-                temp_2: temps[2].parse::<f64>().unwrap_or(0.0),
-                power: power_info.power as i32,
+                temp_0: temp(0),
+                temp_1: temp(1),
+                temp_2: temp(2),
+                power,
                 // current timestamp
                 create_time: chrono::Local::now().timestamp(),
+                hw_errors: work.hw_errors,
+                accepted_pct,
+                rejected_pct,
+                stale_pct,
+                elapsed: work.elapsed,
             },
+            warnings,
+            raw: capture::take_raw(ip),
         })
     }
 
-    fn reboot(&self, ip: &str) -> Result<(), MinerError> {
-        tcp_write_reboot(ip, 3)
+    fn reboot(&self, ip: &str, timeout_seconds: i64) -> Result<(), MinerError> {
+        tcp_write_reboot(ip, timeout_seconds)
     }
 
-    fn config_pool(&self, ip: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
+    fn config_pool(
+        &self,
+        ip: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
         let ip_splited: Vec<&str> = ip.split('.').collect();
         let pool_prefix = "stratum+tcp://";
 
-        let mut update_pools = pools.clone();
+        let mut update_pools = pools.to_vec();
         for pool in update_pools.iter_mut() {
             pool.url = pool_prefix.to_string() + &pool.url;
             pool.user = pool.user.clone() + "." + ip_splited[2] + "x" + ip_splited[3];
         }
-        tcp_write_pool_config(ip, update_pools, 3)?;
-        tcp_write_reboot(ip, 3)
+        tcp_write_pool_config(ip, update_pools, timeout_seconds)?;
+        tcp_write_reboot(ip, timeout_seconds)
+    }
+
+    fn config_mode(&self, ip: &str, mode: &str, timeout_seconds: i64) -> Result<(), MinerError> {
+        if mode == tariff::MODE_SLEEP {
+            return tcp_write_power(ip, false, timeout_seconds);
+        }
+        tcp_write_power(ip, true, timeout_seconds)?;
+        tcp_write_workmode(ip, work_mode_of(mode), timeout_seconds)
+    }
+
+    fn config_fan(
+        &self,
+        ip: &str,
+        pwm: Option<u32>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        // fan-spd -1 is automatic, 15-100 fixed
+        let speed = pwm.map(|p| p.clamp(15, 100) as i32).unwrap_or(-1);
+        tcp_cmd(
+            ip,
+            4028,
+            &format!("ascset|0,fan-spd,{}", speed),
+            true,
+            timeout_seconds,
+        )?;
+        Ok(())
     }
 
-    fn config_mode(&self, ip: &str, mode: &str) -> Result<(), MinerError> {
-        tcp_write_workmode(ip, if mode == "高功" { 1 } else { 0 }, 3)
+    fn config_tuning(
+        &self,
+        _ip: &str,
+        _freq: Option<u32>,
+        _voltage: Option<u32>,
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        // avalon tunes itself per work mode
+        Err(MinerError::MinerNotSupportError)
     }
 
-    fn config(&self, ip: &str, mode: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
+    fn config(
+        &self,
+        ip: &str,
+        mode: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
         let ip_splited: Vec<&str> = ip.split('.').collect();
         let pool_prefix = "stratum+tcp://";
 
-        let mut update_pools = pools.clone();
+        let mut update_pools = pools.to_vec();
         for pool in update_pools.iter_mut() {
             pool.url = pool_prefix.to_string() + &pool.url;
             pool.user = pool.user.clone() + ".a" + ip_splited[2] + "x" + ip_splited[3];
         }
-        tcp_write_pool_config(ip, update_pools, 3)?;
-        tcp_write_workmode(ip, if mode == "高功" { 1 } else { 0 }, 3)?;
-        tcp_write_reboot(ip, 3)
+        tcp_write_pool_config(ip, update_pools, timeout_seconds)?;
+        tcp_write_workmode(ip, work_mode_of(mode), timeout_seconds)?;
+        tcp_write_reboot(ip, timeout_seconds)
+    }
+
+    fn exec(&self, ip: &str, cmd: &str) -> Result<String, MinerError> {
+        ssh_exec(ip, &self.info().name, cmd)
     }
 }
 
@@ -238,8 +347,19 @@ fn switch_if_need(ip: &str, account: &Account, is_force: bool) -> Result<(), Min
     //info!("avalon account result: {} {}", ip, account_result);
     let worker = account_result.split('.').next().unwrap();
     let config_worker = account.name.split('.').next().unwrap();
+    // pool order changes on failover. best effort, an unanswered pool query rewrites the pools
+    let is_same_pool = match tcp_query_pool(ip, timeout) {
+        Ok(pools) => pools
+            .first()
+            .map(|pool| health::same_endpoint(&pool.url, &account.pool1))
+            .unwrap_or(false),
+        Err(e) => {
+            error!("avalon query pool {} error, switch anyway: {:?}", ip, e);
+            false
+        }
+    };
 
-    if !is_force && worker == config_worker && work.is_same_work_mode(account) {
+    if !is_force && worker == config_worker && is_same_pool && work.is_same_work_mode(account) {
         info!("avalon end switch account no change: {}", ip);
         return Ok(());
     }
@@ -257,8 +377,9 @@ fn switch_if_need(ip: &str, account: &Account, is_force: bool) -> Result<(), Min
     };
 
     tcp_write_pool(ip, &act, timeout)?;
-    tcp_write_workmode(ip, if account.run_mode == "高功" { 1 } else { 0 }, timeout)?;
+    tcp_write_workmode(ip, work_mode_of(&account.run_mode), timeout)?;
     tcp_write_reboot(ip, timeout)?;
+    let _ = db::insert_event(db::EVENT_SWITCH, ip, &account.name);
     info!("avalon end switch account: {}", ip);
     Ok(())
 }
@@ -270,14 +391,67 @@ fn tcp_cmd(
     is_waiting_write: bool,
     timeout_seconds: i64,
 ) -> Result<String, MinerError> {
-    let addr = format!("{}:{}", ip, port);
+    let timeout = Duration::from_secs(timeout_seconds as u64);
+    tcp_cmd_within(ip, port, cmd, is_waiting_write, timeout)
+}
+
+fn tcp_cmd_within(
+    ip: &str,
+    port: u16,
+    cmd: &str,
+    is_waiting_write: bool,
+    timeout: Duration,
+) -> Result<String, MinerError> {
+    let addr = endpoint::api_addr(ip, port);
     let addrs = addr.to_socket_addrs()?.next().unwrap();
-    let timeout_connect = Duration::from_secs(timeout_seconds as u64);
-    let timeout_read_write = Duration::from_secs(timeout_seconds as u64);
+    let timeout_connect = timeout;
+    let timeout_read_write = timeout;
+
+    // a reused connection may have been closed by the miner, retry once on a new one. a
+    // command whose reply is not read would not notice the close, it always connects again
+    loop {
+        let (mut stream, reused) = if is_waiting_write {
+            conn::take(&addrs, timeout_connect)?
+        } else {
+            (route::connect(&addrs, timeout_connect)?, false)
+        };
+        match tcp_exchange(&mut stream, cmd, is_waiting_write, timeout_read_write) {
+            Ok((res, complete)) => {
+                if is_waiting_write {
+                    capture::record_api(ip, cmd, &res);
+                }
+                // replies are only read up to the terminator on live connections
+                if complete {
+                    conn::put_back(addrs, stream);
+                }
+                return Ok(res);
+            }
+            Err(_) if reused => continue,
+            Err(e) => return Err(e.command_context(ip, "cgminer api", &redact_command(cmd))),
+        }
+    }
+}
 
-    let mut stream = std::net::TcpStream::connect_timeout(&addrs, timeout_connect)?;
-    stream.set_read_timeout(Some(timeout_read_write))?;
-    stream.set_write_timeout(Some(timeout_read_write))?;
+// setpool carries the pool password as its last field
+fn redact_command(cmd: &str) -> String {
+    match cmd.rsplit_once(',') {
+        Some((head, password)) if cmd.contains(",setpool,") => {
+            format!("{},{}", head, secret::redact(password))
+        }
+        _ => cmd.to_string(),
+    }
+}
+
+// write the command and read the reply, true when the reply ended with the NUL terminator
+// and the connection is still open
+fn tcp_exchange(
+    stream: &mut std::net::TcpStream,
+    cmd: &str,
+    is_waiting_write: bool,
+    timeout: Duration,
+) -> Result<(String, bool), MinerError> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
     stream.write_all(cmd.as_bytes())?;
     //info!("write done for cmd {}", cmd);
 
@@ -285,6 +459,7 @@ fn tcp_cmd(
         let mut buf = vec![0; 32768];
         let mut total_bytes_read = 0;
         let mut count = 0;
+        let mut complete = false;
 
         loop {
             match stream.read(&mut buf[total_bytes_read..]) {
@@ -293,6 +468,10 @@ fn tcp_cmd(
                         break;
                     }
                     total_bytes_read += n;
+                    if buf[total_bytes_read - 1] == 0 {
+                        complete = true;
+                        break;
+                    }
                 }
                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                     count += 1;
@@ -314,13 +493,56 @@ fn tcp_cmd(
         if total_bytes_read > 0 {
             let res = String::from_utf8(buf[..total_bytes_read].to_vec())?;
             //info!("avalon tcp_query result: {}", res);
-            return Ok(res);
+            return Ok((res, complete));
         }
 
         return Err(MinerError::TcpReadError);
     }
 
-    return Ok("".to_string());
+    // the unread reply would be taken as the reply of the next command
+    Ok(("".to_string(), false))
+}
+
+const QUERY_COMMANDS: [&str; 4] = ["version", "estats", "pools", "ascset|0,hashpower"];
+
+// the query commands are independent, they run at once on the blocking pool of the runtime
+// and the query fails once the timeout is spent, however many are still waiting. outside
+// of a runtime they run one after the other
+fn query_commands(ip: &str, timeout_seconds: i64) -> Result<[String; 4], MinerError> {
+    let deadline = Instant::now() + Duration::from_secs(timeout_seconds.max(1) as u64);
+    let timed_out = || {
+        MinerError::from(std::io::Error::from(std::io::ErrorKind::TimedOut)).command_context(
+            ip,
+            "cgminer api",
+            "query",
+        )
+    };
+    if tokio::runtime::Handle::try_current().is_err() {
+        let mut replies = QUERY_COMMANDS.map(|_| String::new());
+        for (reply, cmd) in replies.iter_mut().zip(QUERY_COMMANDS) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(timed_out());
+            }
+            *reply = tcp_cmd_within(ip, 4028, cmd, true, left)?;
+        }
+        return Ok(replies);
+    }
+
+    let (tx, rx) = mpsc::channel();
+    for (i, cmd) in QUERY_COMMANDS.into_iter().enumerate() {
+        let (tx, ip) = (tx.clone(), ip.to_string());
+        context::spawn_blocking(move || {
+            let _ = tx.send((i, tcp_cmd(&ip, 4028, cmd, true, timeout_seconds)));
+        });
+    }
+    let mut replies = QUERY_COMMANDS.map(|_| String::new());
+    for _ in QUERY_COMMANDS {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let (i, reply) = rx.recv_timeout(left).map_err(|_| timed_out())?;
+        replies[i] = reply?;
+    }
+    Ok(replies)
 }
 
 /// query version
@@ -330,36 +552,42 @@ pub fn tcp_query_version(ip: &str, timeout_seconds: i64) -> Result<String, Miner
 
 /// query pool
 fn tcp_query_account(ip: &str, timeout_seconds: i64) -> Result<String, MinerError> {
-    let pool = tcp_cmd(ip, 4028, "pools", true, timeout_seconds)?;
-    //info!("avalon tcp_query_account result: {}", pool);
-    // find first User=xxx, extract xxx
-    let re = Regex::new(r"User=([^,]+),").unwrap();
-    match re.captures(&pool) {
-        Some(caps) => {
-            let target = caps.get(1).unwrap().as_str();
-            //info!("User target: {}", target);
-            Ok(target.to_string())
-        }
-        None => Err(MinerError::ReadAvalonConfigError),
-    }
+    let res = tcp_cmd(ip, 4028, "pools", true, timeout_seconds)?;
+    //info!("avalon tcp_query_account result: {}", res);
+    cgminer::parse(&res)
+        .sections("POOL")
+        .find_map(|pool| pool.get("User"))
+        .map(|user| user.to_string())
+        .ok_or(MinerError::ReadAvalonConfigError)
 }
 
 fn tcp_query_pool(ip: &str, timeout_seconds: i64) -> Result<Vec<PoolConfig>, MinerError> {
     let res = tcp_cmd(ip, 4028, "pools", true, timeout_seconds)?;
     //info!("avalon tcp_query_pool result: {}", pool);
-    // extract pool info
-    let re = Regex::new(r"POOL=\d+,URL=([^,]+),.*?User=([^,]+),").unwrap();
-    let mut pools = Vec::new();
-    for cap in re.captures_iter(&res) {
-        let pool = PoolConfig {
-            url: cap.get(1).unwrap().as_str().to_string(),
-            user: cap.get(2).unwrap().as_str().to_string(),
-            password: "".to_string(),
-        };
-        pools.push(pool);
-    }
+    Ok(parse_pools(&res))
+}
+
+fn parse_pools(res: &str) -> Vec<PoolConfig> {
+    cgminer::parse(res)
+        .sections("POOL")
+        .map(|pool| PoolConfig {
+            url: pool.get("URL").unwrap_or("").to_string(),
+            user: pool.get("User").unwrap_or("").to_string(),
+            password: "".into(),
+        })
+        .collect()
+}
 
-    Ok(pools)
+// accepted, rejected and stale percent over all pools
+fn parse_shares(res: &str) -> (f64, f64, f64) {
+    let reply = cgminer::parse(res);
+    let sum = |key: &str| -> f64 {
+        reply
+            .sections("POOL")
+            .filter_map(|pool| pool.number(key))
+            .sum()
+    };
+    share_pct(sum("Accepted"), sum("Rejected"), sum("Stale"))
 }
 
 /// update pool
@@ -367,17 +595,23 @@ fn tcp_write_pool(ip: &str, pool: &Account, timeout_seconds: i64) -> Result<(),
     // ascset|0,setpool,root,root,2,stratum+tcp://btc.ss.poolin.com:443,cctrix.001,123
     let pool1 = format!(
         "ascset|0,setpool,root,root,0,{},{},{}",
-        pool.pool1, pool.name, pool.password
+        pool.pool1,
+        pool.name,
+        pool.password.expose()
     );
 
     let pool2 = format!(
         "ascset|0,setpool,root,root,1,{},{},{}",
-        pool.pool2, pool.name, pool.password
+        pool.pool2,
+        pool.name,
+        pool.password.expose()
     );
 
     let pool3 = format!(
         "ascset|0,setpool,root,root,2,{},{},{}",
-        pool.pool3, pool.name, pool.password
+        pool.pool3,
+        pool.name,
+        pool.password.expose()
     );
 
     tcp_cmd(ip, 4028, &pool1, true, timeout_seconds)?;
@@ -395,7 +629,10 @@ fn tcp_write_pool_config(
     for (i, pool) in pools.iter().enumerate() {
         let cmd = format!(
             "ascset|0,setpool,root,root,{},{},{},{}",
-            i, pool.url, pool.user, pool.password
+            i,
+            pool.url,
+            pool.user,
+            pool.password.expose()
         );
         tcp_cmd(ip, 4028, &cmd, true, timeout_seconds)?;
     }
@@ -403,6 +640,15 @@ fn tcp_write_pool_config(
     Ok(())
 }
 
+// 1 is the high performance work mode
+fn work_mode_of(run_mode: &str) -> i32 {
+    if RunMode::of(run_mode) == RunMode::High {
+        1
+    } else {
+        0
+    }
+}
+
 fn tcp_write_workmode(ip: &str, mode: i32, timeout_seconds: i64) -> Result<(), MinerError> {
     // ascset|0,workmode,1
     let cmd = format!("ascset|0,workmode,{}", mode);
@@ -410,65 +656,135 @@ fn tcp_write_workmode(ip: &str, mode: i32, timeout_seconds: i64) -> Result<(), M
     Ok(())
 }
 
+/// soft power on or off, hashing stops but the controller stays reachable
+fn tcp_write_power(ip: &str, on: bool, timeout_seconds: i64) -> Result<(), MinerError> {
+    // ascset|0,softoff,1:1715731200
+    let cmd = format!(
+        "ascset|0,{},1:{}",
+        if on { "softon" } else { "softoff" },
+        chrono::Local::now().timestamp()
+    );
+    tcp_cmd(ip, 4028, &cmd, true, timeout_seconds)?;
+    Ok(())
+}
+
 fn tcp_query_status(ip: &str, timeout_seconds: i64) -> Result<AvalonWorkStatus, MinerError> {
     let res = tcp_cmd(ip, 4028, "estats", true, timeout_seconds)?;
     //info!("avalon tcp_query_status result: {}", res);
-    let mut work: AvalonWorkStatus = AvalonWorkStatus::default();
-    // SYSTEMSTATU[Work: In Work, Hash Board: 3 ] ... Elapsed[1697]
-    let re = Regex::new(
-        r"SYSTEMSTATU\[Work: (.*),.*Elapsed\[(\d+)\].*Temp\[(-?\d+)\].*GHSspd\[(\d+\.?\d*)\].**GHSavg\[(\d+\.?\d*)\].*MTavg\[(-?\d+ -?\d+ -?\d+)\].*WORKMODE\[(\d+)\]",
-    )
-    .unwrap();
-    match re.captures(&res) {
-        Some(caps) => {
-            work.work_status = caps.get(1).map_or("", |m| m.as_str()).to_string();
-            work.elapsed = caps
-                .get(2)
-                .map_or(0, |m| m.as_str().parse::<i64>().unwrap());
-            work.temp = caps
-                .get(3)
-                .map_or(0.0, |m| m.as_str().parse::<f64>().unwrap());
-            work.hash_real = caps
-                .get(4)
-                .map_or(0.0, |m| m.as_str().parse::<f64>().unwrap());
-            work.hash_avg = caps
-                .get(5)
-                .map_or(0.0, |m| m.as_str().parse::<f64>().unwrap());
-            work.tavg = caps.get(6).map_or("", |m| m.as_str()).to_string();
-            work.work_mode = caps
-                .get(7)
-                .map_or(0, |m| m.as_str().parse::<i32>().unwrap());
-        }
-        None => return Err(MinerError::ReadAvalonConfigError),
+    parse_status(&res)
+}
+
+// SYSTEMSTATU[Work: In Work, Hash Board: 3 ] ... Elapsed[1697] ... WORKMODE[1]
+// fields are looked up one by one, newer firmwares add and reorder them
+fn parse_status(res: &str) -> Result<AvalonWorkStatus, MinerError> {
+    let reply = cgminer::parse(res);
+    if reply.sections("STATS").next().is_none() {
+        return Err(MinerError::ReadAvalonConfigError);
     }
+    let mut warnings = Vec::new();
+    let mut number =
+        |key: &str| or_warn(reply.bracket_number(key), key, &mut warnings).unwrap_or(0.0);
+    let elapsed = number("Elapsed") as i64;
+    let hash_real = number("GHSspd");
+    let hash_avg = number("GHSavg");
+    let temp = number("Temp");
+    let tavg = or_warn(reply.bracket("MTavg"), "MTavg", &mut warnings)
+        .unwrap_or("")
+        .to_string();
+    let system_status = reply.bracket("SYSTEMSTATU").unwrap_or("");
+    let work_status = system_status
+        .strip_prefix("Work:")
+        .and_then(|status| status.split(',').next())
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    let boards = system_status
+        .split(',')
+        .find_map(|part| part.trim().strip_prefix("Hash Board:"))
+        .and_then(|n| n.trim().parse::<u32>().ok())
+        .unwrap_or(tavg.split_whitespace().count() as u32);
+    Ok(AvalonWorkStatus {
+        elapsed,
+        hash_real,
+        hash_avg,
+        temp,
+        tavg,
+        work_status,
+        boards,
+        // older firmwares have no work modes
+        work_mode: reply.bracket_number("WORKMODE").unwrap_or(0.0) as i32,
+        hw_errors: reply.bracket_number("HW").unwrap_or(0.0) as i64,
+        warnings,
+    })
+}
 
-    Ok(work)
+/// avalon generations, the PS[] layout of the power reply differs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AvalonModel {
+    /// A10 to A12, PS[err control hash amperage power hash_out]
+    A12,
+    /// A13, PS[err control hash amperage power hash_out power_limit]
+    A13,
+    /// A14 and later, PS[err control hash hash_out amperage power power_limit]
+    A14,
+    /// Nano home miners, single board without PS[]
+    Nano,
 }
 
-fn tcp_query_power(ip: &str, timeout_seconds: i64) -> Result<AvalonPowerStatus, MinerError> {
-    let res = tcp_cmd(ip, 4028, "ascset|0,hashpower", true, timeout_seconds)?;
-    let mut power = AvalonPowerStatus::default();
-    // extract PS[0 1196 1284 230 2953 1284] from res
-    let re = Regex::new(r"PS\[(\d+) (\d+) (\d+) (\d+) (\d+) (\d+)\]").unwrap();
-    match re.captures(&res) {
-        Some(caps) => {
-            power.control_board_volt = caps
-                .get(2)
-                .map_or(0.0, |m| m.as_str().parse::<f64>().unwrap());
-            power.hash_board_volt = caps
-                .get(3)
-                .map_or(0.0, |m| m.as_str().parse::<f64>().unwrap());
-            power.amperage = caps
-                .get(4)
-                .map_or(0.0, |m| m.as_str().parse::<f64>().unwrap());
-            power.power = caps
-                .get(5)
-                .map_or(0.0, |m| m.as_str().parse::<f64>().unwrap());
+impl AvalonModel {
+    /// from MODEL of the version reply, e.g. "1246-83", "1366", "Nano3s"
+    pub fn from_model(model: &str) -> AvalonModel {
+        if model.to_lowercase().contains("nano") {
+            return AvalonModel::Nano;
+        }
+        let series = model
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .take(2)
+            .collect::<String>()
+            .parse::<u32>()
+            .unwrap_or(0);
+        match series {
+            13 => AvalonModel::A13,
+            s if s >= 14 => AvalonModel::A14,
+            _ => AvalonModel::A12,
+        }
+    }
+
+    /// hash boards of the model
+    pub fn boards(&self) -> u32 {
+        match self {
+            AvalonModel::Nano => 1,
+            _ => 3,
         }
-        None => return Err(MinerError::ReadAvalonConfigError),
     }
 
-    Ok(power)
+    pub fn power_status(&self, ps: &[f64]) -> AvalonPowerStatus {
+        let (control, hash, amperage, power) = match self {
+            AvalonModel::A12 | AvalonModel::A13 => (1, 2, 3, 4),
+            AvalonModel::A14 => (1, 2, 4, 5),
+            AvalonModel::Nano => return AvalonPowerStatus::default(),
+        };
+        let at = |i: usize| ps.get(i).copied().unwrap_or(0.0);
+        AvalonPowerStatus {
+            control_board_volt: at(control),
+            hash_board_volt: at(hash),
+            amperage: at(amperage),
+            power: at(power),
+        }
+    }
+}
+
+/// values of PS[0 1196 1284 230 2953 1284], empty when the model does not report them
+fn parse_ps(res: &str) -> Vec<f64> {
+    cgminer::parse(res)
+        .bracket("PS")
+        .map(|ps| {
+            ps.split_whitespace()
+                .filter_map(|v| v.parse::<f64>().ok())
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 /// reboot machine
@@ -477,21 +793,6 @@ fn tcp_write_reboot(ip: &str, timeout_seconds: i64) -> Result<(), MinerError> {
     Ok(())
 }
 
-fn try_ping(ip: &str) -> Result<bool, MinerError> {
-    let addr = ip.parse().unwrap();
-    let data = [1, 2, 3, 4]; // ping data
-    let timeout = Duration::from_secs(1);
-    let options = ping_rs::PingOptions {
-        ttl: 128,
-        dont_fragment: true,
-    };
-    let result = ping_rs::send_ping(&addr, timeout, &data, Some(&options));
-    match result {
-        Ok(_reply) => Ok(true),
-        Err(_e) => Err(MinerError::PingFiledError),
-    }
-}
-
 //test
 #[cfg(test)]
 mod tests {
@@ -505,7 +806,53 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_model_fixtures() {
+        // file, model, work mode, tavg, power
+        let cases = [
+            ("avalon-1246.json", AvalonModel::A12, 1, "79 78 79", 2957.0),
+            ("avalon-1366.json", AvalonModel::A13, 1, "77 76 78", 3380.0),
+            ("avalon-1466.json", AvalonModel::A14, 0, "73 72 74", 3577.0),
+            ("avalon-nano3s.json", AvalonModel::Nano, 2, "64", 0.0),
+        ];
+        for (file, model, work_mode, tavg, power) in cases {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("fixtures")
+                .join(file);
+            let fixture = crate::miner::capture::load(&path).unwrap();
+            let version = cgminer::parse(&fixture.api["version"]);
+            let machine_type = version.get("MODEL").unwrap();
+            assert_eq!(AvalonModel::from_model(machine_type), model, "{}", file);
+            let mac = inventory::normalize_mac(version.get("MAC").unwrap_or(""));
+            assert_eq!(mac.len(), 17, "{}", file);
+
+            let status = parse_status(&fixture.api["estats"]).unwrap();
+            assert_eq!(status.work_mode, work_mode, "{}", file);
+            assert_eq!(status.tavg, tavg, "{}", file);
+            assert!(status.hash_avg > 0.0, "{}", file);
+            assert!(status.warnings.is_empty(), "{}", file);
+            assert_eq!(status.boards, model.boards(), "{}", file);
+
+            let ps = parse_ps(&fixture.api["ascset|0,hashpower"]);
+            assert_eq!(model.power_status(&ps).power, power, "{}", file);
+        }
+        let fixture = crate::miner::capture::load(
+            &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/avalon-1246.json"),
+        )
+        .unwrap();
+        let (accepted, rejected, stale) = parse_shares(&fixture.api["pools"]);
+        assert!((rejected - 12.0 * 100.0 / 4833.0).abs() < 0.0001);
+        assert!((accepted + rejected + stale - 100.0).abs() < 0.0001);
+        assert_eq!(parse_pools(&fixture.api["pools"]).len(), 3);
+        let partial = parse_status("STATS=0,ID=AVA100,MM ID0=Elapsed[12] GHSavg[8.1.2]|").unwrap();
+        assert_eq!(partial.elapsed, 12);
+        assert_eq!(partial.hash_avg, 0.0);
+        assert!(partial.warnings.iter().any(|w| w.starts_with("GHSavg")));
+        assert!(parse_status("STATUS=E,Code=14,Msg=Invalid command|").is_err());
+    }
+
     #[tokio::test]
+    #[ignore = "needs an avalon on the lab network"]
     async fn avalon_test_get_config() {
         let _ = *SETUP;
         let ip = "192.168.187.170";
@@ -515,13 +862,14 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs an avalon on the lab network"]
     async fn avalon_test_update_config() {
         let _ = *SETUP;
         let ip = "192.168.189.162";
         let account = Account {
             id: 1i32,
             name: "sl002".to_string(),
-            password: "1212".to_string(),
+            password: "1212".into(),
             pool1: "stratum+tcp://192.168.190.8:9011".to_string(),
             pool2: "stratum+tcp://192.168.190.9:9011".to_string(),
             pool3: "stratum+tcp://192.168.190.8:9011".to_string(),
@@ -554,6 +902,7 @@ mod tests {
     // }
 
     #[tokio::test]
+    #[ignore = "needs an avalon on the lab network"]
     async fn avalon_test_reboot() {
         let _ = *SETUP;
         let ip = "192.168.189.207";
@@ -563,6 +912,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs an avalon on the lab network"]
     async fn avalon_test_query() {
         let _ = *SETUP;
         let ip = "192.168.189.207";
@@ -572,6 +922,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs an avalon on the lab network"]
     fn avalon_tcp_query_version() {
         let _ = *SETUP;
         let ip = "192.168.187.186";
@@ -581,6 +932,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs an avalon on the lab network"]
     fn avalon_tcp_cmd_reboot() {
         let _ = *SETUP;
         let ip = "192.168.189.213";
@@ -589,6 +941,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs an avalon on the lab network"]
     fn avalon_tcp_query_account() {
         let _ = *SETUP;
         let ip = "192.168.189.212";
@@ -598,6 +951,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs an avalon on the lab network"]
     fn avalon_tcp_query_pool() {
         let _ = *SETUP;
         let ip = "192.168.189.212";
@@ -607,13 +961,14 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs an avalon on the lab network"]
     fn avalon_tcp_write_pool() {
         let _ = *SETUP;
         let ip = "192.168.187.186";
         let account = Account {
             id: 1i32,
             name: "sl002".to_string(),
-            password: "1212".to_string(),
+            password: "1212".into(),
             pool1: "stratum+tcp://192.168.190.9:9011".to_string(),
             pool2: "stratum+tcp://192.168.190.8:9011".to_string(),
             pool3: "stratum+tcp://192.168.190.8:9011".to_string(),
@@ -624,6 +979,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs an avalon on the lab network"]
     fn avalon_tcp_query_status() {
         let _ = *SETUP;
         let ip = "192.168.188.22";
@@ -633,11 +989,12 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs an avalon on the lab network"]
     fn avalon_tcp_query_power() {
         let _ = *SETUP;
         let ip = "192.168.189.170";
-        let res = tcp_query_power(ip, 3).unwrap();
-        info!("avalon tcp_query_power result: {:?}", res);
+        let res = parse_ps(&tcp_cmd(ip, 4028, "ascset|0,hashpower", true, 3).unwrap());
+        info!("avalon power result: {:?}", res);
         assert!(true);
     }
 }
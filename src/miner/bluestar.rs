@@ -32,19 +32,49 @@ impl MinerOperation for BlueStarMiner {
         todo!()
     }
 
-    fn reboot(&self, _ip: &str) -> Result<(), MinerError> {
+    fn reboot(&self, _ip: &str, _timeout_seconds: i64) -> Result<(), MinerError> {
         todo!()
     }
 
-    fn config_pool(&self, _ip: &str, _pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
+    fn config_pool(
+        &self,
+        _ip: &str,
+        _pools: &[PoolConfig],
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
         todo!()
     }
 
-    fn config_mode(&self, _ip: &str, _mode: &str) -> Result<(), MinerError> {
+    fn config_mode(&self, _ip: &str, _mode: &str, _timeout_seconds: i64) -> Result<(), MinerError> {
         todo!()
     }
 
-    fn config(&self, _ip: &str, _mode: &str, _pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
+    fn config_fan(
+        &self,
+        _ip: &str,
+        _pwm: Option<u32>,
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        Err(MinerError::MinerNotSupportError)
+    }
+
+    fn config_tuning(
+        &self,
+        _ip: &str,
+        _freq: Option<u32>,
+        _voltage: Option<u32>,
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        Err(MinerError::MinerNotSupportError)
+    }
+
+    fn config(
+        &self,
+        _ip: &str,
+        _mode: &str,
+        _pools: &[PoolConfig],
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
         todo!()
     }
 }
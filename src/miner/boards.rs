@@ -0,0 +1,93 @@
+/// missing hash board watch, a board that stops being detected keeps the machine hashing
+/// at a fraction of its rate without any error
+use std::collections::HashMap;
+
+use log::info;
+
+use super::entry::MachineInfo;
+use super::maintenance;
+use crate::clock;
+use crate::context::Local;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+
+static BOARDS: Local<BoardWatch> = Local::new(BoardWatch::default);
+
+#[derive(Debug, Default)]
+pub struct BoardWatch {
+    /// active boards of the last poll
+    active: HashMap<String, u32>,
+}
+
+impl BoardWatch {
+    /// record one poll, true when a board went missing since the last one, or already
+    /// was on the first poll
+    pub fn check(&mut self, ip: &str, expected: u32, active: u32) -> bool {
+        if expected == 0 {
+            return false;
+        }
+        let last = self.active.insert(ip.to_string(), active);
+        active < expected && last.is_none_or(|last| active < last)
+    }
+}
+
+/// check polled machines and notify the ones that lost a board
+pub async fn apply(machines: &[MachineInfo]) {
+    let missing = BOARDS.with(|watch| {
+        machines
+            .iter()
+            .filter(|m| !maintenance::is_in_maintenance(&m.ip))
+            .filter(|m| watch.check(&m.ip, m.boards_expected, m.boards_active))
+            .map(|m| AlertMachine {
+                ip: m.ip.clone(),
+                detail: template::render(
+                    "boards.detail",
+                    &[
+                        ("model", &m.machine_type),
+                        ("active", &m.boards_active),
+                        ("expected", &m.boards_expected),
+                    ],
+                ),
+            })
+            .collect::<Vec<AlertMachine>>()
+    });
+    if missing.is_empty() {
+        return;
+    }
+    info!("boards missing on {} machines", missing.len());
+
+    notifier::send_alert(&Alert {
+        kind: "boards".to_string(),
+        title: template::render(
+            "boards.title",
+            &[
+                ("time", &clock::now().format("%H:%M:%S")),
+                ("count", &missing.len()),
+            ],
+        ),
+        severity: Severity::Critical,
+        content: "".to_string(),
+        machines: missing,
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_board_missing() {
+        let mut watch = BoardWatch::default();
+        let ip = "192.168.188.41";
+
+        assert!(!watch.check(ip, 3, 3));
+        assert!(watch.check(ip, 3, 2));
+        // alerted once while it stays missing
+        assert!(!watch.check(ip, 3, 2));
+        assert!(watch.check(ip, 3, 1));
+        assert!(!watch.check(ip, 3, 3));
+        assert!(!watch.check(ip, 0, 0));
+
+        assert!(watch.check("192.168.188.42", 3, 2));
+    }
+}
@@ -0,0 +1,173 @@
+/// raw replies of real miners saved as fixtures, one json file per ip, so the parsing can be
+/// tested later against firmware no longer on hand, replayed by the mock feature. also kept
+/// in MachineInfo.raw when asked, for vendor fields the drivers do not parse
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::MinerError;
+use crate::secret;
+
+lazy_static! {
+    // the lock also serializes the read-modify-write of the fixture files
+    static ref DIR: Mutex<String> = Mutex::new(String::new());
+    static ref KEEP_RAW: Mutex<bool> = Mutex::new(false);
+    // latest replies per ip until the query takes them into MachineInfo.raw
+    static ref RAW: Mutex<HashMap<String, HashMap<String, String>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    /// web path, e.g. "/cgi-bin/stats.cgi", to the response body
+    #[serde(default)]
+    pub web: BTreeMap<String, String>,
+    /// cgminer api command, e.g. "estats", to the reply without the NUL terminator
+    #[serde(default)]
+    pub api: BTreeMap<String, String>,
+}
+
+/// directory the fixtures are written to, empty disables capturing
+pub fn set_dir(dir: &str) {
+    *DIR.lock().unwrap() = dir.to_string();
+}
+
+/// keep the replies of each query in MachineInfo.raw
+pub fn set_keep_raw(keep: bool) {
+    *KEEP_RAW.lock().unwrap() = keep;
+    if !keep {
+        RAW.lock().unwrap().clear();
+    }
+}
+
+/// replies recorded for the ip since the last take, empty unless keep_raw is set
+pub(crate) fn take_raw(ip: &str) -> HashMap<String, String> {
+    RAW.lock().unwrap().remove(ip).unwrap_or_default()
+}
+
+pub fn load(path: &Path) -> Result<Fixture, MinerError> {
+    let data = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+fn fixture_path(dir: &str, ip: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.json", ip))
+}
+
+#[cfg_attr(not(feature = "ant-http"), allow(dead_code))]
+pub(crate) fn record_web(ip: &str, path: &str, body: &str) {
+    let body = mask_conf(body);
+    keep_raw(ip, path, &body);
+    record(ip, |fixture| {
+        fixture.web.insert(path.to_string(), body);
+    });
+}
+
+pub(crate) fn record_api(ip: &str, command: &str, reply: &str) {
+    // setpool carries the pool password in the command itself
+    if command.contains(",setpool,") {
+        return;
+    }
+    let reply = reply.trim_end_matches('\0');
+    keep_raw(ip, command, reply);
+    record(ip, |fixture| {
+        fixture.api.insert(command.to_string(), reply.to_string());
+    });
+}
+
+fn keep_raw(ip: &str, key: &str, reply: &str) {
+    if !*KEEP_RAW.lock().unwrap() {
+        return;
+    }
+    RAW.lock()
+        .unwrap()
+        .entry(ip.to_string())
+        .or_default()
+        .insert(key.to_string(), reply.to_string());
+}
+
+fn record(ip: &str, update: impl FnOnce(&mut Fixture)) {
+    let dir = DIR.lock().unwrap();
+    if dir.is_empty() {
+        return;
+    }
+    let path = fixture_path(&dir, ip);
+    let mut fixture = load(&path).unwrap_or_default();
+    update(&mut fixture);
+    let result = std::fs::create_dir_all(dir.as_str())
+        .map_err(MinerError::from)
+        .and_then(|_| Ok(serde_json::to_string_pretty(&fixture)?))
+        .and_then(|data| Ok(std::fs::write(&path, data)?));
+    if let Err(e) = result {
+        error!("capture {} error: {:?}", ip, e);
+    }
+}
+
+// pool passwords of the ant config json are not kept
+#[cfg_attr(not(feature = "ant-http"), allow(dead_code))]
+fn mask_conf(body: &str) -> String {
+    let Ok(mut json) = serde_json::from_str::<Value>(body) else {
+        return body.to_string();
+    };
+    let Some(pools) = json["pools"].as_array_mut() else {
+        return body.to_string();
+    };
+    for pool in pools.iter_mut() {
+        if let Some(pass) = pool.get_mut("pass") {
+            *pass = Value::from(secret::redact(pass.as_str().unwrap_or("")));
+        }
+    }
+    json.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_fixture() {
+        let dir = std::env::temp_dir().join(format!("lcd-capture-{}", std::process::id()));
+        set_dir(&dir.to_string_lossy());
+        record_web(
+            "10.253.0.1",
+            "/cgi-bin/get_miner_conf.cgi",
+            r#"{"pools":[{"url":"btc.f2pool.com:1314","user":"a.1x1","pass":"hunter2"}]}"#,
+        );
+        record_api("10.253.0.1", "estats", "STATS=0,Elapsed=12|\0");
+        record_api(
+            "10.253.0.1",
+            "ascset|0,setpool,root,root,0,url,a,hunter2",
+            "OK\0",
+        );
+        set_dir("");
+        record_api("10.253.0.1", "pools", "POOL=0|\0");
+
+        let fixture = load(&fixture_path(&dir.to_string_lossy(), "10.253.0.1")).unwrap();
+        assert!(!fixture.web["/cgi-bin/get_miner_conf.cgi"].contains("hunter2"));
+        assert_eq!(fixture.api["estats"], "STATS=0,Elapsed=12|");
+        assert_eq!(fixture.api.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_keep_raw() {
+        record_api("10.253.0.2", "estats", "STATS=0,Elapsed=12|\0");
+        assert!(take_raw("10.253.0.2").is_empty());
+
+        set_keep_raw(true);
+        record_api("10.253.0.2", "estats", "STATS=0,Elapsed=12|\0");
+        record_web(
+            "10.253.0.2",
+            "/cgi-bin/get_miner_conf.cgi",
+            r#"{"pools":[{"url":"btc.f2pool.com:1314","user":"a.1x1","pass":"hunter2"}]}"#,
+        );
+        let raw = take_raw("10.253.0.2");
+        set_keep_raw(false);
+        assert_eq!(raw["estats"], "STATS=0,Elapsed=12|");
+        assert!(!raw["/cgi-bin/get_miner_conf.cgi"].contains("hunter2"));
+        assert!(take_raw("10.253.0.2").is_empty());
+    }
+}
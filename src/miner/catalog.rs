@@ -0,0 +1,172 @@
+/// characteristics of the miner models, nominal hashrate, power per mode and the supported
+/// modes. looked up by a fragment of the model string of query, e.g. "S19j Pro" in
+/// "Antminer S19j Pro" or "1246" in "MODEL=1246", configured specs before the built in ones
+use serde::{Deserialize, Serialize};
+
+use super::mode::RunMode;
+
+use crate::context::Local;
+
+static SPECS: Local<Vec<ModelSpec>> = Local::new(Vec::new);
+
+// reported hashrate above the nominal one by this much is a broken reading, e.g. MH/s
+// reported as GH/s
+const MAX_HASH_RATIO: f64 = 1.5;
+// power share 高功 adds
+const HIGH_EXTRA: f64 = 0.15;
+// control board and fans of a sleeping miner
+const SLEEP_POWER: f64 = 15.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSpec {
+    /// fragment of the model string, compared ignoring case
+    pub model: String,
+    /// TH/s in 普通
+    pub hashrate: f64,
+    /// watts in 普通
+    pub power_normal: f64,
+    /// watts in 高功, 0 without it
+    #[serde(default)]
+    pub power_high: f64,
+    /// watts sleeping
+    #[serde(default)]
+    pub power_sleep: f64,
+    pub modes: Vec<RunMode>,
+}
+
+impl ModelSpec {
+    /// J/TH in 普通
+    pub fn efficiency(&self) -> f64 {
+        if self.hashrate > 0.0 {
+            self.power_normal / self.hashrate
+        } else {
+            0.0
+        }
+    }
+
+    pub fn supports(&self, mode: RunMode) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    /// watts in the mode, 普通 for a mode it does not have
+    pub fn power(&self, mode: RunMode) -> f64 {
+        match mode {
+            RunMode::High if self.power_high > 0.0 => self.power_high,
+            RunMode::Sleep => self.power_sleep,
+            _ => self.power_normal,
+        }
+    }
+}
+
+// model fragment, TH/s and watts in 普通
+const BUILT_IN: &[(&str, f64, f64)] = &[
+    ("S21 Hyd", 335.0, 5360.0),
+    ("S21 Pro", 234.0, 3510.0),
+    ("S21", 200.0, 3500.0),
+    ("T21", 190.0, 3610.0),
+    ("S19 XP Hyd", 255.0, 5304.0),
+    ("S19 XP", 140.0, 3010.0),
+    ("S19 Pro Hyd", 177.0, 5221.0),
+    ("S19 Hydro", 158.0, 5451.0),
+    ("S19k Pro", 120.0, 2760.0),
+    ("S19j Pro", 100.0, 2950.0),
+    ("S19 Pro", 110.0, 3245.0),
+    ("S19", 95.0, 3277.5),
+    ("T19", 84.0, 3150.0),
+    ("S17", 56.0, 2520.0),
+    ("1466", 150.0, 3225.0),
+    ("1366", 130.0, 3250.0),
+    ("1346", 110.0, 3300.0),
+    ("1246", 90.0, 3420.0),
+];
+
+fn built_in(model: &str, hashrate: f64, power: f64) -> ModelSpec {
+    ModelSpec {
+        model: model.to_string(),
+        hashrate,
+        power_normal: power,
+        power_high: power * (1.0 + HIGH_EXTRA),
+        power_sleep: SLEEP_POWER,
+        modes: vec![RunMode::High, RunMode::Normal, RunMode::Sleep],
+    }
+}
+
+/// specs of the site, e.g. newer models or a custom firmware without 高功
+pub fn set_specs(specs: Vec<ModelSpec>) {
+    SPECS.set(specs);
+}
+
+// the longest fragment wins, "S19 XP Hyd" is not an "S19 XP"
+fn find(specs: &[ModelSpec], machine_type: &str) -> Option<ModelSpec> {
+    let machine_type = machine_type.to_lowercase();
+    let matches = |model: &str| !model.is_empty() && machine_type.contains(&model.to_lowercase());
+    specs
+        .iter()
+        .filter(|s| matches(&s.model))
+        .max_by_key(|s| s.model.len())
+        .cloned()
+        .or_else(|| {
+            BUILT_IN
+                .iter()
+                .filter(|(model, _, _)| matches(model))
+                .max_by_key(|(model, _, _)| model.len())
+                .map(|(model, hashrate, power)| built_in(model, *hashrate, *power))
+        })
+}
+
+/// spec of the model string of query, None for an unknown model
+pub fn lookup(machine_type: &str) -> Option<ModelSpec> {
+    SPECS.with(|specs| find(specs, machine_type))
+}
+
+/// the problem of a reported hashrate in GH/s, None when plausible or the model is unknown
+pub fn check_hashrate(machine_type: &str, hash: f64) -> Option<String> {
+    let spec = lookup(machine_type)?;
+    let max = spec.hashrate * MAX_HASH_RATIO;
+    if hash / 1000.0 > max {
+        Some(format!(
+            "{:.2} THS above {:.0} THS of {}",
+            hash / 1000.0,
+            max,
+            spec.model
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_specs() {
+        assert_eq!(find(&[], "Antminer S19j Pro").unwrap().model, "S19j Pro");
+        assert_eq!(find(&[], "MODEL=1246").unwrap().hashrate, 90.0);
+        assert!((find(&[], "Antminer S19").unwrap().efficiency() - 34.5).abs() < 0.001);
+        assert_eq!(find(&[], "unknown"), None);
+        assert_eq!(find(&[], "Antminer S19 XP Hyd").unwrap().hashrate, 255.0);
+        assert_eq!(find(&[], "Antminer S19 XP").unwrap().hashrate, 140.0);
+        assert_eq!(find(&[], "Antminer S19 Hydro").unwrap().model, "S19 Hydro");
+        assert_eq!(
+            find(&[], "Antminer S19 Pro Hyd.").unwrap().model,
+            "S19 Pro Hyd"
+        );
+        assert_eq!(find(&[], "Antminer S21 Hyd.").unwrap().model, "S21 Hyd");
+
+        let specs: Vec<ModelSpec> = serde_json::from_str(
+            r#"[{"model": "S19j Pro+", "hashrate": 122, "power_normal": 3355,
+                 "modes": ["Normal", "Sleep"]}]"#,
+        )
+        .unwrap();
+        let spec = find(&specs, "Antminer S19j Pro+").unwrap();
+        assert!(!spec.supports(RunMode::High));
+        assert_eq!(spec.power(RunMode::High), 3355.0);
+        assert_eq!(spec.power(RunMode::Sleep), 0.0);
+        assert_eq!(find(&specs, "Antminer S19j Pro").unwrap().hashrate, 100.0);
+
+        assert_eq!(check_hashrate("Antminer S19", 95000.0), None);
+        assert!(check_hashrate("Antminer S19", 95000000.0).is_some());
+        assert_eq!(check_hashrate("unknown", 95000000.0), None);
+    }
+}
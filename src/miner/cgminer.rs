@@ -0,0 +1,170 @@
+// text replies of the cgminer api on port 4028, e.g.
+// STATUS=S,When=1709018811,Code=7,Msg=3 Pool(s),Description=cgminer 4.11.1|POOL=0,URL=...,User=...|
+// sections are separated by '|', fields by ',', and estats packs its values as KEY[value]
+use std::collections::BTreeMap;
+
+/// one section of the reply, e.g. the STATUS part or one POOL=n part
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Section {
+    /// first key of the section, e.g. "STATUS", "POOL", "VERSION", "STATS"
+    pub name: String,
+    pub fields: BTreeMap<String, String>,
+    /// KEY[value] entries found inside the field values, the first one wins on duplicates
+    pub brackets: BTreeMap<String, String>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(|v| v.as_str())
+    }
+
+    pub fn number(&self, key: &str) -> Option<f64> {
+        self.get(key).and_then(|v| v.trim().parse().ok())
+    }
+
+    pub fn bracket(&self, key: &str) -> Option<&str> {
+        self.brackets.get(key).map(|v| v.as_str())
+    }
+
+    pub fn bracket_number(&self, key: &str) -> Option<f64> {
+        self.bracket(key).and_then(|v| v.parse().ok())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reply {
+    pub sections: Vec<Section>,
+}
+
+impl Reply {
+    pub fn status(&self) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == "STATUS")
+    }
+
+    /// STATUS=S or STATUS=I, E and F are failures
+    pub fn is_ok(&self) -> bool {
+        self.status()
+            .and_then(|s| s.get("STATUS"))
+            .map(|status| status == "S" || status == "I")
+            .unwrap_or(false)
+    }
+
+    /// sections of one kind, e.g. sections("POOL") in pool order
+    pub fn sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Section> {
+        self.sections.iter().filter(move |s| s.name == name)
+    }
+
+    /// first field of that key in any section
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.sections.iter().find_map(|s| s.get(key))
+    }
+
+    /// first KEY[value] of that key in any section
+    pub fn bracket(&self, key: &str) -> Option<&str> {
+        self.sections.iter().find_map(|s| s.bracket(key))
+    }
+
+    pub fn bracket_number(&self, key: &str) -> Option<f64> {
+        self.bracket(key).and_then(|v| v.parse().ok())
+    }
+}
+
+pub fn parse(text: &str) -> Reply {
+    let text = text.trim_end_matches('\0');
+    let sections = split_outside(text, '|')
+        .into_iter()
+        .filter(|part| !part.trim().is_empty())
+        .map(parse_section)
+        .collect();
+    Reply { sections }
+}
+
+fn parse_section(text: &str) -> Section {
+    let mut section = Section::default();
+    for field in split_outside(text, ',') {
+        let (key, value) = field.split_once('=').unwrap_or((field, ""));
+        let key = key.trim();
+        if section.name.is_empty() {
+            section.name = key.to_string();
+        }
+        for (name, inner) in brackets(value) {
+            section.brackets.entry(name).or_insert(inner);
+        }
+        section
+            .fields
+            .entry(key.to_string())
+            .or_insert(value.to_string());
+    }
+    section
+}
+
+// separators inside [...] belong to the value, e.g. SYSTEMSTATU[Work: In Work, Hash Board: 3 ]
+fn split_outside(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            c if c == separator && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+// Ver[1366-N] DNA[0201] Nonce Mask[25], the name is the text since the previous ']'
+// without a leading "label: " as in Msg=ASC 0 set info: PS[0 1212 1318]
+fn brackets(value: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut rest = value;
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find(']').map(|i| open + i) else {
+            break;
+        };
+        let name = rest[..open].rsplit(": ").next().unwrap_or("").trim();
+        if !name.is_empty() {
+            entries.push((name.to_string(), rest[open + 1..close].trim().to_string()));
+        }
+        rest = &rest[close + 1..];
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = parse("STATUS=S,When=1,Code=7,Msg=2 Pool(s),Description=cgminer 4.11.1|POOL=0,URL=stratum+tcp://a:443,User=w.1x1|POOL=1,URL=stratum+tcp://b:443,User=w.1x1|\0");
+        assert!(reply.is_ok());
+        let urls = reply
+            .sections("POOL")
+            .filter_map(|s| s.get("URL"))
+            .collect::<Vec<_>>();
+        assert_eq!(urls, ["stratum+tcp://a:443", "stratum+tcp://b:443"]);
+
+        let reply = parse("STATUS=S|STATS=0,Elapsed=12,MM ID0=Ver[1246-83] SYSTEMSTATU[Work: In Work, Hash Board: 3 ] MTavg[79 78 79] Nonce Mask[25] ITemp[30] Temp[33],MM Count=1|");
+        assert_eq!(reply.get("MM Count"), Some("1"));
+        assert_eq!(reply.get("Elapsed"), Some("12"));
+        assert_eq!(
+            reply.bracket("SYSTEMSTATU"),
+            Some("Work: In Work, Hash Board: 3")
+        );
+        assert_eq!(reply.bracket("MTavg"), Some("79 78 79"));
+        assert_eq!(reply.bracket("Nonce Mask"), Some("25"));
+        assert_eq!(reply.bracket_number("Temp"), Some(33.0));
+
+        let reply =
+            parse("STATUS=I,Msg=ASC 0 set info: PS[0 1212 1318 256 3380],Description=cgminer|");
+        assert_eq!(reply.bracket("PS"), Some("0 1212 1318 256 3380"));
+        assert!(!parse("STATUS=E,Msg=failed|").is_ok());
+        assert!(parse("").sections.is_empty());
+    }
+}
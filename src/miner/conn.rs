@@ -0,0 +1,81 @@
+/// idle cgminer api connections kept per address, reused by the next command of a batch
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use super::route;
+
+use crate::context::Local;
+
+static IDLE: Local<HashMap<SocketAddr, Vec<(TcpStream, Instant)>>> = Local::new(HashMap::new);
+static IDLE_TIMEOUT: Local<Duration> = Local::new(|| Duration::from_secs(30));
+
+/// 0 disables reuse, every command connects again
+pub fn set_idle_seconds(seconds: u64) {
+    IDLE_TIMEOUT.set(Duration::from_secs(seconds));
+    if seconds == 0 {
+        IDLE.with(|idle| idle.clear());
+    }
+}
+
+/// an idle connection of the address, or a new one. true when reused
+pub fn take(addr: &SocketAddr, timeout: Duration) -> std::io::Result<(TcpStream, bool)> {
+    let idle_timeout = IDLE_TIMEOUT.get();
+    let reused = IDLE.with(|idle| {
+        let streams = idle.get_mut(addr)?;
+        while let Some((stream, at)) = streams.pop() {
+            if at.elapsed() < idle_timeout {
+                return Some(stream);
+            }
+        }
+        None
+    });
+    match reused {
+        Some(stream) => Ok((stream, true)),
+        None => Ok((route::connect(addr, timeout)?, false)),
+    }
+}
+
+/// keep a connection whose reply was fully read
+pub fn put_back(addr: SocketAddr, stream: TcpStream) {
+    if IDLE_TIMEOUT.get().is_zero() {
+        return;
+    }
+    IDLE.with(|idle| idle.entry(addr).or_default().push((stream, Instant::now())));
+}
+
+/// drop connections idle longer than the idle timeout
+pub fn evict_idle() {
+    let idle_timeout = IDLE_TIMEOUT.get();
+    IDLE.with(|idle| {
+        for streams in idle.values_mut() {
+            streams.retain(|(_, at)| at.elapsed() < idle_timeout);
+        }
+        idle.retain(|_, streams| !streams.is_empty());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_reuse_and_evict() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (stream, reused) = take(&addr, Duration::from_secs(1)).unwrap();
+        assert!(!reused);
+        put_back(addr, stream);
+        let (stream, reused) = take(&addr, Duration::from_secs(1)).unwrap();
+        assert!(reused);
+
+        put_back(addr, stream);
+        IDLE_TIMEOUT.set(Duration::ZERO);
+        evict_idle();
+        IDLE_TIMEOUT.set(Duration::from_secs(30));
+        let (_, reused) = take(&addr, Duration::from_secs(1)).unwrap();
+        assert!(!reused);
+    }
+}
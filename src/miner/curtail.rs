@@ -0,0 +1,382 @@
+/// demand response, shed fleet power below a target and restore it afterwards
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::catalog;
+use super::entry::{config_mode_ips, MachineRecord};
+use super::group;
+use super::mode::RunMode;
+use super::power::record_power;
+use super::tag::{self, TagExpr};
+use crate::error::MinerError;
+use crate::store::cache::DbCache;
+use crate::store::db;
+use crate::tariff;
+
+// machine records older than this are not considered running
+const RECORD_MAX_AGE: i64 = 900;
+// share of power saved by leaving high performance mode, models not in the catalog
+const DOWNCLOCK_SAVING: f64 = 0.25;
+
+// kept in the db so machines curtailed before a restart are restored after it
+static CURTAILED: DbCache<Vec<CurtailStep>> = DbCache::new(load);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CurtailOrder {
+    /// worst J/TH first
+    Efficiency,
+    /// listed ips first in order, the rest by efficiency
+    Priority(Vec<String>),
+    /// machines whose tags match first, each part by efficiency
+    Tags(TagExpr),
+    /// by the priority of their group, lower first, each group by efficiency
+    GroupPriority,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CurtailAction {
+    Sleep,
+    /// 高功 to 普通, machines not in high performance mode are slept instead
+    Downclock,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurtailStrategy {
+    pub order: CurtailOrder,
+    pub action: CurtailAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurtailStep {
+    pub ip: String,
+    pub action: CurtailAction,
+    /// mode applied on restore
+    pub restore_mode: String,
+    /// estimated watts saved
+    pub saved_power: f64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CurtailResult {
+    /// estimated kW before curtailment
+    pub fleet_power_kw: f64,
+    pub target_power_kw: f64,
+    /// estimated kW after the applied steps
+    pub estimated_power_kw: f64,
+    pub steps: Vec<CurtailStep>,
+    /// ips the miner refused or did not answer
+    pub failed: Vec<String>,
+}
+
+fn efficiency(record: &MachineRecord) -> f64 {
+    if record.hash_avg > 0.0 {
+        record_power(record) / (record.hash_avg / 1000.0)
+    } else {
+        f64::MAX
+    }
+}
+
+/// pick machines until estimated power drops to the target, watts
+pub fn plan(
+    records: &[MachineRecord],
+    target_power: f64,
+    strategy: &CurtailStrategy,
+) -> Vec<CurtailStep> {
+    let mut candidates: Vec<&MachineRecord> = records.iter().collect();
+    candidates.sort_by(|a, b| efficiency(b).total_cmp(&efficiency(a)));
+    // stable sorts keep the efficiency order within a rank
+    match &strategy.order {
+        CurtailOrder::Efficiency => {}
+        CurtailOrder::Priority(ips) => {
+            let rank =
+                |r: &MachineRecord| ips.iter().position(|ip| ip == &r.ip).unwrap_or(ips.len());
+            candidates.sort_by_key(|r| rank(r));
+        }
+        CurtailOrder::Tags(expr) => candidates.sort_by_key(|r| !expr.matches(&tag::tags_of(&r.ip))),
+        CurtailOrder::GroupPriority => candidates.sort_by_key(|r| group::priority_of(&r.ip)),
+    }
+
+    let mut power: f64 = records.iter().map(record_power).sum();
+    let mut steps = vec![];
+    for record in candidates {
+        if power <= target_power {
+            break;
+        }
+        let high = record.work_mode == 1;
+        let restore_mode = if high {
+            tariff::MODE_HIGH
+        } else {
+            tariff::MODE_NORMAL
+        };
+        // a model the catalog lists without the mode is not put into it
+        let spec = catalog::lookup(&record.machine_type);
+        let supports = |mode: RunMode| spec.as_ref().is_none_or(|s| s.supports(mode));
+        let (action, saved_power) =
+            if strategy.action == CurtailAction::Downclock && high && supports(RunMode::Normal) {
+                let saving = spec
+                    .as_ref()
+                    .filter(|s| s.power_high > 0.0)
+                    .map(|s| 1.0 - s.power_normal / s.power_high)
+                    .unwrap_or(DOWNCLOCK_SAVING);
+                (CurtailAction::Downclock, record_power(record) * saving)
+            } else if supports(RunMode::Sleep) {
+                let sleeping = spec.as_ref().map(|s| s.power_sleep).unwrap_or(0.0);
+                (
+                    CurtailAction::Sleep,
+                    (record_power(record) - sleeping).max(0.0),
+                )
+            } else {
+                continue;
+            };
+        power -= saved_power;
+        steps.push(CurtailStep {
+            ip: record.ip.clone(),
+            action,
+            restore_mode: restore_mode.to_string(),
+            saved_power,
+        });
+    }
+    steps
+}
+
+/// sleep or downclock machines until estimated fleet power drops below the target
+pub async fn curtail(
+    runtime: tokio::runtime::Handle,
+    target_power_kw: f64,
+    strategy: CurtailStrategy,
+) -> Result<CurtailResult, MinerError> {
+    let since = chrono::Local::now().timestamp() - RECORD_MAX_AGE;
+    let records = db::query_latest_machine_records(since)?;
+
+    // curtailed machines keep their state and count with the power left
+    let curtailed = curtailed();
+    let (held, records): (Vec<MachineRecord>, Vec<MachineRecord>) = records
+        .into_iter()
+        .partition(|r| curtailed.iter().any(|s| s.ip == r.ip));
+    let held_power: f64 = held
+        .iter()
+        .map(|r| {
+            let saved: f64 = curtailed
+                .iter()
+                .filter(|s| s.ip == r.ip)
+                .map(|s| s.saved_power)
+                .sum();
+            (record_power(r) - saved).max(0.0)
+        })
+        .sum();
+    let fleet_power = held_power + records.iter().map(record_power).sum::<f64>();
+
+    let target = target_power_kw * 1000.0 - held_power;
+    let steps = plan(&records, target, &strategy);
+
+    info!(
+        "curtail to {} kW from {:.1} kW, {} machines, {} already curtailed",
+        target_power_kw,
+        fleet_power / 1000.0,
+        steps.len(),
+        curtailed.len()
+    );
+    let modes = steps
+        .iter()
+        .map(|s| {
+            let mode = match s.action {
+                CurtailAction::Sleep => tariff::MODE_SLEEP,
+                CurtailAction::Downclock => tariff::MODE_NORMAL,
+            };
+            (s.ip.clone(), mode.to_string())
+        })
+        .collect();
+    let failed = config_mode_ips(&runtime, modes, db::EVENT_CURTAIL).await;
+
+    let applied: Vec<CurtailStep> = steps
+        .into_iter()
+        .filter(|s| !failed.contains(&s.ip))
+        .collect();
+    let saved: f64 = applied.iter().map(|s| s.saved_power).sum();
+    for step in applied.iter() {
+        save(step);
+    }
+    CURTAILED.with(|curtailed| curtailed.extend(applied.clone()));
+
+    Ok(CurtailResult {
+        fleet_power_kw: fleet_power / 1000.0,
+        target_power_kw,
+        estimated_power_kw: (fleet_power - saved) / 1000.0,
+        steps: applied,
+        failed,
+    })
+}
+
+/// undo every curtailment, returns ips failed to restore, they are kept for the next restore
+pub async fn restore(runtime: tokio::runtime::Handle) -> Vec<String> {
+    let steps = curtailed();
+    info!("restore {} curtailed machines", steps.len());
+
+    let modes = steps
+        .iter()
+        .map(|s| (s.ip.clone(), s.restore_mode.clone()))
+        .collect();
+    let failed = config_mode_ips(&runtime, modes, db::EVENT_CURTAIL).await;
+
+    for step in steps.iter().filter(|s| !failed.contains(&s.ip)) {
+        if let Err(e) = db::delete_curtail_step(&step.ip) {
+            error!("delete curtail step of {} error: {:?}", step.ip, e);
+        }
+    }
+    CURTAILED.with(|curtailed| {
+        curtailed.retain(|s| failed.contains(&s.ip) || !steps.iter().any(|r| r.ip == s.ip))
+    });
+    failed
+}
+
+/// machines currently curtailed
+pub fn curtailed() -> Vec<CurtailStep> {
+    CURTAILED.with(|curtailed| curtailed.clone())
+}
+
+fn load() -> Vec<CurtailStep> {
+    let bodies = db::query_curtail_steps().unwrap_or_else(|e| {
+        error!("load curtail steps error: {:?}", e);
+        vec![]
+    });
+    bodies
+        .iter()
+        .filter_map(|body| serde_json::from_str(body).ok())
+        .collect()
+}
+
+fn save(step: &CurtailStep) {
+    let saved = serde_json::to_string(step)
+        .map_err(MinerError::from)
+        .and_then(|body| db::save_curtail_step(&step.ip, &body));
+    if let Err(e) = saved {
+        error!("save curtail step of {} error: {:?}", step.ip, e);
+    }
+}
+
+/// load the curtailed machines of the db just opened
+pub fn reload() {
+    CURTAILED.reload();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(ip: &str, hash_avg: f64, power: i32, work_mode: i32) -> MachineRecord {
+        MachineRecord {
+            ip: ip.to_string(),
+            hash_avg,
+            power,
+            work_mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let records = vec![
+            record("192.168.188.41", 100000.0, 3000, 0),
+            record("192.168.188.42", 100000.0, 4000, 1),
+            record("192.168.188.43", 100000.0, 0, 0),
+        ];
+
+        // 10 kW in total, worst efficiency goes first
+        let steps = plan(
+            &records,
+            7000.0,
+            &CurtailStrategy {
+                order: CurtailOrder::Efficiency,
+                action: CurtailAction::Sleep,
+            },
+        );
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].ip, "192.168.188.42");
+        assert_eq!(steps[0].restore_mode, tariff::MODE_HIGH);
+
+        let steps = plan(
+            &records,
+            6500.0,
+            &CurtailStrategy {
+                order: CurtailOrder::Priority(vec!["192.168.188.43".to_string()]),
+                action: CurtailAction::Downclock,
+            },
+        );
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].ip, "192.168.188.43");
+        assert_eq!(steps[0].action, CurtailAction::Sleep);
+        assert_eq!(steps[1].action, CurtailAction::Downclock);
+        assert_eq!(steps[1].saved_power, 1000.0);
+
+        // a sleeping miner of the catalog still draws its control board
+        let mut s19 = record("192.168.188.44", 100000.0, 3000, 0);
+        s19.machine_type = "Antminer S19j Pro".to_string();
+        let steps = plan(
+            &[s19],
+            0.0,
+            &CurtailStrategy {
+                order: CurtailOrder::Efficiency,
+                action: CurtailAction::Sleep,
+            },
+        );
+        assert_eq!(steps[0].saved_power, 2985.0);
+    }
+
+    #[test]
+    fn test_plan_by_tags_and_groups() {
+        let records = vec![
+            record("192.168.188.41", 100000.0, 3000, 0),
+            record("192.168.188.42", 100000.0, 4000, 0),
+            record("192.168.188.43", 100000.0, 3500, 0),
+        ];
+        let order = |order: CurtailOrder| {
+            let strategy = CurtailStrategy {
+                order,
+                action: CurtailAction::Sleep,
+            };
+            plan(&records, 0.0, &strategy)
+                .into_iter()
+                .map(|s| s.ip)
+                .collect::<Vec<String>>()
+        };
+        let site = std::sync::Arc::new(crate::context::Context::default());
+        crate::context::enter(site, || {
+            tag::set_tags(
+                [("192.168.188.41".to_string(), vec!["rack:A7".to_string()])]
+                    .into_iter()
+                    .collect(),
+            );
+            let expr = TagExpr::parse("rack:A7").unwrap();
+            assert_eq!(
+                order(CurtailOrder::Tags(expr)),
+                ["192.168.188.41", "192.168.188.42", "192.168.188.43"]
+            );
+
+            group::set_groups(
+                [("hall-1", 1), ("hall-2", -1)]
+                    .iter()
+                    .map(|(name, priority)| group::GroupConfig {
+                        name: name.to_string(),
+                        max_concurrency: 0,
+                        notify_sinks: vec![],
+                        priority: *priority,
+                    })
+                    .collect(),
+            );
+            group::set_members(
+                [
+                    ("192.168.188.41", "hall-2"),
+                    ("192.168.188.42", "hall-1"),
+                    ("192.168.188.43", "hall-2"),
+                ]
+                .iter()
+                .map(|(ip, group)| (ip.to_string(), group.to_string()))
+                .collect(),
+            );
+            assert_eq!(
+                order(CurtailOrder::GroupPriority),
+                ["192.168.188.43", "192.168.188.41", "192.168.188.42"]
+            );
+        });
+    }
+}
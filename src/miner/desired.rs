@@ -0,0 +1,344 @@
+/// declarative desired state, live state from query is diffed and only drift is applied
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::entry::{with_miner, MachineInfo, MinerOperation, PoolConfig};
+use super::group;
+use super::maintenance;
+use super::mode;
+use crate::clock;
+use crate::context;
+use crate::error::MinerError;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+use crate::pools::health;
+use crate::status;
+use crate::store::db;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FanPolicy {
+    Auto,
+    /// pwm percent
+    Fixed(u32),
+}
+
+/// wanted config of one machine, None fields are left as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DesiredMachine {
+    pub ip: String,
+    /// user is the account, the worker suffix is added per machine
+    pub pools: Option<Vec<PoolConfig>>,
+    /// 高功, 普通 or sleep
+    pub mode: Option<String>,
+    pub fan: Option<FanPolicy>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DesiredState {
+    pub machines: Vec<DesiredMachine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DriftField {
+    Pools,
+    Mode,
+    Fan,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Drift {
+    pub ip: String,
+    pub field: DriftField,
+    pub live: String,
+    pub desired: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateReport {
+    pub drifts: Vec<Drift>,
+    /// ips with every drift applied
+    pub applied: Vec<String>,
+    /// ip and error of failed queries or changes
+    pub failed: Vec<(String, String)>,
+}
+
+fn account_of(worker: &str) -> &str {
+    worker.split('.').next().unwrap_or("")
+}
+
+/// drift of one machine, fields the miner does not report are not compared
+pub fn diff(desired: &DesiredMachine, live: &MachineInfo) -> Vec<Drift> {
+    let mut drifts = vec![];
+    let ip = desired.ip.clone();
+    let mut drift = |field: DriftField, live_value: String, desired_value: String| {
+        drifts.push(Drift {
+            ip: ip.clone(),
+            field,
+            live: live_value,
+            desired: desired_value,
+        })
+    };
+
+    if let Some(pools) = &desired.pools {
+        let live_pools = [(&live.pool1, &live.worker1), (&live.pool2, &live.worker2)];
+        let same = pools
+            .iter()
+            .zip(live_pools.iter())
+            .all(|(pool, (url, worker))| {
+                health::same_endpoint(&pool.url, url)
+                    && account_of(&pool.user) == account_of(worker)
+            });
+        if !same {
+            drift(
+                DriftField::Pools,
+                format!("{} {}", live.pool1, live.worker1),
+                pools
+                    .first()
+                    .map(|p| format!("{} {}", p.url, p.user))
+                    .unwrap_or_default(),
+            );
+        }
+    }
+
+    if let Some(mode) = desired.mode.as_deref().map(mode::normalize) {
+        if !live.mode.is_empty() && live.mode != mode {
+            drift(DriftField::Mode, live.mode.clone(), mode);
+        }
+    }
+
+    if let Some(fan) = &desired.fan {
+        let wanted = match fan {
+            FanPolicy::Auto => "auto".to_string(),
+            FanPolicy::Fixed(pwm) => pwm.to_string(),
+        };
+        // "0" is reported by miners not exposing fan control
+        if !live.fan.is_empty() && live.fan != "0" && live.fan != wanted {
+            drift(DriftField::Fan, live.fan.clone(), wanted);
+        }
+    }
+    drifts
+}
+
+// query live state, apply drift when asked, pools last as they reboot the miner
+pub(crate) fn reconcile_machine(
+    desired: &DesiredMachine,
+    apply: bool,
+) -> Result<Vec<Drift>, MinerError> {
+    let ip = &desired.ip;
+    with_miner(ip, 3, |miner| {
+        let live = miner.query(ip, 3)?;
+        let mut drifts = diff(desired, &live);
+        drifts.sort_by_key(|d| d.field == DriftField::Pools);
+        if !apply {
+            return Ok(drifts);
+        }
+
+        for drift in drifts.iter() {
+            let applied = match drift.field {
+                DriftField::Mode => miner.config_mode(ip, &drift.desired, 3),
+                DriftField::Fan => match &desired.fan {
+                    Some(FanPolicy::Fixed(pwm)) => miner.config_fan(ip, Some(*pwm), 3),
+                    _ => miner.config_fan(ip, None, 3),
+                },
+                DriftField::Pools => match &desired.pools {
+                    Some(pools) => miner.config_pool(ip, pools, 3),
+                    None => Ok(()),
+                },
+            };
+            // fields the model cannot set are left as they are
+            if let Err(MinerError::MinerNotSupportError) = applied {
+                info!(
+                    "{} {:?} not supported by the miner, skipped",
+                    ip, drift.field
+                );
+                continue;
+            }
+            applied?;
+            let detail = format!("{:?} {} -> {}", drift.field, drift.live, drift.desired);
+            let _ = db::insert_event(db::EVENT_RECONCILE, ip, &detail);
+        }
+        Ok(drifts)
+    })
+}
+
+/// diff every machine against live state, apply the drift unless dry run.
+/// machines under maintenance are skipped.
+pub async fn reconcile(
+    runtime: &tokio::runtime::Handle,
+    state: &DesiredState,
+    apply: bool,
+) -> StateReport {
+    let machines: Vec<DesiredMachine> = state
+        .machines
+        .iter()
+        .filter(|m| !maintenance::is_in_maintenance(&m.ip))
+        .cloned()
+        .collect();
+
+    let handles = machines.iter().cloned().map(|desired| {
+        context::spawn(runtime, async move {
+            let _permit = group::permit(&desired.ip).await;
+            reconcile_machine(&desired, apply)
+        })
+    });
+    let results = futures::future::join_all(handles).await;
+
+    let mut report = StateReport::default();
+    for (desired, result) in machines.iter().zip(results) {
+        match result {
+            Ok(Ok(drifts)) => {
+                if apply && !drifts.is_empty() {
+                    report.applied.push(desired.ip.clone());
+                }
+                report.drifts.extend(drifts);
+            }
+            Ok(Err(e)) => report.failed.push((desired.ip.clone(), e.to_string())),
+            Err(e) => report.failed.push((desired.ip.clone(), e.to_string())),
+        }
+    }
+    info!(
+        "desired state: {} drifts, {} applied, {} failed",
+        report.drifts.len(),
+        report.applied.len(),
+        report.failed.len()
+    );
+    report
+}
+
+/// reconcile periodically, drift is notified as it is found
+pub fn schedule_task(
+    runtime: tokio::runtime::Handle,
+    state: DesiredState,
+    interval_seconds: u64,
+    apply: bool,
+) -> tokio::task::JoinHandle<()> {
+    let handle = runtime.clone();
+    context::spawn(&runtime, async move {
+        loop {
+            let report = reconcile(&handle, &state, apply).await;
+            if !report.drifts.is_empty() {
+                notifier::send_alert(&Alert {
+                    kind: "drift".to_string(),
+                    title: template::render(
+                        "drift.title",
+                        &[
+                            ("time", &clock::now().format("%H:%M:%S")),
+                            ("count", &report.drifts.len()),
+                        ],
+                    ),
+                    severity: Severity::Warning,
+                    content: if apply {
+                        template::render("drift.applied", &[("count", &report.applied.len())])
+                    } else {
+                        template::render("drift.not_applied", &[])
+                    },
+                    machines: report
+                        .drifts
+                        .iter()
+                        .map(|d| AlertMachine {
+                            ip: d.ip.clone(),
+                            detail: format!("{:?} {} -> {}", d.field, d.live, d.desired),
+                        })
+                        .collect(),
+                })
+                .await;
+            }
+            for (ip, e) in report.failed.iter() {
+                error!("desired state {} error: {}", ip, e);
+            }
+            if !status::wait(
+                "desired_state",
+                tokio::time::Duration::from_secs(interval_seconds),
+            )
+            .await
+            {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tariff;
+
+    #[test]
+    fn test_diff() {
+        let live = MachineInfo {
+            ip: "192.168.188.41".to_string(),
+            pool1: "btc.ss.poolin.com:443".to_string(),
+            worker1: "lcd.188x41".to_string(),
+            pool2: "btc.f2pool.com:1314".to_string(),
+            worker2: "lcd.188x41".to_string(),
+            mode: "普通".to_string(),
+            fan: "0".to_string(),
+            ..Default::default()
+        };
+        let pool = |url: &str, user: &str| PoolConfig {
+            url: url.to_string(),
+            user: user.to_string(),
+            password: "123".into(),
+        };
+        let mut desired = DesiredMachine {
+            ip: live.ip.clone(),
+            pools: Some(vec![
+                pool("stratum+tcp://btc.ss.poolin.com:443", "lcd"),
+                pool("btc.f2pool.com:1314", "lcd"),
+            ]),
+            mode: Some(tariff::MODE_NORMAL.to_string()),
+            fan: Some(FanPolicy::Fixed(80)),
+        };
+        assert!(diff(&desired, &live).is_empty());
+
+        desired.pools = Some(vec![pool("btc.f2pool.com:1314", "lcd")]);
+        desired.mode = Some(tariff::MODE_HIGH.to_string());
+        let drifts = diff(&desired, &live);
+        assert_eq!(drifts.len(), 2);
+        assert_eq!(drifts[0].field, DriftField::Pools);
+        assert_eq!(drifts[1].desired, tariff::MODE_HIGH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reconcile_denied() {
+        use crate::access::{self, Caller, Role};
+        let viewer = Caller {
+            name: "viewer".to_string(),
+            role: Role::ReadOnly,
+        };
+        let runtime = tokio::runtime::Handle::current();
+        let state = DesiredState::default();
+        let applied = access::scope(
+            viewer.clone(),
+            crate::reconcile_state(runtime.clone(), state.clone(), false),
+        )
+        .await;
+        assert!(matches!(
+            applied,
+            Err(crate::error::MinerError::PermissionDeniedError(_))
+        ));
+        // a dry run only needs to watch
+        let dry = access::scope(viewer, crate::reconcile_state(runtime, state, true)).await;
+        assert!(dry.is_ok());
+    }
+
+    #[cfg(all(feature = "mock", feature = "ant-http"))]
+    #[tokio::test]
+    async fn test_reconcile_ant_converges() {
+        let _ant = crate::miner::mock::FakeAnt::start("10.254.0.20").unwrap();
+        let state = DesiredState {
+            machines: vec![DesiredMachine {
+                ip: "10.254.0.20".to_string(),
+                mode: Some(tariff::MODE_NORMAL.to_string()),
+                ..Default::default()
+            }],
+        };
+        let runtime = tokio::runtime::Handle::current();
+        let report = reconcile(&runtime, &state, true).await;
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        assert_eq!(report.applied, vec!["10.254.0.20".to_string()]);
+
+        // the written mode reads back as the desired one
+        let report = reconcile(&runtime, &state, true).await;
+        assert!(report.drifts.is_empty(), "{:?}", report.drifts);
+    }
+}
@@ -0,0 +1,94 @@
+/// detection results by ip, batch operations reuse them instead of probing again
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::entry::MinerType;
+
+use crate::context::Local;
+
+static TTL: Local<Duration> = Local::new(|| Duration::from_secs(300));
+static CACHE: Local<HashMap<String, Detected>> = Local::new(HashMap::new);
+
+#[derive(Debug, Clone)]
+struct Detected {
+    miner: MinerType,
+    /// model reported by the last query, empty until queried
+    model: String,
+    at: Instant,
+}
+
+/// 0 disables the cache
+pub fn set_ttl(seconds: u64) {
+    TTL.set(Duration::from_secs(seconds));
+    if seconds == 0 {
+        clear();
+    }
+}
+
+pub fn get(ip: &str) -> Option<MinerType> {
+    let ttl = TTL.get();
+    CACHE.with(|cache| match cache.get(ip) {
+        Some(detected) if detected.at.elapsed() < ttl => Some(detected.miner.clone()),
+        Some(_) => {
+            cache.remove(ip);
+            None
+        }
+        None => None,
+    })
+}
+
+pub fn insert(ip: &str, miner: &MinerType) {
+    if TTL.get().is_zero() {
+        return;
+    }
+    let detected = Detected {
+        miner: miner.clone(),
+        model: String::new(),
+        at: Instant::now(),
+    };
+    CACHE.with(|cache| cache.insert(ip.to_string(), detected));
+}
+
+pub fn set_model(ip: &str, model: &str) {
+    CACHE.with(|cache| {
+        if let Some(detected) = cache.get_mut(ip) {
+            detected.model = model.to_string();
+        }
+    });
+}
+
+pub fn model(ip: &str) -> Option<String> {
+    get(ip)?;
+    CACHE
+        .with(|cache| cache.get(ip).map(|d| d.model.clone()))
+        .filter(|m| !m.is_empty())
+}
+
+/// forget the ip, e.g. after the machine was swapped
+pub fn invalidate(ip: &str) {
+    CACHE.with(|cache| cache.remove(ip));
+}
+
+pub fn clear() {
+    CACHE.with(|cache| cache.clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner::entry::classify_version;
+
+    #[test]
+    fn test_detection_cache() {
+        let ip = "192.168.188.250";
+        let miner = classify_version("PROD=AvalonMiner 1246").unwrap();
+        insert(ip, &miner);
+        set_model(ip, "AvalonMiner 1246");
+        assert!(matches!(get(ip), Some(MinerType::Avalon(_))));
+        assert_eq!(model(ip).as_deref(), Some("AvalonMiner 1246"));
+
+        invalidate(ip);
+        assert!(get(ip).is_none());
+        assert!(model(ip).is_none());
+    }
+}
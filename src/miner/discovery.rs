@@ -0,0 +1,260 @@
+/// arp/neighbor table discovery, finds miners whose web ui is hung but still hold an ip
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ant-http")]
+use super::endpoint;
+use super::entry::{scan_miner_detail, MachineInfo};
+use super::group;
+use super::inventory;
+use super::subnet;
+use crate::context;
+
+lazy_static! {
+    static ref CONFIG: Mutex<DiscoveryConfig> = Mutex::new(DiscoveryConfig::default());
+}
+
+/// mac prefix of a vendor block, e.g. "aa:bb:cc" for a bitmain or canaan range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OuiVendor {
+    pub prefix: String,
+    /// ant, avalon or bluestar
+    pub vendor: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiscoveryConfig {
+    /// oui blocks seen on the site, registered ranges differ by batch
+    pub ouis: Vec<OuiVendor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Neighbor {
+    pub ip: String,
+    /// lowercase, colon separated
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiscoveredMachine {
+    pub ip: String,
+    pub mac: String,
+    /// vendor of the mac oui, None when the oui is not configured
+    pub vendor: Option<String>,
+    pub web_alive: bool,
+    /// cgminer api port 4028 accepts connections
+    pub api_alive: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiscoveryReport {
+    /// machines detected and queried over http
+    pub machines: Vec<MachineInfo>,
+    /// in the neighbor table with a miner oui or api, but the web ui does not answer
+    pub web_dead: Vec<DiscoveredMachine>,
+}
+
+pub fn set_config(config: DiscoveryConfig) {
+    *CONFIG.lock().unwrap() = config;
+}
+
+fn normalize_mac(mac: &str) -> String {
+    mac.to_lowercase().replace('-', ":")
+}
+
+/// vendor of the configured oui blocks
+pub fn vendor_of(mac: &str) -> Option<String> {
+    let mac = normalize_mac(mac);
+    CONFIG
+        .lock()
+        .unwrap()
+        .ouis
+        .iter()
+        .find(|oui| mac.starts_with(&normalize_mac(&oui.prefix)))
+        .map(|oui| oui.vendor.clone())
+}
+
+/// entries of /proc/net/arp, incomplete entries are skipped
+pub fn parse_proc_arp(content: &str) -> Vec<Neighbor> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            // IP address, HW type, Flags, HW address, Mask, Device
+            if cols.len() < 4 || cols[2] == "0x0" || cols[3] == "00:00:00:00:00:00" {
+                return None;
+            }
+            Some(Neighbor {
+                ip: cols[0].to_string(),
+                mac: normalize_mac(cols[3]),
+            })
+        })
+        .collect()
+}
+
+/// entries of `arp -an`, e.g. "? (192.168.1.10) at aa:bb:cc:dd:ee:ff on en0"
+pub fn parse_arp_a(content: &str) -> Vec<Neighbor> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let ip = line.split_once('(')?.1.split_once(')')?.0;
+            let mac = line.split_once(" at ")?.1.split_whitespace().next()?;
+            if !mac.contains(':') && !mac.contains('-') {
+                return None;
+            }
+            Some(Neighbor {
+                ip: ip.to_string(),
+                mac: normalize_mac(mac),
+            })
+        })
+        .collect()
+}
+
+/// neighbor table of the host
+pub fn neighbors() -> Vec<Neighbor> {
+    if let Ok(content) = std::fs::read_to_string("/proc/net/arp") {
+        return parse_proc_arp(&content);
+    }
+    match std::process::Command::new("arp").arg("-an").output() {
+        Ok(output) => parse_arp_a(&String::from_utf8_lossy(&output.stdout)),
+        Err(e) => {
+            info!("read neighbor table error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+#[cfg(feature = "ant-http")]
+fn web_alive(ip: &str, timeout_seconds: i64) -> bool {
+    // body is not needed, only whether the server answers
+    crate::http::lan(|client| {
+        client
+            .get(format!("http://{}", endpoint::web_host(ip)))
+            .timeout(Duration::from_secs(timeout_seconds as u64))
+            .send()
+            .is_ok()
+    })
+}
+
+// without the http client the web port accepting a connection is enough
+#[cfg(not(feature = "ant-http"))]
+fn web_alive(ip: &str, timeout_seconds: i64) -> bool {
+    super::reach::port_open(ip, 80, Duration::from_secs(timeout_seconds as u64))
+}
+
+// a blocking connect, routed subnets go through their proxy
+async fn api_alive(ip: &str, timeout_seconds: i64) -> bool {
+    let ip = ip.to_string();
+    let timeout = Duration::from_secs(timeout_seconds as u64);
+    context::spawn_blocking(move || super::reach::port_open(&ip, 4028, timeout))
+        .await
+        .unwrap_or(false)
+}
+
+/// probe the range so the neighbor table is filled, then merge it with http detection
+pub async fn discover(
+    runtime: tokio::runtime::Handle,
+    ip_demo: &str,
+    offset: i32,
+    count: i32,
+    timeout_seconds: i64,
+) -> DiscoveryReport {
+    let ip_prefix = ip_demo.split('.').take(3).collect::<Vec<&str>>().join(".");
+    let ips: Vec<String> = (offset..(offset + count))
+        .map(|i| format!("{}.{}", ip_prefix, i))
+        .collect();
+
+    let handles = ips.iter().cloned().map(|ip| {
+        context::spawn(&runtime, async move {
+            let _permit = group::permit(&ip).await;
+            let probe = subnet::permit(&ip).await;
+            let api = api_alive(&ip, timeout_seconds).await;
+            let web = web_alive(&ip, timeout_seconds);
+            let machine = if web {
+                let result = scan_miner_detail(ip.clone(), timeout_seconds).await;
+                probe.record(&result);
+                result.ok()
+            } else {
+                None
+            };
+            (ip, web, api, machine)
+        })
+    });
+    let probes: Vec<_> = futures::future::join_all(handles)
+        .await
+        .into_iter()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    let neighbors = neighbors();
+    let mut report = DiscoveryReport::default();
+    for (ip, web, api, machine) in probes {
+        if let Some(machine) = machine {
+            report.machines.push(machine);
+            continue;
+        }
+        let mac = neighbors
+            .iter()
+            .find(|n| n.ip == ip)
+            .map(|n| n.mac.clone())
+            .unwrap_or_default();
+        let vendor = vendor_of(&mac);
+        if !web && (api || (!mac.is_empty() && vendor.is_some())) {
+            report.web_dead.push(DiscoveredMachine {
+                ip,
+                mac,
+                vendor,
+                web_alive: web,
+                api_alive: api,
+            });
+        }
+    }
+    info!(
+        "discover {}: {} detected, {} web dead",
+        ip_prefix,
+        report.machines.len(),
+        report.web_dead.len()
+    );
+    inventory::apply().await;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_neighbors() {
+        let proc_arp =
+            "IP address       HW type     Flags       HW address            Mask     Device\n\
+            192.168.188.41   0x1         0x2         AA:BB:CC:01:02:03     *        eth0\n\
+            192.168.188.42   0x1         0x0         00:00:00:00:00:00     *        eth0\n";
+        let neighbors = parse_proc_arp(proc_arp);
+        assert_eq!(
+            neighbors,
+            vec![Neighbor {
+                ip: "192.168.188.41".to_string(),
+                mac: "aa:bb:cc:01:02:03".to_string(),
+            }]
+        );
+
+        let arp_a = "? (192.168.188.43) at dd-ee-ff-01-02-03 on en0 ifscope [ethernet]\n\
+            ? (192.168.188.44) at (incomplete) on en0 ifscope [ethernet]\n";
+        let neighbors = parse_arp_a(arp_a);
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(neighbors[0].mac, "dd:ee:ff:01:02:03");
+
+        set_config(DiscoveryConfig {
+            ouis: vec![OuiVendor {
+                prefix: "AA-BB-CC".to_string(),
+                vendor: "ant".to_string(),
+            }],
+        });
+        assert_eq!(vendor_of("aa:bb:cc:01:02:03").as_deref(), Some("ant"));
+        assert_eq!(vendor_of("dd:ee:ff:01:02:03"), None);
+    }
+}
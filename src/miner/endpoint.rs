@@ -0,0 +1,24 @@
+// where the web server and cgminer api of a miner are reached, the fakes of the mock
+// feature answer on local ports instead of the miner ip, routed miners through a forwarder
+
+/// host part of the web urls
+#[cfg_attr(not(feature = "ant-http"), allow(dead_code))]
+pub fn web_host(ip: &str) -> String {
+    #[cfg(feature = "mock")]
+    if let Some(addr) = super::mock::route(ip, 80) {
+        return addr.to_string();
+    }
+    if let Some(addr) = super::route::forward(ip, 80) {
+        return addr.to_string();
+    }
+    ip.to_string()
+}
+
+/// ip:port of the cgminer api
+pub fn api_addr(ip: &str, port: u16) -> String {
+    #[cfg(feature = "mock")]
+    if let Some(addr) = super::mock::route(ip, port) {
+        return addr.to_string();
+    }
+    format!("{}:{}", ip, port)
+}
@@ -1,158 +1,134 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::pin::Pin;
-//use std::sync::{Arc};
-use std::{collections::BTreeMap, time::Duration};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "ant-http")]
+use std::time::Duration;
 
-use chrono::NaiveTime;
-use curl::easy::Easy;
 use log::info;
 use serde::{Deserialize, Serialize};
 
+use crate::clock;
+use crate::context::{self, Local};
+use crate::error::{BatchError, MinerError};
 use crate::miner::avalon;
+pub use crate::model::{Account, Machine, MachineInfo, MachineRecord, MinerStatus, PoolConfig};
+use crate::notify::{self, notifier, template, throttle, Alert, AlertMachine, Severity};
+use crate::pools::health;
+use crate::profitability;
+use crate::shutdown;
 use crate::store::db::{self};
-use crate::{error::MinerError, notify::feishu};
+use crate::tariff;
+
+use super::anomaly;
+#[cfg(feature = "ant-http")]
+use super::ant::*;
+use super::boards;
+use super::catalog;
+use super::conn;
+use super::detection;
+#[cfg(feature = "ant-http")]
+use super::endpoint;
+use super::group::{self, GroupSelector};
+use super::inventory::{self, ScanDiff};
+use super::job::{self, JobAction};
+use super::maintenance;
+use super::mode::{self, RunMode};
+use super::position;
+use super::restart;
+use super::route;
+use super::sheet::{self, SheetStatus};
+use super::stagger;
+use super::subnet;
+use super::tag;
+use super::thermal;
+use super::window;
+use super::zone;
+use super::{avalon::*, bluestar::*};
+
+lazy_static! {
+    // lock of each machine in use, older ant web servers wedge under parallel probes
+    static ref MACHINE_LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
 
-use super::{ant::*, avalon::*, bluestar::*};
+// held by the running switch of the site, scheduled or called directly
+pub(crate) static SWITCH_RUNNING: Local<Arc<tokio::sync::Mutex<()>>> = Local::new(Arc::default);
 
 #[derive(Debug, Clone)]
 pub enum MinerType {
+    #[cfg(feature = "ant-http")]
     Ant(AntMiner),
     Avalon(AvalonMiner),
     BlueStar(BlueStarMiner),
+    /// scripted miner of the mock feature
+    #[cfg(feature = "mock")]
+    Fake(super::mock::FakeMiner),
 }
 
-// from str to enum
-impl From<&str> for MinerType {
-    fn from(s: &str) -> Self {
-        match s {
-            "ant" => MinerType::Ant(AntMiner {}),
-            "avalon" => MinerType::Avalon(AvalonMiner {}),
-            "bluestar" => MinerType::BlueStar(BlueStarMiner {}),
-            _ => panic!("MinerType not support"),
+// from sheet type cell to enum
+impl TryFrom<&str> for MinerType {
+    type Error = MinerError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.trim() {
+            #[cfg(feature = "ant-http")]
+            "ant" => Ok(MinerType::Ant(AntMiner {})),
+            "avalon" => Ok(MinerType::Avalon(AvalonMiner {})),
+            "bluestar" => Ok(MinerType::BlueStar(BlueStarMiner {})),
+            _ => Err(MinerError::MinerNotSupportError),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-struct TimeConfig {
-    pub start: NaiveTime,     // 00:00:00
-    pub end: NaiveTime,       // 00:00:00
-    pub account_type: String, // main or switch or perf mode(高功/普通)
-}
-
-impl TimeConfig {
-    pub fn now_account(&self) -> Option<&str> {
-        let now_time = chrono::Local::now().time();
-        if (self.start < self.end && now_time >= self.start && now_time <= self.end)
-            || (self.start > self.end && (now_time >= self.start || now_time <= self.end))
-        {
-            return Some(&self.account_type);
-        }
-        None
-    }
-
-    pub fn from_str(start: &str, end: &str, account_type: &str) -> Result<TimeConfig, MinerError> {
-        let start = NaiveTime::parse_from_str(start, "%H:%M:%S")?;
-        let end = NaiveTime::parse_from_str(end, "%H:%M:%S")?;
-        Ok(TimeConfig {
-            start,
-            end,
-            account_type: account_type.to_string(),
-        })
-    }
-
-    pub fn now_perf(&self) -> Option<&str> {
-        let now_time = chrono::Local::now().time();
-        if (self.start < self.end && now_time >= self.start && now_time <= self.end)
-            || (self.start > self.end && (now_time >= self.start || now_time <= self.end))
-        {
-            return Some(&self.account_type);
-        }
-        None
-    }
-}
-
-// String type enum
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub enum MinerStatus {
-    Online,
-    Offline,
-    // Error,
+/// sheet row skipped because it could not be parsed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RowError {
+    pub sheet: String,
+    /// row number as shown in the sheet, the header is row 1
+    pub row: usize,
+    pub ip: String,
+    pub error: String,
 }
 
-impl From<&str> for MinerStatus {
-    fn from(s: &str) -> Self {
-        match s {
-            "上线" => MinerStatus::Online,
-            _ => MinerStatus::Offline,
-        }
-    }
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SwitchReport {
+    /// ips switched or already on the wanted account
+    pub switched: Vec<String>,
+    /// ip and error of failed switches
+    pub failed: Vec<(String, String)>,
+    pub row_errors: Vec<RowError>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Machine {
-    pub id: i32,
-    pub account_id: i32,
+/// last account applied to a machine, kept in the db across restarts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SwitchState {
     pub ip: String,
-    pub name: String, // ant, avalon, bluestar
-    pub status: MinerStatus,
-    pub account: Account,
-    pub switch_account: Option<Account>,
-    pub addition_info: String,
+    pub account: String,
     pub run_mode: String,
-    pub is_run_mode_fixed: bool,
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct MachineRecord {
-    pub id: i32,
-    pub ip: String,
-    pub machine_type: String,
-    pub work_mode: i32,
-    pub hash_real: f64,
-    pub hash_avg: f64,
-    pub temp_0: f64,
-    pub temp_1: f64,
-    pub temp_2: f64,
-    pub power: i32,
-    pub create_time: i64,
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct MachineInfo {
-    pub ip: String,
-    pub machine_type: String,
-    pub hash_real: String,
-    pub hash_avg: String,
-    pub pool_hash_real: String,
-    pub pool_hash_avg: String,
-    pub temp: String,
-    pub fan: String,
-    pub elapsed: String,
-    pub mode: String,
-    pub pool1: String,
-    pub worker1: String,
-    pub pool2: String,
-    pub worker2: String,
-    pub record: MachineRecord, // for db record
+    /// empty when the switch succeeded
+    pub error: String,
+    pub update_time: i64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Account {
-    pub id: i32,
-    pub name: String,
-    pub password: String,
-    pub pool1: String,
-    pub pool2: String,
-    pub pool3: String,
-    pub run_mode: String,
+/// accepted, rejected and stale as percent of all submitted shares, 0 before the first share
+pub fn share_pct(accepted: f64, rejected: f64, stale: f64) -> (f64, f64, f64) {
+    let total = accepted + rejected + stale;
+    if total <= 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    (
+        accepted * 100.0 / total,
+        rejected * 100.0 / total,
+        stale * 100.0 / total,
+    )
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PoolConfig {
-    pub url: String,
-    pub user: String,
-    pub password: String,
+/// the value, noting a warning when the miner did not report it or it did not parse
+pub fn or_warn<T>(value: Option<T>, field: &str, warnings: &mut Vec<String>) -> Option<T> {
+    if value.is_none() {
+        warnings.push(format!("{} missing or unreadable", field));
+    }
+    value
 }
 
 #[derive(Debug, Clone)]
@@ -173,10 +149,40 @@ pub trait MinerOperation {
         account: &Account,
         is_force: bool,
     ) -> AsyncOpType<()>;
-    fn reboot(&self, ip: &str) -> Result<(), MinerError>;
-    fn config_pool(&self, ip: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError>;
-    fn config_mode(&self, ip: &str, mode: &str) -> Result<(), MinerError>;
-    fn config(&self, ip: &str, mode: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError>;
+    fn reboot(&self, ip: &str, timeout_seconds: i64) -> Result<(), MinerError>;
+    fn config_pool(
+        &self,
+        ip: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError>;
+    fn config_mode(&self, ip: &str, mode: &str, timeout_seconds: i64) -> Result<(), MinerError>;
+    /// fixed fan pwm percent, None for automatic control
+    fn config_fan(
+        &self,
+        ip: &str,
+        pwm: Option<u32>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError>;
+    /// chip frequency MHz and voltage mV, None keeps the current value
+    fn config_tuning(
+        &self,
+        ip: &str,
+        freq: Option<u32>,
+        voltage: Option<u32>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError>;
+    fn config(
+        &self,
+        ip: &str,
+        mode: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError>;
+    /// shell command on the miner, the output. for drivers with ssh access
+    fn exec(&self, _ip: &str, _cmd: &str) -> Result<String, MinerError> {
+        Err(MinerError::MinerNotSupportError)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -186,7 +192,8 @@ pub struct MinerInfo {
 }
 
 /// supported miner array
-pub const MINERS: [MinerType; 3] = [
+pub const MINERS: &[MinerType] = &[
+    #[cfg(feature = "ant-http")]
     MinerType::Ant(AntMiner {}),
     MinerType::Avalon(AvalonMiner {}),
     MinerType::BlueStar(BlueStarMiner {}),
@@ -195,17 +202,23 @@ pub const MINERS: [MinerType; 3] = [
 impl MinerOperation for MinerType {
     fn info(&self) -> MinerInfo {
         match self {
+            #[cfg(feature = "ant-http")]
             MinerType::Ant(miner) => miner.info(),
             MinerType::Avalon(miner) => miner.info(),
             MinerType::BlueStar(miner) => miner.info(),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.info(),
         }
     }
 
     fn detect(&self, headers: Vec<String>, body: &str) -> Result<MinerType, MinerError> {
         match self {
+            #[cfg(feature = "ant-http")]
             MinerType::Ant(miner) => miner.detect(headers, body),
             MinerType::Avalon(miner) => miner.detect(headers, body),
             MinerType::BlueStar(miner) => miner.detect(headers, body),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.detect(headers, body),
         }
     }
 
@@ -216,91 +229,193 @@ impl MinerOperation for MinerType {
         is_force: bool,
     ) -> AsyncOpType<()> {
         match self {
+            #[cfg(feature = "ant-http")]
             MinerType::Ant(miner) => miner.switch_account_if_diff(ip, account, is_force),
             MinerType::Avalon(miner) => miner.switch_account_if_diff(ip, account, is_force),
             MinerType::BlueStar(miner) => miner.switch_account_if_diff(ip, account, is_force),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.switch_account_if_diff(ip, account, is_force),
         }
     }
 
     fn query(&self, ip: &str, timeout_seconds: i64) -> Result<MachineInfo, MinerError> {
         match self {
+            #[cfg(feature = "ant-http")]
             MinerType::Ant(miner) => miner.query(ip, timeout_seconds),
             MinerType::Avalon(miner) => miner.query(ip, timeout_seconds),
             MinerType::BlueStar(miner) => miner.query(ip, timeout_seconds),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.query(ip, timeout_seconds),
         }
     }
 
-    fn reboot(&self, ip: &str) -> Result<(), MinerError> {
+    fn reboot(&self, ip: &str, timeout_seconds: i64) -> Result<(), MinerError> {
         match self {
-            MinerType::Ant(miner) => miner.reboot(ip),
-            MinerType::Avalon(miner) => miner.reboot(ip),
-            MinerType::BlueStar(miner) => miner.reboot(ip),
+            #[cfg(feature = "ant-http")]
+            MinerType::Ant(miner) => miner.reboot(ip, timeout_seconds),
+            MinerType::Avalon(miner) => miner.reboot(ip, timeout_seconds),
+            MinerType::BlueStar(miner) => miner.reboot(ip, timeout_seconds),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.reboot(ip, timeout_seconds),
         }
     }
 
-    fn config_pool(&self, ip: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
+    fn config_pool(
+        &self,
+        ip: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
         match self {
-            MinerType::Ant(miner) => miner.config_pool(ip, pools),
-            MinerType::Avalon(miner) => miner.config_pool(ip, pools),
-            MinerType::BlueStar(miner) => miner.config_pool(ip, pools),
+            #[cfg(feature = "ant-http")]
+            MinerType::Ant(miner) => miner.config_pool(ip, pools, timeout_seconds),
+            MinerType::Avalon(miner) => miner.config_pool(ip, pools, timeout_seconds),
+            MinerType::BlueStar(miner) => miner.config_pool(ip, pools, timeout_seconds),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.config_pool(ip, pools, timeout_seconds),
         }
     }
 
-    fn config_mode(&self, ip: &str, mode: &str) -> Result<(), MinerError> {
+    fn config_mode(&self, ip: &str, mode: &str, timeout_seconds: i64) -> Result<(), MinerError> {
         match self {
-            MinerType::Ant(miner) => miner.config_mode(ip, mode),
-            MinerType::Avalon(miner) => miner.config_mode(ip, mode),
-            MinerType::BlueStar(miner) => miner.config_mode(ip, mode),
+            #[cfg(feature = "ant-http")]
+            MinerType::Ant(miner) => miner.config_mode(ip, mode, timeout_seconds),
+            MinerType::Avalon(miner) => miner.config_mode(ip, mode, timeout_seconds),
+            MinerType::BlueStar(miner) => miner.config_mode(ip, mode, timeout_seconds),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.config_mode(ip, mode, timeout_seconds),
         }
     }
 
-    fn config(&self, ip: &str, mode: &str, pools: &Vec<PoolConfig>) -> Result<(), MinerError> {
+    fn config_fan(
+        &self,
+        ip: &str,
+        pwm: Option<u32>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
         match self {
-            MinerType::Ant(miner) => miner.config(ip, mode, pools),
-            MinerType::Avalon(miner) => miner.config(ip, mode, pools),
-            MinerType::BlueStar(miner) => miner.config(ip, mode, pools),
+            #[cfg(feature = "ant-http")]
+            MinerType::Ant(miner) => miner.config_fan(ip, pwm, timeout_seconds),
+            MinerType::Avalon(miner) => miner.config_fan(ip, pwm, timeout_seconds),
+            MinerType::BlueStar(miner) => miner.config_fan(ip, pwm, timeout_seconds),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.config_fan(ip, pwm, timeout_seconds),
         }
     }
-}
 
-fn find_miner(ip: &str, timeout_seconds: i64) -> Result<MinerType, MinerError> {
-    info!("start detect: {}", ip);
-    let mut easy = Easy::new();
-    easy.url(&ip)?;
-    // timeout 5s
-    easy.timeout(Duration::from_secs(timeout_seconds as u64))?;
-    let mut headers = Vec::new();
-    let mut data = Vec::new();
-    {
-        let mut transfer = easy.transfer();
-        transfer.write_function(|new_data| {
-            data.extend_from_slice(new_data);
-            Ok(new_data.len())
-        })?;
-        transfer.header_function(|header| {
-            headers.push(String::from_utf8(header.to_vec()).unwrap());
-            true
-        })?;
-        match transfer.perform() {
-            Ok(_) => {}
-            Err(_) => {
-                let _ = avalon::tcp_query_version(ip, timeout_seconds)?;
-                return Ok(MinerType::Avalon(AvalonMiner {}));
-            }
+    fn config_tuning(
+        &self,
+        ip: &str,
+        freq: Option<u32>,
+        voltage: Option<u32>,
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        match self {
+            #[cfg(feature = "ant-http")]
+            MinerType::Ant(miner) => miner.config_tuning(ip, freq, voltage, timeout_seconds),
+            MinerType::Avalon(miner) => miner.config_tuning(ip, freq, voltage, timeout_seconds),
+            MinerType::BlueStar(miner) => miner.config_tuning(ip, freq, voltage, timeout_seconds),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.config_tuning(ip, freq, voltage, timeout_seconds),
         }
     }
 
-    // catch timeout then try to use tcp connection for avalon
-    match easy.perform() {
-        Ok(_) => {}
-        Err(_) => {
-            info!("find miner open web fail, try avalon tcp: {}", ip);
-            let _ = avalon::tcp_query_version(ip, timeout_seconds)?;
-            return Ok(MinerType::Avalon(AvalonMiner {}));
+    fn config(
+        &self,
+        ip: &str,
+        mode: &str,
+        pools: &[PoolConfig],
+        timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        match self {
+            #[cfg(feature = "ant-http")]
+            MinerType::Ant(miner) => miner.config(ip, mode, pools, timeout_seconds),
+            MinerType::Avalon(miner) => miner.config(ip, mode, pools, timeout_seconds),
+            MinerType::BlueStar(miner) => miner.config(ip, mode, pools, timeout_seconds),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.config(ip, mode, pools, timeout_seconds),
+        }
+    }
+
+    fn exec(&self, ip: &str, cmd: &str) -> Result<String, MinerError> {
+        match self {
+            #[cfg(feature = "ant-http")]
+            MinerType::Ant(miner) => miner.exec(ip, cmd),
+            MinerType::Avalon(miner) => miner.exec(ip, cmd),
+            MinerType::BlueStar(miner) => miner.exec(ip, cmd),
+            #[cfg(feature = "mock")]
+            MinerType::Fake(miner) => miner.exec(ip, cmd),
         }
     }
+}
+
+// run f while no other detection or operation of the machine runs, callers wait their turn
+fn lock_machine<T>(ip: &str, f: impl FnOnce() -> T) -> T {
+    let lock = MACHINE_LOCKS
+        .lock()
+        .unwrap()
+        .entry(ip.to_string())
+        .or_default()
+        .clone();
+    let result = {
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    };
+    // nobody else holds or waits for it, the map and this caller only
+    let mut locks = MACHINE_LOCKS.lock().unwrap();
+    if Arc::strong_count(&lock) == 2 {
+        locks.remove(ip);
+    }
+    result
+}
+
+/// detected miner of the ip, cached for the detection ttl. a probe already running for the
+/// ip is waited for and its result reused
+pub(crate) fn find_miner(ip: &str, timeout_seconds: i64) -> Result<MinerType, MinerError> {
+    lock_machine(ip, || detect_cached(ip, timeout_seconds))
+}
+
+/// detect the miner and run the operation on it, nothing else runs on the machine meanwhile
+pub(crate) fn with_miner<T>(
+    ip: &str,
+    timeout_seconds: i64,
+    op: impl FnOnce(&MinerType) -> Result<T, MinerError>,
+) -> Result<T, MinerError> {
+    lock_machine(ip, || op(&detect_cached(ip, timeout_seconds)?))
+}
+
+fn detect_cached(ip: &str, timeout_seconds: i64) -> Result<MinerType, MinerError> {
+    #[cfg(feature = "mock")]
+    if let Some(miner) = super::mock::fake_miner(ip) {
+        return Ok(miner);
+    }
+    if let Some(miner) = detection::get(ip) {
+        return Ok(miner);
+    }
+    let miner = detect_miner(ip, timeout_seconds)?;
+    detection::insert(ip, &miner);
+    Ok(miner)
+}
 
-    let body = String::from_utf8(data).unwrap();
+#[cfg(feature = "ant-http")]
+fn detect_miner(ip: &str, timeout_seconds: i64) -> Result<MinerType, MinerError> {
+    info!("start detect: {}", ip);
+    let page = crate::http::lan(|client| {
+        let response = client
+            .get(format!("http://{}", endpoint::web_host(ip)))
+            .timeout(Duration::from_secs(timeout_seconds as u64))
+            .send()?;
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes())))
+            .collect::<Vec<String>>();
+        Ok::<_, reqwest::Error>((headers, response.text()?))
+    });
+    // catch timeout then try to use tcp connection for avalon
+    let Ok((headers, body)) = page else {
+        return detect_by_api(ip, timeout_seconds);
+    };
 
     for miner in MINERS.iter() {
         if let Ok(miner_inst) = miner.detect(headers.clone(), &body) {
@@ -310,13 +425,61 @@ fn find_miner(ip: &str, timeout_seconds: i64) -> Result<MinerType, MinerError> {
         }
     }
 
-    Err(MinerError::MinerNotSupportError)
+    // web answers with an unknown page, the cgminer api may still tell the vendor
+    detect_by_api(ip, timeout_seconds)
+}
+
+// built without the http client, only the cgminer api tells the vendor
+#[cfg(not(feature = "ant-http"))]
+fn detect_miner(ip: &str, timeout_seconds: i64) -> Result<MinerType, MinerError> {
+    detect_by_api(ip, timeout_seconds)
+}
+
+/// miner type of a cgminer api `version` reply
+pub(crate) fn classify_version(version: &str) -> Option<MinerType> {
+    let version = version.to_lowercase();
+    #[cfg(feature = "ant-http")]
+    if version.contains("antminer") || version.contains("bmminer") {
+        return Some(MinerType::Ant(AntMiner {}));
+    }
+    if version.contains("avalon") || version.contains("cgminer") {
+        return Some(MinerType::Avalon(AvalonMiner {}));
+    }
+    None
+}
+
+// fallback detection over port 4028 when the web server is broken
+fn detect_by_api(ip: &str, timeout_seconds: i64) -> Result<MinerType, MinerError> {
+    info!("find miner by web fail, try cgminer api: {}", ip);
+    let version = avalon::tcp_query_version(ip, timeout_seconds)?;
+    let miner = classify_version(&version).ok_or(MinerError::MinerNotSupportError)?;
+    info!("detect miner by api: {} {}", ip, miner.info().name);
+    Ok(miner)
 }
 
 pub fn scan_miner_detail(ip: String, timeout_seconds: i64) -> AsyncOpType<MachineInfo> {
     Box::pin(async move {
-        let miner = find_miner(&ip, timeout_seconds)?;
-        let mut machine_info = miner.query(&ip, timeout_seconds)?;
+        let mut machine_info = with_miner(&ip, timeout_seconds, |miner| {
+            miner.query(&ip, timeout_seconds)
+        })
+        .inspect_err(|_| {
+            // the machine may have been swapped, detect again next time
+            detection::invalidate(&ip);
+        })?;
+        tag::set_model(&ip, &machine_info.record.machine_type);
+        detection::set_model(&ip, &machine_info.record.machine_type);
+        inventory::track(&machine_info, machine_info.record.create_time)?;
+        // a broken hashrate reading is kept but flagged, it would skew the sums of the fleet
+        if let Some(problem) = catalog::check_hashrate(
+            &machine_info.record.machine_type,
+            machine_info
+                .record
+                .hash_real
+                .max(machine_info.record.hash_avg),
+        ) {
+            info!("{} implausible hashrate: {}", ip, problem);
+            db::insert_event(db::EVENT_IMPLAUSIBLE_HASH, &ip, &problem)?;
+        }
         // process db record
         db::insert_machine_record(&machine_info.record)?;
         // query pool record
@@ -336,60 +499,91 @@ pub fn scan_miner_detail(ip: String, timeout_seconds: i64) -> AsyncOpType<Machin
     })
 }
 
-fn scan_reboot(ip: String) -> Result<(), MinerError> {
+/// exec of the drivers with a shell, through the ssh profile of their model
+#[cfg_attr(not(feature = "ssh"), allow(unused_variables))]
+pub(crate) fn ssh_exec(ip: &str, model: &str, cmd: &str) -> Result<String, MinerError> {
+    #[cfg(feature = "ssh")]
+    return super::ssh::exec(ip, model, cmd);
+    #[cfg(not(feature = "ssh"))]
+    Err(MinerError::FeatureDisabledError("ssh".to_string()))
+}
+
+fn scan_reboot(ip: String, timeout_seconds: i64) -> Result<(), MinerError> {
     info!("try to reboot: {}", ip);
-    let miner = find_miner(&ip, 3)?;
-    miner.reboot(&ip)
+    with_miner(&ip, timeout_seconds, |miner| {
+        miner.reboot(&ip, timeout_seconds)
+    })
 }
 
 pub async fn load_machines_from_feishu(
     excel: &str,
     sheets: Vec<&str>,
     pools_map: &HashMap<String, Vec<String>>,
-) -> Result<BTreeMap<String, Vec<Machine>>, MinerError> {
+) -> Result<(BTreeMap<String, Vec<Machine>>, Vec<RowError>), MinerError> {
     let mut machine_map: BTreeMap<String, Vec<Machine>> = BTreeMap::new();
+    let mut row_errors = vec![];
+    let mut members = HashMap::new();
+    let mut tags = HashMap::new();
+    let mut positions = HashMap::new();
+    let columns = sheet::get_columns();
     // go through sheets to load
     for sheet in sheets.iter() {
-        let json_result = feishu::query_sheet(excel, sheet).await?;
-        // query data.valueRange.values
-        let values = json_result["data"]["valueRange"]["values"]
-            .as_array()
-            .ok_or(MinerError::FeishuParserJsonError)?;
-        // ignore first row
-        for row in values.iter().skip(1) {
-            let miner_type;
+        let values = notify::query_sheet_values(excel, sheet).await?;
+        // first row is header, locate columns by name
+        let header = values.first().ok_or(MinerError::FeishuParserJsonError)?;
+        let cols = columns.resolve(header)?;
+        for (idx, row) in values.iter().enumerate().skip(1) {
             let account;
             let pool;
             let switch_account_name: Option<String>;
             let switch_pool: Option<String>;
             let switch_account: Option<Account>;
-            match row[0].as_str() {
-                Some("avalon") => {
-                    miner_type = MinerType::Avalon(AvalonMiner {});
-                }
-                Some("ant") => {
-                    miner_type = MinerType::Ant(AntMiner {});
-                }
-                Some("bluestar") => {
-                    miner_type = MinerType::BlueStar(BlueStarMiner {});
-                }
+            // rows without a type are not machines, a wrong type is reported and skipped
+            let miner_type = match row[cols.miner_type].as_str() {
+                Some(cell) if !cell.trim().is_empty() => match MinerType::try_from(cell) {
+                    Ok(miner_type) => miner_type,
+                    Err(e) => {
+                        row_errors.push(RowError {
+                            sheet: sheet.to_string(),
+                            row: idx + 1,
+                            ip: row[cols.ip].as_str().unwrap_or("").to_string(),
+                            error: format!("{}: {}", e, cell),
+                        });
+                        continue;
+                    }
+                },
                 _ => continue,
-            }
-            let ip = match row[3].as_str() {
+            };
+            let ip = match row[cols.ip].as_str() {
                 Some(ip) => ip,
                 None => continue,
             };
-            //let name = row[5].as_str().ok_or(MinerError::FeishuParserJsonError)?;
-            let status: MinerStatus = match row[4].as_str() {
-                Some(sts) => sts.into(),
+            let group = group::row_group(row, cols.group, sheet);
+            members.insert(ip.to_string(), group.clone());
+            tags.insert(
+                ip.to_string(),
+                group::row_tags(row, cols.miner_type, cols.addition_info, &group),
+            );
+            // an unknown status is reported, the machine is left alone as offline
+            let status = match row[cols.status].as_str() {
+                Some(label) => sheet::status(label).unwrap_or_else(|| {
+                    info!("{} row {}: unknown status {:?}", sheet, idx + 1, label);
+                    row_errors.push(RowError {
+                        sheet: sheet.to_string(),
+                        row: idx + 1,
+                        ip: ip.to_string(),
+                        error: format!("unknown status: {}, taken as offline", label),
+                    });
+                    MinerStatus::Offline
+                }),
                 _ => continue,
             };
-            let account_name = match row[8].as_str() {
+            let account_name = match row[cols.account].as_str() {
                 Some(account_name) => account_name,
                 None => continue,
             };
 
-            match row[9].as_str() {
+            match row[cols.pool].as_str() {
                 Some(main_pool) => {
                     // ignore empty string
                     if main_pool.len() > 0 {
@@ -404,7 +598,7 @@ pub async fn load_machines_from_feishu(
                 }
             }
 
-            match row[10].as_str() {
+            match sheet::cell(row, cols.switch_account) {
                 Some(acct) => {
                     // ignore empty string
                     if acct.len() > 0 {
@@ -418,7 +612,7 @@ pub async fn load_machines_from_feishu(
                 }
             }
 
-            match row[11].as_str() {
+            match sheet::cell(row, cols.switch_pool) {
                 Some(pool) => {
                     // ignore empty string
                     if pool.len() > 0 {
@@ -432,13 +626,13 @@ pub async fn load_machines_from_feishu(
                 }
             }
 
-            let main_account_working_mode = match row[12].as_str() {
-                Some(mode) => mode.to_string(),
+            let main_account_working_mode = match sheet::cell(row, cols.run_mode) {
+                Some(label) => mode::normalize(label),
                 None => "".to_string(),
             };
 
-            let switch_account_working_mode = match row[13].as_str() {
-                Some(mode) => mode.to_string(),
+            let switch_account_working_mode = match sheet::cell(row, cols.switch_run_mode) {
+                Some(label) => mode::normalize(label),
                 None => "".to_string(),
             };
 
@@ -451,7 +645,7 @@ pub async fn load_machines_from_feishu(
             account = Account {
                 id: 0,
                 name: account_name.to_string(),
-                password: "auto".to_string(),
+                password: "auto".into(),
                 pool1: pools[0].to_owned(),
                 pool2: pools[1].to_owned(),
                 pool3: pools[2].to_owned(),
@@ -467,7 +661,7 @@ pub async fn load_machines_from_feishu(
                     Some(Account {
                         id: 0,
                         name: name.to_string(),
-                        password: "auto".to_string(),
+                        password: "auto".into(),
                         pool1: pools[0].to_owned(),
                         pool2: pools[1].to_owned(),
                         pool3: pools[2].to_owned(),
@@ -477,15 +671,18 @@ pub async fn load_machines_from_feishu(
                 None => None,
             };
 
-            let addition_info = match row[14].as_str() {
+            let addition_info = match sheet::cell(row, cols.addition_info) {
                 Some(info) => info.to_string(),
                 None => "".to_string(),
             };
 
-            let position = match row[2].as_str() {
+            let position = match sheet::cell(row, cols.position) {
                 Some(pos) => pos.to_string(),
                 None => "".to_string(),
             };
+            if let Some(parsed) = position::parse(&position) {
+                positions.insert(ip.to_string(), parsed);
+            }
 
             let machine = Machine {
                 id: 0,
@@ -497,10 +694,8 @@ pub async fn load_machines_from_feishu(
                 switch_account: switch_account,
                 run_mode: "".to_string(),
                 addition_info: format!("{} {}", position, addition_info),
-                is_run_mode_fixed: match row[17].as_str() {
-                    Some("1") => true,
-                    _ => false,
-                },
+                is_run_mode_fixed: matches!(sheet::cell(row, cols.run_mode_fixed), Some("1")),
+                group,
             };
 
             // put into map
@@ -508,19 +703,21 @@ pub async fn load_machines_from_feishu(
             machines.push(machine);
         }
     }
+    group::set_members(members);
+    tag::set_tags(tags);
+    position::set_positions(positions);
+    for e in row_errors.iter() {
+        info!("skip sheet {} row {}: {}", e.sheet, e.row, e.error);
+    }
 
-    Ok(machine_map)
+    Ok((machine_map, row_errors))
 }
 
 pub async fn get_pools_from_feishu(
     excel: &str,
     sheet: &str,
 ) -> Result<HashMap<String, Vec<String>>, MinerError> {
-    let json_result = feishu::query_sheet(excel, sheet).await?;
-
-    let values = json_result["data"]["valueRange"]["values"]
-        .as_array()
-        .ok_or(MinerError::FeishuParserJsonError)?;
+    let values = notify::query_sheet_values(excel, sheet).await?;
 
     let mut pools_map: HashMap<String, Vec<String>> = HashMap::new();
 
@@ -540,54 +737,21 @@ pub async fn get_pools_from_feishu(
 }
 
 pub async fn get_perf_time_from_feishu(excel: &str, sheet: &str) -> Result<String, MinerError> {
-    let json_result = feishu::query_sheet(excel, sheet).await?;
-
-    let values = json_result["data"]["valueRange"]["values"]
-        .as_array()
-        .ok_or(MinerError::FeishuParserJsonError)?;
-
-    for row in values.iter().skip(1) {
-        let start = row[1].as_str().ok_or(MinerError::FeishuParserJsonError)?;
-        let end = row[2].as_str().ok_or(MinerError::FeishuParserJsonError)?;
-        let account_type = row[0].as_str().ok_or(MinerError::FeishuParserJsonError)?;
-        let time_config = TimeConfig::from_str(start, end, account_type)?;
-        if let Some(perf) = time_config.now_perf() {
-            return Ok(perf.to_string());
-        }
-    }
-
-    // default as 普通
-    Ok("普通".to_string())
+    let values = notify::query_sheet_values(excel, sheet).await?;
+    let windows = window::parse_rows(&values)?;
+    window::perf_at(&windows, clock::now().time())
 }
 
 pub async fn get_now_account_type_from_feishu(
     excel: &str,
     sheet: &str,
 ) -> Result<String, MinerError> {
-    let json_result = feishu::query_sheet(excel, sheet).await?;
-
-    let values = json_result["data"]["valueRange"]["values"]
-        .as_array()
-        .ok_or(MinerError::FeishuParserJsonError)?;
-
-    //let mut time_configs: TimeConfigs = vec![];
-    for row in values.iter().skip(1) {
-        let start = row[1].as_str().ok_or(MinerError::FeishuParserJsonError)?;
-        let end = row[2].as_str().ok_or(MinerError::FeishuParserJsonError)?;
-        let account_type = row[0].as_str().ok_or(MinerError::FeishuParserJsonError)?;
-        //time_configs.push(TimeConfig::from_str(start, end, account_type)?);
-        info!(
-            "start: {}, end: {}, account_type: {}",
-            start, end, account_type
-        );
-
-        let time_config = TimeConfig::from_str(start, end, account_type)?;
-        if let Some(account) = time_config.now_account() {
-            return Ok(account.to_string());
-        }
-    }
-
-    Err(MinerError::FeishuParserJsonError)
+    let values = notify::query_sheet_values(excel, sheet).await?;
+    let windows = window::parse_rows(&values)?;
+    let now = clock::now().time();
+    let account = window::account_at(&windows, now)?;
+    info!("account type at {}: {}", now, account);
+    Ok(account)
 }
 
 pub async fn switch_if_need(
@@ -597,18 +761,71 @@ pub async fn switch_if_need(
     account_time_sheet: &str,
     perf_time_sheet: &str,
     pool_sheet: &str,
-) -> Result<(), MinerError> {
+    selector: &GroupSelector,
+) -> Result<SwitchReport, MinerError> {
+    let _operation = shutdown::begin()?;
+    // two runs would each write the machines the other one is switching
+    let Ok(_running) = SWITCH_RUNNING.get().try_lock_owned() else {
+        return Err(MinerError::SwitchRunningError);
+    };
     info!("start switch action");
     let account_type = get_now_account_type_from_feishu(excel, account_time_sheet).await?;
-    let perf_mode = get_perf_time_from_feishu(excel, perf_time_sheet).await?;
-    let pools_map = get_pools_from_feishu(excel, pool_sheet).await?;
-    let machine_map = load_machines_from_feishu(excel, sheets, &pools_map).await?;
-    let mut handles = Vec::new();
+    // profitability decides the mode when configured, then the tariff policy, then the perf
+    // time sheet
+    let profit = profitability::current().await?;
+    let perf_mode = match &profit {
+        Some(profit) => profit.fleet_mode().to_string(),
+        None => match tariff::current().await? {
+            Some((_, mode)) => mode.to_string(),
+            None => get_perf_time_from_feishu(excel, perf_time_sheet).await?,
+        },
+    };
+    let mut pools_map = get_pools_from_feishu(excel, pool_sheet).await?;
+    // unreachable primaries go behind backups, machines pick up the order on switch
+    health::apply(&mut pools_map).await;
+    let (machine_map, row_errors) =
+        load_machines_from_feishu(excel, sheets.clone(), &pools_map).await?;
+    let mut report = SwitchReport {
+        row_errors,
+        ..Default::default()
+    };
+
+    // machines are recorded once the mode is applied, failed ones are retried next run
+    let is_sleeping = |ip: &str| tariff::applied_mode(ip).as_deref() == Some(tariff::MODE_SLEEP);
+    if perf_mode == tariff::MODE_SLEEP {
+        let slept = config_mode_batch(&runtime, &machine_map, tariff::MODE_SLEEP, |ip| {
+            !is_sleeping(ip)
+        })
+        .await;
+        for ip in slept.iter() {
+            tariff::record_mode(ip, tariff::MODE_SLEEP);
+        }
+        info!("fleet sleeping, skip switch");
+        return Ok(report);
+    }
+    // wake up, switch below applies the work mode of each account
+    let woken = config_mode_batch(&runtime, &machine_map, tariff::MODE_NORMAL, is_sleeping).await;
+    if !woken.is_empty() {
+        profitability::clear_sleeping();
+    }
+    for ip in woken.iter() {
+        tariff::record_mode(ip, &perf_mode);
+    }
+    // models not paying their power sleep while the rest of the fleet runs
+    let mut sleeps = vec![];
+    let mut wakes = vec![];
+    let mut switches = Vec::new();
+    let mut targets = Vec::new();
     let mut process_machines = vec![];
+    let mut process_accounts = vec![];
 
     for (miner_type, machines) in machine_map.iter() {
         for machine in machines {
-            if machine.switch_account.is_some() && machine.status == MinerStatus::Online {
+            if machine.switch_account.is_some()
+                && machine.status == MinerStatus::Online
+                && selector.matches(&machine.ip, &machine.group)
+                && !maintenance::is_in_maintenance(&machine.ip)
+            {
                 // switch account
                 let mut switch_account = if account_type == "main" {
                     machine.account.clone()
@@ -616,50 +833,101 @@ pub async fn switch_if_need(
                     machine.switch_account.clone().unwrap()
                 };
 
-                // check switch_account run_mode, if be “高功", the perf also should be "高功", then we set
-                if switch_account.run_mode == "高功"
-                    && (machine.is_run_mode_fixed || perf_mode == "高功")
-                {
-                    switch_account.run_mode = "高功".to_string();
-                } else {
-                    switch_account.run_mode = "普通".to_string();
+                let machine_mode = profit
+                    .as_ref()
+                    .and_then(|p| p.mode_of_ip(&machine.ip))
+                    .unwrap_or(&perf_mode);
+                if RunMode::of(machine_mode) == RunMode::Sleep {
+                    sleeps.push((machine.ip.clone(), RunMode::Sleep.to_string()));
+                    continue;
                 }
+                if profitability::take_sleeping(&machine.ip) {
+                    wakes.push((machine.ip.clone(), RunMode::Normal.to_string()));
+                }
+
+                // check switch_account run_mode, if be High, the perf also should be High, then we set
+                let high = RunMode::of(&switch_account.run_mode) == RunMode::High
+                    && (machine.is_run_mode_fixed || RunMode::of(machine_mode) == RunMode::High);
+                switch_account.run_mode = if high && !thermal::is_throttled(&machine.ip) {
+                    RunMode::High.to_string()
+                } else {
+                    RunMode::Normal.to_string()
+                };
 
                 let ip = machine.ip.clone();
-                let miner: MinerType = miner_type.as_str().into();
-                handles.push(runtime.spawn(miner.switch_account_if_diff(
-                    &ip,
-                    &switch_account,
-                    false,
-                )));
+                let Ok(miner) = MinerType::try_from(miner_type.as_str()) else {
+                    continue;
+                };
+                let switch = miner.switch_account_if_diff(&ip, &switch_account, false);
+                targets.push((ip.clone(), JobAction::Switch(switch_account.clone())));
+                switches.push((ip, switch));
 
                 process_machines.push(machine);
+                process_accounts.push((switch_account.name, switch_account.run_mode));
             }
         }
     }
 
+    let slept: Vec<String> = sleeps.iter().map(|(ip, _)| ip.clone()).collect();
+    let failed = config_mode_ips(&runtime, sleeps, db::EVENT_PROFITABILITY).await;
+    let slept: Vec<String> = slept
+        .into_iter()
+        .filter(|ip| !failed.contains(ip))
+        .collect();
+    profitability::set_sleeping(&slept);
+    // the switch below applies the work mode of each account
+    config_mode_ips(&runtime, wakes, db::EVENT_PROFITABILITY).await;
+
+    // waves by group priority, the switch reboots the machine
+    let job_id = job::start(job::JOB_SWITCH, targets);
+    let ips: Vec<String> = switches.iter().map(|(ip, _)| ip.clone()).collect();
+    let handles: Vec<_> = switches
+        .into_iter()
+        .zip(stagger::delays(&ips))
+        .map(|((ip, switch), delay)| {
+            context::spawn(&runtime, async move {
+                tokio::time::sleep(delay).await;
+                let _permit = group::permit(&ip).await;
+                let result = switch.await;
+                job::target_done(job_id, &ip, &result);
+                result
+            })
+        })
+        .collect();
+
     info!("switch action len: {:?}", handles.len());
     let result = futures::future::join_all(handles).await;
+    job::finish(job_id);
     info!("switch result len: {:?}", result.len());
 
     let mut error_ips: Vec<String> = vec![];
+    let mut error_machines: Vec<AlertMachine> = vec![];
     let mut result_iter = result.iter();
+    let mut statuses: HashMap<String, SheetStatus> = HashMap::new();
+    let now = clock::now().format("%Y-%m-%d %H:%M:%S").to_string();
 
-    for machine in process_machines {
+    for (machine, (account_name, run_mode)) in process_machines.iter().zip(process_accounts) {
+        let mut status = SheetStatus::default();
         match result_iter.next() {
             Some(res) => match res {
                 Ok(action_result) => match action_result {
                     Ok(_) => {
                         //info!("switch success: {}", &machine.ip);
+                        report.switched.push(machine.ip.clone());
+                        status.last_seen = Some(now.clone());
+                        status.current_account = Some(account_name.clone());
+                        status.last_error = Some("".to_string());
                     }
                     Err(e) => {
                         info!("switch failed: {} error: {:?}", &machine.ip, e);
                         error_ips.push(format!("[{}-{}]", &machine.ip, &machine.addition_info));
+                        status.last_error = Some(e.to_string());
                     }
                 },
                 Err(e) => {
                     info!("join switch failed: {}, error: {:?}", &machine.ip, e);
                     error_ips.push(format!("[{}-{}] ", &machine.ip, &machine.addition_info));
+                    status.last_error = Some(e.to_string());
                 }
             },
             None => {
@@ -667,36 +935,278 @@ pub async fn switch_if_need(
                 error_ips.push(format!("[{}-{}] ", &machine.ip, &machine.addition_info));
             }
         }
+        let error = match (&status.current_account, &status.last_error) {
+            (Some(_), _) => "",
+            (None, Some(e)) => e.as_str(),
+            (None, None) => "no result",
+        };
+        record_switch_state(&machine.ip, &account_name, &run_mode, error);
+        // only a successful switch sets the applied account
+        if status.current_account.is_none() {
+            report.failed.push((
+                machine.ip.clone(),
+                status.last_error.clone().unwrap_or_default(),
+            ));
+            error_machines.push(AlertMachine {
+                ip: machine.ip.clone(),
+                detail: template::render(
+                    "switch_failed.detail",
+                    &[("position", &machine.addition_info), ("error", &error)],
+                ),
+            });
+        }
+        statuses.insert(machine.ip.clone(), status);
     }
 
-    if error_ips.len() > 0 {
-        // check ERR_MAP, count when matched count > 3, notify
-        // let mut selected_ips = vec![];
-        // for ip in error_ips.iter() {
-        //     let count = err_map.entry(ip.to_string()).or_insert(0);
-        //     *count += 1;
-        //     if *count >= 3 {
-        //         *count = 0;
-        //         selected_ips.push(ip.to_string());
-        //     }
-        // }
-
-        //if selected_ips.len() > 0 {
-        let mut msg = format!(
-            "{} 访问故障: ",
-            chrono::Local::now().format("%H:%M:%S").to_string()
+    // bad rows get their error written back so they can be fixed in the sheet
+    for row_error in report.row_errors.iter().filter(|e| !e.ip.is_empty()) {
+        statuses.insert(
+            row_error.ip.clone(),
+            SheetStatus {
+                last_error: Some(row_error.error.clone()),
+                ..Default::default()
+            },
         );
-        //for ip in selected_ips.iter() {
+    }
+    if let Err(e) = write_sheet_status(excel, &sheets, &statuses).await {
+        info!("write switch status to sheet error: {:?}", e);
+    }
+
+    if error_ips.len() > 0 {
+        let mut msg = format!("{} 访问故障: ", clock::now().format("%H:%M:%S"));
         for ip in error_ips.iter() {
             msg.push_str(ip);
         }
         info!("{}", msg);
-        feishu::notify(&msg).await;
-        //}
+    }
+
+    // only machines failed for enough consecutive cycles go into the digest
+    let failed_count = error_machines.len();
+    let selected_machines = throttle::filter_failures(error_machines);
+    if !selected_machines.is_empty() {
+        // more than half failed looks like a site wide problem
+        let severity = if failed_count * 2 > report.switched.len() + report.failed.len() {
+            Severity::Critical
+        } else {
+            Severity::Warning
+        };
+        notifier::send_alert(&Alert {
+            kind: "switch_failed".to_string(),
+            title: template::render(
+                "switch_failed.title",
+                &[
+                    ("time", &clock::now().format("%H:%M:%S")),
+                    ("count", &selected_machines.len()),
+                ],
+            ),
+            severity,
+            content: "".to_string(),
+            machines: selected_machines,
+        })
+        .await;
     }
 
     info!("end switch action");
-    Ok(())
+    Ok(report)
+}
+
+/// apply run mode to the pending online machines, errors are logged, returns the applied ips
+async fn config_mode_batch(
+    runtime: &tokio::runtime::Handle,
+    machine_map: &BTreeMap<String, Vec<Machine>>,
+    mode: &str,
+    pending: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    let mut miners = vec![];
+    let mut ips = vec![];
+    for (miner_type, machines) in machine_map.iter() {
+        for machine in machines.iter().filter(|m| {
+            m.status == MinerStatus::Online
+                && !maintenance::is_in_maintenance(&m.ip)
+                && pending(&m.ip)
+        }) {
+            let Ok(miner) = MinerType::try_from(miner_type.as_str()) else {
+                continue;
+            };
+            ips.push(machine.ip.clone());
+            miners.push(miner);
+        }
+    }
+    if ips.is_empty() {
+        return ips;
+    }
+    let handles: Vec<_> = ips
+        .iter()
+        .cloned()
+        .zip(miners)
+        .zip(stagger::delays(&ips))
+        .map(|((ip, miner), delay)| {
+            let mode = mode.to_string();
+            context::spawn(runtime, async move {
+                tokio::time::sleep(delay).await;
+                let _permit = group::permit(&ip).await;
+                miner.config_mode(&ip, &mode, 3)
+            })
+        })
+        .collect();
+
+    info!("config mode {} for {} machines", mode, handles.len());
+    let results = futures::future::join_all(handles).await;
+    let mut applied = vec![];
+    for (ip, result) in ips.into_iter().zip(results) {
+        match result {
+            Ok(Ok(_)) => applied.push(ip),
+            Ok(Err(e)) => info!("config mode failed: {} error: {:?}", ip, e),
+            Err(e) => info!("join config mode failed: {} error: {:?}", ip, e),
+        }
+    }
+    applied
+}
+
+/// apply run mode per ip, each success is recorded as an event, returns failed ips
+pub(crate) async fn config_mode_ips(
+    runtime: &tokio::runtime::Handle,
+    modes: Vec<(String, String)>,
+    event_type: &str,
+) -> Vec<String> {
+    let Ok(_operation) = shutdown::begin() else {
+        return modes.into_iter().map(|(ip, _)| ip).collect();
+    };
+    let handles = modes.iter().map(|(ip, mode)| {
+        let ip = ip.clone();
+        let mode = mode.clone();
+        context::spawn(runtime, async move {
+            let _permit = group::permit(&ip).await;
+            with_miner(&ip, 3, |miner| miner.config_mode(&ip, &mode, 3))
+        })
+    });
+    let results = futures::future::join_all(handles).await;
+
+    let mut failed = vec![];
+    for ((ip, mode), result) in modes.iter().zip(results) {
+        match result {
+            Ok(Ok(_)) => {
+                let _ = db::insert_event(event_type, ip, mode);
+            }
+            Ok(Err(e)) => {
+                info!("{} config mode failed: {} error: {:?}", event_type, ip, e);
+                failed.push(ip.clone());
+            }
+            Err(e) => {
+                info!("{} join failed: {} error: {:?}", event_type, ip, e);
+                failed.push(ip.clone());
+            }
+        }
+    }
+    failed
+}
+
+/// set the pools on the machines, each success is recorded as an event, returns failed ips
+pub(crate) async fn config_pool_ips(
+    runtime: &tokio::runtime::Handle,
+    ips: Vec<String>,
+    pools: &[PoolConfig],
+    event_type: &str,
+) -> Vec<String> {
+    let Ok(_operation) = shutdown::begin() else {
+        return ips;
+    };
+    let handles = ips.iter().map(|ip| {
+        let ip = ip.clone();
+        let pools = pools.to_vec();
+        context::spawn(runtime, async move {
+            let _permit = group::permit(&ip).await;
+            with_miner(&ip, 3, |miner| miner.config_pool(&ip, &pools, 3))
+        })
+    });
+    let results = futures::future::join_all(handles).await;
+
+    let detail = pools
+        .iter()
+        .map(|p| p.url.as_str())
+        .collect::<Vec<&str>>()
+        .join(",");
+    let mut failed = vec![];
+    for (ip, result) in ips.iter().zip(results) {
+        match result {
+            Ok(Ok(_)) => {
+                let _ = db::insert_event(event_type, ip, &detail);
+            }
+            Ok(Err(e)) => {
+                info!("{} config pool failed: {} error: {:?}", event_type, ip, e);
+                failed.push(ip.clone());
+            }
+            Err(e) => {
+                info!("{} join failed: {} error: {:?}", event_type, ip, e);
+                failed.push(ip.clone());
+            }
+        }
+    }
+    failed
+}
+
+/// run the shell command on the machines, returns the failed ips
+pub(crate) async fn exec_ips(
+    runtime: &tokio::runtime::Handle,
+    ips: Vec<String>,
+    command: &str,
+    event_type: &str,
+) -> Vec<String> {
+    let Ok(_operation) = shutdown::begin() else {
+        return ips;
+    };
+    let handles = ips.iter().map(|ip| {
+        let ip = ip.clone();
+        let command = command.to_string();
+        context::spawn(runtime, async move {
+            with_miner(&ip, 3, |miner| miner.exec(&ip, &command))
+        })
+    });
+    let results = futures::future::join_all(handles).await;
+
+    let mut failed = vec![];
+    for (ip, result) in ips.iter().zip(results) {
+        match result {
+            Ok(Ok(_)) => {
+                let _ = db::insert_event(event_type, ip, command);
+            }
+            Ok(Err(e)) => {
+                info!("{} exec failed: {} error: {:?}", event_type, ip, e);
+                failed.push(ip.clone());
+            }
+            Err(e) => {
+                info!("{} join failed: {} error: {:?}", event_type, ip, e);
+                failed.push(ip.clone());
+            }
+        }
+    }
+    failed
+}
+
+/// write per machine status into the configured status columns of the sheets
+pub async fn write_sheet_status(
+    excel: &str,
+    sheets: &[&str],
+    statuses: &HashMap<String, SheetStatus>,
+) -> Result<(), MinerError> {
+    let columns = sheet::get_columns();
+    if !columns.has_status_columns() || statuses.is_empty() {
+        return Ok(());
+    }
+
+    let mut ranges = vec![];
+    for sheet in sheets.iter() {
+        let values = notify::query_sheet_values(excel, sheet).await?;
+        let header = values.first().ok_or(MinerError::FeishuParserJsonError)?;
+        let cols = columns.resolve(header)?;
+        for (idx, row) in values.iter().enumerate().skip(1) {
+            if let Some(status) = row[cols.ip].as_str().and_then(|ip| statuses.get(ip)) {
+                ranges.extend(sheet::status_ranges(sheet, idx + 1, &cols, status));
+            }
+        }
+    }
+
+    notify::update_sheet_ranges(excel, ranges).await
 }
 
 fn get_pool(
@@ -728,7 +1238,7 @@ pub async fn scan(
     offset: i32,
     count: i32,
     timeout_seconds: i64,
-) -> Result<Vec<MachineInfo>, String> {
+) -> Result<Vec<MachineInfo>, MinerError> {
     let ip_prefix = ip_demo.split('.').take(3).collect::<Vec<&str>>().join(".");
     info!(
         "scan_and_update_db ip_prefix: {} {} {}",
@@ -738,7 +1248,13 @@ pub async fn scan(
     let mut handles = vec![];
     for i in offset..(offset + count) {
         let ip = format!("{}.{}", ip_prefix, i);
-        handles.push(runtime.spawn(async move { scan_miner_detail(ip, timeout_seconds).await }));
+        handles.push(context::spawn(&runtime, async move {
+            let _permit = group::permit(&ip).await;
+            let probe = subnet::permit(&ip).await;
+            let result = scan_miner_detail(ip, timeout_seconds).await;
+            probe.record(&result);
+            result
+        }));
     }
 
     let result = futures::future::join_all(handles).await;
@@ -759,22 +1275,57 @@ pub async fn scan(
             }
         }
     }
+    inventory::apply().await;
 
     Ok(machines)
 }
 
+/// scan the range and diff the machines against the last scan of it
+pub async fn scan_diff(
+    runtime: tokio::runtime::Handle,
+    ip_demo: &str,
+    offset: i32,
+    count: i32,
+    timeout_seconds: i64,
+) -> Result<ScanDiff, MinerError> {
+    let ip_prefix = ip_demo.split('.').take(3).collect::<Vec<&str>>().join(".");
+    let machines = scan(runtime, ip_demo, offset, count, timeout_seconds).await?;
+    let diff = inventory::compare(
+        &inventory::range_of(&ip_prefix, offset, count),
+        machines,
+        chrono::Local::now().timestamp(),
+    )?;
+    info!(
+        "scan diff {}: {} new, {} gone, {} changed, {} moved",
+        diff.range,
+        diff.new.len(),
+        diff.gone.len(),
+        diff.changed.len(),
+        diff.moved.len()
+    );
+    Ok(diff)
+}
+
 pub async fn watching(
     runtime: tokio::runtime::Handle,
     ips: Vec<String>,
     timeout_seconds: i64,
-) -> Result<Vec<MachineInfo>, String> {
+) -> Result<Vec<MachineInfo>, MinerError> {
     info!("watching ips: {:?}", ips);
     let mut handles = vec![];
     for ip in ips {
-        handles.push(runtime.spawn(async move { scan_miner_detail(ip, timeout_seconds).await }));
+        handles.push(context::spawn(&runtime, async move {
+            let _permit = group::permit(&ip).await;
+            let probe = subnet::permit(&ip).await;
+            let result = scan_miner_detail(ip, timeout_seconds).await;
+            probe.record(&result);
+            result
+        }));
     }
 
     let result = futures::future::join_all(handles).await;
+    conn::evict_idle();
+    route::evict_idle();
 
     let mut machines = vec![];
     for res in result {
@@ -791,18 +1342,73 @@ pub async fn watching(
         }
     }
 
+    let records: Vec<MachineRecord> = machines.iter().map(|m| m.record.clone()).collect();
+    thermal::apply(&runtime, &records).await;
+    zone::apply(&records).await;
+    boards::apply(&machines).await;
+    restart::apply(&runtime, &records).await;
+    anomaly::apply(&records).await;
+    inventory::apply().await;
+
     Ok(machines)
 }
 
-pub async fn reboot_batch(runtime: tokio::runtime::Handle, ips: Vec<String>) -> Result<(), String> {
-    let mut handles = vec![];
+/// watching, then write last seen, current account and hashrate back to the sheets
+pub async fn watching_with_sheet_status(
+    runtime: tokio::runtime::Handle,
+    ips: Vec<String>,
+    timeout_seconds: i64,
+    excel: &str,
+    sheets: Vec<&str>,
+) -> Result<Vec<MachineInfo>, MinerError> {
+    let machines = watching(runtime, ips.clone(), timeout_seconds).await?;
+
+    let now = clock::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let mut statuses: HashMap<String, SheetStatus> = HashMap::new();
     for ip in ips {
-        handles.push(runtime.spawn(async move { scan_reboot(ip) }));
+        statuses.insert(
+            ip,
+            SheetStatus {
+                last_error: Some(template::render("sheet.unreachable", &[])),
+                ..Default::default()
+            },
+        );
+    }
+    for machine in machines.iter() {
+        statuses.insert(
+            machine.ip.clone(),
+            SheetStatus {
+                last_seen: Some(now.clone()),
+                current_account: Some(machine.worker1.clone()),
+                hashrate: Some(machine.hash_avg.clone()),
+                last_error: Some("".to_string()),
+            },
+        );
     }
 
-    let _result = futures::future::join_all(handles).await;
+    if let Err(e) = write_sheet_status(excel, &sheets, &statuses).await {
+        info!("write watching status to sheet error: {:?}", e);
+    }
+
+    Ok(machines)
+}
+
+pub async fn reboot_batch(
+    runtime: tokio::runtime::Handle,
+    ips: Vec<String>,
+    timeout_seconds: i64,
+) -> Result<(), MinerError> {
+    let _operation = shutdown::begin()?;
+    let handles = ips.iter().cloned().map(|ip| {
+        context::spawn(&runtime, async move {
+            let _permit = group::permit(&ip).await;
+            scan_reboot(ip.clone(), timeout_seconds).map_err(|e| e.context(&ip, "reboot"))
+        })
+    });
+    let result = futures::future::join_all(handles).await;
 
-    Ok(())
+    let (succeeded, failed) = batch_failures(&ips, result);
+    Ok(BatchError::check(succeeded, failed)?)
 }
 
 pub async fn config_batch(
@@ -810,49 +1416,163 @@ pub async fn config_batch(
     ips: Vec<String>,
     pools: Vec<PoolConfig>,
     run_mode: String,
-) -> Result<i64, String> {
-    let mut handles = vec![];
-    for ip in ips {
+    timeout_seconds: i64,
+) -> Result<i64, MinerError> {
+    let _operation = shutdown::begin()?;
+    let job_id = job::start(
+        job::JOB_CONFIG,
+        ips.iter()
+            .map(|ip| {
+                let action = JobAction::Config {
+                    pools: pools.clone(),
+                    run_mode: run_mode.clone(),
+                };
+                (ip.clone(), action)
+            })
+            .collect(),
+    );
+    let delays = stagger::delays(&ips);
+    let handles = ips.iter().cloned().zip(delays).map(|(ip, delay)| {
         let act = pools.clone();
         let md = run_mode.clone();
-        handles.push(runtime.spawn(async move {
-            let miner = find_miner(&ip, 3)?;
-            miner.config(&ip, &md, &act)
-        }));
-    }
+        context::spawn(&runtime, async move {
+            tokio::time::sleep(delay).await;
+            let _permit = group::permit(&ip).await;
+            let result = with_miner(&ip, timeout_seconds, |miner| {
+                miner.config(&ip, &md, &act, timeout_seconds)
+            });
+            job::target_done(job_id, &ip, &result);
+            result.map_err(|e| e.context(&ip, "config"))
+        })
+    });
+    let result = futures::future::join_all(handles).await;
+    job::finish(job_id);
 
+    let (succeeded, failed) = batch_failures(&ips, result);
+    BatchError::check(succeeded, failed)?;
+    Ok(succeeded as i64)
+}
+
+/// push the account to every ip, the diff check is skipped
+pub async fn force_switch_batch(
+    runtime: tokio::runtime::Handle,
+    ips: Vec<String>,
+    account: Account,
+    timeout_seconds: i64,
+) -> Result<(), MinerError> {
+    let _operation = shutdown::begin()?;
+    let job_id = job::start(
+        job::JOB_FORCE_SWITCH,
+        ips.iter()
+            .map(|ip| (ip.clone(), JobAction::Switch(account.clone())))
+            .collect(),
+    );
+    let handles = ips.iter().cloned().map(|ip| {
+        let account = account.clone();
+        context::spawn(&runtime, async move {
+            let _permit = group::permit(&ip).await;
+            let result = match find_miner(&ip, timeout_seconds) {
+                Ok(miner) => {
+                    let account = with_pool_prefix(&miner, account);
+                    let result = miner.switch_account_if_diff(&ip, &account, true).await;
+                    let error = result
+                        .as_ref()
+                        .err()
+                        .map(|e| e.to_string())
+                        .unwrap_or_default();
+                    record_switch_state(&ip, &account.name, &account.run_mode, &error);
+                    result
+                }
+                Err(e) => Err(e),
+            };
+            job::target_done(job_id, &ip, &result);
+            result.map_err(|e| e.context(&ip, "force switch"))
+        })
+    });
     let result = futures::future::join_all(handles).await;
-    let mut count = 0;
-    for res in result {
+    job::finish(job_id);
+
+    let (succeeded, failed) = batch_failures(&ips, result);
+    Ok(BatchError::check(succeeded, failed)?)
+}
+
+pub(crate) fn record_switch_state(ip: &str, account: &str, run_mode: &str, error: &str) {
+    let state = SwitchState {
+        ip: ip.to_string(),
+        account: account.to_string(),
+        run_mode: run_mode.to_string(),
+        error: error.to_string(),
+        update_time: chrono::Local::now().timestamp(),
+    };
+    if let Err(e) = db::set_switch_state(&state) {
+        info!("save switch state {} error: {:?}", ip, e);
+    }
+}
+
+/// last applied account of the ips, every machine when empty
+pub fn query_switch_state(ips: &[String]) -> Result<Vec<SwitchState>, MinerError> {
+    let states = db::query_switch_state()?;
+    Ok(states
+        .into_iter()
+        .filter(|s| ips.is_empty() || ips.contains(&s.ip))
+        .collect())
+}
+
+// pools as get_pool builds them from the pool sheet, avalon needs the scheme
+pub(crate) fn with_pool_prefix(miner: &MinerType, mut account: Account) -> Account {
+    let prefix = match miner {
+        MinerType::Avalon(_) => "stratum+tcp://",
+        _ => return account,
+    };
+    for pool in [&mut account.pool1, &mut account.pool2, &mut account.pool3] {
+        if !pool.is_empty() && !pool.contains("://") {
+            *pool = format!("{}{}", prefix, pool);
+        }
+    }
+    account
+}
+
+// count of successes and the error of every failed ip
+fn batch_failures(
+    ips: &[String],
+    result: Vec<Result<Result<(), MinerError>, tokio::task::JoinError>>,
+) -> (usize, Vec<(String, MinerError)>) {
+    let mut succeeded = 0;
+    let mut failed = vec![];
+    for (ip, res) in ips.iter().zip(result) {
         match res {
-            Ok(Ok(())) => {
-                count += 1;
-            }
+            Ok(Ok(())) => succeeded += 1,
             Ok(Err(e)) => {
-                info!("scan_and_update_db error: {:?}", e);
+                info!("batch {} error: {:?}", ip, e);
+                failed.push((ip.clone(), e));
             }
             Err(e) => {
-                info!("scan_and_update_db join error: {:?}", e);
+                info!("batch {} join error: {:?}", ip, e);
+                failed.push((ip.clone(), e.into()));
             }
         }
     }
-
-    Ok(count)
+    (succeeded, failed)
 }
 
 //test
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "feishu")]
+    use crate::notify::feishu;
     use tokio::runtime::Runtime;
 
     lazy_static! {
         static ref SETUP: () = {
             env_logger::init();
-            let cli_id = std::env::var("CLIENT_ID").expect("CLIENT_ID is not set in env");
-            let secret = std::env::var("SECRET").expect("SECRET is not set in env");
-            let bot = std::env::var("BOT").expect("BOT is not set in env");
-            feishu::init(&cli_id, &secret, &bot);
+            #[cfg(feature = "feishu")]
+            {
+                let cli_id = std::env::var("CLIENT_ID").expect("CLIENT_ID is not set in env");
+                let secret = std::env::var("SECRET").expect("SECRET is not set in env");
+                let bot = std::env::var("BOT").expect("BOT is not set in env");
+                feishu::init(&cli_id, &secret, &bot);
+            }
         };
 
         static ref TEST_RUNTIME: Runtime = tokio::runtime::Builder::new_multi_thread()
@@ -862,7 +1582,90 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_lock_machine() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let (running, most) = (running.clone(), most.clone());
+                // two machines, four callers each
+                let ip = format!("10.253.0.{}", i % 2);
+                std::thread::spawn(move || {
+                    lock_machine(&ip, || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // at most one per machine at a time, locks dropped once idle
+        assert!(most.load(Ordering::SeqCst) <= 2);
+        let locks = MACHINE_LOCKS.lock().unwrap();
+        assert!(!locks.keys().any(|ip| ip.starts_with("10.253.0.")));
+    }
+
+    #[test]
+    fn test_miner_type_try_from() {
+        #[cfg(feature = "ant-http")]
+        assert!(matches!(MinerType::try_from("ant"), Ok(MinerType::Ant(_))));
+        assert!(matches!(
+            MinerType::try_from(" avalon "),
+            Ok(MinerType::Avalon(_))
+        ));
+        assert!(matches!(
+            MinerType::try_from("antt"),
+            Err(MinerError::MinerNotSupportError)
+        ));
+    }
+
+    #[test]
+    fn test_classify_version() {
+        let avalon = "STATUS=S,When=1700000000,Code=22,Msg=CGMiner versions,Description=cgminer 4.11.1|VERSION,CGMiner=4.11.1,API=3.7,PROD=AvalonMiner 1246,MODEL=1246-83|";
+        assert!(matches!(
+            classify_version(avalon),
+            Some(MinerType::Avalon(_))
+        ));
+        #[cfg(feature = "ant-http")]
+        {
+            let ant = "STATUS=S,When=1700000000,Code=22,Msg=BMMiner versions|VERSION,BMMiner=1.0.0,API=3.1,Type=Antminer S19j Pro|";
+            assert!(matches!(classify_version(ant), Some(MinerType::Ant(_))));
+        }
+        assert!(classify_version("STATUS=E,Msg=Invalid command").is_none());
+    }
+
+    #[test]
+    fn test_force_switch_pool_prefix() {
+        let account = Account {
+            id: 0,
+            name: "acct".to_string(),
+            password: "auto".into(),
+            pool1: "btc.f2pool.com:1314".to_string(),
+            pool2: "stratum+tcp://btc-asia.f2pool.com:1314".to_string(),
+            pool3: "".to_string(),
+            run_mode: "".to_string(),
+        };
+        let avalon = classify_version("PROD=AvalonMiner 1246").unwrap();
+        let prefixed = with_pool_prefix(&avalon, account.clone());
+        assert_eq!(prefixed.pool1, "stratum+tcp://btc.f2pool.com:1314");
+        assert_eq!(prefixed.pool2, account.pool2);
+        assert_eq!(prefixed.pool3, "");
+
+        #[cfg(feature = "ant-http")]
+        {
+            let ant = classify_version("Type=Antminer S19j Pro").unwrap();
+            assert_eq!(with_pool_prefix(&ant, account.clone()).pool1, account.pool1);
+        }
+    }
+
     #[tokio::test]
+    #[ignore = "needs feishu credentials"]
     async fn test_now_account() {
         let _ = &*SETUP;
         let account = get_now_account_type_from_feishu("PwjYsZoefh6rXZt3mIucC9XmnZb", "hoH6Gm")
@@ -872,6 +1675,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs feishu credentials"]
     async fn test_pools_map() {
         let _ = &*SETUP;
         let pools_map = get_pools_from_feishu("PwjYsZoefh6rXZt3mIucC9XmnZb", "IHJgN0")
@@ -887,6 +1691,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs feishu credentials and miners on the lab network"]
     async fn test_auto_switch() {
         let _ = &*SETUP;
 
@@ -897,6 +1702,7 @@ mod tests {
             "hoH6Gm",
             "u9zVVA",
             "IHJgN0",
+            &GroupSelector::All,
         )
         .await
         .unwrap();
@@ -904,6 +1710,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs miners on the lab network"]
     async fn test_scan_and_update_db() {
         let _ = &*SETUP;
 
@@ -0,0 +1,384 @@
+/// the whole fleet definition in one versioned json document: the machine rows of the sheets
+/// with their accounts and pools, the pool sheet, the account and perf time sheets and the
+/// pool worker names. exported to back it up or review it in git, imported to restore it
+/// onto the sheets of a new controller
+use std::collections::{BTreeMap, HashSet};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::sheet::{self, SheetColumnIndex, SheetColumns};
+use crate::clock;
+use crate::error::MinerError;
+use crate::notify;
+use crate::pools::pool;
+
+/// version of the document written by export, import reads this and older ones
+pub const FLEET_VERSION: u32 = 1;
+
+/// one machine row, cells as text
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FleetMachine {
+    pub ip: String,
+    pub miner_type: String,
+    pub status: String,
+    pub account: String,
+    pub pool: String,
+    pub switch_account: String,
+    pub switch_pool: String,
+    pub run_mode: String,
+    pub switch_run_mode: String,
+    pub run_mode_fixed: bool,
+    pub group: String,
+    pub position: String,
+    pub addition_info: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FleetSheet {
+    pub name: String,
+    /// in sheet order
+    pub machines: Vec<FleetMachine>,
+}
+
+/// a sheet kept as it is, rows of text with the header first
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FleetTable {
+    pub name: String,
+    pub rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FleetDocument {
+    pub version: u32,
+    pub exported_at: i64,
+    pub machine_sheets: Vec<FleetSheet>,
+    pub pool_sheet: String,
+    /// pool type to its three pools
+    pub pools: BTreeMap<String, Vec<String>>,
+    pub account_time: FleetTable,
+    pub perf_time: FleetTable,
+    /// ip to pool worker
+    #[serde(default)]
+    pub worker_names: BTreeMap<String, String>,
+}
+
+/// what an import wrote
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FleetImport {
+    pub sheets: Vec<String>,
+    pub machines: usize,
+    pub pools: usize,
+}
+
+impl FleetDocument {
+    pub fn to_json(&self) -> Result<String, MinerError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// documents of a newer version are refused
+    pub fn from_json(json: &str) -> Result<FleetDocument, MinerError> {
+        let document: FleetDocument = serde_json::from_str(json)?;
+        if document.version == 0 || document.version > FLEET_VERSION {
+            return Err(MinerError::FleetDocumentError(format!(
+                "version {} not supported, {} at most",
+                document.version, FLEET_VERSION
+            )));
+        }
+        Ok(document)
+    }
+
+    /// ips listed more than once, sorted
+    pub fn duplicate_ips(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut duplicates: Vec<String> = self
+            .machine_sheets
+            .iter()
+            .flat_map(|s| s.machines.iter())
+            .filter(|m| !seen.insert(m.ip.as_str()))
+            .map(|m| m.ip.clone())
+            .collect();
+        duplicates.sort();
+        duplicates.dedup();
+        duplicates
+    }
+}
+
+// numbers and bools of the sheets as text, empty for missing cells
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn cell(row: &Value, idx: Option<usize>) -> String {
+    idx.map(|i| text(&row[i])).unwrap_or_default()
+}
+
+/// machine rows of a sheet, rows without an ip are left out
+pub fn machines_of(values: &[Value], cols: &SheetColumnIndex) -> Vec<FleetMachine> {
+    values
+        .iter()
+        .skip(1)
+        .filter_map(|row| {
+            let ip = cell(row, Some(cols.ip)).trim().to_string();
+            if ip.is_empty() {
+                return None;
+            }
+            Some(FleetMachine {
+                ip,
+                miner_type: cell(row, Some(cols.miner_type)),
+                status: cell(row, Some(cols.status)),
+                account: cell(row, Some(cols.account)),
+                pool: cell(row, Some(cols.pool)),
+                switch_account: cell(row, cols.switch_account),
+                switch_pool: cell(row, cols.switch_pool),
+                run_mode: cell(row, cols.run_mode),
+                switch_run_mode: cell(row, cols.switch_run_mode),
+                run_mode_fixed: cell(row, cols.run_mode_fixed).trim() == "1",
+                group: cell(row, cols.group),
+                position: cell(row, cols.position),
+                addition_info: cell(row, cols.addition_info),
+            })
+        })
+        .collect()
+}
+
+// column name and the cell of a machine
+type Column<'a> = (&'a str, fn(&FleetMachine) -> String);
+
+/// header and rows of the machines under the configured column names, columns configured
+/// empty are left out
+pub fn machine_rows(machines: &[FleetMachine], columns: &SheetColumns) -> Vec<Vec<Value>> {
+    let fields: [Column; 13] = [
+        (&columns.miner_type, |m| m.miner_type.clone()),
+        (&columns.ip, |m| m.ip.clone()),
+        (&columns.status, |m| m.status.clone()),
+        (&columns.account, |m| m.account.clone()),
+        (&columns.pool, |m| m.pool.clone()),
+        (&columns.switch_account, |m| m.switch_account.clone()),
+        (&columns.switch_pool, |m| m.switch_pool.clone()),
+        (&columns.run_mode, |m| m.run_mode.clone()),
+        (&columns.switch_run_mode, |m| m.switch_run_mode.clone()),
+        (&columns.run_mode_fixed, |m| {
+            if m.run_mode_fixed { "1" } else { "" }.to_string()
+        }),
+        (&columns.group, |m| m.group.clone()),
+        (&columns.position, |m| m.position.clone()),
+        (&columns.addition_info, |m| m.addition_info.clone()),
+    ];
+    let fields: Vec<_> = fields.iter().filter(|(name, _)| !name.is_empty()).collect();
+
+    let header = fields.iter().map(|(name, _)| Value::from(*name)).collect();
+    let rows = machines
+        .iter()
+        .map(|m| fields.iter().map(|(_, get)| Value::from(get(m))).collect());
+    std::iter::once(header).chain(rows).collect()
+}
+
+async fn read_table(excel: &str, sheet: &str) -> Result<FleetTable, MinerError> {
+    let values = notify::query_sheet_values(excel, sheet).await?;
+    Ok(FleetTable {
+        name: sheet.to_string(),
+        rows: values
+            .iter()
+            .map(|row| row.as_array().map(|cells| cells.iter().map(text).collect()))
+            .map(Option::unwrap_or_default)
+            .collect(),
+    })
+}
+
+/// read the fleet from the sheets the switch reads
+pub async fn export(
+    excel: &str,
+    sheets: Vec<&str>,
+    account_time_sheet: &str,
+    perf_time_sheet: &str,
+    pool_sheet: &str,
+) -> Result<FleetDocument, MinerError> {
+    let columns = sheet::get_columns();
+    let mut machine_sheets = vec![];
+    for name in sheets {
+        let values = notify::query_sheet_values(excel, name).await?;
+        let header = values.first().ok_or(MinerError::FeishuParserJsonError)?;
+        let cols = columns.resolve(header)?;
+        machine_sheets.push(FleetSheet {
+            name: name.to_string(),
+            machines: machines_of(&values, &cols),
+        });
+    }
+
+    let pools = super::entry::get_pools_from_feishu(excel, pool_sheet)
+        .await?
+        .into_iter()
+        .collect();
+    let document = FleetDocument {
+        version: FLEET_VERSION,
+        exported_at: clock::now().timestamp(),
+        machine_sheets,
+        pool_sheet: pool_sheet.to_string(),
+        pools,
+        account_time: read_table(excel, account_time_sheet).await?,
+        perf_time: read_table(excel, perf_time_sheet).await?,
+        worker_names: pool::worker_names().into_iter().collect(),
+    };
+    info!(
+        "exported fleet of {} machines",
+        document
+            .machine_sheets
+            .iter()
+            .map(|s| s.machines.len())
+            .sum::<usize>()
+    );
+    Ok(document)
+}
+
+// replace the sheet from A1, rows it had beyond the new ones are blanked
+async fn write_table(excel: &str, sheet: &str, rows: Vec<Vec<Value>>) -> Result<(), MinerError> {
+    let existing = notify::query_sheet_values(excel, sheet).await?;
+    let width = rows
+        .iter()
+        .map(|r| r.len())
+        .chain(existing.iter().map(|r| r.as_array().map_or(0, |c| c.len())))
+        .max()
+        .unwrap_or(0);
+    let height = rows.len().max(existing.len());
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let mut rows = rows;
+    rows.resize(height, vec![]);
+    for row in rows.iter_mut() {
+        row.resize(width, Value::from(""));
+    }
+    let range = format!("{}!A1:{}{}", sheet, sheet::column_name(width - 1), height);
+    notify::update_sheet_range(excel, &range, rows).await
+}
+
+fn table_rows(table: &FleetTable) -> Vec<Vec<Value>> {
+    table
+        .rows
+        .iter()
+        .map(|row| row.iter().map(|c| Value::from(c.as_str())).collect())
+        .collect()
+}
+
+/// write the fleet onto the sheets named in the document and take its worker names, the
+/// worker names last until the next init unless put in the config too
+pub async fn import(excel: &str, document: &FleetDocument) -> Result<FleetImport, MinerError> {
+    let duplicates = document.duplicate_ips();
+    if !duplicates.is_empty() {
+        return Err(MinerError::FleetDocumentError(format!(
+            "duplicate ips: {}",
+            duplicates.join(", ")
+        )));
+    }
+
+    let columns = sheet::get_columns();
+    let mut report = FleetImport::default();
+    for machine_sheet in document.machine_sheets.iter() {
+        let rows = machine_rows(&machine_sheet.machines, &columns);
+        write_table(excel, &machine_sheet.name, rows).await?;
+        report.sheets.push(machine_sheet.name.clone());
+        report.machines += machine_sheet.machines.len();
+    }
+
+    let pool_rows = document
+        .pools
+        .iter()
+        .map(|(pool_type, pools)| {
+            std::iter::once(pool_type)
+                .chain(pools.iter())
+                .map(|c| Value::from(c.as_str()))
+                .collect()
+        })
+        .collect();
+    write_table(excel, &document.pool_sheet, pool_rows).await?;
+    report.sheets.push(document.pool_sheet.clone());
+    report.pools = document.pools.len();
+
+    for table in [&document.account_time, &document.perf_time] {
+        write_table(excel, &table.name, table_rows(table)).await?;
+        report.sheets.push(table.name.clone());
+    }
+
+    pool::set_worker_names(document.worker_names.clone().into_iter().collect());
+    info!(
+        "imported fleet of {} machines into {} sheets",
+        report.machines,
+        report.sheets.len()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fleet_rows() {
+        let columns = SheetColumns {
+            group: "".to_string(),
+            ..Default::default()
+        };
+        let machines = vec![
+            FleetMachine {
+                ip: "192.168.1.10".to_string(),
+                miner_type: "ant".to_string(),
+                status: "上线".to_string(),
+                account: "acc".to_string(),
+                pool: "btc".to_string(),
+                run_mode: "高功".to_string(),
+                run_mode_fixed: true,
+                position: "A-03-12-4".to_string(),
+                ..Default::default()
+            },
+            FleetMachine {
+                ip: "192.168.1.11".to_string(),
+                miner_type: "avalon".to_string(),
+                switch_account: "acc2".to_string(),
+                switch_pool: "btc2".to_string(),
+                ..Default::default()
+            },
+        ];
+        let rows = machine_rows(&machines, &columns);
+        assert_eq!(rows[0].len(), 12);
+        let values: Vec<Value> = rows.into_iter().map(Value::from).collect();
+        let cols = columns.resolve(&values[0]).unwrap();
+        assert_eq!(cols.group, None);
+        assert_eq!(machines_of(&values, &cols), machines);
+
+        // cells of other types come back as text
+        let values = vec![
+            values[0].clone(),
+            serde_json::json!(["ant", "10.0.0.1", null, 7]),
+        ];
+        let cols = SheetColumns::default().resolve(&values[0]).unwrap();
+        let read = machines_of(&values, &cols);
+        assert_eq!(
+            (read[0].status.as_str(), read[0].account.as_str()),
+            ("", "7")
+        );
+
+        let document = FleetDocument {
+            version: FLEET_VERSION,
+            machine_sheets: vec![FleetSheet {
+                name: "ftMgRx".to_string(),
+                machines: vec![machines[0].clone(), machines[0].clone()],
+            }],
+            ..Default::default()
+        };
+        let json = document.to_json().unwrap();
+        assert_eq!(
+            FleetDocument::from_json(&json).unwrap().duplicate_ips(),
+            ["192.168.1.10"]
+        );
+        let newer = json.replacen("\"version\": 1", "\"version\": 2", 1);
+        assert!(FleetDocument::from_json(&newer).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+/// site/zone grouping of machines, selectors for batch operations and per group limits
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::position;
+use super::sheet;
+use super::tag::{self, TagExpr};
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::notify::{self, notifier::NotifySink, Alert};
+
+static GROUPS: Local<HashMap<String, GroupConfig>> = Local::new(HashMap::new);
+static LIMITS: Local<HashMap<String, Arc<Semaphore>>> = Local::new(HashMap::new);
+// ip to group, refreshed whenever machine sheets are loaded
+static MEMBERS: Local<HashMap<String, String>> = Local::new(HashMap::new);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupConfig {
+    /// group cell value, or sheet name when the sheet has no group column
+    pub name: String,
+    /// machines of the group operated at the same time, 0 for unlimited
+    pub max_concurrency: usize,
+    /// extra sinks receiving alerts of the group machines, e.g. the site feishu bot
+    pub notify_sinks: Vec<NotifySink>,
+    /// lower goes first in staggered switches, machines of unconfigured groups are 0
+    pub priority: i32,
+}
+
+/// target of a batch operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GroupSelector {
+    /// every loaded machine
+    All,
+    Groups(Vec<String>),
+    Ips(Vec<String>),
+    /// machines whose tags match, see TagExpr
+    Tags(TagExpr),
+}
+
+impl From<Vec<String>> for GroupSelector {
+    fn from(ips: Vec<String>) -> Self {
+        GroupSelector::Ips(ips)
+    }
+}
+
+impl GroupSelector {
+    /// selector of a tag expression like "rack:A7 AND model:1246"
+    pub fn tags(expr: &str) -> Result<GroupSelector, MinerError> {
+        Ok(GroupSelector::Tags(TagExpr::parse(expr)?))
+    }
+
+    pub fn matches(&self, ip: &str, group: &str) -> bool {
+        match self {
+            GroupSelector::All => true,
+            GroupSelector::Groups(groups) => groups.iter().any(|g| g == group),
+            GroupSelector::Ips(ips) => ips.iter().any(|i| i == ip),
+            GroupSelector::Tags(expr) => expr.matches(&tag::tags_of(ip)),
+        }
+    }
+
+    /// ips of the selector, groups are resolved against loaded members
+    pub fn resolve(&self) -> Vec<String> {
+        if let GroupSelector::Ips(ips) = self {
+            return ips.clone();
+        }
+        let mut ips: Vec<String> = MEMBERS.with(|members| {
+            members
+                .iter()
+                .filter(|(ip, group)| self.matches(ip, group))
+                .map(|(ip, _)| ip.clone())
+                .collect()
+        });
+        ips.sort();
+        ips
+    }
+}
+
+pub fn set_groups(groups: Vec<GroupConfig>) {
+    LIMITS.set(
+        groups
+            .iter()
+            .filter(|g| g.max_concurrency > 0)
+            .map(|g| (g.name.clone(), Arc::new(Semaphore::new(g.max_concurrency))))
+            .collect(),
+    );
+    GROUPS.set(groups.into_iter().map(|g| (g.name.clone(), g)).collect());
+}
+
+pub fn set_members(members: HashMap<String, String>) {
+    MEMBERS.set(members);
+}
+
+pub fn group_of(ip: &str) -> Option<String> {
+    MEMBERS.with(|members| members.get(ip).cloned())
+}
+
+pub fn priority_of(ip: &str) -> i32 {
+    group_of(ip)
+        .and_then(|group| GROUPS.with(|groups| groups.get(&group).map(|g| g.priority)))
+        .unwrap_or(0)
+}
+
+/// members by group
+pub fn members() -> HashMap<String, Vec<String>> {
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    MEMBERS.with(|members| {
+        for (ip, group) in members.iter() {
+            groups.entry(group.clone()).or_default().push(ip.clone());
+        }
+    });
+    for ips in groups.values_mut() {
+        ips.sort();
+    }
+    groups
+}
+
+/// wait for a slot of the machine group, None when the group is unlimited
+pub async fn permit(ip: &str) -> Option<OwnedSemaphorePermit> {
+    let limit = group_of(ip).and_then(|group| LIMITS.with(|limits| limits.get(&group).cloned()))?;
+    limit.acquire_owned().await.ok()
+}
+
+/// alert narrowed to the machines of each group having its own sinks
+pub fn group_alerts(alert: &Alert) -> Vec<(Vec<NotifySink>, Alert)> {
+    let groups = GROUPS.get();
+    let members = MEMBERS.get();
+
+    let mut alerts = vec![];
+    for group in groups.values().filter(|g| !g.notify_sinks.is_empty()) {
+        let machines: Vec<_> = alert
+            .machines
+            .iter()
+            .filter(|m| members.get(&m.ip) == Some(&group.name))
+            .cloned()
+            .collect();
+        if machines.is_empty() {
+            continue;
+        }
+        let mut group_alert = alert.clone();
+        group_alert.title = format!("[{}] {}", group.name, alert.title);
+        group_alert.machines = machines;
+        alerts.push((group.notify_sinks.clone(), group_alert));
+    }
+    alerts
+}
+
+/// group of a sheet row, the sheet name when the group column is absent or empty
+pub fn row_group(row: &serde_json::Value, col: Option<usize>, sheet: &str) -> String {
+    match sheet::cell(row, col) {
+        Some(group) if !group.trim().is_empty() => group.trim().to_string(),
+        _ => sheet.to_string(),
+    }
+}
+
+/// addition info tags plus type:<miner type> and group:<group>
+pub fn row_tags(
+    row: &serde_json::Value,
+    type_col: usize,
+    info_col: Option<usize>,
+    group: &str,
+) -> Vec<String> {
+    let mut tags = tag::parse_tags(sheet::cell(row, info_col).unwrap_or(""));
+    if let Some(miner_type) = row[type_col].as_str() {
+        tags.push(format!("type:{}", miner_type));
+    }
+    tags.push(format!("group:{}", group));
+    tags
+}
+
+/// reload group members and tags from the machine sheets
+pub async fn load_from_sheets(
+    excel: &str,
+    sheets: &[&str],
+) -> Result<HashMap<String, Vec<String>>, MinerError> {
+    let columns = sheet::get_columns();
+    let mut loaded = HashMap::new();
+    let mut tags = HashMap::new();
+    let mut positions = HashMap::new();
+    for sheet in sheets.iter() {
+        let values = notify::query_sheet_values(excel, sheet).await?;
+        let header = values.first().ok_or(MinerError::FeishuParserJsonError)?;
+        let cols = columns.resolve(header)?;
+        for row in values.iter().skip(1) {
+            if let Some(ip) = row[cols.ip].as_str() {
+                let group = row_group(row, cols.group, sheet);
+                tags.insert(
+                    ip.to_string(),
+                    row_tags(row, cols.miner_type, cols.addition_info, &group),
+                );
+                if let Some(parsed) = sheet::cell(row, cols.position).and_then(position::parse) {
+                    positions.insert(ip.to_string(), parsed);
+                }
+                loaded.insert(ip.to_string(), group);
+            }
+        }
+    }
+    set_members(loaded);
+    tag::set_tags(tags);
+    position::set_positions(positions);
+    Ok(members())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_selector_and_row_group() {
+        let selector = GroupSelector::Groups(vec!["A区".to_string()]);
+        assert!(selector.matches("192.168.188.41", "A区"));
+        assert!(!selector.matches("192.168.188.41", "B区"));
+        assert!(
+            GroupSelector::from(vec!["192.168.188.41".to_string()]).matches("192.168.188.41", "")
+        );
+
+        let row = json!(["ant", "192.168.188.41", "A区", ""]);
+        assert_eq!(row_group(&row, Some(2), "sheet1"), "A区");
+        assert_eq!(row_group(&row, Some(3), "sheet1"), "sheet1");
+        assert_eq!(row_group(&row, None, "sheet1"), "sheet1");
+    }
+}
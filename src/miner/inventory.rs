@@ -0,0 +1,368 @@
+/// machines a scan of each ip range found, kept to diff the next scan of the range against,
+/// e.g. after a rack was re-cabled: machines new to the range, machines gone from it and
+/// machines whose vendor, model or worker changed. every queried machine reporting a mac is
+/// kept by its mac too, so a machine given another ip by dhcp is still the same machine:
+/// the inventory follows it, worker names configured for the old ip move with it and the
+/// change is notified for the sheets keyed by ip
+use std::collections::HashMap;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::detection;
+use super::entry::{MachineInfo, MinerOperation};
+use super::position::{self, Position};
+use crate::clock;
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+use crate::pools::pool;
+use crate::store::db;
+
+// ip changes found since the last notify
+static MOVES: Local<Vec<IpChange>> = Local::new(Vec::new);
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScannedMachine {
+    pub ip: String,
+    /// ant, avalon or bluestar, empty when the detection is no longer cached
+    pub miner: String,
+    pub machine_type: String,
+    pub worker: String,
+    /// empty when not reported
+    pub mac: String,
+    pub serial: String,
+}
+
+impl ScannedMachine {
+    pub fn of(info: &MachineInfo) -> Self {
+        ScannedMachine {
+            ip: info.ip.clone(),
+            miner: detection::get(&info.ip)
+                .map(|miner| miner.info().name)
+                .unwrap_or_default(),
+            machine_type: info.machine_type.clone(),
+            worker: info.worker1.clone(),
+            mac: info.mac.clone(),
+            serial: info.serial.clone(),
+        }
+    }
+}
+
+/// a known machine found at another ip
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct IpChange {
+    pub mac: String,
+    pub serial: String,
+    pub machine_type: String,
+    /// worker the machine reports
+    pub worker: String,
+    pub from: String,
+    pub to: String,
+    pub time: i64,
+}
+
+/// a machine by its mac, where it was seen last
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InventoryEntry {
+    pub mac: String,
+    pub serial: String,
+    pub ip: String,
+    pub machine_type: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    /// from the sheets by the ip, None when not listed or not parsed
+    #[serde(default)]
+    pub position: Option<Position>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangedMachine {
+    pub before: ScannedMachine,
+    pub after: ScannedMachine,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ScanDiff {
+    /// e.g. "192.168.1.1-254"
+    pub range: String,
+    /// time of the scan diffed against, None when no scan of the range found machines yet
+    pub previous_time: Option<i64>,
+    /// machines of this scan
+    pub machines: Vec<MachineInfo>,
+    pub new: Vec<ScannedMachine>,
+    pub gone: Vec<ScannedMachine>,
+    pub changed: Vec<ChangedMachine>,
+    /// the same mac at another ip of the range, left out of new and gone
+    pub moved: Vec<ChangedMachine>,
+}
+
+/// lowercase and colon separated, e.g. "B4-A2-EB-3A-1F-22" or "b4a2eb3a1f22" to
+/// "b4:a2:eb:3a:1f:22", empty for anything else. firmware without a mac reports all zero or
+/// broadcast, those would make every such machine one
+pub fn normalize_mac(mac: &str) -> String {
+    let hex: String = mac
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect::<String>()
+        .to_lowercase();
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return "".to_string();
+    }
+    if hex.chars().all(|c| c == '0') || hex.chars().all(|c| c == 'f') {
+        return "".to_string();
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| String::from_utf8_lossy(pair).to_string())
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// key of the range a scan covers
+pub fn range_of(ip_prefix: &str, offset: i32, count: i32) -> String {
+    format!("{}.{}-{}", ip_prefix, offset, offset + count - 1)
+}
+
+// a vendor or identity not known on either side is no change
+fn differs(before: &ScannedMachine, after: &ScannedMachine) -> bool {
+    let known_differs = |b: &str, a: &str| !b.is_empty() && !a.is_empty() && b != a;
+    known_differs(&before.miner, &after.miner)
+        || known_differs(&before.mac, &after.mac)
+        || known_differs(&before.serial, &after.serial)
+        || before.machine_type != after.machine_type
+        || before.worker != after.worker
+}
+
+/// new, gone and changed machines of the current scan against the previous, ordered by ip
+pub fn diff(previous: &[ScannedMachine], current: &[ScannedMachine]) -> ScanDiff {
+    let before: HashMap<&str, &ScannedMachine> =
+        previous.iter().map(|m| (m.ip.as_str(), m)).collect();
+    let after: HashMap<&str, &ScannedMachine> =
+        current.iter().map(|m| (m.ip.as_str(), m)).collect();
+
+    let mut diff = ScanDiff {
+        new: current
+            .iter()
+            .filter(|m| !before.contains_key(m.ip.as_str()))
+            .cloned()
+            .collect(),
+        gone: previous
+            .iter()
+            .filter(|m| !after.contains_key(m.ip.as_str()))
+            .cloned()
+            .collect(),
+        changed: current
+            .iter()
+            .filter_map(|m| {
+                let b = before.get(m.ip.as_str())?;
+                differs(b, m).then(|| ChangedMachine {
+                    before: (*b).clone(),
+                    after: m.clone(),
+                })
+            })
+            .collect(),
+        ..Default::default()
+    };
+    // gone from one ip and new at another with the same mac moved
+    for i in (0..diff.new.len()).rev() {
+        let mac = &diff.new[i].mac;
+        if mac.is_empty() {
+            continue;
+        }
+        if let Some(j) = diff.gone.iter().position(|m| &m.mac == mac) {
+            diff.moved.push(ChangedMachine {
+                before: diff.gone.remove(j),
+                after: diff.new.remove(i),
+            });
+        }
+    }
+    diff.moved.sort_by(|a, b| a.after.ip.cmp(&b.after.ip));
+    diff.new.sort_by(|a, b| a.ip.cmp(&b.ip));
+    diff.gone.sort_by(|a, b| a.ip.cmp(&b.ip));
+    diff.changed.sort_by(|a, b| a.after.ip.cmp(&b.after.ip));
+    diff
+}
+
+/// diff the machines of a scan against the last scan of the range, then keep them for the
+/// next one
+pub fn compare(range: &str, machines: Vec<MachineInfo>, now: i64) -> Result<ScanDiff, MinerError> {
+    let current: Vec<ScannedMachine> = machines.iter().map(ScannedMachine::of).collect();
+    let (previous, previous_time) = db::query_scan(range)?;
+    let diff = diff(&previous, &current);
+    db::save_scan(range, &current, now)?;
+    Ok(ScanDiff {
+        range: range.to_string(),
+        previous_time,
+        machines,
+        ..diff
+    })
+}
+
+/// keep the machine by its mac. a known machine at another ip is logged as an event, takes
+/// the worker name of its old ip along and is notified on the next apply
+pub fn track(info: &MachineInfo, now: i64) -> Result<Option<IpChange>, MinerError> {
+    if normalize_mac(&info.mac).is_empty() {
+        return Ok(None);
+    }
+    let previous = db::upsert_inventory(&InventoryEntry {
+        mac: info.mac.clone(),
+        serial: info.serial.clone(),
+        ip: info.ip.clone(),
+        machine_type: info.machine_type.clone(),
+        first_seen: now,
+        last_seen: now,
+        position: None,
+    })?;
+    if previous.as_deref() == Some(info.ip.as_str()) {
+        return Ok(None);
+    }
+    // whichever machine held the ip before got another one
+    db::release_inventory_ip(&info.ip, &info.mac)?;
+    let Some(from) = previous.filter(|ip| !ip.is_empty()) else {
+        return Ok(None);
+    };
+
+    info!("{} moved from {} to {}", info.mac, from, info.ip);
+    db::insert_event(
+        db::EVENT_IP_CHANGE,
+        &info.ip,
+        &format!("{} from {}", info.mac, from),
+    )?;
+    pool::move_worker_name(&from, &info.ip);
+    let change = IpChange {
+        mac: info.mac.clone(),
+        serial: info.serial.clone(),
+        machine_type: info.machine_type.clone(),
+        worker: info.worker1.clone(),
+        from,
+        to: info.ip.clone(),
+        time: now,
+    };
+    MOVES.with(|moves| moves.push(change.clone()));
+    Ok(Some(change))
+}
+
+/// notify the ip changes tracked since the last call, sheets and pool workers keyed by ip
+/// need the new one
+pub async fn apply() {
+    let moves = MOVES.with(std::mem::take);
+    if moves.is_empty() {
+        return;
+    }
+    info!("{} machines changed ip", moves.len());
+
+    notifier::send_alert(&Alert {
+        kind: "ip_change".to_string(),
+        title: template::render(
+            "ip_change.title",
+            &[
+                ("time", &clock::now().format("%H:%M:%S")),
+                ("count", &moves.len()),
+            ],
+        ),
+        severity: Severity::Info,
+        content: "".to_string(),
+        machines: moves
+            .iter()
+            .map(|m| AlertMachine {
+                ip: m.to.clone(),
+                detail: template::render(
+                    "ip_change.detail",
+                    &[
+                        ("model", &m.machine_type),
+                        ("from", &m.from),
+                        ("mac", &m.mac),
+                        ("worker", &m.worker),
+                    ],
+                ),
+            })
+            .collect(),
+    })
+    .await;
+}
+
+/// every machine seen with a mac and its position, by last seen, newest first
+pub fn query() -> Result<Vec<InventoryEntry>, MinerError> {
+    let mut entries = db::query_inventory()?;
+    for entry in entries.iter_mut() {
+        entry.position = position::of(&entry.ip);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(ip: &str, miner: &str, machine_type: &str, worker: &str) -> ScannedMachine {
+        ScannedMachine {
+            ip: ip.to_string(),
+            miner: miner.to_string(),
+            machine_type: machine_type.to_string(),
+            worker: worker.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_scan_diff() {
+        assert_eq!(range_of("192.168.1", 1, 254), "192.168.1.1-254");
+        let previous = vec![
+            machine("192.168.1.10", "ant", "Antminer S19", "acc.1x10"),
+            machine("192.168.1.11", "ant", "Antminer S19", "acc.1x11"),
+            machine("192.168.1.12", "avalon", "1246", "acc.1x12"),
+            machine("192.168.1.13", "ant", "Antminer S19", "acc.1x13"),
+        ];
+        let current = vec![
+            // vendor unknown after the detection cache expired
+            machine("192.168.1.10", "", "Antminer S19", "acc.1x10"),
+            machine("192.168.1.12", "ant", "Antminer S21", "acc.1x12"),
+            machine("192.168.1.13", "ant", "Antminer S19", "acc.1x14"),
+            machine("192.168.1.14", "ant", "Antminer S19", "acc.1x14"),
+        ];
+        let found = diff(&previous, &current);
+        assert_eq!(found.new, [current[3].clone()]);
+        assert_eq!(found.gone, [previous[1].clone()]);
+        let ips: Vec<&str> = found.changed.iter().map(|c| c.after.ip.as_str()).collect();
+        assert_eq!(ips, ["192.168.1.12", "192.168.1.13"]);
+        assert_eq!(found.changed[1].before.worker, "acc.1x13");
+
+        // a new dhcp lease is a move, not a new and a gone machine
+        let with_mac = |m: &ScannedMachine| ScannedMachine {
+            mac: normalize_mac("B4-A2-EB-3A-1F-22"),
+            ..m.clone()
+        };
+        let found = diff(&[with_mac(&previous[1])], &[with_mac(&current[3])]);
+        assert!(found.new.is_empty() && found.gone.is_empty());
+        assert_eq!(found.moved[0].before.ip, "192.168.1.11");
+        assert_eq!(found.moved[0].after.mac, "b4:a2:eb:3a:1f:22");
+        assert_eq!(normalize_mac("b4a2eb3a1f22"), "b4:a2:eb:3a:1f:22");
+        assert_eq!(normalize_mac("unknown"), "");
+        assert_eq!(normalize_mac("00:00:00:00:00:00"), "");
+        assert_eq!(normalize_mac("FF-FF-FF-FF-FF-FF"), "");
+        assert_eq!(normalize_mac("ff:ff:ff:00:00:00"), "ff:ff:ff:00:00:00");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_track_skips_placeholder_mac() {
+        let site = std::sync::Arc::new(crate::context::Context::default());
+        crate::context::enter(site, || {
+            db::init(db::MEMORY).unwrap();
+            for (ip, mac) in [
+                ("192.168.1.10", "00:00:00:00:00:00"),
+                ("192.168.1.11", "00:00:00:00:00:00"),
+                ("192.168.1.12", "ff:ff:ff:ff:ff:ff"),
+            ] {
+                let info = MachineInfo {
+                    ip: ip.to_string(),
+                    mac: mac.to_string(),
+                    ..Default::default()
+                };
+                assert!(track(&info, 1000).unwrap().is_none());
+            }
+            assert!(db::query_inventory().unwrap().is_empty());
+        });
+    }
+}
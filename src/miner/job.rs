@@ -0,0 +1,170 @@
+/// batch operations kept in the db while they run, the targets a crash or restart cut short
+/// are verified and finished by resume
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::desired::{self, DesiredMachine};
+use super::entry::{
+    find_miner, record_switch_state, with_pool_prefix, Account, MinerOperation, PoolConfig,
+};
+use super::group;
+use crate::context;
+use crate::error::{BatchError, MinerError};
+use crate::shutdown;
+use crate::store::db;
+
+// t_job kinds
+pub const JOB_SWITCH: &str = "switch";
+pub const JOB_FORCE_SWITCH: &str = "force_switch";
+pub const JOB_CONFIG: &str = "config";
+
+/// what a job does to one machine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JobAction {
+    /// account as the switch got it, the avalon pool prefix is added again on resume
+    Switch(Account),
+    Config {
+        pools: Vec<PoolConfig>,
+        run_mode: String,
+    },
+}
+
+/// record a job before its targets start, returns its id, -1 without db
+pub fn start(kind: &str, targets: Vec<(String, JobAction)>) -> i64 {
+    let targets = targets
+        .into_iter()
+        .filter_map(|(ip, action)| serde_json::to_string(&action).ok().map(|a| (ip, a)))
+        .collect::<Vec<(String, String)>>();
+    db::insert_job(kind, &targets).unwrap_or_else(|e| {
+        error!("save {} job error: {:?}", kind, e);
+        -1
+    })
+}
+
+/// progress of one target, failed ones are done too, they are reported not retried
+pub fn target_done<T>(job_id: i64, ip: &str, result: &Result<T, MinerError>) {
+    let error = result
+        .as_ref()
+        .err()
+        .map(|e| e.to_string())
+        .unwrap_or_default();
+    if let Err(e) = db::set_job_target_done(job_id, ip, &error) {
+        error!("save job {} progress {} error: {:?}", job_id, ip, e);
+    }
+}
+
+pub fn finish(job_id: i64) {
+    if let Err(e) = db::finish_job(job_id) {
+        error!("finish job {} error: {:?}", job_id, e);
+    }
+}
+
+// the diff check of the switch and the drift of the desired state skip what already got
+// applied before the crash
+async fn resume_target(ip: String, action: JobAction) -> Result<(), MinerError> {
+    match action {
+        JobAction::Switch(account) => {
+            let miner = find_miner(&ip, 3)?;
+            let account = with_pool_prefix(&miner, account);
+            let result = miner.switch_account_if_diff(&ip, &account, false).await;
+            let error = result
+                .as_ref()
+                .err()
+                .map(|e| e.to_string())
+                .unwrap_or_default();
+            record_switch_state(&ip, &account.name, &account.run_mode, &error);
+            result
+        }
+        JobAction::Config { pools, run_mode } => {
+            let desired = DesiredMachine {
+                ip: ip.clone(),
+                pools: Some(pools),
+                mode: (!run_mode.is_empty()).then_some(run_mode),
+                fan: None,
+            };
+            desired::reconcile_machine(&desired, true).map(|_| ())
+        }
+    }
+}
+
+/// verify and finish the targets of jobs left unfinished, returns the resumed targets
+pub async fn resume_pending(runtime: tokio::runtime::Handle) -> Result<usize, MinerError> {
+    let _operation = shutdown::begin()?;
+    let targets = db::query_pending_job_targets()?;
+    if targets.is_empty() {
+        return Ok(0);
+    }
+    info!("resume {} pending job targets", targets.len());
+
+    let mut ips = vec![];
+    let mut handles = vec![];
+    for (job_id, ip, action) in targets {
+        let action = match serde_json::from_str::<JobAction>(&action) {
+            Ok(action) => action,
+            Err(e) => {
+                error!("job {} action of {} error: {:?}", job_id, ip, e);
+                continue;
+            }
+        };
+        ips.push(ip.clone());
+        handles.push(context::spawn(&runtime, async move {
+            let _permit = group::permit(&ip).await;
+            let result = resume_target(ip.clone(), action).await;
+            target_done(job_id, &ip, &result);
+            result.map_err(|e| e.context(&ip, "resume"))
+        }));
+    }
+    let results = futures::future::join_all(handles).await;
+    db::finish_pending_jobs()?;
+
+    let mut succeeded = 0;
+    let mut failed = vec![];
+    for (ip, result) in ips.into_iter().zip(results) {
+        match result {
+            Ok(Ok(())) => succeeded += 1,
+            Ok(Err(e)) => failed.push((ip, e)),
+            Err(e) => failed.push((ip, e.into())),
+        }
+    }
+    BatchError::check(succeeded, failed)?;
+    Ok(succeeded)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::store::db::{DB, MEMORY};
+    use crate::tariff;
+
+    #[test]
+    fn test_pending_targets() {
+        let db = DB::new(MEMORY).unwrap();
+        let action = serde_json::to_string(&JobAction::Config {
+            pools: vec![PoolConfig::default()],
+            run_mode: tariff::MODE_NORMAL.to_string(),
+        })
+        .unwrap();
+        let targets = vec![
+            ("10.0.0.1".to_string(), action.clone()),
+            ("10.0.0.2".to_string(), action.clone()),
+        ];
+        let done = db.insert_job(JOB_CONFIG, &targets, 100).unwrap();
+        db.set_job_target_done(done, "10.0.0.1", "").unwrap();
+        db.set_job_target_done(done, "10.0.0.2", "timeout").unwrap();
+        db.finish_job(done, 110).unwrap();
+        let cut = db.insert_job(JOB_CONFIG, &targets, 200).unwrap();
+        db.set_job_target_done(cut, "10.0.0.1", "").unwrap();
+
+        let pending = db.query_pending_job_targets().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].0, pending[0].1.as_str()), (cut, "10.0.0.2"));
+        assert!(matches!(
+            serde_json::from_str::<JobAction>(&pending[0].2).unwrap(),
+            JobAction::Config { run_mode, .. } if run_mode == tariff::MODE_NORMAL
+        ));
+
+        db.finish_pending_jobs(210).unwrap();
+        assert!(db.query_pending_job_targets().unwrap().is_empty());
+        assert_eq!(db.clear_jobs_before(300).unwrap(), 2);
+    }
+}
@@ -0,0 +1,119 @@
+/// machines under maintenance, kept out of switching, throttling and alerts
+use std::collections::HashMap;
+
+use log::{error, info};
+
+use crate::error::MinerError;
+use crate::notify::Alert;
+use crate::store::cache::DbCache;
+use crate::store::db;
+
+// ip to until
+static MAINTENANCE: DbCache<HashMap<String, i64>> = DbCache::new(load);
+
+fn load() -> HashMap<String, i64> {
+    match db::query_maintenance(chrono::Local::now().timestamp()) {
+        Ok(entries) => entries.into_iter().collect(),
+        Err(e) => {
+            error!("load maintenance error: {:?}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// load the flags of the db just opened
+pub fn reload() {
+    MAINTENANCE.reload();
+}
+
+/// flag machines until the time, a past time clears them
+pub fn set(ips: &[String], until: i64) -> Result<(), MinerError> {
+    info!("maintenance {:?} until {}", ips, until);
+    for ip in ips.iter() {
+        db::set_maintenance(ip, until)?;
+    }
+    MAINTENANCE.with(|entries| {
+        for ip in ips.iter() {
+            entries.insert(ip.clone(), until);
+        }
+    });
+    Ok(())
+}
+
+pub fn clear(ips: &[String]) -> Result<(), MinerError> {
+    for ip in ips.iter() {
+        db::clear_maintenance(ip)?;
+    }
+    MAINTENANCE.with(|entries| {
+        for ip in ips.iter() {
+            entries.remove(ip);
+        }
+    });
+    Ok(())
+}
+
+pub fn is_in_maintenance(ip: &str) -> bool {
+    let now = chrono::Local::now().timestamp();
+    MAINTENANCE.with(|entries| entries.get(ip).map(|until| *until > now).unwrap_or(false))
+}
+
+/// flagged machines and their end time
+pub fn list() -> Vec<(String, i64)> {
+    let now = chrono::Local::now().timestamp();
+    let mut list: Vec<(String, i64)> = MAINTENANCE.with(|entries| {
+        entries
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(ip, until)| (ip.clone(), *until))
+            .collect()
+    });
+    list.sort();
+    list
+}
+
+/// alert without machines under maintenance, None when only those were listed
+pub fn filter_alert(alert: &Alert) -> Option<Alert> {
+    if alert.machines.is_empty() {
+        return Some(alert.clone());
+    }
+    let mut alert = alert.clone();
+    alert.machines.retain(|m| !is_in_maintenance(&m.ip));
+    if alert.machines.is_empty() {
+        None
+    } else {
+        Some(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::{AlertMachine, Severity};
+
+    #[test]
+    fn test_maintenance_filters_alert() {
+        let now = chrono::Local::now().timestamp();
+        set(&["192.168.188.41".to_string()], now + 3600).unwrap();
+        set(&["192.168.188.42".to_string()], now - 1).unwrap();
+        assert!(is_in_maintenance("192.168.188.41"));
+        assert!(!is_in_maintenance("192.168.188.42"));
+
+        let machine = |ip: &str| AlertMachine {
+            ip: ip.to_string(),
+            detail: "".to_string(),
+        };
+        let mut alert = Alert {
+            kind: "".to_string(),
+            title: "访问故障".to_string(),
+            severity: Severity::Warning,
+            content: "".to_string(),
+            machines: vec![machine("192.168.188.41"), machine("192.168.188.42")],
+        };
+        assert_eq!(filter_alert(&alert).unwrap().machines.len(), 1);
+        alert.machines.pop();
+        assert!(filter_alert(&alert).is_none());
+
+        clear(&["192.168.188.41".to_string()]).unwrap();
+        assert!(!is_in_maintenance("192.168.188.41"));
+    }
+}
@@ -0,0 +1,768 @@
+/// fake miners for tests of scan, switch and config without a farm. each fake is keyed by a
+/// made up ip, the drivers reach it on a local port through the endpoint routing
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::capture::Fixture;
+use super::detection;
+use super::entry::*;
+use crate::error::MinerError;
+
+lazy_static! {
+    static ref ROUTES: Mutex<HashMap<(String, u16), SocketAddr>> = Mutex::new(HashMap::new());
+    static ref FAKE_MINERS: Mutex<HashMap<String, FakeMiner>> = Mutex::new(HashMap::new());
+}
+
+// a timeout reply holds the connection at most this long
+const HOLD: Duration = Duration::from_secs(30);
+
+const DIGEST_CHALLENGE: &str =
+    "WWW-Authenticate: Digest realm=\"antMiner Configuration\", nonce=\"6d6f636b\", qop=\"auth\"";
+
+const ANT_STATS: &str = r#"{"INFO":{"type":"Antminer S19j Pro"},"STATS":[{"elapsed":3723,"rate_5s":104512.3,"rate_avg":104020.8}]}"#;
+
+const ANT_POOLS: &str = r#"{"POOLS":[{"index":0,"url":"btc.f2pool.com:1314","user":"mock.1x1","accepted":990,"rejected":10,"stale":0}]}"#;
+
+const ANT_CONF: &str = r#"{"pools":[{"url":"btc.f2pool.com:1314","user":"mock.1x1","pass":"123"},{"url":"btc.f2pool.com:1314","user":"mock.1x1","pass":"123"},{"url":"btc.f2pool.com:1314","user":"mock.1x1","pass":"123"}],"api-listen":true,"api-network":true,"api-groups":"A:stats:pools:devs:summary:version","api-allow":"A:0/0,W:*","bitmain-fan-ctrl":false,"bitmain-fan-pwm":"100","bitmain-use-vil":true,"bitmain-freq":"","bitmain-voltage":"1400","bitmain-ccdelay":"0","bitmain-pwth":"0","bitmain-work-mode":"0","bitmain-freq-level":"100"}"#;
+
+const AVALON_VERSION: &str = "STATUS=S,When=0,Code=22,Msg=CGMiner versions,Description=cgminer 4.11.1|VERSION,CGMiner=4.11.1,API=3.7,PROD=AvalonMiner 1246,MODEL=1246,HWTYPE=MM3v2_X3,|";
+
+const AVALON_ESTATS: &str = "STATUS=S,When=0,Code=70,Msg=CGMiner stats,Description=cgminer 4.11.1|STATS=0,ID=AVA100,Elapsed=1697,MM ID0=Ver[1246-N-mock] SYSTEMSTATU[Work: In Work, Hash Board: 3 ] Elapsed[1697] Temp[32] GHSspd[90123.45] DHspd[0.00%] GHSmm[91000.00] GHSavg[89000.12] WU[1243010.98] MTavg[80 81 82] WORKMODE[1]|";
+
+const AVALON_POWER: &str = "STATUS=I,When=0,Code=118,Msg=ASC 0 set info: PS[0 1196 1284 230 2953 1284],Description=cgminer 4.11.1|";
+
+const AVALON_OK: &str = "STATUS=I,When=0,Code=118,Msg=ASC 0 set OK,Description=cgminer 4.11.1|";
+
+/// scripted answer of a fake
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// answered with this body
+    Ok(String),
+    /// http status without body, an error status on the cgminer api
+    Status(u16),
+    /// bytes that are not the expected format, the connection is closed after them
+    Garbage(Vec<u8>),
+    /// never answers, the client runs into its timeout
+    Timeout,
+    /// closes the connection without answering
+    Close,
+}
+
+/// request received by a fake, the path or cgminer command and the body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub key: String,
+    pub body: String,
+}
+
+/// address the fake of the ip listens on for the miner port
+pub(crate) fn route(ip: &str, port: u16) -> Option<SocketAddr> {
+    ROUTES.lock().unwrap().get(&(ip.to_string(), port)).copied()
+}
+
+/// scripted miner registered for the ip, found without detection
+pub(crate) fn fake_miner(ip: &str) -> Option<MinerType> {
+    FAKE_MINERS
+        .lock()
+        .unwrap()
+        .get(ip)
+        .map(|miner| MinerType::Fake(miner.clone()))
+}
+
+#[derive(Default)]
+struct Shared {
+    replies: Mutex<HashMap<String, Reply>>,
+    requests: Mutex<Vec<Request>>,
+    connections: AtomicUsize,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn record(&self, key: &str, body: &str) -> Option<Reply> {
+        self.requests.lock().unwrap().push(Request {
+            key: key.to_string(),
+            body: body.to_string(),
+        });
+        self.replies.lock().unwrap().get(key).cloned()
+    }
+
+    // keep the connection open until the client gives up or the fake stops
+    fn hold(&self, stream: &mut TcpStream) {
+        let _ = stream.set_read_timeout(Some(Duration::from_millis(50)));
+        let start = Instant::now();
+        let mut buf = [0u8; 1024];
+        while !self.stop.load(Ordering::Relaxed) && start.elapsed() < HOLD {
+            match stream.read(&mut buf) {
+                Ok(0) => return,
+                Ok(_) => continue,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+// listener thread with a thread per connection, stopped by the owner on drop
+struct Server {
+    ip: String,
+    port: u16,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    fn start<F>(ip: &str, port: u16, handle: F) -> Result<Server, MinerError>
+    where
+        F: Fn(&mut TcpStream, &Shared) -> std::io::Result<()> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+        let handle = Arc::new(handle);
+        let accept_shared = shared.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shared.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                accept_shared.connections.fetch_add(1, Ordering::Relaxed);
+                let shared = accept_shared.clone();
+                let handle = handle.clone();
+                std::thread::spawn(move || {
+                    let _ = handle(&mut stream, &shared);
+                });
+            }
+        });
+        ROUTES.lock().unwrap().insert((ip.to_string(), port), addr);
+        detection::invalidate(ip);
+        Ok(Server {
+            ip: ip.to_string(),
+            port,
+            addr,
+            shared,
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        ROUTES.lock().unwrap().remove(&(self.ip.clone(), self.port));
+        detection::invalidate(&self.ip);
+        self.shared.stop.store(true, Ordering::Relaxed);
+        // wake the accept loop so it sees the stop flag
+        let _ = TcpStream::connect_timeout(&self.addr, Duration::from_secs(1));
+    }
+}
+
+/// antminer web server, digest auth is asked for and any credentials are accepted.
+/// get_miner_conf.cgi answers the config last written by set_miner_conf.cgi
+pub struct FakeAnt {
+    server: Server,
+}
+
+impl FakeAnt {
+    pub fn start(ip: &str) -> Result<FakeAnt, MinerError> {
+        let conf = Mutex::new(ANT_CONF.to_string());
+        let server = Server::start(ip, 80, move |stream, shared| {
+            serve_ant(stream, shared, &conf)
+        })?;
+        Ok(FakeAnt { server })
+    }
+
+    /// reply of the path, e.g. "/cgi-bin/stats.cgi", instead of the default
+    pub fn set_reply(&self, path: &str, reply: Reply) {
+        set_reply(&self.server.shared, path, reply);
+    }
+
+    /// authenticated requests received so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.server.shared.requests.lock().unwrap().clone()
+    }
+}
+
+/// avalon cgminer api on port 4028, setpool changes what pools answers
+pub struct FakeAvalon {
+    server: Server,
+    close: Arc<AtomicBool>,
+}
+
+impl FakeAvalon {
+    pub fn start(ip: &str) -> Result<FakeAvalon, MinerError> {
+        let pools = Mutex::new(vec![
+            (
+                "stratum+tcp://btc.ss.poolin.com:443".to_string(),
+                "mock.1x1".to_string(),
+            ),
+            (
+                "stratum+tcp://btc.ss.poolin.com:443".to_string(),
+                "mock.1x1".to_string(),
+            ),
+            (
+                "stratum+tcp://btc.ss.poolin.com:443".to_string(),
+                "mock.1x1".to_string(),
+            ),
+        ]);
+        let close = Arc::new(AtomicBool::new(false));
+        let serve_close = close.clone();
+        let server = Server::start(ip, 4028, move |stream, shared| {
+            serve_avalon(stream, shared, &pools, &serve_close)
+        })?;
+        Ok(FakeAvalon { server, close })
+    }
+
+    /// close the connection after each reply like stock cgminer, kept open by default
+    pub fn close_after_reply(&self, close: bool) {
+        self.close.store(close, Ordering::Relaxed);
+    }
+
+    /// reply of the command, the full command like "ascset|0,hashpower" or its name before
+    /// the first '|' like "estats"
+    pub fn set_reply(&self, command: &str, reply: Reply) {
+        set_reply(&self.server.shared, command, reply);
+    }
+
+    /// commands received so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.server.shared.requests.lock().unwrap().clone()
+    }
+
+    /// connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.server.shared.connections.load(Ordering::Relaxed)
+    }
+}
+
+/// fakes answering the captured replies of a fixture, started for its web and api parts
+pub struct Replay {
+    pub ant: Option<FakeAnt>,
+    pub avalon: Option<FakeAvalon>,
+}
+
+pub fn replay(ip: &str, fixture: &Fixture) -> Result<Replay, MinerError> {
+    let ant = if fixture.web.is_empty() {
+        None
+    } else {
+        let ant = FakeAnt::start(ip)?;
+        for (path, body) in fixture.web.iter() {
+            ant.set_reply(path, Reply::Ok(body.clone()));
+        }
+        Some(ant)
+    };
+    let avalon = if fixture.api.is_empty() {
+        None
+    } else {
+        let avalon = FakeAvalon::start(ip)?;
+        for (command, reply) in fixture.api.iter() {
+            avalon.set_reply(command, Reply::Ok(reply.clone()));
+        }
+        Some(avalon)
+    };
+    Ok(Replay { ant, avalon })
+}
+
+fn set_reply(shared: &Shared, key: &str, reply: Reply) {
+    shared
+        .replies
+        .lock()
+        .unwrap()
+        .insert(key.to_string(), reply);
+}
+
+// head and body of one request, None when the client closed first
+fn read_http(stream: &mut TcpStream) -> std::io::Result<Option<(String, String)>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+    let header = |name: &str| {
+        head.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_string())
+    };
+    let length = header("content-length")
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    if header("expect").is_some_and(|value| value.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    while data.len() < head_end + length {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let body = String::from_utf8_lossy(&data[head_end..]).to_string();
+    Ok(Some((head, body)))
+}
+
+fn write_http(
+    stream: &mut TcpStream,
+    status: u16,
+    headers: &[&str],
+    body: &str,
+) -> std::io::Result<()> {
+    let mut response = format!("HTTP/1.1 {} Mock\r\n", status);
+    for header in headers {
+        response += header;
+        response += "\r\n";
+    }
+    response += &format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())
+}
+
+fn serve_ant(stream: &mut TcpStream, shared: &Shared, conf: &Mutex<String>) -> std::io::Result<()> {
+    let Some((head, body)) = read_http(stream)? else {
+        return Ok(());
+    };
+    let path = head
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .split('?')
+        .next()
+        .unwrap_or("/")
+        .to_string();
+    let authorized = head
+        .lines()
+        .any(|line| line.to_lowercase().starts_with("authorization:"));
+    if !authorized {
+        return write_http(stream, 401, &[DIGEST_CHALLENGE], "");
+    }
+
+    let reply = shared
+        .record(&path, &body)
+        .unwrap_or_else(|| match path.as_str() {
+            "/cgi-bin/stats.cgi" => Reply::Ok(ANT_STATS.to_string()),
+            "/cgi-bin/pools.cgi" => Reply::Ok(ANT_POOLS.to_string()),
+            "/cgi-bin/get_miner_conf.cgi" => Reply::Ok(conf.lock().unwrap().clone()),
+            "/cgi-bin/set_miner_conf.cgi" => {
+                *conf.lock().unwrap() = body.clone();
+                Reply::Ok(r#"{"stats":"success","code":"M000","msg":"OK!"}"#.to_string())
+            }
+            "/cgi-bin/reboot.cgi" => Reply::Ok("".to_string()),
+            _ => Reply::Status(404),
+        });
+    match reply {
+        Reply::Ok(body) => write_http(stream, 200, &["Content-Type: application/json"], &body),
+        Reply::Status(status) => write_http(stream, status, &[], ""),
+        Reply::Garbage(bytes) => stream.write_all(&bytes),
+        Reply::Timeout => {
+            shared.hold(stream);
+            Ok(())
+        }
+        Reply::Close => Ok(()),
+    }
+}
+
+// one command per read, the connection stays open for the next unless closed after each reply
+// as stock cgminer does
+fn serve_avalon(
+    stream: &mut TcpStream,
+    shared: &Shared,
+    pools: &Mutex<Vec<(String, String)>>,
+    close: &AtomicBool,
+) -> std::io::Result<()> {
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 || shared.stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let command = String::from_utf8_lossy(&buf[..n]).trim().to_string();
+        let name = command.split('|').next().unwrap_or("").to_string();
+        let scripted = shared
+            .record(&command, "")
+            .or_else(|| shared.replies.lock().unwrap().get(&name).cloned());
+        let reply = scripted.unwrap_or_else(|| {
+            Reply::Ok(match name.as_str() {
+                "version" => AVALON_VERSION.to_string(),
+                "estats" => AVALON_ESTATS.to_string(),
+                "pools" => avalon_pools(&pools.lock().unwrap()),
+                _ if command == "ascset|0,hashpower" => AVALON_POWER.to_string(),
+                _ => {
+                    set_avalon_pool(&command, &mut pools.lock().unwrap());
+                    AVALON_OK.to_string()
+                }
+            })
+        });
+        match reply {
+            Reply::Ok(body) => {
+                stream.write_all(body.as_bytes())?;
+                stream.write_all(&[0])?;
+            }
+            Reply::Status(code) => {
+                let body = format!("STATUS=E,When=0,Code={},Msg=mock error|", code);
+                stream.write_all(body.as_bytes())?;
+                stream.write_all(&[0])?;
+            }
+            Reply::Garbage(bytes) => return stream.write_all(&bytes),
+            Reply::Timeout => {
+                shared.hold(stream);
+                return Ok(());
+            }
+            Reply::Close => return Ok(()),
+        }
+        if close.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+    }
+}
+
+fn avalon_pools(pools: &[(String, String)]) -> String {
+    let mut res = "STATUS=S,When=0,Code=7,Msg=3 Pool(s),Description=cgminer 4.11.1|".to_string();
+    for (idx, (url, user)) in pools.iter().enumerate() {
+        res += &format!(
+            "POOL={},URL={},Status=Alive,Priority={},User={},Stratum Active=true,|",
+            idx, url, idx, user
+        );
+    }
+    res
+}
+
+// ascset|0,setpool,root,root,<index>,<url>,<user>,<password>
+fn set_avalon_pool(command: &str, pools: &mut [(String, String)]) {
+    let fields: Vec<&str> = command.split(',').collect();
+    if fields.len() < 7 || fields[1] != "setpool" {
+        return;
+    }
+    if let Some(pool) = fields[4]
+        .parse::<usize>()
+        .ok()
+        .and_then(|idx| pools.get_mut(idx))
+    {
+        *pool = (fields[5].to_string(), fields[6].to_string());
+    }
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    info: MachineInfo,
+    reply: Option<Reply>,
+    calls: Vec<String>,
+}
+
+/// MinerOperation without any network, registered for an ip until removed.
+/// operations answer the scripted reply: Ok or none succeeds, the others fail at once
+#[derive(Debug, Clone)]
+pub struct FakeMiner {
+    ip: String,
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeMiner {
+    pub fn add(ip: &str) -> FakeMiner {
+        let miner = FakeMiner {
+            ip: ip.to_string(),
+            state: Arc::new(Mutex::new(FakeState {
+                info: MachineInfo {
+                    ip: ip.to_string(),
+                    machine_type: "fake".to_string(),
+                    record: MachineRecord {
+                        ip: ip.to_string(),
+                        machine_type: "fake".to_string(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            })),
+        };
+        FAKE_MINERS
+            .lock()
+            .unwrap()
+            .insert(ip.to_string(), miner.clone());
+        miner
+    }
+
+    pub fn remove(ip: &str) {
+        FAKE_MINERS.lock().unwrap().remove(ip);
+    }
+
+    pub fn set_info(&self, info: MachineInfo) {
+        self.state.lock().unwrap().info = info;
+    }
+
+    pub fn set_reply(&self, reply: Reply) {
+        self.state.lock().unwrap().reply = Some(reply);
+    }
+
+    /// operations called so far, e.g. "switch acc", "config 高功 1"
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    fn call(&self, call: String) -> Result<(), MinerError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(call);
+        match &state.reply {
+            None | Some(Reply::Ok(_)) => Ok(()),
+            Some(Reply::Status(_)) => Err(MinerError::HttpError),
+            Some(Reply::Garbage(_)) => Err(MinerError::TcpReadError),
+            Some(Reply::Timeout) => Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+            Some(Reply::Close) => {
+                Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into())
+            }
+        }
+    }
+}
+
+impl MinerOperation for FakeMiner {
+    fn info(&self) -> MinerInfo {
+        MinerInfo {
+            name: "fake".to_string(),
+            detail: format!("scripted miner of {}", self.ip),
+        }
+    }
+
+    fn detect(&self, _headers: Vec<String>, _body: &str) -> Result<MinerType, MinerError> {
+        Err(MinerError::MinerNotSupportError)
+    }
+
+    fn query(&self, _ip: &str, _timeout_seconds: i64) -> Result<MachineInfo, MinerError> {
+        self.call("query".to_string())?;
+        let mut info = self.state.lock().unwrap().info.clone();
+        info.record.create_time = chrono::Local::now().timestamp();
+        Ok(info)
+    }
+
+    fn switch_account_if_diff(
+        &self,
+        _ip: &str,
+        account: &Account,
+        is_force: bool,
+    ) -> AsyncOpType<()> {
+        let result = self.call(format!(
+            "switch {}{}",
+            account.name,
+            if is_force { " force" } else { "" }
+        ));
+        Box::pin(async move { result })
+    }
+
+    fn reboot(&self, _ip: &str, _timeout_seconds: i64) -> Result<(), MinerError> {
+        self.call("reboot".to_string())
+    }
+
+    fn config_pool(
+        &self,
+        _ip: &str,
+        pools: &[PoolConfig],
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        self.call(format!("config_pool {}", pools.len()))
+    }
+
+    fn config_mode(&self, _ip: &str, mode: &str, _timeout_seconds: i64) -> Result<(), MinerError> {
+        self.call(format!("config_mode {}", mode))
+    }
+
+    fn config_fan(
+        &self,
+        _ip: &str,
+        pwm: Option<u32>,
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        self.call(format!("config_fan {:?}", pwm))
+    }
+
+    fn config_tuning(
+        &self,
+        _ip: &str,
+        freq: Option<u32>,
+        voltage: Option<u32>,
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        self.call(format!("config_tuning {:?} {:?}", freq, voltage))
+    }
+
+    fn config(
+        &self,
+        _ip: &str,
+        mode: &str,
+        pools: &[PoolConfig],
+        _timeout_seconds: i64,
+    ) -> Result<(), MinerError> {
+        self.call(format!("config {} {}", mode, pools.len()))
+    }
+
+    fn exec(&self, _ip: &str, cmd: &str) -> Result<String, MinerError> {
+        self.call(format!("exec {}", cmd))?;
+        Ok(String::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner::{avalon, capture};
+    use crate::tariff;
+
+    fn account() -> Account {
+        Account {
+            id: 1,
+            name: "other".to_string(),
+            password: "123".into(),
+            pool1: "stratum+tcp://btc.f2pool.com:1314".to_string(),
+            pool2: "stratum+tcp://btc.f2pool.com:1314".to_string(),
+            pool3: "stratum+tcp://btc.f2pool.com:1314".to_string(),
+            run_mode: tariff::MODE_NORMAL.to_string(),
+        }
+    }
+
+    #[cfg(feature = "ant-http")]
+    #[tokio::test]
+    async fn test_fake_ant() {
+        let ant = FakeAnt::start("10.254.0.1").unwrap();
+        let info = scan_miner_detail("10.254.0.1".to_string(), 2)
+            .await
+            .unwrap();
+        assert_eq!(info.machine_type, "Antminer S19j Pro");
+        assert_eq!(info.worker1, "mock.1x1");
+        assert_eq!(info.record.rejected_pct, 1.0);
+
+        let miner = find_miner("10.254.0.1", 2).unwrap();
+        miner
+            .switch_account_if_diff("10.254.0.1", &account(), false)
+            .await
+            .unwrap();
+        let info = miner.query("10.254.0.1", 2).unwrap();
+        assert_eq!(info.worker1, "other.s0x1");
+        assert!(ant
+            .requests()
+            .iter()
+            .any(|r| r.key == "/cgi-bin/reboot.cgi"));
+
+        ant.set_reply("/cgi-bin/stats.cgi", Reply::Timeout);
+        assert!(miner.query("10.254.0.1", 1).unwrap_err().is_timeout());
+        ant.set_reply("/cgi-bin/stats.cgi", Reply::Ok("<html>".to_string()));
+        assert_eq!(miner.query("10.254.0.1", 1).unwrap_err().code(), 3001);
+    }
+
+    #[tokio::test]
+    async fn test_fake_avalon() {
+        let avalon = FakeAvalon::start("10.254.0.2").unwrap();
+        let miner = find_miner("10.254.0.2", 2).unwrap();
+        assert!(matches!(miner, MinerType::Avalon(_)));
+        let info = miner.query("10.254.0.2", 2).unwrap();
+        assert_eq!(info.machine_type, "1246");
+        assert_eq!(info.record.power, 2953);
+
+        miner
+            .switch_account_if_diff("10.254.0.2", &account(), false)
+            .await
+            .unwrap();
+        assert_eq!(miner.query("10.254.0.2", 2).unwrap().worker1, "other.0x2");
+
+        // the reboot reply is not read, an idle connection the miner closed would swallow it
+        let reboots = || {
+            avalon
+                .requests()
+                .iter()
+                .filter(|r| r.key == "ascset|0,reboot,0")
+                .count()
+        };
+        // the switch rebooted once already
+        let (connections, rebooted) = (avalon.connections(), reboots());
+        miner.reboot("10.254.0.2", 2).unwrap();
+        // the connection is counted before its command is recorded
+        while reboots() == rebooted {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(avalon.connections(), connections + 1);
+
+        // pooled connections closed by the miner are replaced, one per command
+        let stock = FakeAvalon::start("10.254.0.5").unwrap();
+        stock.close_after_reply(true);
+        for _ in 0..2 {
+            assert_eq!(miner.query("10.254.0.5", 2).unwrap().worker1, "mock.1x1");
+        }
+        assert_eq!(stock.connections(), stock.requests().len());
+
+        avalon.set_reply("estats", Reply::Garbage(b"\xff\xfe".to_vec()));
+        assert!(miner.query("10.254.0.2", 1).is_err());
+
+        // a fresh address, idle connections of the other fake would each be retried
+        let silent = FakeAvalon::start("10.254.0.4").unwrap();
+        silent.set_reply("version", Reply::Timeout);
+        assert!(avalon::tcp_query_version("10.254.0.4", 1).is_err());
+        // the query commands share one timeout
+        silent.set_reply("estats", Reply::Timeout);
+        let start = std::time::Instant::now();
+        assert!(miner.query("10.254.0.4", 1).unwrap_err().is_timeout());
+        assert!(start.elapsed() < std::time::Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn test_replay_fixture() {
+        let path =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/avalon-1246.json");
+        let _replay = replay("10.254.0.6", &capture::load(&path).unwrap()).unwrap();
+        let info = find_miner("10.254.0.6", 2)
+            .unwrap()
+            .query("10.254.0.6", 2)
+            .unwrap();
+        assert_eq!(info.machine_type, "1246-83");
+        assert_eq!(info.worker1, "sl002.190x21");
+        assert_eq!(info.hash_avg, "89.34 THS");
+        assert_eq!(info.record.power, 2957);
+
+        #[cfg(feature = "ant-http")]
+        {
+            let path =
+                std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/antminer-s21.json");
+            let _replay = replay("10.254.0.7", &capture::load(&path).unwrap()).unwrap();
+            let info = find_miner("10.254.0.7", 2)
+                .unwrap()
+                .query("10.254.0.7", 2)
+                .unwrap();
+            assert_eq!(info.machine_type, "Antminer S21");
+            assert_eq!(info.hash_avg, "199.954 THS");
+            assert_eq!(info.temp, "44/68/69/70");
+            assert_eq!(info.fan, "3960/3960/3840/3840");
+            assert_eq!(info.record.temp_2, Some(70.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fake_miner() {
+        let fake = FakeMiner::add("10.254.0.3");
+        let miner = find_miner("10.254.0.3", 1).unwrap();
+        miner
+            .switch_account_if_diff("10.254.0.3", &account(), true)
+            .await
+            .unwrap();
+        fake.set_reply(Reply::Timeout);
+        assert!(miner.reboot("10.254.0.3", 1).unwrap_err().is_timeout());
+        assert_eq!(fake.calls(), vec!["switch other force", "reboot"]);
+        FakeMiner::remove("10.254.0.3");
+    }
+}
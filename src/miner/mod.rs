@@ -1,4 +1,42 @@
+pub mod anomaly;
+#[cfg(feature = "ant-http")]
 mod ant;
 mod avalon;
 mod bluestar;
+pub mod boards;
+pub mod capture;
+pub mod catalog;
+pub mod cgminer;
+pub mod conn;
+pub mod curtail;
+pub mod desired;
+pub mod detection;
+pub mod discovery;
+mod endpoint;
 pub mod entry;
+pub mod fleet;
+pub mod group;
+pub mod inventory;
+pub mod job;
+pub mod maintenance;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod mode;
+pub mod plan;
+pub mod position;
+pub mod power;
+pub mod profile;
+pub mod reach;
+pub mod restart;
+pub mod route;
+pub mod schedule;
+pub mod sheet;
+#[cfg(feature = "ssh")]
+pub mod ssh;
+pub mod stagger;
+pub mod subnet;
+pub mod tag;
+pub mod thermal;
+pub mod validate;
+pub mod window;
+pub mod zone;
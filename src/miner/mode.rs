@@ -0,0 +1,125 @@
+/// run modes of the miners. internally, in the db, the events and the jobs they stay the
+/// 高功/普通/sleep strings, labels from the sheets and the api calls are parsed through
+/// aliases, so a sheet can say "high" or a label of the site's own
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::context::Local;
+use crate::tariff::{MODE_HIGH, MODE_NORMAL, MODE_SLEEP};
+
+static ALIASES: Local<RunModeAliases> = Local::new(RunModeAliases::default);
+
+// accepted besides the configured aliases, compared ignoring case
+const HIGH: &[&str] = &[MODE_HIGH, "high", "high_perf", "performance"];
+const NORMAL: &[&str] = &[MODE_NORMAL, "normal", "low", "low_power"];
+const SLEEP: &[&str] = &[MODE_SLEEP, "睡眠", "休眠"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RunMode {
+    High,
+    Normal,
+    Sleep,
+}
+
+/// extra labels per mode, e.g. a site writing "full" for High
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunModeAliases {
+    #[serde(default)]
+    pub high: Vec<String>,
+    #[serde(default)]
+    pub normal: Vec<String>,
+    #[serde(default)]
+    pub sleep: Vec<String>,
+}
+
+impl RunModeAliases {
+    fn find(&self, label: &str) -> Option<RunMode> {
+        let is = |aliases: &[String]| aliases.iter().any(|a| a.trim().eq_ignore_ascii_case(label));
+        if is(&self.high) {
+            Some(RunMode::High)
+        } else if is(&self.normal) {
+            Some(RunMode::Normal)
+        } else if is(&self.sleep) {
+            Some(RunMode::Sleep)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn set_aliases(aliases: RunModeAliases) {
+    ALIASES.set(aliases);
+}
+
+impl RunMode {
+    /// the string kept in the db and sent to the drivers
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunMode::High => MODE_HIGH,
+            RunMode::Normal => MODE_NORMAL,
+            RunMode::Sleep => MODE_SLEEP,
+        }
+    }
+
+    /// the configured aliases first, then the built in labels, None when unknown
+    pub fn parse(label: &str) -> Option<RunMode> {
+        let label = label.trim();
+        if let Some(mode) = ALIASES.with(|aliases| aliases.find(label)) {
+            return Some(mode);
+        }
+        let is = |labels: &[&str]| labels.iter().any(|l| l.eq_ignore_ascii_case(label));
+        if is(HIGH) {
+            Some(RunMode::High)
+        } else if is(NORMAL) {
+            Some(RunMode::Normal)
+        } else if is(SLEEP) {
+            Some(RunMode::Sleep)
+        } else {
+            None
+        }
+    }
+
+    /// unknown and empty labels are Normal, as the switch always ran them
+    pub fn of(label: &str) -> RunMode {
+        RunMode::parse(label).unwrap_or(RunMode::Normal)
+    }
+}
+
+impl fmt::Display for RunMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// the internal string of a label, unknown labels are kept as they are
+pub fn normalize(label: &str) -> String {
+    match RunMode::parse(label) {
+        Some(mode) => mode.to_string(),
+        None => label.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_mode_labels() {
+        assert_eq!(RunMode::parse("高功"), Some(RunMode::High));
+        assert_eq!(RunMode::parse(" High "), Some(RunMode::High));
+        assert_eq!(RunMode::parse("low_power"), Some(RunMode::Normal));
+        assert_eq!(RunMode::parse("SLEEP"), Some(RunMode::Sleep));
+        assert_eq!(RunMode::parse("turbo"), None);
+        assert_eq!(RunMode::of(""), RunMode::Normal);
+        assert_eq!(normalize("normal"), "普通");
+        assert_eq!(normalize("turbo"), "turbo");
+
+        let aliases = RunModeAliases {
+            high: vec!["Turbo".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(aliases.find("turbo"), Some(RunMode::High));
+        assert_eq!(aliases.find("eco"), None);
+    }
+}
@@ -0,0 +1,487 @@
+/// review before apply for fleet documents: plan diffs a document against the fleet the
+/// sheets define now, machine by machine and field by field, apply writes the document onto
+/// the sheets and switches the machines whose account, pool or mode changed. a plan is made
+/// against a fingerprint of the sheets and refused once they changed, plan again then
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::entry::{switch_if_need, SwitchReport};
+use super::fleet::{self, FleetDocument, FleetImport, FleetMachine};
+use super::group::GroupSelector;
+use crate::error::MinerError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Add,
+    Remove,
+    Change,
+}
+
+impl ChangeKind {
+    fn sign(&self) -> char {
+        match self {
+            ChangeKind::Add => '+',
+            ChangeKind::Remove => '-',
+            ChangeKind::Change => '~',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineChange {
+    pub kind: ChangeKind,
+    pub ip: String,
+    /// sheet of the machine after the change, before it for a removal
+    pub sheet: String,
+    /// changed fields, empty for additions and removals
+    pub fields: Vec<FieldChange>,
+}
+
+/// changes of the pool types, the time sheets and the worker names, each a field change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingChange {
+    pub kind: ChangeKind,
+    pub change: FieldChange,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FleetPlan {
+    /// fingerprint of the fleet the plan was made against
+    pub base: String,
+    /// the document apply writes
+    pub document: FleetDocument,
+    pub machines: Vec<MachineChange>,
+    pub settings: Vec<SettingChange>,
+}
+
+/// what apply did
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FleetApply {
+    pub import: FleetImport,
+    /// None when no machine needed a switch
+    pub switch: Option<SwitchReport>,
+}
+
+// cells besides the ip, the bool as the sheet has it
+fn fields(m: &FleetMachine) -> [(&'static str, String); 12] {
+    [
+        ("miner_type", m.miner_type.clone()),
+        ("status", m.status.clone()),
+        ("account", m.account.clone()),
+        ("pool", m.pool.clone()),
+        ("switch_account", m.switch_account.clone()),
+        ("switch_pool", m.switch_pool.clone()),
+        ("run_mode", m.run_mode.clone()),
+        ("switch_run_mode", m.switch_run_mode.clone()),
+        (
+            "run_mode_fixed",
+            if m.run_mode_fixed { "1" } else { "" }.to_string(),
+        ),
+        ("group", m.group.clone()),
+        ("position", m.position.clone()),
+        ("addition_info", m.addition_info.clone()),
+    ]
+}
+
+// fields the sheets only describe, changing them needs no switch
+const DESCRIPTIVE: &[&str] = &["group", "position", "addition_info"];
+
+/// sha256 of the document without its export time
+pub fn fingerprint(document: &FleetDocument) -> Result<String, MinerError> {
+    let json = serde_json::to_string(&FleetDocument {
+        exported_at: 0,
+        ..document.clone()
+    })?;
+    Ok(Sha256::digest(json.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn machines_by_ip(document: &FleetDocument) -> BTreeMap<&str, (&str, &FleetMachine)> {
+    document
+        .machine_sheets
+        .iter()
+        .flat_map(|s| {
+            s.machines
+                .iter()
+                .map(move |m| (m.ip.as_str(), (s.name.as_str(), m)))
+        })
+        .collect()
+}
+
+fn setting(kind: ChangeKind, field: String, before: String, after: String) -> SettingChange {
+    SettingChange {
+        kind,
+        change: FieldChange {
+            field,
+            before,
+            after,
+        },
+    }
+}
+
+// added, removed and changed values of two maps as setting changes named by the prefix
+fn diff_map(
+    prefix: &str,
+    before: &BTreeMap<String, String>,
+    after: &BTreeMap<String, String>,
+) -> Vec<SettingChange> {
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let field = format!("{} {}", prefix, key);
+            match (before.get(key), after.get(key)) {
+                (None, Some(a)) => Some(setting(ChangeKind::Add, field, "".into(), a.clone())),
+                (Some(b), None) => Some(setting(ChangeKind::Remove, field, b.clone(), "".into())),
+                (Some(b), Some(a)) if a != b => {
+                    Some(setting(ChangeKind::Change, field, b.clone(), a.clone()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// the changes from the current fleet to the document
+pub fn diff(current: &FleetDocument, document: &FleetDocument) -> Result<FleetPlan, MinerError> {
+    let duplicates = document.duplicate_ips();
+    if !duplicates.is_empty() {
+        return Err(MinerError::FleetDocumentError(format!(
+            "duplicate ips: {}",
+            duplicates.join(", ")
+        )));
+    }
+    let before = machines_by_ip(current);
+    let after = machines_by_ip(document);
+    let mut machines = vec![];
+    for (ip, (sheet, machine)) in after.iter() {
+        let Some((old_sheet, old)) = before.get(ip) else {
+            machines.push(MachineChange {
+                kind: ChangeKind::Add,
+                ip: ip.to_string(),
+                sheet: sheet.to_string(),
+                fields: vec![],
+            });
+            continue;
+        };
+        let mut changed: Vec<FieldChange> = fields(old)
+            .into_iter()
+            .zip(fields(machine))
+            .filter(|(b, a)| b.1 != a.1)
+            .map(|((field, before), (_, after))| FieldChange {
+                field: field.to_string(),
+                before,
+                after,
+            })
+            .collect();
+        if old_sheet != sheet {
+            changed.insert(
+                0,
+                FieldChange {
+                    field: "sheet".to_string(),
+                    before: old_sheet.to_string(),
+                    after: sheet.to_string(),
+                },
+            );
+        }
+        if !changed.is_empty() {
+            machines.push(MachineChange {
+                kind: ChangeKind::Change,
+                ip: ip.to_string(),
+                sheet: sheet.to_string(),
+                fields: changed,
+            });
+        }
+    }
+    for (ip, (sheet, _)) in before.iter().filter(|(ip, _)| !after.contains_key(*ip)) {
+        machines.push(MachineChange {
+            kind: ChangeKind::Remove,
+            ip: ip.to_string(),
+            sheet: sheet.to_string(),
+            fields: vec![],
+        });
+    }
+
+    let pools = |d: &FleetDocument| -> BTreeMap<String, String> {
+        d.pools
+            .iter()
+            .map(|(pool_type, pools)| (pool_type.clone(), pools.join(", ")))
+            .collect()
+    };
+    let mut settings = diff_map("pool", &pools(current), &pools(document));
+    for (old, new) in [
+        (&current.account_time, &document.account_time),
+        (&current.perf_time, &document.perf_time),
+    ] {
+        if old.rows != new.rows {
+            settings.push(setting(
+                ChangeKind::Change,
+                format!("sheet {}", new.name),
+                format!("{} rows", old.rows.len()),
+                format!("{} rows", new.rows.len()),
+            ));
+        }
+    }
+    settings.extend(diff_map(
+        "worker",
+        &current.worker_names,
+        &document.worker_names,
+    ));
+
+    Ok(FleetPlan {
+        base: fingerprint(current)?,
+        document: document.clone(),
+        machines,
+        settings,
+    })
+}
+
+impl FleetPlan {
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty() && self.settings.is_empty()
+    }
+
+    fn count(&self, kind: ChangeKind) -> usize {
+        self.machines.iter().filter(|m| m.kind == kind).count()
+            + self.settings.iter().filter(|s| s.kind == kind).count()
+    }
+
+    /// ips to switch on apply: machines added or changed beyond the descriptive fields and
+    /// machines on a pool type that changed
+    pub fn switch_targets(&self) -> Vec<String> {
+        let pool_types: HashSet<&str> = self
+            .settings
+            .iter()
+            .filter_map(|s| s.change.field.strip_prefix("pool "))
+            .collect();
+        let changed: HashSet<&str> = self
+            .machines
+            .iter()
+            .filter(|m| match m.kind {
+                ChangeKind::Add => true,
+                ChangeKind::Remove => false,
+                ChangeKind::Change => m
+                    .fields
+                    .iter()
+                    .any(|f| !DESCRIPTIVE.contains(&f.field.as_str())),
+            })
+            .map(|m| m.ip.as_str())
+            .collect();
+        let mut ips: Vec<String> = self
+            .document
+            .machine_sheets
+            .iter()
+            .flat_map(|s| s.machines.iter())
+            .filter(|m| {
+                changed.contains(m.ip.as_str())
+                    || pool_types.contains(m.pool.as_str())
+                    || pool_types.contains(m.switch_pool.as_str())
+            })
+            .map(|m| m.ip.clone())
+            .collect();
+        ips.sort();
+        ips.dedup();
+        ips
+    }
+
+    /// one line per change, fields of changed machines indented below them
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for m in self.machines.iter() {
+            let _ = writeln!(out, "{} {} ({})", m.kind.sign(), m.ip, m.sheet);
+            if m.kind == ChangeKind::Add {
+                if let Some((_, machine)) = machines_by_ip(&self.document).get(m.ip.as_str()) {
+                    for (field, value) in fields(machine).iter().filter(|f| !f.1.is_empty()) {
+                        let _ = writeln!(out, "    {}: {}", field, value);
+                    }
+                }
+            }
+            for f in m.fields.iter() {
+                let _ = writeln!(out, "    {}: {:?} -> {:?}", f.field, f.before, f.after);
+            }
+        }
+        for s in self.settings.iter() {
+            let c = &s.change;
+            let _ = match s.kind {
+                ChangeKind::Add => writeln!(out, "+ {}: {:?}", c.field, c.after),
+                ChangeKind::Remove => writeln!(out, "- {}: {:?}", c.field, c.before),
+                ChangeKind::Change => {
+                    writeln!(out, "~ {}: {:?} -> {:?}", c.field, c.before, c.after)
+                }
+            };
+        }
+        let _ = write!(
+            out,
+            "{} to add, {} to change, {} to remove, {} machines to switch",
+            self.count(ChangeKind::Add),
+            self.count(ChangeKind::Change),
+            self.count(ChangeKind::Remove),
+            self.switch_targets().len()
+        );
+        out
+    }
+}
+
+async fn current(excel: &str, document: &FleetDocument) -> Result<FleetDocument, MinerError> {
+    fleet::export(
+        excel,
+        document
+            .machine_sheets
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect(),
+        &document.account_time.name,
+        &document.perf_time.name,
+        &document.pool_sheet,
+    )
+    .await
+}
+
+/// the changes the document makes to the fleet of the sheets it names
+pub async fn plan(excel: &str, document: &FleetDocument) -> Result<FleetPlan, MinerError> {
+    let current = current(excel, document).await?;
+    diff(&current, document)
+}
+
+/// write the planned document onto the sheets and switch the machines it changed, refused
+/// when the sheets changed since the plan
+pub async fn apply(
+    runtime: tokio::runtime::Handle,
+    excel: &str,
+    plan: &FleetPlan,
+) -> Result<FleetApply, MinerError> {
+    let document = &plan.document;
+    if fingerprint(&current(excel, document).await?)? != plan.base {
+        return Err(MinerError::FleetDocumentError(
+            "sheets changed since the plan, plan again".to_string(),
+        ));
+    }
+    if plan.is_empty() {
+        return Ok(FleetApply::default());
+    }
+
+    let import = fleet::import(excel, document).await?;
+    let targets = plan.switch_targets();
+    info!("fleet plan applied, switch {} machines", targets.len());
+    let switch = if targets.is_empty() {
+        None
+    } else {
+        Some(
+            switch_if_need(
+                runtime,
+                excel,
+                document
+                    .machine_sheets
+                    .iter()
+                    .map(|s| s.name.as_str())
+                    .collect(),
+                &document.account_time.name,
+                &document.perf_time.name,
+                &document.pool_sheet,
+                &GroupSelector::from(targets),
+            )
+            .await?,
+        )
+    };
+    Ok(FleetApply { import, switch })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner::fleet::{FleetSheet, FLEET_VERSION};
+
+    fn machine(ip: &str, account: &str) -> FleetMachine {
+        FleetMachine {
+            ip: ip.to_string(),
+            miner_type: "ant".to_string(),
+            account: account.to_string(),
+            pool: "btc".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn document(machines: Vec<FleetMachine>, pool: &str) -> FleetDocument {
+        FleetDocument {
+            version: FLEET_VERSION,
+            machine_sheets: vec![FleetSheet {
+                name: "ftMgRx".to_string(),
+                machines,
+            }],
+            pools: BTreeMap::from([
+                ("btc".to_string(), vec![pool.to_string(); 3]),
+                ("ltc".to_string(), vec!["ltc.pool:3333".to_string(); 3]),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fleet_plan() {
+        let current = document(
+            vec![
+                machine("10.0.0.1", "acc"),
+                machine("10.0.0.2", "acc"),
+                machine("10.0.0.3", "acc"),
+            ],
+            "btc.pool:3333",
+        );
+        let mut moved = machine("10.0.0.2", "acc");
+        moved.position = "A-03-12-4".to_string();
+        let target = document(
+            vec![
+                machine("10.0.0.1", "acc2"),
+                moved,
+                machine("10.0.0.4", "acc"),
+            ],
+            "btc.pool:3333",
+        );
+
+        let plan = diff(&current, &target).unwrap();
+        let kinds: Vec<(ChangeKind, &str)> = plan
+            .machines
+            .iter()
+            .map(|m| (m.kind, m.ip.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (ChangeKind::Change, "10.0.0.1"),
+                (ChangeKind::Change, "10.0.0.2"),
+                (ChangeKind::Add, "10.0.0.4"),
+                (ChangeKind::Remove, "10.0.0.3"),
+            ]
+        );
+        assert_eq!(plan.machines[0].fields[0].after, "acc2");
+        // a new position needs no switch
+        assert_eq!(plan.switch_targets(), ["10.0.0.1", "10.0.0.4"]);
+        let text = plan.render();
+        assert!(
+            text.contains("    account: \"acc\" -> \"acc2\""),
+            "{}",
+            text
+        );
+        assert!(text.ends_with("1 to add, 2 to change, 1 to remove, 2 machines to switch"));
+
+        // a new pool switches every machine on it
+        let repooled = document(current.machine_sheets[0].machines.clone(), "new.pool:3333");
+        let plan = diff(&current, &repooled).unwrap();
+        assert_eq!(plan.settings.len(), 1);
+        assert_eq!(plan.switch_targets().len(), 3);
+
+        assert!(diff(&current, &current).unwrap().is_empty());
+        assert_eq!(plan.base, fingerprint(&current).unwrap());
+    }
+}
@@ -0,0 +1,258 @@
+/// where the machines stand, room, row, rack and shelf slot parsed from the position column of
+/// the sheets by a configurable format, e.g. "A-03-12-4" by "{room}-{row}-{rack}-{slot}".
+/// the latest telemetry of each machine keyed by its position lets the frontend draw thermal
+/// and hashrate heatmaps of the room
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::store::db;
+
+static FORMAT: Local<PositionFormat> =
+    Local::new(|| PositionFormat::parse(DEFAULT_FORMAT).unwrap());
+// ip to position from the sheets
+static POSITIONS: Local<HashMap<String, Position>> = Local::new(HashMap::new);
+
+pub const DEFAULT_FORMAT: &str = "{room}-{row}-{rack}-{slot}";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    /// empty when the format has no such field
+    pub room: String,
+    pub row: String,
+    pub rack: String,
+    /// shelf slot in the rack
+    pub slot: String,
+}
+
+// numbers by value, "2" before "10", anything else as text
+fn natural(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+impl Position {
+    /// room, row, rack then slot, numbers by value
+    pub fn cmp_natural(&self, other: &Position) -> Ordering {
+        natural(&self.room, &other.room)
+            .then_with(|| natural(&self.row, &other.row))
+            .then_with(|| natural(&self.rack, &other.rack))
+            .then_with(|| natural(&self.slot, &other.slot))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Room,
+    Row,
+    Rack,
+    Slot,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Field(Field),
+    Literal(String),
+}
+
+/// compiled format, fields in braces and the text between them matched literally
+#[derive(Debug, Clone)]
+pub struct PositionFormat {
+    pattern: String,
+    parts: Vec<Part>,
+}
+
+impl PositionFormat {
+    /// fields need text between them to be told apart, e.g. "{row}{rack}" is rejected
+    pub fn parse(pattern: &str) -> Result<PositionFormat, MinerError> {
+        let error =
+            |reason: &str| MinerError::PositionFormatError(format!("{}: {}", reason, pattern));
+        let mut parts = vec![];
+        let mut rest = pattern.trim();
+        while !rest.is_empty() {
+            let Some(start) = rest.find('{') else {
+                parts.push(Part::Literal(rest.to_string()));
+                break;
+            };
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').ok_or_else(|| error("unclosed {"))? + start;
+            let field = match rest[start + 1..end].trim().to_lowercase().as_str() {
+                "room" => Field::Room,
+                "row" => Field::Row,
+                "rack" => Field::Rack,
+                "slot" | "shelf" => Field::Slot,
+                other => return Err(error(&format!("unknown field {}", other))),
+            };
+            if let Some(Part::Field(_)) = parts.last() {
+                return Err(error("fields without text between them"));
+            }
+            parts.push(Part::Field(field));
+            rest = &rest[end + 1..];
+        }
+        if !parts.iter().any(|p| matches!(p, Part::Field(_))) {
+            return Err(error("no field"));
+        }
+        Ok(PositionFormat {
+            pattern: pattern.to_string(),
+            parts,
+        })
+    }
+
+    /// None when the text does not match, the text before the first field and after the last
+    /// included
+    pub fn matches(&self, text: &str) -> Option<Position> {
+        let mut position = Position::default();
+        let mut rest = text.trim();
+        for (i, part) in self.parts.iter().enumerate() {
+            match part {
+                Part::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+                Part::Field(field) => {
+                    let end = match self.parts.get(i + 1) {
+                        Some(Part::Literal(next)) => rest.find(next.as_str())?,
+                        _ => rest.len(),
+                    };
+                    let value = rest[..end].trim();
+                    if value.is_empty() {
+                        return None;
+                    }
+                    let target = match field {
+                        Field::Room => &mut position.room,
+                        Field::Row => &mut position.row,
+                        Field::Rack => &mut position.rack,
+                        Field::Slot => &mut position.slot,
+                    };
+                    *target = value.to_string();
+                    rest = &rest[end..];
+                }
+            }
+        }
+        rest.is_empty().then_some(position)
+    }
+}
+
+/// latest telemetry of a machine at a position, None without a record in the range
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PositionCell {
+    pub ip: String,
+    pub position: Position,
+    pub machine_type: String,
+    /// TH/s
+    pub hashrate: Option<f64>,
+    /// hottest board
+    pub temperature: Option<f64>,
+    pub power: Option<i32>,
+    pub time: Option<i64>,
+}
+
+/// empty for the default format, the previous format is kept on an error
+pub fn set_format(pattern: &str) -> Result<(), MinerError> {
+    let pattern = if pattern.trim().is_empty() {
+        DEFAULT_FORMAT
+    } else {
+        pattern
+    };
+    FORMAT.set(PositionFormat::parse(pattern)?);
+    Ok(())
+}
+
+/// position cell of a sheet by the configured format, None for an empty or unmatched cell
+pub fn parse(text: &str) -> Option<Position> {
+    if text.trim().is_empty() {
+        return None;
+    }
+    FORMAT.with(|format| {
+        let position = format.matches(text);
+        if position.is_none() {
+            info!("position {:?} does not match {}", text, format.pattern);
+        }
+        position
+    })
+}
+
+/// replace the positions with the ones of the reloaded sheets
+pub fn set_positions(positions: HashMap<String, Position>) {
+    POSITIONS.set(positions);
+}
+
+pub fn of(ip: &str) -> Option<Position> {
+    POSITIONS.with(|positions| positions.get(ip).cloned())
+}
+
+/// every machine with a position and its latest record of the time range, in room, row, rack
+/// and slot order, limited to one room unless empty
+pub fn heatmap(
+    start_time: i64,
+    end_time: i64,
+    room: &str,
+) -> Result<Vec<PositionCell>, MinerError> {
+    let mut cells: HashMap<String, PositionCell> = POSITIONS
+        .get()
+        .iter()
+        .filter(|(_, position)| room.is_empty() || position.room == room)
+        .map(|(ip, position)| {
+            let cell = PositionCell {
+                ip: ip.clone(),
+                position: position.clone(),
+                ..Default::default()
+            };
+            (ip.clone(), cell)
+        })
+        .collect();
+    for record in db::query_all_records_by_time(start_time, end_time)? {
+        let Some(cell) = cells.get_mut(&record.ip) else {
+            continue;
+        };
+        if cell.time.is_some_and(|time| time > record.create_time) {
+            continue;
+        }
+        cell.machine_type = record.machine_type.clone();
+        cell.hashrate = Some(record.hash_avg / 1000.0);
+        cell.temperature = [record.temp_0, record.temp_1, record.temp_2]
+            .into_iter()
+            .flatten()
+            .reduce(f64::max);
+        cell.power = Some(record.power);
+        cell.time = Some(record.create_time);
+    }
+
+    let mut cells: Vec<PositionCell> = cells.into_values().collect();
+    cells.sort_by(|a, b| a.position.cmp_natural(&b.position).then(a.ip.cmp(&b.ip)));
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_format() {
+        let format = PositionFormat::parse(DEFAULT_FORMAT).unwrap();
+        let position = format.matches(" A-03-12-4 ").unwrap();
+        assert_eq!((position.room.as_str(), position.row.as_str()), ("A", "03"));
+        assert_eq!(
+            (position.rack.as_str(), position.slot.as_str()),
+            ("12", "4")
+        );
+        assert_eq!(format.matches("A-03-12"), None);
+        assert_eq!(format.matches("A--12-4"), None);
+
+        let format = PositionFormat::parse("{room}号机房 {row}排{rack}架 第{shelf}层").unwrap();
+        let position = format.matches("2号机房 5排10架 第3层").unwrap();
+        assert_eq!(position.rack, "10");
+        assert_eq!(position.slot, "3");
+        let other = format.matches("2号机房 5排2架 第3层").unwrap();
+        assert_eq!(other.cmp_natural(&position), Ordering::Less);
+
+        assert!(PositionFormat::parse("{row}{rack}").is_err());
+        assert!(PositionFormat::parse("{room}-{cabinet}").is_err());
+        assert!(PositionFormat::parse("rack").is_err());
+    }
+}
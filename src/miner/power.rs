@@ -0,0 +1,201 @@
+/// machine power, reported by the miner or estimated from its model, and energy usage
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::catalog;
+use super::entry::MachineRecord;
+use crate::error::MinerError;
+use crate::store::db;
+
+// J/TH assumed when the model is not in the catalog
+const DEFAULT_EFFICIENCY: f64 = 30.0;
+// extra power share of high performance mode of a model not in the catalog
+const HIGH_PERF_EXTRA: f64 = 0.15;
+// records further apart are a gap, the machine is not counted in between
+const MAX_RECORD_GAP: i64 = 1800;
+const BUCKET_SECONDS: i64 = 3600;
+
+/// J/TH of the model in normal mode
+pub fn model_efficiency(machine_type: &str) -> f64 {
+    catalog::lookup(machine_type)
+        .map(|spec| spec.efficiency())
+        .filter(|efficiency| *efficiency > 0.0)
+        .unwrap_or(DEFAULT_EFFICIENCY)
+}
+
+/// extra power share of high performance mode against normal mode
+pub fn high_extra(machine_type: &str) -> f64 {
+    match catalog::lookup(machine_type) {
+        Some(spec) if spec.power_high > 0.0 && spec.power_normal > 0.0 => {
+            spec.power_high / spec.power_normal - 1.0
+        }
+        _ => HIGH_PERF_EXTRA,
+    }
+}
+
+/// watts estimated from model, work mode and hashrate in GH/s
+pub fn estimate_power(machine_type: &str, work_mode: i32, hash_avg: f64) -> i32 {
+    let mut power = hash_avg / 1000.0 * model_efficiency(machine_type);
+    if work_mode == 1 {
+        power *= 1.0 + high_extra(machine_type);
+    }
+    power.round() as i32
+}
+
+/// reported watts, estimated when the miner reports none
+pub fn record_power(record: &MachineRecord) -> f64 {
+    if record.power > 0 {
+        record.power as f64
+    } else {
+        estimate_power(&record.machine_type, record.work_mode, record.hash_avg) as f64
+    }
+}
+
+fn watts(value: &Value) -> Option<i32> {
+    let watts = match value {
+        Value::Number(n) => n.as_f64()?,
+        // "3250 W"
+        Value::String(s) => s
+            .trim()
+            .trim_end_matches(['W', 'w'])
+            .trim()
+            .parse::<f64>()
+            .ok()?,
+        _ => return None,
+    };
+    if watts > 0.0 {
+        Some(watts as i32)
+    } else {
+        None
+    }
+}
+
+/// power of antminer stats, only newer firmwares report it
+pub fn parse_ant_power(stats: &Value) -> Option<i32> {
+    let stat = &stats["STATS"][0];
+    ["chain_power", "power", "total_power", "Power"]
+        .iter()
+        .find_map(|key| watts(&stat[*key]))
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PowerPoint {
+    /// start of the hour
+    pub time: i64,
+    pub kwh: f64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MachinePowerUsage {
+    pub ip: String,
+    pub machine_type: String,
+    pub kwh: f64,
+    pub series: Vec<PowerPoint>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PowerUsage {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub total_kwh: f64,
+    /// fleet hourly kWh
+    pub series: Vec<PowerPoint>,
+    pub machines: Vec<MachinePowerUsage>,
+}
+
+fn to_series(buckets: &BTreeMap<i64, f64>) -> Vec<PowerPoint> {
+    buckets
+        .iter()
+        .map(|(time, kwh)| PowerPoint {
+            time: *time,
+            kwh: *kwh,
+        })
+        .collect()
+}
+
+/// energy between consecutive records of each machine, records ordered by ip and time
+pub fn power_usage(records: &[MachineRecord], start_time: i64, end_time: i64) -> PowerUsage {
+    let mut usage = PowerUsage {
+        start_time,
+        end_time,
+        ..Default::default()
+    };
+    let mut fleet: BTreeMap<i64, f64> = BTreeMap::new();
+
+    for machine_records in records.chunk_by(|a, b| a.ip == b.ip) {
+        let mut buckets: BTreeMap<i64, f64> = BTreeMap::new();
+        for pair in machine_records.windows(2) {
+            let elapsed = pair[1].create_time - pair[0].create_time;
+            if elapsed <= 0 || elapsed > MAX_RECORD_GAP {
+                continue;
+            }
+            let kwh = record_power(&pair[0]) * elapsed as f64 / 3600000.0;
+            let bucket = pair[0].create_time - pair[0].create_time.rem_euclid(BUCKET_SECONDS);
+            *buckets.entry(bucket).or_insert(0.0) += kwh;
+            *fleet.entry(bucket).or_insert(0.0) += kwh;
+        }
+
+        let last = &machine_records[machine_records.len() - 1];
+        usage.machines.push(MachinePowerUsage {
+            ip: last.ip.clone(),
+            machine_type: last.machine_type.clone(),
+            kwh: buckets.values().sum(),
+            series: to_series(&buckets),
+        });
+    }
+
+    usage.total_kwh = fleet.values().sum();
+    usage.series = to_series(&fleet);
+    usage
+}
+
+pub fn query_power_usage(start_time: i64, end_time: i64) -> Result<PowerUsage, MinerError> {
+    let records = db::query_all_records_by_time(start_time, end_time)?;
+    Ok(power_usage(&records, start_time, end_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_power_estimate_and_parse() {
+        assert_eq!(estimate_power("Antminer S19j Pro", 0, 100000.0), 2950);
+        assert_eq!(estimate_power("unknown", 0, 100000.0), 3000);
+        assert_eq!(
+            parse_ant_power(&json!({"STATS": [{"chain_power": "3250 W"}]})),
+            Some(3250)
+        );
+        assert_eq!(
+            parse_ant_power(&json!({"STATS": [{"rate_avg": 1.0}]})),
+            None
+        );
+    }
+
+    #[test]
+    fn test_power_usage() {
+        let record = |ip: &str, power: i32, create_time: i64| MachineRecord {
+            ip: ip.to_string(),
+            power,
+            create_time,
+            ..Default::default()
+        };
+        let records = vec![
+            record("192.168.188.41", 3600, 0),
+            record("192.168.188.41", 3600, 600),
+            // gap, not counted
+            record("192.168.188.41", 3600, 7200),
+            record("192.168.188.42", 1800, 3000),
+            record("192.168.188.42", 1800, 4200),
+        ];
+        let usage = power_usage(&records, 0, 7200);
+        assert_eq!(usage.machines.len(), 2);
+        assert!((usage.machines[0].kwh - 0.6).abs() < 0.0001);
+        assert!((usage.machines[1].kwh - 0.6).abs() < 0.0001);
+        assert!((usage.total_kwh - 1.2).abs() < 0.0001);
+        assert_eq!(usage.series.len(), 1);
+    }
+}
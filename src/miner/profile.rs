@@ -0,0 +1,231 @@
+/// named config profiles combining pools, run mode, fan and tuning
+use std::collections::HashMap;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::desired::{self, DesiredMachine, DesiredState, FanPolicy, StateReport};
+use super::entry::{with_miner, MinerOperation, PoolConfig};
+use super::group::{self, GroupSelector};
+use super::maintenance;
+use super::mode;
+use crate::context;
+use crate::error::MinerError;
+use crate::notify;
+use crate::store::cache::DbCache;
+use crate::store::db;
+
+static PROFILES: DbCache<HashMap<String, ConfigProfile>> = DbCache::new(load);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigProfile {
+    /// e.g. "night-high-power", "curtailment", "standard-f2pool"
+    pub name: String,
+    /// user is the account, the worker suffix is added per machine
+    pub pools: Option<Vec<PoolConfig>>,
+    /// 高功, 普通 or sleep
+    pub mode: Option<String>,
+    pub fan: Option<FanPolicy>,
+    /// chip frequency MHz, antminer only
+    pub freq: Option<u32>,
+    /// chip voltage mV, antminer only
+    pub voltage: Option<u32>,
+}
+
+impl ConfigProfile {
+    pub fn desired(&self, ip: &str) -> DesiredMachine {
+        DesiredMachine {
+            ip: ip.to_string(),
+            pools: self.pools.clone(),
+            mode: self.mode.clone(),
+            fan: self.fan.clone(),
+        }
+    }
+}
+
+fn load() -> HashMap<String, ConfigProfile> {
+    let bodies = db::query_profiles().unwrap_or_else(|e| {
+        error!("load profiles error: {:?}", e);
+        vec![]
+    });
+    bodies
+        .iter()
+        .filter_map(|body| serde_json::from_str::<ConfigProfile>(body).ok())
+        .map(|p| (p.name.clone(), p))
+        .collect()
+}
+
+/// load the profiles of the db just opened
+pub fn reload() {
+    PROFILES.reload();
+}
+
+/// store a profile, replaces the one of the same name
+pub fn save(profile: ConfigProfile) -> Result<(), MinerError> {
+    db::save_profile(&profile.name, &serde_json::to_string(&profile)?)?;
+    PROFILES.with(|profiles| profiles.insert(profile.name.clone(), profile));
+    Ok(())
+}
+
+pub fn delete(name: &str) -> Result<(), MinerError> {
+    db::delete_profile(name)?;
+    PROFILES.with(|profiles| profiles.remove(name));
+    Ok(())
+}
+
+pub fn get(name: &str) -> Option<ConfigProfile> {
+    PROFILES.with(|profiles| profiles.get(name).cloned())
+}
+
+pub fn list() -> Vec<ConfigProfile> {
+    let mut list: Vec<ConfigProfile> =
+        PROFILES.with(|profiles| profiles.values().cloned().collect());
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    list
+}
+
+fn cell(row: &Value, names: &[String], name: &str) -> Option<String> {
+    let idx = names.iter().position(|n| n == name)?;
+    match &row[idx] {
+        Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// profile of a sheet row, columns 名称 矿池 账户 密码 工作模式 风扇 频率 电压.
+/// 矿池 lists urls separated by commas, 风扇 is auto or a pwm percent.
+pub fn parse_row(row: &Value, names: &[String]) -> Option<ConfigProfile> {
+    let name = cell(row, names, "名称")?;
+    let account = cell(row, names, "账户").unwrap_or_default();
+    let password = cell(row, names, "密码").unwrap_or_default();
+    let pools = cell(row, names, "矿池").map(|urls| {
+        urls.split([',', '，'])
+            .map(|url| PoolConfig {
+                url: url.trim().to_string(),
+                user: account.clone(),
+                password: password.as_str().into(),
+            })
+            .collect()
+    });
+    let fan = cell(row, names, "风扇").map(|fan| match fan.parse::<u32>() {
+        Ok(pwm) => FanPolicy::Fixed(pwm),
+        Err(_) => FanPolicy::Auto,
+    });
+
+    Some(ConfigProfile {
+        name,
+        pools,
+        mode: cell(row, names, "工作模式").map(|label| mode::normalize(&label)),
+        fan,
+        freq: cell(row, names, "频率").and_then(|v| v.parse().ok()),
+        voltage: cell(row, names, "电压").and_then(|v| v.parse().ok()),
+    })
+}
+
+/// load and store every profile of the sheet
+pub async fn load_from_sheet(excel: &str, sheet: &str) -> Result<Vec<ConfigProfile>, MinerError> {
+    let values = notify::query_sheet_values(excel, sheet).await?;
+    let header = values.first().ok_or(MinerError::FeishuParserJsonError)?;
+    let names: Vec<String> = header
+        .as_array()
+        .map(|cells| {
+            cells
+                .iter()
+                .map(|c| c.as_str().unwrap_or("").trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let profiles: Vec<ConfigProfile> = values
+        .iter()
+        .skip(1)
+        .filter_map(|row| parse_row(row, &names))
+        .collect();
+    for profile in profiles.iter() {
+        save(profile.clone())?;
+    }
+    info!("loaded {} profiles from sheet {}", profiles.len(), sheet);
+    Ok(profiles)
+}
+
+/// apply the profile to the selected machines, only drift is changed, tuning is always written
+pub async fn apply(
+    runtime: &tokio::runtime::Handle,
+    selector: &GroupSelector,
+    name: &str,
+) -> Result<StateReport, MinerError> {
+    let profile = get(name).ok_or_else(|| MinerError::ProfileNotFoundError(name.to_string()))?;
+    let ips = selector.resolve();
+    info!("apply profile {} to {} machines", name, ips.len());
+
+    let state = DesiredState {
+        machines: ips.iter().map(|ip| profile.desired(ip)).collect(),
+    };
+    let mut report = desired::reconcile(runtime, &state, true).await;
+
+    if profile.freq.is_some() || profile.voltage.is_some() {
+        let targets: Vec<String> = ips
+            .into_iter()
+            .filter(|ip| {
+                !maintenance::is_in_maintenance(ip)
+                    && !report.failed.iter().any(|(failed, _)| failed == ip)
+            })
+            .collect();
+        let handles = targets.iter().cloned().map(|ip| {
+            let (freq, voltage) = (profile.freq, profile.voltage);
+            context::spawn(runtime, async move {
+                let _permit = group::permit(&ip).await;
+                with_miner(&ip, 3, |miner| miner.config_tuning(&ip, freq, voltage, 3))
+            })
+        });
+        let results = futures::future::join_all(handles).await;
+        for (ip, result) in targets.iter().zip(results) {
+            match result {
+                Ok(Ok(_)) => {}
+                // models without tuning keep the rest of the profile
+                Ok(Err(MinerError::MinerNotSupportError)) => {
+                    info!("{} tuning not supported by the miner, skipped", ip)
+                }
+                Ok(Err(e)) => report.failed.push((ip.clone(), e.to_string())),
+                Err(e) => report.failed.push((ip.clone(), e.to_string())),
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_row() {
+        let names: Vec<String> = ["名称", "矿池", "账户", "工作模式", "风扇", "频率"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let profile = parse_row(
+            &json!([
+                "standard-f2pool",
+                "btc.f2pool.com:1314, btc-asia.f2pool.com:1314",
+                "lcd",
+                "普通",
+                "auto",
+                525
+            ]),
+            &names,
+        )
+        .unwrap();
+        assert_eq!(profile.pools.as_ref().unwrap().len(), 2);
+        assert_eq!(profile.pools.unwrap()[1].url, "btc-asia.f2pool.com:1314");
+        assert_eq!(profile.mode.as_deref(), Some("普通"));
+        assert_eq!(profile.fan, Some(FanPolicy::Auto));
+        assert_eq!(profile.freq, Some(525));
+        assert_eq!(profile.voltage, None);
+
+        assert!(parse_row(&json!(["", "x"]), &names).is_none());
+    }
+}
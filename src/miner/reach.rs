@@ -0,0 +1,153 @@
+/// reachability of a machine without raw sockets, so it works unprivileged on any target: a
+/// tcp connect to the web or cgminer api port, the system ping command as fallback
+use std::net::{IpAddr, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{endpoint, route};
+use crate::error::MinerError;
+
+lazy_static! {
+    static ref CONFIG: Mutex<ReachConfig> = Mutex::new(ReachConfig::default());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReachMethod {
+    /// connect to the ports in turn
+    Tcp,
+    /// the ping command of the system
+    SystemPing,
+    /// the ports, then ping when none accepts
+    TcpThenPing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReachConfig {
+    pub method: ReachMethod,
+    /// tried in order, web ui and cgminer api by default
+    pub ports: Vec<u16>,
+    /// per port connect and for the ping reply
+    pub timeout_ms: u64,
+}
+
+impl Default for ReachConfig {
+    fn default() -> Self {
+        ReachConfig {
+            method: ReachMethod::TcpThenPing,
+            ports: vec![80, 4028],
+            timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Reachability {
+    /// the port accepted a connection
+    Port(u16),
+    /// answered the system ping
+    Ping,
+    Unreachable,
+}
+
+impl Reachability {
+    pub fn is_reachable(&self) -> bool {
+        *self != Reachability::Unreachable
+    }
+}
+
+pub fn set_config(config: ReachConfig) {
+    *CONFIG.lock().unwrap() = config;
+}
+
+/// check with the configured method, an ip that does not parse is an error
+pub fn check(ip: &str) -> Result<Reachability, MinerError> {
+    let config = CONFIG.lock().unwrap().clone();
+    check_with(ip, &config)
+}
+
+pub fn check_with(ip: &str, config: &ReachConfig) -> Result<Reachability, MinerError> {
+    let addr = ip
+        .parse::<IpAddr>()
+        .map_err(|_| MinerError::InvalidIpError(ip.to_string()))?;
+    let timeout = Duration::from_millis(config.timeout_ms.max(1));
+    if config.method != ReachMethod::SystemPing {
+        if let Some(port) = config.ports.iter().find(|p| port_open(ip, **p, timeout)) {
+            return Ok(Reachability::Port(*port));
+        }
+    }
+    if config.method != ReachMethod::Tcp && system_ping(addr, timeout) {
+        return Ok(Reachability::Ping);
+    }
+    Ok(Reachability::Unreachable)
+}
+
+/// the port accepts a connection, the fakes of the mock feature answer on local ports
+pub(crate) fn port_open(ip: &str, port: u16, timeout: Duration) -> bool {
+    endpoint::api_addr(ip, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .is_some_and(|addr| route::connect(&addr, timeout).is_ok())
+}
+
+// a missing ping command counts as no reply
+fn system_ping(addr: IpAddr, timeout: Duration) -> bool {
+    let mut command = Command::new("ping");
+    ping_args(&mut command, timeout);
+    command
+        .arg(addr.to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// one echo, the reply timeout flag differs by system
+#[cfg(windows)]
+fn ping_args(command: &mut Command, timeout: Duration) {
+    command.args(["-n", "1", "-w", &timeout.as_millis().to_string()]);
+}
+
+#[cfg(target_os = "macos")]
+fn ping_args(command: &mut Command, timeout: Duration) {
+    command.args(["-c", "1", "-t", &timeout.as_secs().max(1).to_string()]);
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn ping_args(command: &mut Command, timeout: Duration) {
+    command.args(["-c", "1", "-W", &timeout.as_secs().max(1).to_string()]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reach_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = {
+            let other = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            other.local_addr().unwrap().port()
+        };
+        let config = ReachConfig {
+            method: ReachMethod::Tcp,
+            ports: vec![closed, open],
+            timeout_ms: 500,
+        };
+
+        assert_eq!(
+            check_with("127.0.0.1", &config).unwrap(),
+            Reachability::Port(open)
+        );
+        let config = ReachConfig {
+            ports: vec![closed],
+            ..config
+        };
+        assert!(!check_with("127.0.0.1", &config).unwrap().is_reachable());
+        assert_eq!(check_with("10.0.0.256", &config).unwrap_err().code(), 3007);
+    }
+}
@@ -0,0 +1,222 @@
+/// crash loop detection, cgminer restarting over and over shows as the reported elapsed
+/// going back to a few seconds poll after poll
+use std::collections::{HashMap, VecDeque};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::entry::{config_mode_ips, exec_ips, MachineRecord};
+use super::maintenance;
+use crate::clock;
+use crate::context::Local;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+use crate::store::db;
+use crate::tariff;
+
+static RESTARTS: Local<RestartWatch> = Local::new(|| RestartWatch::new(CrashLoopConfig::default()));
+
+/// what is done to a crash looping machine besides the alert
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CrashLoopAction {
+    /// alert only
+    Alert,
+    /// to 普通, a psu at its limit often resets the miner in 高功
+    Downclock,
+    Sleep,
+    /// the command over ssh, e.g. restarting a wedged cgminer
+    Exec,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashLoopConfig {
+    /// restarts within the polls flagging a crash loop, 0 to disable
+    pub restarts: u32,
+    /// consecutive polls looked at
+    pub polls: u32,
+    pub action: CrashLoopAction,
+    /// shell command of the Exec action
+    #[serde(default)]
+    pub command: String,
+}
+
+impl Default for CrashLoopConfig {
+    fn default() -> Self {
+        CrashLoopConfig {
+            restarts: 3,
+            polls: 10,
+            action: CrashLoopAction::Alert,
+            command: "".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    last_elapsed: i64,
+    /// one entry per poll, true when the miner restarted since the one before
+    restarts: VecDeque<bool>,
+    looping: bool,
+}
+
+#[derive(Debug)]
+pub struct RestartWatch {
+    config: CrashLoopConfig,
+    history: HashMap<String, History>,
+}
+
+impl RestartWatch {
+    pub fn new(config: CrashLoopConfig) -> Self {
+        RestartWatch {
+            config,
+            history: HashMap::new(),
+        }
+    }
+
+    /// record one poll, returns the restarts in the window when the machine starts
+    /// crash looping. cleared once the window has no restart
+    pub fn check(&mut self, ip: &str, elapsed: i64) -> Option<u32> {
+        // 0 when the miner did not report it
+        if self.config.restarts == 0 || elapsed <= 0 {
+            return None;
+        }
+        let history = self.history.entry(ip.to_string()).or_default();
+        let restarted = history.last_elapsed > 0 && elapsed < history.last_elapsed;
+        history.last_elapsed = elapsed;
+        history.restarts.push_back(restarted);
+        while history.restarts.len() > self.config.polls as usize {
+            history.restarts.pop_front();
+        }
+
+        let count = history.restarts.iter().filter(|r| **r).count() as u32;
+        if count == 0 {
+            history.looping = false;
+        }
+        if count >= self.config.restarts && !history.looping {
+            history.looping = true;
+            return Some(count);
+        }
+        None
+    }
+
+    pub fn is_looping(&self, ip: &str) -> bool {
+        self.history.get(ip).map(|h| h.looping).unwrap_or(false)
+    }
+}
+
+pub fn set_config(config: CrashLoopConfig) {
+    RESTARTS.set(RestartWatch::new(config));
+}
+
+/// machine flagged as crash looping and not recovered yet
+pub fn is_looping(ip: &str) -> bool {
+    RESTARTS.with(|watch| watch.is_looping(ip))
+}
+
+/// check polled records, alert the machines starting to crash loop and apply the action
+pub async fn apply(runtime: &tokio::runtime::Handle, records: &[MachineRecord]) {
+    let (looping, action, command, polls) = RESTARTS.with(|watch| {
+        let looping = records
+            .iter()
+            .filter(|r| !maintenance::is_in_maintenance(&r.ip))
+            .filter_map(|r| watch.check(&r.ip, r.elapsed).map(|n| (r.ip.clone(), n)))
+            .collect::<Vec<(String, u32)>>();
+        (
+            looping,
+            watch.config.action,
+            watch.config.command.clone(),
+            watch.config.polls,
+        )
+    });
+    if looping.is_empty() {
+        return;
+    }
+    info!("crash loop on {} machines", looping.len());
+
+    let mode = match action {
+        CrashLoopAction::Alert => None,
+        CrashLoopAction::Downclock => Some(tariff::MODE_NORMAL),
+        CrashLoopAction::Sleep => Some(tariff::MODE_SLEEP),
+        CrashLoopAction::Exec => Some("ssh"),
+    };
+    let ips = looping.iter().map(|(ip, _)| ip.clone());
+    let failed = match (action, mode) {
+        (CrashLoopAction::Exec, _) => {
+            exec_ips(runtime, ips.collect(), &command, db::EVENT_CRASH_LOOP).await
+        }
+        (_, Some(mode)) => {
+            let modes = ips.map(|ip| (ip, mode.to_string())).collect();
+            config_mode_ips(runtime, modes, db::EVENT_CRASH_LOOP).await
+        }
+        (_, None) => vec![],
+    };
+
+    notifier::send_alert(&Alert {
+        kind: "crash_loop".to_string(),
+        title: template::render(
+            "crash_loop.title",
+            &[
+                ("time", &clock::now().format("%H:%M:%S")),
+                ("count", &looping.len()),
+            ],
+        ),
+        severity: Severity::Critical,
+        content: "".to_string(),
+        machines: looping
+            .iter()
+            .map(|(ip, count)| AlertMachine {
+                ip: ip.clone(),
+                detail: template::render(
+                    "crash_loop.detail",
+                    &[
+                        ("polls", &polls),
+                        ("restarts", count),
+                        (
+                            "action",
+                            &match mode {
+                                Some(_) if failed.contains(ip) => {
+                                    format!(" {}", template::render("crash_loop.failed", &[]))
+                                }
+                                Some(mode) => format!(" -> {}", mode),
+                                None => "".to_string(),
+                            },
+                        ),
+                    ],
+                ),
+            })
+            .collect(),
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_loop() {
+        let mut watch = RestartWatch::new(CrashLoopConfig {
+            restarts: 2,
+            polls: 4,
+            action: CrashLoopAction::Alert,
+            command: "".to_string(),
+        });
+        let ip = "192.168.188.41";
+
+        assert_eq!(watch.check(ip, 3600), None);
+        assert_eq!(watch.check(ip, 3900), None);
+        assert_eq!(watch.check(ip, 120), None);
+        // unknown elapsed is not a restart
+        assert_eq!(watch.check(ip, 0), None);
+        assert_eq!(watch.check(ip, 60), Some(2));
+        assert!(watch.is_looping(ip));
+        // flagged once while it keeps looping
+        assert_eq!(watch.check(ip, 30), None);
+
+        for elapsed in [330, 630, 930] {
+            assert_eq!(watch.check(ip, elapsed), None);
+        }
+        assert!(watch.is_looping(ip));
+        assert_eq!(watch.check(ip, 1230), None);
+        assert!(!watch.is_looping(ip));
+    }
+}
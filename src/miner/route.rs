@@ -0,0 +1,473 @@
+/// per subnet transport of the miner traffic. remote sites behind a jump host are reached
+/// through a socks5 proxy or an ssh tunnel the crate starts and restarts, the other ips go
+/// direct. the cgminer api connects through it, the web client through a local forwarder
+/// per miner port
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::error::MinerError;
+use crate::secret::{self, SecretString};
+
+lazy_static! {
+    static ref ROUTES: Mutex<Vec<(IpNet, Transport)>> = Mutex::new(vec![]);
+    // user@host:port of the jump host to its running tunnel
+    static ref TUNNELS: Mutex<HashMap<String, Tunnel>> = Mutex::new(HashMap::new());
+    // miner address to the local forwarder of the web client
+    static ref FORWARDS: Mutex<HashMap<SocketAddr, Forward>> = Mutex::new(HashMap::new());
+}
+
+// forwarders unused this long are closed by evict_idle
+const FORWARD_IDLE: Duration = Duration::from_secs(300);
+const FORWARD_CONNECT: Duration = Duration::from_secs(10);
+const TUNNEL_START: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RouteConfig {
+    /// first matching rule wins, ips of no rule are reached directly
+    pub rules: Vec<RouteRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteRule {
+    /// "10.8.0.0/16", or a single ip
+    pub subnet: String,
+    pub transport: Transport,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Transport {
+    /// also to exempt part of a routed range
+    Direct,
+    Socks5 {
+        /// "10.0.0.1:1080"
+        addr: String,
+        /// no authentication when empty
+        #[serde(default)]
+        user: String,
+        /// "secret:<name>" is resolved
+        #[serde(default)]
+        password: SecretString,
+    },
+    /// ssh -D to the jump host, a socks5 proxy on a local port
+    Ssh(SshTunnel),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SshTunnel {
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+    pub user: String,
+    /// private key, the ssh agent and config of the user when empty
+    #[serde(default)]
+    pub identity_file: String,
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+struct Tunnel {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Forward {
+    local: SocketAddr,
+    used: Arc<Mutex<Instant>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // wake the accept so the thread sees the flag
+        let _ = TcpStream::connect_timeout(&self.local, Duration::from_millis(200));
+    }
+}
+
+/// an invalid subnet keeps the previous routes, the tunnels and forwarders are restarted
+pub fn set_config(config: RouteConfig) -> Result<(), MinerError> {
+    let routes = config
+        .rules
+        .into_iter()
+        .map(|rule| Ok((parse_subnet(&rule.subnet)?, rule.transport)))
+        .collect::<Result<Vec<_>, MinerError>>()?;
+    *ROUTES.lock().unwrap() = routes;
+    close();
+    Ok(())
+}
+
+/// stop the tunnels and forwarders, the next connection starts them again
+pub fn close() {
+    FORWARDS.lock().unwrap().clear();
+    TUNNELS.lock().unwrap().clear();
+}
+
+/// close the forwarders unused for a while
+pub fn evict_idle() {
+    FORWARDS
+        .lock()
+        .unwrap()
+        .retain(|_, forward| forward.used.lock().unwrap().elapsed() < FORWARD_IDLE);
+}
+
+fn parse_subnet(subnet: &str) -> Result<IpNet, MinerError> {
+    let subnet = subnet.trim();
+    subnet
+        .parse::<IpNet>()
+        .or_else(|_| subnet.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| MinerError::InvalidIpError(subnet.to_string()))
+}
+
+/// transport of the ip, direct when no rule matches
+pub fn transport(ip: IpAddr) -> Transport {
+    transport_of(&ROUTES.lock().unwrap(), ip)
+}
+
+fn transport_of(routes: &[(IpNet, Transport)], ip: IpAddr) -> Transport {
+    routes
+        .iter()
+        .find(|(net, _)| net.contains(&ip))
+        .map(|(_, transport)| transport.clone())
+        .unwrap_or(Transport::Direct)
+}
+
+/// tcp connection to the miner through the transport of its subnet
+pub fn connect(addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    connect_via(&transport(addr.ip()), addr, timeout)
+}
+
+fn connect_via(
+    transport: &Transport,
+    addr: &SocketAddr,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    match transport {
+        Transport::Direct => TcpStream::connect_timeout(addr, timeout),
+        Transport::Socks5 {
+            addr: proxy,
+            user,
+            password,
+        } => {
+            let password =
+                secret::resolve(password.expose()).map_err(|e| io::Error::other(e.to_string()))?;
+            socks5_connect(proxy, user, &password, addr, timeout)
+        }
+        Transport::Ssh(ssh) => {
+            let proxy = tunnel_addr(ssh)?;
+            socks5_connect(&proxy.to_string(), "", "", addr, timeout)
+        }
+    }
+}
+
+/// local address the web client uses for a routed miner, None when it goes direct
+pub(crate) fn forward(ip: &str, port: u16) -> Option<SocketAddr> {
+    let target = SocketAddr::new(ip.parse().ok()?, port);
+    let transport = transport(target.ip());
+    if transport == Transport::Direct {
+        return None;
+    }
+    let mut forwards = FORWARDS.lock().unwrap();
+    if let Some(forward) = forwards.get(&target) {
+        *forward.used.lock().unwrap() = Instant::now();
+        return Some(forward.local);
+    }
+    match start_forward(target, transport) {
+        Ok(forward) => {
+            let local = forward.local;
+            forwards.insert(target, forward);
+            Some(local)
+        }
+        Err(e) => {
+            error!("forward to {} error: {:?}", target, e);
+            None
+        }
+    }
+}
+
+fn start_forward(target: SocketAddr, transport: Transport) -> io::Result<Forward> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let forward = Forward {
+        local: listener.local_addr()?,
+        used: Arc::new(Mutex::new(Instant::now())),
+        closed: Arc::new(AtomicBool::new(false)),
+    };
+    let (used, closed) = (forward.used.clone(), forward.closed.clone());
+    std::thread::spawn(move || {
+        for client in listener.incoming() {
+            if closed.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(client) = client else {
+                continue;
+            };
+            *used.lock().unwrap() = Instant::now();
+            let transport = transport.clone();
+            std::thread::spawn(
+                move || match connect_via(&transport, &target, FORWARD_CONNECT) {
+                    Ok(upstream) => pipe(client, upstream),
+                    Err(e) => error!("forward to {} error: {:?}", target, e),
+                },
+            );
+        }
+    });
+    Ok(forward)
+}
+
+// copy both ways until the miner closes, then drop the client side too
+fn pipe(client: TcpStream, upstream: TcpStream) {
+    let (Ok(mut client_read), Ok(mut upstream_read)) = (client.try_clone(), upstream.try_clone())
+    else {
+        return;
+    };
+    let (mut client_write, mut upstream_write) = (client, upstream);
+    let up = std::thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut upstream_read, &mut client_write);
+    let _ = client_write.shutdown(Shutdown::Both);
+    let _ = up.join();
+}
+
+fn socks_error(reason: &str) -> io::Error {
+    io::Error::other(format!("socks5 {}", reason))
+}
+
+// rfc 1928 connect, with the rfc 1929 user/password when a user is set
+fn socks5_connect(
+    proxy: &str,
+    user: &str,
+    password: &str,
+    target: &SocketAddr,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let proxy_addr = proxy
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| socks_error(&format!("proxy {} not resolved", proxy)))?;
+    let mut stream = TcpStream::connect_timeout(&proxy_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let method = if user.is_empty() { 0x00 } else { 0x02 };
+    stream.write_all(&[0x05, 0x01, method])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply != [0x05, method] {
+        return Err(socks_error("auth method refused"));
+    }
+    if method == 0x02 {
+        if user.len() > 255 || password.len() > 255 {
+            return Err(socks_error("user or password too long"));
+        }
+        let mut auth = vec![0x01, user.len() as u8];
+        auth.extend(user.as_bytes());
+        auth.push(password.len() as u8);
+        auth.extend(password.as_bytes());
+        stream.write_all(&auth)?;
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0x00 {
+            return Err(socks_error("authentication failed"));
+        }
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    match target.ip() {
+        IpAddr::V4(ip) => {
+            request.push(0x01);
+            request.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(0x04);
+            request.extend(ip.octets());
+        }
+    }
+    request.extend(target.port().to_be_bytes());
+    stream.write_all(&request)?;
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head)?;
+    if head[1] != 0x00 {
+        return Err(socks_error(&format!(
+            "connect to {} failed: {}",
+            target, head[1]
+        )));
+    }
+    // the bound address is not needed
+    let len = match head[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(socks_error("bad address type")),
+    };
+    stream.read_exact(&mut vec![0u8; len + 2])?;
+
+    // the callers set their own timeouts
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
+
+// local socks5 address of the tunnel to the jump host, started again when ssh exited
+fn tunnel_addr(ssh: &SshTunnel) -> io::Result<SocketAddr> {
+    let key = format!("{}@{}:{}", ssh.user, ssh.host, ssh.port);
+    let mut tunnels = TUNNELS.lock().unwrap();
+    if let Some(tunnel) = tunnels.get_mut(&key) {
+        if matches!(tunnel.child.try_wait(), Ok(None)) {
+            return Ok(tunnel.addr);
+        }
+        info!("ssh tunnel {} exited, restarting", key);
+    }
+    let tunnel = start_tunnel(ssh)?;
+    let addr = tunnel.addr;
+    tunnels.insert(key, tunnel);
+    Ok(addr)
+}
+
+fn start_tunnel(ssh: &SshTunnel) -> io::Result<Tunnel> {
+    // a free local port, ssh binds it right after
+    let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port();
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut command = Command::new("ssh");
+    command.args([
+        "-N",
+        "-D",
+        &addr.to_string(),
+        "-p",
+        &ssh.port.to_string(),
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        "ExitOnForwardFailure=yes",
+        "-o",
+        "ServerAliveInterval=30",
+    ]);
+    if !ssh.identity_file.is_empty() {
+        command.args(["-i", &ssh.identity_file]);
+    }
+    let child = command
+        .arg(format!("{}@{}", ssh.user, ssh.host))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // dropped on every error path, which kills ssh
+    let mut tunnel = Tunnel { child, addr };
+
+    let started = Instant::now();
+    while started.elapsed() < TUNNEL_START {
+        if let Some(status) = tunnel.child.try_wait()? {
+            return Err(io::Error::other(format!(
+                "ssh to {} exited: {}",
+                ssh.host, status
+            )));
+        }
+        if TcpStream::connect_timeout(&addr, Duration::from_millis(200)).is_ok() {
+            info!("ssh tunnel to {} on {}", ssh.host, addr);
+            return Ok(tunnel);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("ssh tunnel to {} not ready", ssh.host),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // no auth socks5 server of one connection, connects to the requested port on loopback
+    fn fake_socks5() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 10];
+            client.read_exact(&mut buf[..3]).unwrap();
+            client.write_all(&[0x05, 0x00]).unwrap();
+            client.read_exact(&mut buf).unwrap();
+            let port = u16::from_be_bytes([buf[8], buf[9]]);
+            let upstream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            client
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+            pipe(client, upstream);
+        });
+        addr
+    }
+
+    #[test]
+    fn test_route_socks5() {
+        let routes = vec![
+            (parse_subnet("10.253.7.9").unwrap(), Transport::Direct),
+            (
+                parse_subnet("10.253.0.0/16").unwrap(),
+                Transport::Socks5 {
+                    addr: "127.0.0.1:1080".to_string(),
+                    user: "".to_string(),
+                    password: SecretString::default(),
+                },
+            ),
+        ];
+        assert_eq!(
+            transport_of(&routes, "10.253.7.9".parse().unwrap()),
+            Transport::Direct
+        );
+        assert_ne!(
+            transport_of(&routes, "10.253.7.10".parse().unwrap()),
+            Transport::Direct
+        );
+        assert_eq!(
+            transport_of(&routes, "192.168.1.2".parse().unwrap()),
+            Transport::Direct
+        );
+        assert_eq!(parse_subnet("10.253.0.0/33").unwrap_err().code(), 3007);
+
+        // the web client talks to the forwarder, the miner answers through the proxy
+        let miner = TcpListener::bind("127.0.0.1:0").unwrap();
+        let miner_port = miner.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = miner.accept().unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(b"pong").unwrap();
+        });
+        let transport = Transport::Socks5 {
+            addr: fake_socks5().to_string(),
+            user: "".to_string(),
+            password: SecretString::default(),
+        };
+        let target = SocketAddr::from(([10, 253, 0, 1], miner_port));
+        let forward = start_forward(target, transport).unwrap();
+        let mut stream = TcpStream::connect(forward.local).unwrap();
+        stream.write_all(b"ping").unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "pong");
+    }
+}
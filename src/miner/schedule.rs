@@ -0,0 +1,158 @@
+/// cron driven switch_if_need, each run is kept in the db
+use std::str::FromStr;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::entry;
+use super::group::GroupSelector;
+use crate::clock;
+use crate::context::{self, Local};
+use crate::error::MinerError;
+use crate::status;
+use crate::store::db;
+
+// sheets of the scheduler of the site, and its last run
+static CONFIG: Local<Option<SwitchScheduleConfig>> = Local::new(Option::default);
+static LAST_RUN: Local<Option<SwitchRun>> = Local::new(Option::default);
+
+/// sheets passed to switch_if_need on every run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SwitchScheduleConfig {
+    pub excel: String,
+    pub sheets: Vec<String>,
+    pub account_time_sheet: String,
+    pub perf_time_sheet: String,
+    pub pool_sheet: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SwitchRun {
+    pub start_time: i64,
+    pub end_time: i64,
+    /// started by trigger_now instead of the schedule
+    pub manual: bool,
+    pub switched: usize,
+    pub failed: usize,
+    pub row_errors: usize,
+    /// empty when the run finished
+    pub error: String,
+}
+
+fn save(run: &SwitchRun) {
+    LAST_RUN.set(Some(run.clone()));
+    match serde_json::to_string(run) {
+        Ok(detail) => {
+            if let Err(e) = db::insert_event(db::EVENT_SWITCH_RUN, "", &detail) {
+                error!("save switch run error: {:?}", e);
+            }
+        }
+        Err(e) => error!("save switch run error: {:?}", e),
+    }
+}
+
+/// last run of this process, or the last one stored before a restart
+pub fn last_run() -> Option<SwitchRun> {
+    if let Some(run) = LAST_RUN.get() {
+        return Some(run);
+    }
+    let (detail, _) = db::query_last_event(db::EVENT_SWITCH_RUN).ok()??;
+    serde_json::from_str(&detail).ok()
+}
+
+/// one switch over all machines, fails right away while another run is going
+pub async fn run(
+    runtime: tokio::runtime::Handle,
+    config: &SwitchScheduleConfig,
+    manual: bool,
+) -> Result<SwitchRun, MinerError> {
+    let start_time = chrono::Local::now().timestamp();
+    let result = entry::switch_if_need(
+        runtime,
+        &config.excel,
+        config.sheets.iter().map(|s| s.as_str()).collect(),
+        &config.account_time_sheet,
+        &config.perf_time_sheet,
+        &config.pool_sheet,
+        &GroupSelector::All,
+    )
+    .await;
+    // a run refused while another one is going is not a run
+    if let Err(MinerError::SwitchRunningError) = result {
+        return Err(MinerError::SwitchRunningError);
+    }
+
+    let mut run = SwitchRun {
+        start_time,
+        end_time: chrono::Local::now().timestamp(),
+        manual,
+        ..Default::default()
+    };
+    match &result {
+        Ok(report) => {
+            run.switched = report.switched.len();
+            run.failed = report.failed.len();
+            run.row_errors = report.row_errors.len();
+        }
+        Err(e) => run.error = e.to_string(),
+    }
+    save(&run);
+    result?;
+    Ok(run)
+}
+
+/// run the switch on the cron schedule, e.g. "0 */10 * * * *"
+pub fn start(
+    runtime: tokio::runtime::Handle,
+    config: SwitchScheduleConfig,
+    cron_expr: &str,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    let schedule = cron::Schedule::from_str(cron_expr)?;
+    CONFIG.set(Some(config.clone()));
+    Ok(context::spawn(&runtime.clone(), async move {
+        while let Some(next) = schedule.after(&clock::now()).next() {
+            let wait = (next - clock::now()).to_std().unwrap_or_default();
+            if !status::wait("switch_scheduler", wait).await {
+                break;
+            }
+
+            info!("switch task scheduled.");
+            match run(runtime.clone(), &config, false).await {
+                Ok(run) => info!(
+                    "switch run done, switched: {}, failed: {}",
+                    run.switched, run.failed
+                ),
+                Err(e) => error!("switch run error: {:?}", e),
+            }
+        }
+    }))
+}
+
+/// run the scheduled switch now
+pub async fn trigger_now(runtime: tokio::runtime::Handle) -> Result<SwitchRun, MinerError> {
+    let config = CONFIG
+        .get()
+        .ok_or(MinerError::SwitchScheduleNotStartedError)?;
+    run(runtime, &config, true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_not_overlapping() {
+        let _running = entry::SWITCH_RUNNING.get().lock_owned().await;
+        let runtime = tokio::runtime::Handle::current();
+        let config = SwitchScheduleConfig::default();
+        assert!(matches!(
+            run(runtime.clone(), &config, true).await,
+            Err(MinerError::SwitchRunningError)
+        ));
+        // a switch called directly is refused too
+        assert!(matches!(
+            entry::switch_if_need(runtime, "", vec![], "", "", "", &GroupSelector::All).await,
+            Err(MinerError::SwitchRunningError)
+        ));
+    }
+}
@@ -0,0 +1,323 @@
+/// Header based column mapping for the machine sheets
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::model::MinerStatus;
+
+static SHEET_COLUMNS: Local<SheetColumns> = Local::new(SheetColumns::default);
+static STATUS_ALIASES: Local<StatusAliases> = Local::new(StatusAliases::default);
+
+/// Column header names of the machine sheet, matched against the first row.
+/// Empty name means the column is not used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SheetColumns {
+    pub miner_type: String,
+    pub position: String,
+    pub ip: String,
+    pub status: String,
+    pub account: String,
+    pub pool: String,
+    pub switch_account: String,
+    pub switch_pool: String,
+    pub run_mode: String,
+    pub switch_run_mode: String,
+    pub addition_info: String,
+    pub run_mode_fixed: String,
+    /// site/zone of the machine, the sheet name is used when absent
+    pub group: String,
+    // status columns written back after switch/watching, empty to disable
+    pub last_seen: String,
+    pub current_account: String,
+    pub hashrate: String,
+    pub last_error: String,
+}
+
+impl Default for SheetColumns {
+    fn default() -> Self {
+        SheetColumns {
+            miner_type: "类型".to_string(),
+            position: "位置".to_string(),
+            ip: "IP".to_string(),
+            status: "状态".to_string(),
+            account: "账户".to_string(),
+            pool: "矿池".to_string(),
+            switch_account: "切换账户".to_string(),
+            switch_pool: "切换矿池".to_string(),
+            run_mode: "工作模式".to_string(),
+            switch_run_mode: "切换工作模式".to_string(),
+            addition_info: "备注".to_string(),
+            run_mode_fixed: "固定模式".to_string(),
+            group: "分组".to_string(),
+            last_seen: "".to_string(),
+            current_account: "".to_string(),
+            hashrate: "".to_string(),
+            last_error: "".to_string(),
+        }
+    }
+}
+
+/// labels of the status column besides 上线/下线, online/offline and on/off, ignoring case
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatusAliases {
+    #[serde(default)]
+    pub online: Vec<String>,
+    #[serde(default)]
+    pub offline: Vec<String>,
+}
+
+impl StatusAliases {
+    fn find(&self, label: &str) -> Option<MinerStatus> {
+        let is = |aliases: &[String]| aliases.iter().any(|a| a.trim().eq_ignore_ascii_case(label));
+        if is(&self.online) {
+            Some(MinerStatus::Online)
+        } else if is(&self.offline) {
+            Some(MinerStatus::Offline)
+        } else {
+            None
+        }
+    }
+}
+
+/// Resolved column index of one sheet, None for optional columns not present
+#[derive(Debug, Clone, Default)]
+pub struct SheetColumnIndex {
+    pub miner_type: usize,
+    pub position: Option<usize>,
+    pub ip: usize,
+    pub status: usize,
+    pub account: usize,
+    pub pool: usize,
+    pub switch_account: Option<usize>,
+    pub switch_pool: Option<usize>,
+    pub run_mode: Option<usize>,
+    pub switch_run_mode: Option<usize>,
+    pub addition_info: Option<usize>,
+    pub run_mode_fixed: Option<usize>,
+    pub group: Option<usize>,
+    pub last_seen: Option<usize>,
+    pub current_account: Option<usize>,
+    pub hashrate: Option<usize>,
+    pub last_error: Option<usize>,
+}
+
+/// per machine status written back to the sheet, None keeps the cell untouched
+#[derive(Debug, Clone, Default)]
+pub struct SheetStatus {
+    pub last_seen: Option<String>,
+    pub current_account: Option<String>,
+    pub hashrate: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl SheetColumns {
+    pub fn has_status_columns(&self) -> bool {
+        !(self.last_seen.is_empty()
+            && self.current_account.is_empty()
+            && self.hashrate.is_empty()
+            && self.last_error.is_empty())
+    }
+
+    /// match header row, all missing required columns are reported in one error
+    pub fn resolve(&self, header: &Value) -> Result<SheetColumnIndex, MinerError> {
+        let names: Vec<String> = header
+            .as_array()
+            .map(|cells| {
+                cells
+                    .iter()
+                    .map(|cell| cell.as_str().unwrap_or("").trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let find = |name: &str| -> Option<usize> {
+            if name.is_empty() {
+                return None;
+            }
+            names.iter().position(|n| n == name.trim())
+        };
+
+        let mut missing = vec![];
+        let mut required = |name: &str| -> usize {
+            match find(name) {
+                Some(idx) => idx,
+                None => {
+                    missing.push(name.to_string());
+                    0
+                }
+            }
+        };
+
+        let index = SheetColumnIndex {
+            miner_type: required(&self.miner_type),
+            ip: required(&self.ip),
+            status: required(&self.status),
+            account: required(&self.account),
+            pool: required(&self.pool),
+            position: find(&self.position),
+            switch_account: find(&self.switch_account),
+            switch_pool: find(&self.switch_pool),
+            run_mode: find(&self.run_mode),
+            switch_run_mode: find(&self.switch_run_mode),
+            addition_info: find(&self.addition_info),
+            run_mode_fixed: find(&self.run_mode_fixed),
+            group: find(&self.group),
+            last_seen: find(&self.last_seen),
+            current_account: find(&self.current_account),
+            hashrate: find(&self.hashrate),
+            last_error: find(&self.last_error),
+        };
+
+        if !missing.is_empty() {
+            return Err(MinerError::SheetColumnMissingError(missing.join(", ")));
+        }
+
+        Ok(index)
+    }
+}
+
+/// read a cell as str, None for missing column or non string cell
+pub fn cell(row: &Value, idx: Option<usize>) -> Option<&str> {
+    idx.and_then(|i| row[i].as_str())
+}
+
+/// column index to A1 letters, 0 -> A, 26 -> AA
+pub fn column_name(idx: usize) -> String {
+    let mut name = vec![];
+    let mut n = idx + 1;
+    while n > 0 {
+        let rem = (n - 1) % 26;
+        name.push((b'A' + rem as u8) as char);
+        n = (n - 1) / 26;
+    }
+    name.iter().rev().collect()
+}
+
+/// build single cell ranges for one row, row is 1 based as shown in sheet
+pub fn status_ranges(
+    sheet: &str,
+    row: usize,
+    index: &SheetColumnIndex,
+    status: &SheetStatus,
+) -> Vec<(String, Vec<Vec<Value>>)> {
+    let cells = [
+        (index.last_seen, &status.last_seen),
+        (index.current_account, &status.current_account),
+        (index.hashrate, &status.hashrate),
+        (index.last_error, &status.last_error),
+    ];
+
+    cells
+        .iter()
+        .filter_map(|(col, value)| match (col, value) {
+            (Some(col), Some(value)) => {
+                let cell = format!("{}{}", column_name(*col), row);
+                Some((
+                    format!("{}!{}:{}", sheet, cell, cell),
+                    vec![vec![Value::String(value.clone())]],
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// status of a status cell, the configured aliases first, None when unknown
+pub fn status(label: &str) -> Option<MinerStatus> {
+    let label = label.trim();
+    STATUS_ALIASES
+        .with(|aliases| aliases.find(label))
+        .or_else(|| MinerStatus::parse(label))
+}
+
+pub fn set_status_aliases(aliases: StatusAliases) {
+    STATUS_ALIASES.set(aliases);
+}
+
+pub fn set_columns(columns: SheetColumns) {
+    SHEET_COLUMNS.set(columns);
+}
+
+pub fn get_columns() -> SheetColumns {
+    SHEET_COLUMNS.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve_columns() {
+        #[rustfmt::skip]
+        let header = json!([
+            "类型", "型号", "位置", "IP", "状态", "x", "x", "x", "账户", "矿池", "切换账户",
+            "切换矿池", "工作模式", "切换工作模式", "备注", "x", "x", "固定模式"
+        ]);
+        let index = SheetColumns::default().resolve(&header).unwrap();
+        assert_eq!(index.miner_type, 0);
+        assert_eq!(index.ip, 3);
+        assert_eq!(index.account, 8);
+        assert_eq!(index.switch_pool, Some(11));
+        assert_eq!(index.run_mode_fixed, Some(17));
+    }
+
+    #[test]
+    fn test_resolve_missing_columns() {
+        let header = json!(["类型", "IP", "状态"]);
+        match SheetColumns::default().resolve(&header) {
+            Err(MinerError::SheetColumnMissingError(missing)) => {
+                assert_eq!(missing, "账户, 矿池");
+            }
+            _ => panic!("missing columns not reported"),
+        }
+
+        let header = json!(["类型", "IP", "状态", "账户", "矿池"]);
+        let index = SheetColumns::default().resolve(&header).unwrap();
+        assert_eq!(index.switch_account, None);
+    }
+
+    #[test]
+    fn test_status_ranges() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+
+        let columns = SheetColumns {
+            last_seen: "最后在线".to_string(),
+            last_error: "错误".to_string(),
+            ..Default::default()
+        };
+        let header = json!(["类型", "IP", "状态", "账户", "矿池", "最后在线", "错误"]);
+        let index = columns.resolve(&header).unwrap();
+        let status = SheetStatus {
+            last_seen: Some("12:00:00".to_string()),
+            hashrate: Some("90 THS".to_string()),
+            last_error: Some("".to_string()),
+            ..Default::default()
+        };
+        let ranges = status_ranges("ftMgRx", 3, &index, &status);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].0, "ftMgRx!F3:F3");
+        assert_eq!(ranges[1].0, "ftMgRx!G3:G3");
+    }
+
+    #[test]
+    fn test_status_labels() {
+        assert_eq!(status("上线"), Some(MinerStatus::Online));
+        assert_eq!(status(" ON "), Some(MinerStatus::Online));
+        assert_eq!(status("下线"), Some(MinerStatus::Offline));
+        assert_eq!(status("Offline"), Some(MinerStatus::Offline));
+        assert_eq!(status("维修"), None);
+
+        let aliases = StatusAliases {
+            online: vec!["运行".to_string()],
+            offline: vec!["维修".to_string()],
+        };
+        assert_eq!(aliases.find("运行"), Some(MinerStatus::Online));
+        assert_eq!(aliases.find("维修"), Some(MinerStatus::Offline));
+        assert_eq!(aliases.find("上线"), None);
+    }
+}
@@ -0,0 +1,317 @@
+/// shell commands on the miners over ssh, for recoveries the web ui and cgminer api cannot
+/// do. runs the ssh client of the system with the credential profile of the miner model,
+/// routed subnets go through their jump host or the local forwarder of their socks5 proxy.
+/// host keys are pinned on first connect in a known hosts file of the crate
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::route::{self, Transport};
+use crate::error::MinerError;
+use crate::secret::{self, SecretString};
+
+lazy_static! {
+    static ref CONFIG: Mutex<SshConfig> = Mutex::new(SshConfig::default());
+    // script answering the password prompt of ssh in its private directory, written on
+    // first use
+    static ref ASKPASS: Mutex<Option<(tempfile::TempDir, PathBuf)>> = Mutex::new(None);
+}
+
+const PASSWORD_ENV: &str = "LCD_SSH_PASSWORD";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SshProfile {
+    /// driver name, e.g. "ant" or "avalon", empty for every model without its own profile
+    pub model: String,
+    pub user: String,
+    /// "secret:<name>" is resolved, empty for key authentication
+    #[serde(default)]
+    pub password: SecretString,
+    /// private key, the ssh agent and config of the user when empty
+    #[serde(default)]
+    pub identity_file: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+fn default_port() -> u16 {
+    22
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshConfig {
+    pub profiles: Vec<SshProfile>,
+    /// whole run of a command, connecting included
+    pub timeout_seconds: u64,
+    /// host keys of the miners, "<app_path>/ssh/known_hosts" when empty. a reflashed miner
+    /// gets a new key and is refused until its line is removed, e.g.
+    /// "ssh-keygen -R 192.168.1.10 -f <known_hosts>"
+    #[serde(default)]
+    pub known_hosts: String,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        SshConfig {
+            profiles: vec![],
+            timeout_seconds: 30,
+            known_hosts: String::new(),
+        }
+    }
+}
+
+/// known hosts file of the config, under the app path when not set
+pub fn get_known_hosts_path(app_path: &str, known_hosts: &str) -> String {
+    if known_hosts.is_empty() {
+        app_path.to_owned() + "/ssh/known_hosts"
+    } else {
+        known_hosts.to_string()
+    }
+}
+
+pub fn set_config(config: SshConfig) {
+    *CONFIG.lock().unwrap() = config;
+}
+
+fn profile_of(profiles: &[SshProfile], model: &str) -> Option<SshProfile> {
+    profiles
+        .iter()
+        .find(|p| p.model == model)
+        .or_else(|| profiles.iter().find(|p| p.model.is_empty()))
+        .cloned()
+}
+
+/// run the command as the profile of the model, the stdout when it exits 0
+pub fn exec(ip: &str, model: &str, command: &str) -> Result<String, MinerError> {
+    let config = CONFIG.lock().unwrap().clone();
+    let profile = profile_of(&config.profiles, model)
+        .ok_or_else(|| MinerError::SshError(format!("no profile for {}", model)))?;
+    let timeout = Duration::from_secs(config.timeout_seconds.max(1));
+    info!("ssh {}@{}: {}", profile.user, ip, command);
+    let mut ssh = ssh_command(ip, &profile, &config.known_hosts, timeout)?;
+    ssh.arg(command);
+    run(&mut ssh, timeout)
+}
+
+fn ssh_command(
+    ip: &str,
+    profile: &SshProfile,
+    known_hosts: &str,
+    timeout: Duration,
+) -> Result<Command, MinerError> {
+    let mut ssh = Command::new("ssh");
+    ssh.args([
+        "-o",
+        &format!("ConnectTimeout={}", timeout.as_secs()),
+        // a new miner is trusted on first connect, a changed key is refused
+        "-o",
+        "StrictHostKeyChecking=accept-new",
+        "-o",
+        "LogLevel=ERROR",
+    ]);
+    if !known_hosts.is_empty() {
+        if let Some(dir) = std::path::Path::new(known_hosts).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        ssh.args(["-o", &format!("UserKnownHostsFile={}", known_hosts)]);
+    }
+    if !profile.identity_file.is_empty() {
+        ssh.args(["-i", &profile.identity_file]);
+    }
+    let (mut host, mut port) = (ip.to_string(), profile.port);
+    if let Ok(addr) = ip.parse() {
+        match route::transport(addr) {
+            Transport::Direct => {}
+            // the forwarder does the socks5 handshake, credentials included, the key stays
+            // pinned to the miner ip
+            Transport::Socks5 { .. } => {
+                let local = route::forward(ip, profile.port)
+                    .ok_or_else(|| MinerError::SshError(format!("no forward to {}", ip)))?;
+                host = local.ip().to_string();
+                port = local.port();
+                ssh.args(["-o", &format!("HostKeyAlias={}", ip)]);
+            }
+            Transport::Ssh(jump) => {
+                ssh.args(["-J", &format!("{}@{}:{}", jump.user, jump.host, jump.port)]);
+            }
+        }
+    }
+    ssh.args(["-p", &port.to_string()]);
+
+    let password = secret::resolve(profile.password.expose())?;
+    if password.is_empty() {
+        ssh.args(["-o", "BatchMode=yes"]);
+    } else {
+        ssh.args(["-o", "NumberOfPasswordPrompts=1"])
+            .env("SSH_ASKPASS", askpass()?)
+            .env("SSH_ASKPASS_REQUIRE", "force")
+            .env(PASSWORD_ENV, password);
+    }
+    ssh.arg(format!("{}@{}", profile.user, host));
+    Ok(ssh)
+}
+
+// the password goes through the environment, never the command line. the script is created
+// new in a directory of a random name only we can enter, so no other user can put their own
+// script in its place
+#[cfg(unix)]
+fn askpass() -> Result<PathBuf, MinerError> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut askpass = ASKPASS.lock().unwrap();
+    if let Some((_, path)) = askpass.as_ref() {
+        return Ok(path.clone());
+    }
+    let dir = tempfile::Builder::new()
+        .prefix("lcd-askpass")
+        .permissions(std::fs::Permissions::from_mode(0o700))
+        .tempdir()?;
+    let path = dir.path().join("askpass");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(&path)?
+        .write_all(format!("#!/bin/sh\nprintf '%s\\n' \"${}\"\n", PASSWORD_ENV).as_bytes())?;
+    *askpass = Some((dir, path.clone()));
+    Ok(path)
+}
+
+#[cfg(not(unix))]
+fn askpass() -> Result<PathBuf, MinerError> {
+    Err(MinerError::SshError(
+        "password login needs a unix host, use a key".to_string(),
+    ))
+}
+
+// killed at the timeout, the output is read on threads so a full pipe cannot stall it
+fn run(command: &mut Command, timeout: Duration) -> Result<String, MinerError> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = read_thread(child.stdout.take());
+    let stderr = read_thread(child.stderr.take());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        return Err(MinerError::SshError(format!(
+            "{}: {}",
+            status,
+            stderr.trim()
+        )));
+    }
+    Ok(stdout)
+}
+
+fn read_thread(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut output);
+        }
+        output
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_profile() {
+        let profiles: Vec<SshProfile> = serde_json::from_str(
+            r#"[
+                {"model": "ant", "user": "root", "password": "secret:ant-root"},
+                {"model": "", "user": "admin", "identity_file": "/etc/lcd/id_ed25519"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(profile_of(&profiles, "ant").unwrap().user, "root");
+        assert_eq!(profile_of(&profiles, "avalon").unwrap().user, "admin");
+        assert_eq!(profile_of(&profiles, "avalon").unwrap().port, 22);
+        assert_eq!(profile_of(&profiles[..1], "avalon"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let known_hosts = dir.path().join("ssh").join("known_hosts");
+        let known_hosts = known_hosts.to_str().unwrap();
+        let ssh = ssh_command(
+            "10.254.1.1",
+            &profiles[1],
+            known_hosts,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let args: Vec<String> = ssh
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        assert!(args.contains(&"StrictHostKeyChecking=accept-new".to_string()));
+        assert!(args.contains(&format!("UserKnownHostsFile={}", known_hosts)));
+        assert!(!args.iter().any(|arg| arg.contains("/dev/null")));
+        assert_eq!(args.last().unwrap(), "admin@10.254.1.1");
+        assert!(dir.path().join("ssh").is_dir());
+        assert_eq!(
+            get_known_hosts_path("/opt/lcd", ""),
+            "/opt/lcd/ssh/known_hosts"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_askpass_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = askpass().unwrap();
+        let mode = |path: &std::path::Path| path.metadata().unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o700);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        assert_eq!(askpass().unwrap(), path);
+        let output = run(
+            Command::new(&path).env(PASSWORD_ENV, "hunter2"),
+            Duration::from_secs(5),
+        );
+        assert_eq!(output.unwrap(), "hunter2\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_timeout() {
+        let output = run(
+            Command::new("sh").args(["-c", "echo ok"]),
+            Duration::from_secs(5),
+        );
+        assert_eq!(output.unwrap(), "ok\n");
+        let failed = run(
+            Command::new("sh").args(["-c", "echo wedged >&2; exit 3"]),
+            Duration::from_secs(5),
+        );
+        assert!(failed.unwrap_err().to_string().contains("wedged"));
+        let slow = run(
+            Command::new("sh").args(["-c", "sleep 5"]),
+            Duration::from_millis(200),
+        );
+        assert_eq!(slow.unwrap_err().code(), 2001);
+    }
+}
@@ -0,0 +1,80 @@
+/// fleet wide switches and configs start in waves, so machines do not reboot and
+/// reconnect to the pools all at once
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::group;
+
+use crate::context::Local;
+
+static CONFIG: Local<StaggerConfig> = Local::new(StaggerConfig::default);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StaggerConfig {
+    /// machines per wave, 0 starts every machine at once
+    pub wave_size: usize,
+    /// seconds between the start of two waves
+    pub wave_delay_seconds: u64,
+}
+
+pub fn set_config(config: StaggerConfig) {
+    CONFIG.set(config);
+}
+
+/// start delay of each ip in the given order, see delays_with
+pub fn delays(ips: &[String]) -> Vec<Duration> {
+    let config = CONFIG.get();
+    delays_with(ips, &config, group::priority_of)
+}
+
+/// machines of lower priority numbers take the first waves, ties keep their order
+pub fn delays_with(
+    ips: &[String],
+    config: &StaggerConfig,
+    priority: impl Fn(&str) -> i32,
+) -> Vec<Duration> {
+    let mut delays = vec![Duration::ZERO; ips.len()];
+    if config.wave_size == 0 {
+        return delays;
+    }
+    let mut order: Vec<usize> = (0..ips.len()).collect();
+    order.sort_by_key(|&i| priority(&ips[i]));
+    for (position, i) in order.into_iter().enumerate() {
+        let wave = (position / config.wave_size) as u64;
+        delays[i] = Duration::from_secs(wave * config.wave_delay_seconds);
+    }
+    delays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wave_delays() {
+        let ips: Vec<String> = (1..=5).map(|i| format!("192.168.188.{}", i)).collect();
+        let config = StaggerConfig {
+            wave_size: 2,
+            wave_delay_seconds: 30,
+        };
+        // .4 and .5 are in the first group to go
+        let priority = |ip: &str| {
+            if ip.ends_with(".4") || ip.ends_with(".5") {
+                0
+            } else {
+                1
+            }
+        };
+        let seconds: Vec<u64> = delays_with(&ips, &config, priority)
+            .iter()
+            .map(|d| d.as_secs())
+            .collect();
+        assert_eq!(seconds, vec![30, 30, 60, 0, 0]);
+
+        let config = StaggerConfig::default();
+        assert!(delays_with(&ips, &config, priority)
+            .iter()
+            .all(|d| d.is_zero()));
+    }
+}
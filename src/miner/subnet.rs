@@ -0,0 +1,256 @@
+/// scan queues per /24 subnet, scanning several at once saturates the uplinks of the
+/// management switches. each subnet probes with its own parallelism and delay between probe
+/// starts, both backed off while the timeouts of the subnet spike and restored once they calm
+/// down. on a congested network a scan takes longer instead of timing out wholesale
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+
+use crate::error::MinerError;
+
+lazy_static! {
+    static ref CONFIG: Mutex<ScanQueueConfig> = Mutex::new(ScanQueueConfig::default());
+    static ref QUEUES: Mutex<HashMap<String, Arc<SubnetQueue>>> = Mutex::new(HashMap::new());
+}
+
+// delay between probes of a slowed subnet configured without one
+const SLOW_DELAY_MS: u64 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanQueueConfig {
+    /// probes of one subnet at the same time, at least 1
+    pub parallelism: usize,
+    /// ms between the probe starts of one subnet
+    pub probe_delay_ms: u64,
+    /// timeout percent of the recent probes at which the subnet slows down
+    pub slow_timeout_pct: f64,
+    /// and at or below which it speeds up again
+    pub recover_timeout_pct: f64,
+    /// recent probes the timeout percent is taken over
+    pub window: usize,
+    /// most the parallelism is divided and the delay multiplied by, halving per step
+    pub max_slowdown: usize,
+}
+
+impl Default for ScanQueueConfig {
+    fn default() -> Self {
+        ScanQueueConfig {
+            parallelism: 64,
+            probe_delay_ms: 0,
+            slow_timeout_pct: 30.0,
+            recover_timeout_pct: 10.0,
+            window: 20,
+            max_slowdown: 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueueState {
+    /// parallelism divided and delay multiplied by this
+    slowdown: usize,
+    /// permits to forget as they come back, shrinking below the ones in use
+    debt: usize,
+    next_probe: Instant,
+    /// recent probes, true for a timeout
+    outcomes: VecDeque<bool>,
+}
+
+#[derive(Debug)]
+struct SubnetQueue {
+    subnet: String,
+    config: ScanQueueConfig,
+    permits: Arc<Semaphore>,
+    state: Mutex<QueueState>,
+}
+
+impl SubnetQueue {
+    fn new(subnet: &str, config: ScanQueueConfig) -> Self {
+        SubnetQueue {
+            subnet: subnet.to_string(),
+            permits: Arc::new(Semaphore::new(config.parallelism.max(1))),
+            config,
+            state: Mutex::new(QueueState {
+                slowdown: 1,
+                debt: 0,
+                next_probe: Instant::now(),
+                outcomes: VecDeque::new(),
+            }),
+        }
+    }
+
+    fn parallelism(&self, slowdown: usize) -> usize {
+        (self.config.parallelism / slowdown).max(1)
+    }
+
+    fn delay(&self, slowdown: usize) -> Duration {
+        let delay = if slowdown > 1 {
+            self.config.probe_delay_ms.max(SLOW_DELAY_MS)
+        } else {
+            self.config.probe_delay_ms
+        };
+        Duration::from_millis(delay * slowdown as u64)
+    }
+
+    // start of the next probe, spaced by the delay of the current slowdown
+    fn next_slot(&self) -> Instant {
+        let mut state = self.state.lock().unwrap();
+        let slot = state.next_probe.max(Instant::now());
+        state.next_probe = slot + self.delay(state.slowdown);
+        slot
+    }
+
+    // a permit came back, kept unless the queue shrank meanwhile
+    fn release(&self, permit: OwnedSemaphorePermit) {
+        let mut state = self.state.lock().unwrap();
+        if state.debt > 0 {
+            state.debt -= 1;
+            permit.forget();
+        }
+    }
+
+    fn resize(&self, state: &mut QueueState, slowdown: usize) {
+        let (from, to) = (self.parallelism(state.slowdown), self.parallelism(slowdown));
+        if to < from {
+            let forgotten = self.permits.forget_permits(from - to);
+            state.debt += from - to - forgotten;
+        } else {
+            let grow = to - from;
+            let paid = grow.min(state.debt);
+            state.debt -= paid;
+            self.permits.add_permits(grow - paid);
+        }
+        state.slowdown = slowdown;
+        state.outcomes.clear();
+    }
+
+    /// count the probe, the new slowdown when it changed
+    fn observe(&self, timed_out: bool) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        let window = self.config.window.max(1);
+        state.outcomes.push_back(timed_out);
+        if state.outcomes.len() > window {
+            state.outcomes.pop_front();
+        }
+        if state.outcomes.len() < window {
+            return None;
+        }
+
+        let timeouts = state.outcomes.iter().filter(|t| **t).count();
+        let pct = timeouts as f64 * 100.0 / window as f64;
+        let slowdown = if pct >= self.config.slow_timeout_pct {
+            (state.slowdown * 2).min(self.config.max_slowdown.max(1))
+        } else if pct <= self.config.recover_timeout_pct {
+            (state.slowdown / 2).max(1)
+        } else {
+            state.slowdown
+        };
+        if slowdown == state.slowdown {
+            return None;
+        }
+        // each step judged on probes of its own
+        self.resize(&mut state, slowdown);
+        Some(slowdown)
+    }
+}
+
+/// a running probe of the subnet, record its outcome before dropping it
+pub struct SubnetPermit {
+    queue: Arc<SubnetQueue>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl SubnetPermit {
+    /// count the outcome of the probe toward the timeout percent of the subnet
+    pub fn record<T>(&self, result: &Result<T, MinerError>) {
+        let timed_out = result.as_ref().err().is_some_and(|e| e.is_timeout());
+        if let Some(slowdown) = self.queue.observe(timed_out) {
+            info!(
+                "subnet {} timeouts changed, parallelism {} delay {:?}",
+                self.queue.subnet,
+                self.queue.parallelism(slowdown),
+                self.queue.delay(slowdown)
+            );
+        }
+    }
+}
+
+impl Drop for SubnetPermit {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            self.queue.release(permit);
+        }
+    }
+}
+
+/// queues start over with the config
+pub fn set_config(config: ScanQueueConfig) {
+    *CONFIG.lock().unwrap() = config;
+    QUEUES.lock().unwrap().clear();
+}
+
+/// the /24 of an ipv4 address, the whole address otherwise
+pub fn subnet_of(ip: &str) -> &str {
+    match ip.rsplit_once('.') {
+        Some((subnet, _)) => subnet,
+        None => ip,
+    }
+}
+
+fn queue_of(ip: &str) -> Arc<SubnetQueue> {
+    let subnet = subnet_of(ip);
+    QUEUES
+        .lock()
+        .unwrap()
+        .entry(subnet.to_string())
+        .or_insert_with(|| Arc::new(SubnetQueue::new(subnet, CONFIG.lock().unwrap().clone())))
+        .clone()
+}
+
+/// wait for a probe slot of the subnet of the ip and its turn after the probe delay
+pub async fn permit(ip: &str) -> SubnetPermit {
+    let queue = queue_of(ip);
+    let permit = queue.permits.clone().acquire_owned().await.ok();
+    tokio::time::sleep_until(queue.next_slot()).await;
+    SubnetPermit { queue, permit }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet_slowdown() {
+        assert_eq!(subnet_of("192.168.3.17"), "192.168.3");
+        let config = ScanQueueConfig {
+            parallelism: 8,
+            window: 4,
+            ..Default::default()
+        };
+        let queue = SubnetQueue::new("192.168.3", config);
+
+        // two probes held while the subnet steps down to 4 and then 2 at once
+        let held: Vec<_> = (0..2)
+            .map(|_| queue.permits.clone().try_acquire_owned().unwrap())
+            .collect();
+        let outcomes = [true, true, false, false];
+        let changes: Vec<_> = outcomes.iter().map(|t| queue.observe(*t)).collect();
+        assert_eq!(changes, [None, None, None, Some(2)]);
+        outcomes.iter().for_each(|t| _ = queue.observe(*t));
+        assert_eq!(queue.state.lock().unwrap().slowdown, 4);
+        assert_eq!(queue.permits.available_permits(), 0);
+        assert_eq!(queue.delay(4), Duration::from_millis(4 * SLOW_DELAY_MS));
+
+        // calm again, back to 8 once the held probes are in
+        for _ in 0..8 {
+            queue.observe(false);
+        }
+        held.into_iter().for_each(|permit| queue.release(permit));
+        assert_eq!(queue.state.lock().unwrap().slowdown, 1);
+        assert_eq!(queue.permits.available_permits(), 8);
+    }
+}
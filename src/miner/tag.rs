@@ -0,0 +1,178 @@
+/// free-form machine tags and tag expressions for batch targeting
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::context::Local;
+use crate::error::MinerError;
+
+// ip to tags from the sheets
+static TAGS: Local<HashMap<String, Vec<String>>> = Local::new(HashMap::new);
+// ip to miner reported model, kept across sheet reloads
+static MODELS: Local<HashMap<String, String>> = Local::new(HashMap::new);
+
+/// parsed expression, e.g. "rack:A7 AND (model:1246 OR model:1346) AND NOT broken"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TagExpr {
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Vec<TagExpr>),
+    Or(Vec<TagExpr>),
+}
+
+impl TagExpr {
+    pub fn parse(expr: &str) -> Result<TagExpr, MinerError> {
+        let tokens = tokenize(expr);
+        let mut pos = 0;
+        let parsed = parse_or(&tokens, &mut pos)?;
+        if pos != tokens.len() {
+            return Err(MinerError::TagExprError(format!(
+                "unexpected {} in {}",
+                tokens[pos], expr
+            )));
+        }
+        Ok(parsed)
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagExpr::Tag(term) => tags.iter().any(|tag| tag_matches(term, tag)),
+            TagExpr::Not(expr) => !expr.matches(tags),
+            TagExpr::And(exprs) => exprs.iter().all(|e| e.matches(tags)),
+            TagExpr::Or(exprs) => exprs.iter().any(|e| e.matches(tags)),
+        }
+    }
+}
+
+/// bare terms match the whole tag, key:value terms match a tag of the key whose value is the
+/// term value or has it as a word, "model:1246" matches "model:AvalonMiner 1246" but
+/// "rack:A7" does not match "rack:A70"
+fn tag_matches(term: &str, tag: &str) -> bool {
+    let term = term.to_lowercase();
+    let tag = tag.to_lowercase();
+    match (term.split_once(':'), tag.split_once(':')) {
+        (Some((key, value)), Some((tag_key, tag_value))) => {
+            key == tag_key
+                && (tag_value == value || tag_value.split_whitespace().any(|word| word == value))
+        }
+        _ => term == tag,
+    }
+}
+
+fn tokenize(expr: &str) -> Vec<String> {
+    expr.replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(|t| t.to_string())
+        .collect()
+}
+
+fn is_keyword(token: &str, keyword: &str) -> bool {
+    token.eq_ignore_ascii_case(keyword)
+}
+
+fn parse_or(tokens: &[String], pos: &mut usize) -> Result<TagExpr, MinerError> {
+    let mut exprs = vec![parse_and(tokens, pos)?];
+    while *pos < tokens.len() && is_keyword(&tokens[*pos], "OR") {
+        *pos += 1;
+        exprs.push(parse_and(tokens, pos)?);
+    }
+    Ok(if exprs.len() == 1 {
+        exprs.remove(0)
+    } else {
+        TagExpr::Or(exprs)
+    })
+}
+
+fn parse_and(tokens: &[String], pos: &mut usize) -> Result<TagExpr, MinerError> {
+    let mut exprs = vec![parse_unary(tokens, pos)?];
+    while *pos < tokens.len() && is_keyword(&tokens[*pos], "AND") {
+        *pos += 1;
+        exprs.push(parse_unary(tokens, pos)?);
+    }
+    Ok(if exprs.len() == 1 {
+        exprs.remove(0)
+    } else {
+        TagExpr::And(exprs)
+    })
+}
+
+fn parse_unary(tokens: &[String], pos: &mut usize) -> Result<TagExpr, MinerError> {
+    let token = tokens
+        .get(*pos)
+        .ok_or_else(|| MinerError::TagExprError("unexpected end".to_string()))?;
+    *pos += 1;
+    if is_keyword(token, "NOT") {
+        return Ok(TagExpr::Not(Box::new(parse_unary(tokens, pos)?)));
+    }
+    if token == "(" {
+        let expr = parse_or(tokens, pos)?;
+        if tokens.get(*pos).map(|t| t.as_str()) != Some(")") {
+            return Err(MinerError::TagExprError("missing )".to_string()));
+        }
+        *pos += 1;
+        return Ok(expr);
+    }
+    if token == ")" || is_keyword(token, "AND") || is_keyword(token, "OR") {
+        return Err(MinerError::TagExprError(format!("unexpected {}", token)));
+    }
+    Ok(TagExpr::Tag(token.clone()))
+}
+
+/// tags of the addition info, separated by spaces or commas, "key：value" is normalized
+pub fn parse_tags(addition_info: &str) -> Vec<String> {
+    addition_info
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '，' || c == ';' || c == '；')
+        .map(|t| t.trim().replace('：', ":"))
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+pub fn set_tags(tags: HashMap<String, Vec<String>>) {
+    TAGS.set(tags);
+}
+
+/// model reported by the miner, tagged as model:<machine_type>
+pub fn set_model(ip: &str, machine_type: &str) {
+    MODELS.with(|models| models.insert(ip.to_string(), machine_type.to_string()));
+}
+
+pub fn tags_of(ip: &str) -> Vec<String> {
+    let mut tags = TAGS.with(|tags| tags.get(ip).cloned().unwrap_or_default());
+    if let Some(model) = MODELS.with(|models| models.get(ip).cloned()) {
+        tags.push(format!("model:{}", model));
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_expr() {
+        let tags = parse_tags("rack：A7, 新上架 model:AvalonMiner 1246");
+        assert_eq!(tags, vec!["rack:A7", "新上架", "model:AvalonMiner", "1246"]);
+
+        let tags = vec![
+            "rack:A7".to_string(),
+            "model:AvalonMiner 1246".to_string(),
+            "type:avalon".to_string(),
+        ];
+        assert!(TagExpr::parse("rack:A7 AND model:1246")
+            .unwrap()
+            .matches(&tags));
+        assert!(!TagExpr::parse("rack:A7 AND NOT type:avalon")
+            .unwrap()
+            .matches(&tags));
+        assert!(TagExpr::parse("rack:B1 or (rack:a7 and model:1246)")
+            .unwrap()
+            .matches(&tags));
+        // whole values, not prefixes
+        let racks = vec!["rack:A70".to_string()];
+        assert!(!TagExpr::parse("rack:A7").unwrap().matches(&racks));
+        assert!(TagExpr::parse("rack:a70").unwrap().matches(&racks));
+        assert!(TagExpr::parse("rack:A7 AND").is_err());
+        assert!(TagExpr::parse("(rack:A7").is_err());
+    }
+}
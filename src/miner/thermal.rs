@@ -0,0 +1,324 @@
+/// temperature aware throttling, hot machines step down from 高功 to 普通 then to sleep
+use std::collections::HashMap;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::entry::{config_mode_ips, MachineRecord};
+use super::maintenance;
+use crate::clock;
+use crate::context::Local;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+use crate::store::db;
+use crate::tariff;
+
+static THERMAL: Local<ThermalGuard> = Local::new(|| ThermalGuard::new(ThermalConfig::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThermalConfig {
+    /// off by default
+    pub enabled: bool,
+    /// board temp stepping a machine down one level
+    pub high_temp: f64,
+    /// board temp stepping a machine up one level
+    pub normal_temp: f64,
+    /// consecutive polls over or under the threshold before acting
+    pub polls: u32,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        ThermalConfig {
+            enabled: false,
+            high_temp: 85.0,
+            normal_temp: 75.0,
+            polls: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum ThermalLevel {
+    Normal,
+    Downclocked,
+    Sleeping,
+}
+
+#[derive(Debug, Clone)]
+struct ThermalState {
+    level: ThermalLevel,
+    /// machine was in 高功 before the first step down
+    was_high: bool,
+    hot: u32,
+    cool: u32,
+}
+
+#[derive(Debug)]
+pub struct ThermalGuard {
+    config: ThermalConfig,
+    states: HashMap<String, ThermalState>,
+}
+
+impl ThermalGuard {
+    pub fn new(config: ThermalConfig) -> Self {
+        ThermalGuard {
+            config,
+            states: HashMap::new(),
+        }
+    }
+
+    /// record one poll, returns the level to apply when it changes. the level is only taken
+    /// once advance confirms it was applied, a failed one is proposed again next poll
+    pub fn check(&mut self, ip: &str, temp: f64, work_mode: i32) -> Option<ThermalLevel> {
+        let state = self.states.entry(ip.to_string()).or_insert(ThermalState {
+            level: ThermalLevel::Normal,
+            was_high: work_mode == 1,
+            hot: 0,
+            cool: 0,
+        });
+        if state.level == ThermalLevel::Normal {
+            state.was_high = work_mode == 1;
+        }
+
+        if temp >= self.config.high_temp {
+            state.hot += 1;
+            state.cool = 0;
+        } else if temp <= self.config.normal_temp {
+            state.cool += 1;
+            state.hot = 0;
+        } else {
+            state.hot = 0;
+            state.cool = 0;
+        }
+
+        let next = if state.hot >= self.config.polls {
+            match state.level {
+                ThermalLevel::Normal if state.was_high => ThermalLevel::Downclocked,
+                _ => ThermalLevel::Sleeping,
+            }
+        } else if state.cool >= self.config.polls {
+            match state.level {
+                ThermalLevel::Sleeping if state.was_high => ThermalLevel::Downclocked,
+                _ => ThermalLevel::Normal,
+            }
+        } else {
+            return None;
+        };
+
+        if next == state.level {
+            return None;
+        }
+        Some(next)
+    }
+
+    /// check of a polled record, a sleeping miner reports no temperature and counts as cool
+    pub fn poll(&mut self, record: &MachineRecord) -> Option<ThermalLevel> {
+        let temp = max_temp(record);
+        if temp <= 0.0 && self.level(&record.ip) != ThermalLevel::Sleeping {
+            return None;
+        }
+        self.check(&record.ip, temp, record.work_mode)
+    }
+
+    /// take the level once it is applied to the machine
+    pub fn advance(&mut self, ip: &str, level: ThermalLevel) {
+        if let Some(state) = self.states.get_mut(ip) {
+            state.level = level;
+            state.hot = 0;
+            state.cool = 0;
+        }
+    }
+
+    fn mode(&self, ip: &str, level: ThermalLevel) -> &'static str {
+        let was_high = self.states.get(ip).map(|s| s.was_high).unwrap_or(false);
+        match level {
+            ThermalLevel::Sleeping => tariff::MODE_SLEEP,
+            ThermalLevel::Downclocked => tariff::MODE_NORMAL,
+            ThermalLevel::Normal if was_high => tariff::MODE_HIGH,
+            ThermalLevel::Normal => tariff::MODE_NORMAL,
+        }
+    }
+
+    pub fn level(&self, ip: &str) -> ThermalLevel {
+        self.states
+            .get(ip)
+            .map(|s| s.level)
+            .unwrap_or(ThermalLevel::Normal)
+    }
+}
+
+/// hottest board, 0 when the miner reports no temperature
+pub fn max_temp(record: &MachineRecord) -> f64 {
+    [record.temp_0, record.temp_1, record.temp_2]
+        .into_iter()
+        .flatten()
+        .fold(0.0, f64::max)
+}
+
+pub fn set_config(config: ThermalConfig) {
+    THERMAL.set(ThermalGuard::new(config));
+}
+
+/// machine stepped down by the policy, switch keeps it out of 高功
+pub fn is_throttled(ip: &str) -> bool {
+    THERMAL.with(|guard| guard.level(ip) != ThermalLevel::Normal)
+}
+
+/// check polled records, apply level changes, audit and notify them
+pub async fn apply(runtime: &tokio::runtime::Handle, records: &[MachineRecord]) {
+    let changes = THERMAL.with(|guard| {
+        let mut changes = vec![];
+        if !guard.config.enabled {
+            return changes;
+        }
+        for record in records
+            .iter()
+            .filter(|r| !maintenance::is_in_maintenance(&r.ip))
+        {
+            if let Some(level) = guard.poll(record) {
+                let temp = max_temp(record);
+                info!("thermal {} {:.1}℃ -> {:?}", record.ip, temp, level);
+                changes.push((
+                    record.ip.clone(),
+                    level,
+                    guard.mode(&record.ip, level),
+                    temp,
+                ));
+            }
+        }
+        changes
+    });
+    if changes.is_empty() {
+        return;
+    }
+
+    let modes = changes
+        .iter()
+        .map(|(ip, _, mode, _)| (ip.clone(), mode.to_string()))
+        .collect();
+    let failed = config_mode_ips(runtime, modes, db::EVENT_THERMAL).await;
+    THERMAL.with(|guard| {
+        for (ip, level, _, _) in changes.iter().filter(|(ip, ..)| !failed.contains(ip)) {
+            guard.advance(ip, *level);
+        }
+    });
+
+    notifier::send_alert(&Alert {
+        kind: "thermal".to_string(),
+        title: template::render(
+            "thermal.title",
+            &[
+                ("time", &clock::now().format("%H:%M:%S")),
+                ("count", &changes.len()),
+            ],
+        ),
+        severity: Severity::Warning,
+        content: "".to_string(),
+        machines: changes
+            .iter()
+            .map(|(ip, _, mode, temp)| AlertMachine {
+                ip: ip.clone(),
+                detail: format!(
+                    "{:.1}℃ -> {}{}",
+                    temp,
+                    mode,
+                    if failed.contains(ip) {
+                        format!(" {}", template::render("thermal.failed", &[]))
+                    } else {
+                        "".to_string()
+                    }
+                ),
+            })
+            .collect(),
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_guard() -> ThermalGuard {
+        ThermalGuard::new(ThermalConfig {
+            enabled: true,
+            high_temp: 85.0,
+            normal_temp: 75.0,
+            polls: 2,
+        })
+    }
+
+    // check and advance as if the mode was applied
+    fn step(guard: &mut ThermalGuard, ip: &str, temp: f64, work_mode: i32) -> Option<ThermalLevel> {
+        let level = guard.check(ip, temp, work_mode)?;
+        guard.advance(ip, level);
+        Some(level)
+    }
+
+    #[test]
+    fn test_step_down_and_up() {
+        let mut guard = new_guard();
+        let ip = "192.168.188.41";
+
+        assert_eq!(step(&mut guard, ip, 90.0, 1), None);
+        assert_eq!(
+            step(&mut guard, ip, 90.0, 1),
+            Some(ThermalLevel::Downclocked)
+        );
+        assert_eq!(guard.mode(ip, guard.level(ip)), tariff::MODE_NORMAL);
+        // still hot in 普通
+        assert_eq!(step(&mut guard, ip, 88.0, 0), None);
+        assert_eq!(step(&mut guard, ip, 88.0, 0), Some(ThermalLevel::Sleeping));
+        assert_eq!(step(&mut guard, ip, 80.0, 0), None);
+
+        assert_eq!(step(&mut guard, ip, 60.0, 0), None);
+        assert_eq!(
+            step(&mut guard, ip, 60.0, 0),
+            Some(ThermalLevel::Downclocked)
+        );
+        assert_eq!(step(&mut guard, ip, 60.0, 0), None);
+        assert_eq!(step(&mut guard, ip, 60.0, 0), Some(ThermalLevel::Normal));
+        assert_eq!(guard.mode(ip, guard.level(ip)), tariff::MODE_HIGH);
+    }
+
+    #[test]
+    fn test_failed_step_retried() {
+        let mut guard = new_guard();
+        let ip = "192.168.188.41";
+
+        assert_eq!(guard.check(ip, 90.0, 1), None);
+        assert_eq!(guard.check(ip, 90.0, 1), Some(ThermalLevel::Downclocked));
+        // not applied, the machine keeps its level and the step is proposed again
+        assert_eq!(guard.level(ip), ThermalLevel::Normal);
+        assert_eq!(guard.check(ip, 90.0, 1), Some(ThermalLevel::Downclocked));
+        guard.advance(ip, ThermalLevel::Downclocked);
+        assert_eq!(guard.level(ip), ThermalLevel::Downclocked);
+        assert_eq!(guard.check(ip, 90.0, 0), None);
+    }
+
+    #[test]
+    fn test_sleeping_without_temp_wakes() {
+        let mut guard = new_guard();
+        let mut record = MachineRecord {
+            ip: "192.168.188.41".to_string(),
+            temp_0: Some(90.0),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            if let Some(level) = guard.poll(&record) {
+                guard.advance(&record.ip, level);
+            }
+        }
+        assert_eq!(guard.level(&record.ip), ThermalLevel::Sleeping);
+
+        // asleep the miner reports no temperature
+        record.temp_0 = None;
+        assert_eq!(guard.poll(&record), None);
+        assert_eq!(guard.poll(&record), Some(ThermalLevel::Normal));
+
+        // a running miner without a reading is left alone
+        let mut other = new_guard();
+        record.ip = "192.168.188.42".to_string();
+        assert_eq!(other.poll(&record), None);
+        assert_eq!(other.poll(&record), None);
+    }
+}
@@ -0,0 +1,337 @@
+/// dry run over the spreadsheet configuration, reports problems without switching
+use std::collections::HashMap;
+
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::entry::{get_pools_from_feishu, MinerType};
+use super::sheet;
+use crate::error::MinerError;
+use crate::notify;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IssueKind {
+    DuplicateIp,
+    UnknownMinerType,
+    /// pool column names a type missing from the pool sheet
+    UnknownPoolType,
+    /// switch account set without a switch pool
+    MissingSwitchPool,
+    BadTime,
+    OverlappingWindows,
+    UncoveredTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigIssue {
+    pub kind: IssueKind,
+    pub sheet: String,
+    /// row number as shown in the sheet, the header is row 1
+    pub row: Option<usize>,
+    pub ip: Option<String>,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    /// machine rows checked
+    pub machines: usize,
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(
+        &mut self,
+        kind: IssueKind,
+        sheet: &str,
+        row: Option<usize>,
+        ip: Option<&str>,
+        detail: String,
+    ) {
+        self.issues.push(ConfigIssue {
+            kind,
+            sheet: sheet.to_string(),
+            row,
+            ip: ip.map(|ip| ip.to_string()),
+            detail,
+        });
+    }
+}
+
+const DAY_SECONDS: usize = 24 * 60 * 60;
+
+fn format_second(s: usize) -> String {
+    format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+/// overlaps between windows and, when full_day, times no window covers.
+/// windows are (row, start, end) covering start up to but not including end, start > end
+/// crosses midnight and start == end is the whole day, as they are evaluated at switch time.
+pub fn window_issues(
+    windows: &[(usize, NaiveTime, NaiveTime)],
+    full_day: bool,
+) -> Vec<(IssueKind, Option<usize>, String)> {
+    let mut issues = vec![];
+    // first row covering each second of the day
+    let mut covered: Vec<Option<usize>> = vec![None; DAY_SECONDS];
+    let mut overlaps: HashMap<(usize, usize), usize> = HashMap::new();
+    for (row, start, end) in windows.iter() {
+        let start = start.num_seconds_from_midnight() as usize;
+        let end = end.num_seconds_from_midnight() as usize;
+        let seconds: Box<dyn Iterator<Item = usize>> = if start < end {
+            Box::new(start..end)
+        } else {
+            Box::new((start..DAY_SECONDS).chain(0..end))
+        };
+        for s in seconds {
+            if let Some(other) = covered[s] {
+                if other != *row {
+                    *overlaps.entry((other, *row)).or_default() += 1;
+                }
+            }
+            covered[s].get_or_insert(*row);
+        }
+    }
+
+    let mut overlaps: Vec<_> = overlaps.into_iter().collect();
+    overlaps.sort();
+    for ((first, second), seconds) in overlaps {
+        issues.push((
+            IssueKind::OverlappingWindows,
+            Some(second),
+            format!("overlaps row {} for {} seconds", first, seconds),
+        ));
+    }
+
+    if full_day {
+        let mut s = 0;
+        while s < DAY_SECONDS {
+            if covered[s].is_some() {
+                s += 1;
+                continue;
+            }
+            let gap_start = s;
+            while s < DAY_SECONDS && covered[s].is_none() {
+                s += 1;
+            }
+            issues.push((
+                IssueKind::UncoveredTime,
+                None,
+                format!(
+                    "{} - {} not covered",
+                    format_second(gap_start),
+                    format_second(s - 1)
+                ),
+            ));
+        }
+    }
+    issues
+}
+
+async fn validate_time_sheet(
+    report: &mut ValidationReport,
+    excel: &str,
+    sheet: &str,
+    full_day: bool,
+) -> Result<(), MinerError> {
+    let values = notify::query_sheet_values(excel, sheet).await?;
+    let mut windows = vec![];
+    for (idx, row) in values.iter().enumerate().skip(1) {
+        let cells = (row[0].as_str(), row[1].as_str(), row[2].as_str());
+        let (Some(_), Some(start), Some(end)) = cells else {
+            report.push(
+                IssueKind::BadTime,
+                sheet,
+                Some(idx + 1),
+                None,
+                "missing cell".to_string(),
+            );
+            continue;
+        };
+        match (
+            NaiveTime::parse_from_str(start, "%H:%M:%S"),
+            NaiveTime::parse_from_str(end, "%H:%M:%S"),
+        ) {
+            (Ok(start), Ok(end)) => windows.push((idx + 1, start, end)),
+            _ => report.push(
+                IssueKind::BadTime,
+                sheet,
+                Some(idx + 1),
+                None,
+                format!("{} - {} is not HH:MM:SS", start, end),
+            ),
+        }
+    }
+    for (kind, row, detail) in window_issues(&windows, full_day) {
+        report.push(kind, sheet, row, None, detail);
+    }
+    Ok(())
+}
+
+fn validate_machine_row(
+    report: &mut ValidationReport,
+    sheet: &str,
+    row_number: usize,
+    row: &Value,
+    cols: &sheet::SheetColumnIndex,
+    pools_map: &HashMap<String, Vec<String>>,
+    seen: &mut HashMap<String, (String, usize)>,
+) {
+    let miner_type = match row[cols.miner_type].as_str() {
+        Some(cell) if !cell.trim().is_empty() => cell,
+        _ => return,
+    };
+    let ip = row[cols.ip].as_str();
+    report.machines += 1;
+    if MinerType::try_from(miner_type).is_err() {
+        report.push(
+            IssueKind::UnknownMinerType,
+            sheet,
+            Some(row_number),
+            ip,
+            format!("miner type {}", miner_type),
+        );
+    }
+    let Some(ip) = ip else {
+        return;
+    };
+
+    if let Some((first_sheet, first_row)) = seen.get(ip) {
+        report.push(
+            IssueKind::DuplicateIp,
+            sheet,
+            Some(row_number),
+            Some(ip),
+            format!("also in {} row {}", first_sheet, first_row),
+        );
+    } else {
+        seen.insert(ip.to_string(), (sheet.to_string(), row_number));
+    }
+
+    let pool = row[cols.pool].as_str().unwrap_or("");
+    if !pool.is_empty() && !pools_map.contains_key(pool) {
+        report.push(
+            IssueKind::UnknownPoolType,
+            sheet,
+            Some(row_number),
+            Some(ip),
+            format!(
+                "pool type {} of account {}",
+                pool,
+                row[cols.account].as_str().unwrap_or("")
+            ),
+        );
+    }
+
+    let switch_account = sheet::cell(row, cols.switch_account).unwrap_or("");
+    if switch_account.is_empty() {
+        return;
+    }
+    match sheet::cell(row, cols.switch_pool) {
+        Some(pool) if !pool.is_empty() => {
+            if !pools_map.contains_key(pool) {
+                report.push(
+                    IssueKind::UnknownPoolType,
+                    sheet,
+                    Some(row_number),
+                    Some(ip),
+                    format!("pool type {} of switch account {}", pool, switch_account),
+                );
+            }
+        }
+        _ => report.push(
+            IssueKind::MissingSwitchPool,
+            sheet,
+            Some(row_number),
+            Some(ip),
+            format!("switch account {}", switch_account),
+        ),
+    }
+}
+
+/// load machine, pool and time sheets and check them, nothing is switched or written
+pub async fn validate(
+    excel: &str,
+    sheets: Vec<&str>,
+    account_time_sheet: &str,
+    perf_time_sheet: &str,
+    pool_sheet: &str,
+) -> Result<ValidationReport, MinerError> {
+    let mut report = ValidationReport::default();
+    let pools_map = get_pools_from_feishu(excel, pool_sheet).await?;
+    let columns = sheet::get_columns();
+    let mut seen = HashMap::new();
+    for sheet in sheets.iter() {
+        let values = notify::query_sheet_values(excel, sheet).await?;
+        let header = values.first().ok_or(MinerError::FeishuParserJsonError)?;
+        let cols = columns.resolve(header)?;
+        for (idx, row) in values.iter().enumerate().skip(1) {
+            validate_machine_row(
+                &mut report,
+                sheet,
+                idx + 1,
+                row,
+                &cols,
+                &pools_map,
+                &mut seen,
+            );
+        }
+    }
+
+    // the account sheet must cover the whole day, perf mode falls back to 普通
+    validate_time_sheet(&mut report, excel, account_time_sheet, true).await?;
+    validate_time_sheet(&mut report, excel, perf_time_sheet, false).await?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_window_issues() {
+        // touching boundaries and midnight crossing cover the day
+        let windows = vec![
+            (2, t("08:00:00"), t("20:00:00")),
+            (3, t("20:00:00"), t("08:00:00")),
+        ];
+        assert!(window_issues(&windows, true).is_empty());
+
+        let windows = vec![
+            (2, t("08:00:00"), t("20:00:00")),
+            (3, t("19:00:00"), t("06:00:00")),
+        ];
+        let issues = window_issues(&windows, true);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].0, IssueKind::OverlappingWindows);
+        assert_eq!(issues[0].1, Some(3));
+        assert_eq!(issues[1].0, IssueKind::UncoveredTime);
+        assert_eq!(issues[0].2, "overlaps row 2 for 3600 seconds");
+        assert_eq!(issues[1].0, IssueKind::UncoveredTime);
+        assert_eq!(issues[1].2, "06:00:00 - 07:59:59 not covered");
+
+        // gaps are fine when not full day
+        assert_eq!(window_issues(&windows, false).len(), 1);
+
+        // start equal to end is the whole day, as at switch time
+        let windows = vec![(2, t("00:00:00"), t("00:00:00"))];
+        assert!(window_issues(&windows, true).is_empty());
+        let windows = vec![
+            (2, t("00:00:00"), t("00:00:00")),
+            (3, t("08:00:00"), t("08:00:01")),
+        ];
+        assert_eq!(
+            window_issues(&windows, true)[0].2,
+            "overlaps row 2 for 1 seconds"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+/// time windows of the account and perf sheets, evaluated at an injected now
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::mode::{self, RunMode};
+use crate::context::Local;
+use crate::error::MinerError;
+
+static CONFIG: Local<WindowConfig> = Local::new(WindowConfig::default);
+
+/// what to do when now falls in windows of different types
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum OverlapPolicy {
+    /// the upper row wins
+    #[default]
+    First,
+    Error,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WindowConfig {
+    pub overlap: OverlapPolicy,
+    /// account type used when no window covers now, empty to fail the switch
+    pub default_account: String,
+}
+
+pub fn set_config(config: WindowConfig) {
+    CONFIG.set(config);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+    pub start: NaiveTime, // 00:00:00
+    pub end: NaiveTime,   // 00:00:00
+    /// account type (main or switch) or perf mode (高功/普通)
+    pub value: String,
+}
+
+impl TimeWindow {
+    pub fn from_str(start: &str, end: &str, value: &str) -> Result<TimeWindow, MinerError> {
+        Ok(TimeWindow {
+            start: NaiveTime::parse_from_str(start, "%H:%M:%S")?,
+            end: NaiveTime::parse_from_str(end, "%H:%M:%S")?,
+            value: value.to_string(),
+        })
+    }
+
+    /// from start up to but not including end, so back to back windows do not overlap.
+    /// start after end spans midnight, start equal to end is the whole day
+    pub fn contains(&self, now: NaiveTime) -> bool {
+        if self.start < self.end {
+            now >= self.start && now < self.end
+        } else if self.start > self.end {
+            now >= self.start || now < self.end
+        } else {
+            true
+        }
+    }
+}
+
+/// rows after the header, columns are type, start, end
+pub fn parse_rows(values: &[Value]) -> Result<Vec<TimeWindow>, MinerError> {
+    values
+        .iter()
+        .skip(1)
+        .map(|row| {
+            let start = row[1].as_str().ok_or(MinerError::FeishuParserJsonError)?;
+            let end = row[2].as_str().ok_or(MinerError::FeishuParserJsonError)?;
+            let value = row[0].as_str().ok_or(MinerError::FeishuParserJsonError)?;
+            TimeWindow::from_str(start, end, value)
+        })
+        .collect()
+}
+
+/// value of the window covering now, None in a gap
+pub fn pick(
+    windows: &[TimeWindow],
+    now: NaiveTime,
+    overlap: &OverlapPolicy,
+) -> Result<Option<String>, MinerError> {
+    let mut matched = windows.iter().filter(|w| w.contains(now));
+    let Some(first) = matched.next() else {
+        return Ok(None);
+    };
+    if *overlap == OverlapPolicy::Error {
+        if let Some(other) = matched.find(|w| w.value != first.value) {
+            return Err(MinerError::TimeWindowOverlapError(format!(
+                "{} {}-{} and {} {}-{} at {}",
+                first.value, first.start, first.end, other.value, other.start, other.end, now
+            )));
+        }
+    }
+    Ok(Some(first.value.clone()))
+}
+
+/// account type at now, the configured default fills gaps
+pub fn account_at(windows: &[TimeWindow], now: NaiveTime) -> Result<String, MinerError> {
+    let config = CONFIG.get();
+    match pick(windows, now, &config.overlap)? {
+        Some(account) => Ok(account),
+        None if !config.default_account.is_empty() => Ok(config.default_account),
+        None => Err(MinerError::ReadTimeConfigError),
+    }
+}
+
+/// perf mode at now, the label of the sheet as its run mode, Normal in gaps
+pub fn perf_at(windows: &[TimeWindow], now: NaiveTime) -> Result<String, MinerError> {
+    let overlap = CONFIG.with(|config| config.overlap.clone());
+    Ok(match pick(windows, now, &overlap)? {
+        Some(label) => mode::normalize(&label),
+        None => RunMode::Normal.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_pick_window() {
+        let windows = vec![
+            TimeWindow::from_str("08:00:00", "20:00:00", "main").unwrap(),
+            TimeWindow::from_str("19:00:00", "02:00:00", "switch").unwrap(),
+        ];
+        let first = OverlapPolicy::First;
+        assert_eq!(
+            pick(&windows, t("12:00:00"), &first).unwrap().as_deref(),
+            Some("main")
+        );
+        // spans midnight
+        assert_eq!(
+            pick(&windows, t("01:00:00"), &first).unwrap().as_deref(),
+            Some("switch")
+        );
+        assert_eq!(pick(&windows, t("05:00:00"), &first).unwrap(), None);
+
+        assert_eq!(
+            pick(&windows, t("19:30:00"), &first).unwrap().as_deref(),
+            Some("main")
+        );
+        assert!(pick(&windows, t("19:30:00"), &OverlapPolicy::Error).is_err());
+
+        let whole_day = TimeWindow::from_str("00:00:00", "00:00:00", "main").unwrap();
+        assert!(whole_day.contains(t("13:14:15")));
+        assert!(whole_day.contains(t("00:00:00")));
+    }
+
+    #[test]
+    fn test_window_boundaries() {
+        let windows = vec![
+            TimeWindow::from_str("08:00:00", "20:00:00", "main").unwrap(),
+            TimeWindow::from_str("20:00:00", "08:00:00", "switch").unwrap(),
+        ];
+        let error = OverlapPolicy::Error;
+        for (now, value) in [
+            ("08:00:00", "main"),
+            ("19:59:59", "main"),
+            ("20:00:00", "switch"),
+            ("00:00:00", "switch"),
+            ("07:59:59", "switch"),
+        ] {
+            assert_eq!(
+                pick(&windows, t(now), &error).unwrap().as_deref(),
+                Some(value),
+                "{}",
+                now
+            );
+        }
+
+        // ending at midnight covers the last second of the day only
+        let evening = TimeWindow::from_str("20:00:00", "00:00:00", "switch").unwrap();
+        assert!(evening.contains(t("23:59:59")));
+        assert!(!evening.contains(t("00:00:00")));
+    }
+}
@@ -0,0 +1,294 @@
+/// cooling failures by zone, the average board temperature of the machines of a rack or a room
+/// rising above its threshold is alerted before single machines get hot enough for the
+/// thermal policy. racks and rooms come from the positions of the sheets
+use std::collections::{BTreeMap, HashMap};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::entry::MachineRecord;
+use super::maintenance;
+use super::position::{self, Position};
+use super::thermal;
+use crate::clock;
+use crate::context::Local;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+
+static ZONES: Local<ZoneWatch> = Local::new(|| ZoneWatch::new(ZoneTempConfig::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneTempConfig {
+    /// alerts on watching, off by default
+    pub enabled: bool,
+    /// average board temp of a rack alerted at, 0 leaves racks alone
+    pub rack_temp: f64,
+    /// average board temp of a room alerted at, 0 leaves rooms alone
+    pub room_temp: f64,
+    /// degrees below the threshold a zone has to cool to before it is alerted again
+    pub recover_margin: f64,
+    /// machines reporting a temperature before a zone is judged
+    pub min_machines: usize,
+    /// consecutive polls over the threshold before alerting
+    pub polls: u32,
+}
+
+impl Default for ZoneTempConfig {
+    fn default() -> Self {
+        ZoneTempConfig {
+            enabled: false,
+            rack_temp: 80.0,
+            room_temp: 75.0,
+            recover_margin: 3.0,
+            min_machines: 3,
+            polls: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ZoneLevel {
+    Rack,
+    Room,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneTemp {
+    pub level: ZoneLevel,
+    /// e.g. "A-03-12" for a rack, "A" for a room
+    pub zone: String,
+    pub avg_temp: f64,
+    pub max_temp: f64,
+    /// ip of the hottest machine
+    pub hottest: String,
+    pub machines: usize,
+    pub threshold: f64,
+}
+
+#[derive(Debug, Default)]
+struct ZoneState {
+    hot: u32,
+    alerted: bool,
+}
+
+#[derive(Debug)]
+pub struct ZoneWatch {
+    config: ZoneTempConfig,
+    states: HashMap<(ZoneLevel, String), ZoneState>,
+}
+
+// rack and room of a position, None for the levels the position has no name for
+fn zones_of(position: &Position) -> [Option<(ZoneLevel, String)>; 2] {
+    let rack = (!position.rack.is_empty()).then(|| {
+        let names = [&position.room, &position.row, &position.rack];
+        let names: Vec<&str> = names
+            .iter()
+            .filter(|n| !n.is_empty())
+            .map(|n| n.as_str())
+            .collect();
+        (ZoneLevel::Rack, names.join("-"))
+    });
+    let room = (!position.room.is_empty()).then(|| (ZoneLevel::Room, position.room.clone()));
+    [rack, room]
+}
+
+impl ZoneWatch {
+    pub fn new(config: ZoneTempConfig) -> Self {
+        ZoneWatch {
+            config,
+            states: HashMap::new(),
+        }
+    }
+
+    fn threshold(&self, level: ZoneLevel) -> f64 {
+        match level {
+            ZoneLevel::Rack => self.config.rack_temp,
+            ZoneLevel::Room => self.config.room_temp,
+        }
+    }
+
+    /// average of each zone with enough machines, readings are ip, position and board temp
+    pub fn averages(&self, readings: &[(String, Position, f64)]) -> Vec<ZoneTemp> {
+        let mut zones: BTreeMap<(ZoneLevel, String), Vec<(&str, f64)>> = BTreeMap::new();
+        for (ip, position, temp) in readings.iter() {
+            for zone in zones_of(position).into_iter().flatten() {
+                if self.threshold(zone.0) > 0.0 {
+                    zones.entry(zone).or_default().push((ip, *temp));
+                }
+            }
+        }
+        zones
+            .into_iter()
+            .filter(|(_, temps)| temps.len() >= self.config.min_machines.max(1))
+            .map(|((level, zone), temps)| {
+                let (hottest, max_temp) = temps
+                    .iter()
+                    .copied()
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .unwrap_or_default();
+                ZoneTemp {
+                    level,
+                    zone,
+                    avg_temp: temps.iter().map(|t| t.1).sum::<f64>() / temps.len() as f64,
+                    max_temp,
+                    hottest: hottest.to_string(),
+                    machines: temps.len(),
+                    threshold: self.threshold(level),
+                }
+            })
+            .collect()
+    }
+
+    /// record one poll, the zones that just became hot
+    pub fn check(&mut self, readings: &[(String, Position, f64)]) -> Vec<ZoneTemp> {
+        let mut hot = vec![];
+        for zone in self.averages(readings) {
+            let recover = zone.threshold - self.config.recover_margin;
+            let state = self
+                .states
+                .entry((zone.level, zone.zone.clone()))
+                .or_default();
+            if zone.avg_temp >= zone.threshold {
+                state.hot += 1;
+                if state.hot >= self.config.polls && !state.alerted {
+                    state.alerted = true;
+                    hot.push(zone);
+                }
+            } else if zone.avg_temp <= recover {
+                if state.alerted {
+                    info!(
+                        "zone {:?} {} cooled down to {:.1}℃",
+                        zone.level, zone.zone, zone.avg_temp
+                    );
+                }
+                *state = ZoneState::default();
+            } else {
+                state.hot = 0;
+            }
+        }
+        hot
+    }
+}
+
+pub fn set_config(config: ZoneTempConfig) {
+    ZONES.set(ZoneWatch::new(config));
+}
+
+/// check the board temps of polled records by rack and room, notify the zones that just
+/// became hot
+pub async fn apply(records: &[MachineRecord]) {
+    let hot: Vec<ZoneTemp> = ZONES.with(|watch| {
+        if !watch.config.enabled {
+            return vec![];
+        }
+        let readings: Vec<(String, Position, f64)> = records
+            .iter()
+            .filter(|r| !maintenance::is_in_maintenance(&r.ip))
+            .filter_map(|r| {
+                let temp = thermal::max_temp(r);
+                let position = position::of(&r.ip).filter(|_| temp > 0.0)?;
+                Some((r.ip.clone(), position, temp))
+            })
+            .collect();
+        watch.check(&readings)
+    });
+    if hot.is_empty() {
+        return;
+    }
+    info!("average temperature high in {} zones", hot.len());
+
+    notifier::send_alert(&Alert {
+        kind: "zone_temp".to_string(),
+        title: template::render(
+            "zone_temp.title",
+            &[
+                ("time", &clock::now().format("%H:%M:%S")),
+                ("count", &hot.len()),
+            ],
+        ),
+        severity: Severity::Critical,
+        content: "".to_string(),
+        machines: hot
+            .iter()
+            .map(|z| AlertMachine {
+                ip: z.hottest.clone(),
+                detail: template::render(
+                    "zone_temp.detail",
+                    &[
+                        (
+                            "level",
+                            &template::render(
+                                match z.level {
+                                    ZoneLevel::Rack => "zone_temp.rack",
+                                    ZoneLevel::Room => "zone_temp.room",
+                                },
+                                &[],
+                            ),
+                        ),
+                        ("zone", &z.zone),
+                        ("avg", &format!("{:.1}", z.avg_temp)),
+                        ("threshold", &format!("{:.1}", z.threshold)),
+                        ("count", &z.machines),
+                        ("max", &format!("{:.1}", z.max_temp)),
+                    ],
+                ),
+            })
+            .collect(),
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(ip: &str, rack: &str, temp: f64) -> (String, Position, f64) {
+        let position = Position {
+            room: "A".to_string(),
+            row: "03".to_string(),
+            rack: rack.to_string(),
+            slot: "1".to_string(),
+        };
+        (ip.to_string(), position, temp)
+    }
+
+    #[test]
+    fn test_zone_temp() {
+        let mut watch = ZoneWatch::new(ZoneTempConfig {
+            enabled: true,
+            min_machines: 2,
+            ..Default::default()
+        });
+        // rack 12 lost its cooling, rack 13 is fine and keeps the room average down
+        let readings = vec![
+            reading("10.0.0.1", "12", 82.0),
+            reading("10.0.0.2", "12", 84.0),
+            reading("10.0.0.3", "13", 62.0),
+            reading("10.0.0.4", "13", 64.0),
+        ];
+        let averages = watch.averages(&readings);
+        assert_eq!(averages.len(), 3);
+        assert_eq!(averages[0].zone, "A-03-12");
+        assert_eq!(
+            (averages[0].avg_temp, averages[0].hottest.as_str()),
+            (83.0, "10.0.0.2")
+        );
+        assert_eq!(
+            (averages[2].level, averages[2].avg_temp),
+            (ZoneLevel::Room, 73.0)
+        );
+
+        assert!(watch.check(&readings).is_empty());
+        let hot = watch.check(&readings);
+        assert_eq!(hot.len(), 1);
+        assert_eq!(hot[0].level, ZoneLevel::Rack);
+        // alerted once until it cooled down
+        assert!(watch.check(&readings).is_empty());
+        let cooled: Vec<_> = readings
+            .iter()
+            .map(|(ip, p, _)| (ip.clone(), p.clone(), 70.0))
+            .collect();
+        assert!(watch.check(&cooled).is_empty());
+        watch.check(&readings);
+        assert_eq!(watch.check(&readings).len(), 1);
+    }
+}
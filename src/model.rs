@@ -0,0 +1,197 @@
+/// data models of the machines, shared by the engine and the dashboards. also built without
+/// the engine feature, for wasm32. MachineInfo, MachineRecord and PoolWorker are a stable
+/// wire format, see "Wire format" in the README
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::secret::SecretString;
+
+/// version of the MachineInfo, MachineRecord and PoolWorker json. fields are only added, with
+/// a default, and a renamed field keeps its old name as an alias. bumped when the meaning of
+/// a field changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// schema version a value was written with, 0 for snapshots from before the versioning.
+/// new values carry SCHEMA_VERSION
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SchemaVersion(pub u32);
+
+impl SchemaVersion {
+    pub fn unversioned() -> Self {
+        SchemaVersion(0)
+    }
+}
+
+impl Default for SchemaVersion {
+    fn default() -> Self {
+        SchemaVersion(SCHEMA_VERSION)
+    }
+}
+
+// String type enum
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MinerStatus {
+    Online,
+    Offline,
+    // Error,
+}
+
+impl MinerStatus {
+    /// the built in labels, ignoring case, None when unknown
+    pub fn parse(s: &str) -> Option<MinerStatus> {
+        let s = s.trim();
+        let is = |labels: &[&str]| labels.iter().any(|l| l.eq_ignore_ascii_case(s));
+        if is(&["上线", "online", "on"]) {
+            Some(MinerStatus::Online)
+        } else if is(&["下线", "offline", "off"]) {
+            Some(MinerStatus::Offline)
+        } else {
+            None
+        }
+    }
+}
+
+// unknown labels are Offline, the machine sheet reports them, see sheet::status
+impl From<&str> for MinerStatus {
+    fn from(s: &str) -> Self {
+        MinerStatus::parse(s).unwrap_or(MinerStatus::Offline)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Machine {
+    pub id: i32,
+    pub account_id: i32,
+    pub ip: String,
+    pub name: String, // ant, avalon, bluestar
+    pub status: MinerStatus,
+    pub account: Account,
+    pub switch_account: Option<Account>,
+    pub addition_info: String,
+    pub run_mode: String,
+    pub is_run_mode_fixed: bool,
+    pub group: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MachineRecord {
+    #[serde(default = "SchemaVersion::unversioned")]
+    pub schema_version: SchemaVersion,
+    pub id: i32,
+    pub ip: String,
+    pub machine_type: String,
+    pub work_mode: i32,
+    pub hash_real: f64,
+    pub hash_avg: f64,
+    /// None when the miner did not report the board or the value did not parse
+    pub temp_0: Option<f64>,
+    pub temp_1: Option<f64>,
+    pub temp_2: Option<f64>,
+    pub power: i32,
+    pub create_time: i64,
+    /// hardware errors since the miner started
+    #[serde(default)]
+    pub hw_errors: i64,
+    /// pool shares since the miner started, percent of all submitted
+    #[serde(default)]
+    pub accepted_pct: f64,
+    #[serde(default)]
+    pub rejected_pct: f64,
+    #[serde(default)]
+    pub stale_pct: f64,
+    /// seconds since cgminer started, 0 when not reported
+    #[serde(default)]
+    pub elapsed: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MachineInfo {
+    #[serde(default = "SchemaVersion::unversioned")]
+    pub schema_version: SchemaVersion,
+    pub ip: String,
+    pub machine_type: String,
+    pub hash_real: String,
+    pub hash_avg: String,
+    pub pool_hash_real: String,
+    pub pool_hash_avg: String,
+    pub temp: String,
+    pub fan: String,
+    pub elapsed: String,
+    pub mode: String,
+    pub pool1: String,
+    pub worker1: String,
+    pub pool2: String,
+    pub worker2: String,
+    /// hash boards of the model, 0 when unknown
+    #[serde(default)]
+    pub boards_expected: u32,
+    /// hash boards the miner reports as working
+    #[serde(default)]
+    pub boards_active: u32,
+    /// lowercase, colon separated, empty when not reported
+    #[serde(default)]
+    pub mac: String,
+    /// empty when not reported
+    #[serde(default)]
+    pub serial: String,
+    pub record: MachineRecord, // for db record
+    /// fields the miner did not report or that did not parse, the rest is still filled
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// vendor replies by command or web path, e.g. "estats" or "/cgi-bin/stats.cgi",
+    /// only filled when keep_raw is set
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub raw: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub id: i32,
+    pub name: String,
+    pub password: SecretString,
+    pub pool1: String,
+    pub pool2: String,
+    pub pool3: String,
+    pub run_mode: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PoolConfig {
+    pub url: String,
+    pub user: String,
+    pub password: SecretString,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_version() {
+        // a watching snapshot from before the versioning, board and share fields missing
+        let old: MachineInfo = serde_json::from_str(
+            r#"{"ip": "10.1.0.7", "machine_type": "ant", "hash_real": "110.2", "hash_avg": "109.8",
+                "pool_hash_real": "", "pool_hash_avg": "", "temp": "65", "fan": "5400",
+                "elapsed": "3600", "mode": "高功", "pool1": "btc.f2pool.com:1314",
+                "worker1": "lcd.7x1", "pool2": "", "worker2": "",
+                "record": {"id": 0, "ip": "10.1.0.7", "machine_type": "ant", "work_mode": 1,
+                    "hash_real": 110.2, "hash_avg": 109.8, "temp_0": 64.0, "temp_1": 65.0,
+                    "temp_2": 63.0, "power": 3250, "create_time": 1700000000}}"#,
+        )
+        .unwrap();
+        assert_eq!(old.schema_version, SchemaVersion::unversioned());
+        assert_eq!(old.record.schema_version, SchemaVersion::unversioned());
+        assert_eq!(old.record.temp_1, Some(65.0));
+        assert_eq!(old.boards_expected, 0);
+
+        let json = serde_json::to_value(MachineInfo::default()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["record"]["schema_version"], SCHEMA_VERSION);
+        let new: MachineInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(new.schema_version, SchemaVersion(SCHEMA_VERSION));
+    }
+}
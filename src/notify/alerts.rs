@@ -0,0 +1,292 @@
+/// raised alerts kept in the db as incidents a frontend can list: open, acknowledged, then
+/// resolved by hand or once quiet. a repeat of an unresolved alert updates its row
+use std::collections::BTreeMap;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::{escalation, Alert, AlertMachine, Severity};
+use crate::clock;
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::store::db;
+
+static CONFIG: Local<AlertStoreConfig> = Local::new(AlertStoreConfig::default);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertStoreConfig {
+    /// minutes without a repeat before an alert is resolved, 0 to resolve only by hand
+    pub resolve_minutes: i64,
+}
+
+impl Default for AlertStoreConfig {
+    fn default() -> Self {
+        AlertStoreConfig {
+            resolve_minutes: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Open,
+    Acknowledged,
+    Resolved,
+}
+
+impl AlertState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertState::Open => "open",
+            AlertState::Acknowledged => "acknowledged",
+            AlertState::Resolved => "resolved",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<AlertState> {
+        match s {
+            "open" => Some(AlertState::Open),
+            "acknowledged" => Some(AlertState::Acknowledged),
+            "resolved" => Some(AlertState::Resolved),
+            _ => None,
+        }
+    }
+}
+
+/// one stored alert, title, content and machines of its latest repeat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertEntry {
+    pub id: i64,
+    pub dedup_key: String,
+    pub kind: String,
+    pub severity: Severity,
+    pub title: String,
+    pub content: String,
+    pub machines: Vec<AlertMachine>,
+    pub state: AlertState,
+    /// times raised, the first included
+    pub count: i64,
+    pub first_time: i64,
+    pub last_time: i64,
+    pub ack_by: Option<String>,
+    pub ack_time: Option<i64>,
+    pub resolve_time: Option<i64>,
+}
+
+/// filter of query, last_time in the range, newest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlertQuery {
+    /// every state when None
+    #[serde(default)]
+    pub state: Option<AlertState>,
+    pub start_time: i64,
+    pub end_time: i64,
+    /// 0 for every row
+    #[serde(default)]
+    pub limit: u32,
+}
+
+/// alerts of one kind or one machine over a time range
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AlertStats {
+    /// the kind, or the ip of a machine
+    pub key: String,
+    /// stored alerts, a repeat of an unresolved one is not a new alert
+    pub alerts: i64,
+    /// times raised, repeats included
+    pub raised: i64,
+    pub resolved: i64,
+    /// mean minutes from first raised to resolved, of the resolved alerts
+    pub mttr_minutes: f64,
+    /// "%Y-%m-%d" to alerts first raised that day
+    pub per_day: BTreeMap<String, i64>,
+}
+
+/// alert frequency of the time range, most raised first
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AlertAnalytics {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub alerts: i64,
+    pub mttr_minutes: f64,
+    pub by_kind: Vec<AlertStats>,
+    /// the top offenders, cut to the asked count
+    pub by_machine: Vec<AlertStats>,
+}
+
+impl AlertStats {
+    fn add(&mut self, entry: &AlertEntry) {
+        self.alerts += 1;
+        self.raised += entry.count;
+        if let Some(resolve_time) = entry.resolve_time {
+            // running mean over the resolved ones
+            self.resolved += 1;
+            let minutes = (resolve_time - entry.first_time) as f64 / 60.0;
+            self.mttr_minutes += (minutes - self.mttr_minutes) / self.resolved as f64;
+        }
+        let day = chrono::DateTime::from_timestamp(entry.first_time, 0)
+            .map(|t| clock::at(t).format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        *self.per_day.entry(day).or_default() += 1;
+    }
+}
+
+pub fn set_config(config: AlertStoreConfig) {
+    CONFIG.set(config);
+}
+
+// unresolved alerts quiet for resolve_minutes
+fn resolve_quiet(now: i64) -> Result<(), MinerError> {
+    let minutes = CONFIG.with(|config| config.resolve_minutes);
+    if minutes > 0 {
+        let resolved = db::resolve_quiet_alerts(now - minutes * 60, now)?;
+        if resolved > 0 {
+            info!("{} quiet alerts resolved", resolved);
+        }
+    }
+    Ok(())
+}
+
+/// store the alert or count the repeat of its unresolved row, returns the row id
+pub fn record(alert: &Alert) -> Result<i64, MinerError> {
+    let now = chrono::Local::now().timestamp();
+    resolve_quiet(now)?;
+    db::record_alert(alert, &alert.incident_key(), now)
+}
+
+pub fn query(query: &AlertQuery) -> Result<Vec<AlertEntry>, MinerError> {
+    resolve_quiet(chrono::Local::now().timestamp())?;
+    db::query_alerts(query)
+}
+
+/// someone is on it, the escalation of the alert stops too
+pub fn ack(id: i64, by: &str) -> Result<(), MinerError> {
+    let key = db::set_alert_state(id, AlertState::Acknowledged, by)?
+        .ok_or(MinerError::AlertNotFoundError(id))?;
+    escalation::ack_key(&key, by);
+    Ok(())
+}
+
+/// frequency per kind and per machine of the alerts raised in the range, top machines only
+pub fn analyze(
+    entries: &[AlertEntry],
+    start_time: i64,
+    end_time: i64,
+    top: usize,
+) -> AlertAnalytics {
+    let mut total = AlertStats::default();
+    let mut by_kind: BTreeMap<&str, AlertStats> = BTreeMap::new();
+    let mut by_machine: BTreeMap<&str, AlertStats> = BTreeMap::new();
+    for entry in entries.iter() {
+        total.add(entry);
+        by_kind.entry(entry.kind.as_str()).or_default().add(entry);
+        for machine in entry.machines.iter() {
+            by_machine
+                .entry(machine.ip.as_str())
+                .or_default()
+                .add(entry);
+        }
+    }
+
+    let sorted = |stats: BTreeMap<&str, AlertStats>| {
+        let mut stats: Vec<AlertStats> = stats
+            .into_iter()
+            .map(|(key, stats)| AlertStats {
+                key: key.to_string(),
+                ..stats
+            })
+            .collect();
+        stats.sort_by(|a, b| b.raised.cmp(&a.raised).then(b.alerts.cmp(&a.alerts)));
+        stats
+    };
+    let mut by_machine = sorted(by_machine);
+    by_machine.truncate(top);
+    AlertAnalytics {
+        start_time,
+        end_time,
+        alerts: total.alerts,
+        mttr_minutes: total.mttr_minutes,
+        by_kind: sorted(by_kind),
+        by_machine,
+    }
+}
+
+/// analyze the stored alerts last raised in the range
+pub fn analytics(start_time: i64, end_time: i64, top: usize) -> Result<AlertAnalytics, MinerError> {
+    let entries = db::query_alerts(&AlertQuery {
+        start_time,
+        end_time,
+        ..Default::default()
+    })?;
+    Ok(analyze(&entries, start_time, end_time, top))
+}
+
+pub fn resolve(id: i64) -> Result<(), MinerError> {
+    db::set_alert_state(id, AlertState::Resolved, "")?.ok_or(MinerError::AlertNotFoundError(id))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(
+        kind: &str,
+        ips: &[&str],
+        count: i64,
+        first_time: i64,
+        resolve: Option<i64>,
+    ) -> AlertEntry {
+        AlertEntry {
+            id: 0,
+            dedup_key: "".to_string(),
+            kind: kind.to_string(),
+            severity: Severity::Warning,
+            title: "".to_string(),
+            content: "".to_string(),
+            machines: ips
+                .iter()
+                .map(|ip| AlertMachine {
+                    ip: ip.to_string(),
+                    detail: "".to_string(),
+                })
+                .collect(),
+            state: if resolve.is_some() {
+                AlertState::Resolved
+            } else {
+                AlertState::Open
+            },
+            count,
+            first_time,
+            last_time: first_time,
+            ack_by: None,
+            ack_time: None,
+            resolve_time: resolve.map(|t| first_time + t),
+        }
+    }
+
+    #[test]
+    fn test_alert_analytics() {
+        let entries = vec![
+            entry("boards", &["10.0.0.1"], 5, 0, Some(600)),
+            entry("boards", &["10.0.0.1"], 2, 86400, Some(1800)),
+            entry("switch_failed", &["10.0.0.1", "10.0.0.2"], 1, 100, None),
+            entry("thermal", &["10.0.0.3"], 3, 200, Some(1200)),
+        ];
+        let analytics = analyze(&entries, 0, 2 * 86400, 2);
+        assert_eq!(analytics.alerts, 4);
+        assert!((analytics.mttr_minutes - 20.0).abs() < 0.001);
+
+        assert_eq!(analytics.by_kind[0].key, "boards");
+        assert_eq!(analytics.by_kind[0].raised, 7);
+        assert!((analytics.by_kind[0].mttr_minutes - 20.0).abs() < 0.001);
+        assert_eq!(analytics.by_kind[0].per_day.len(), 2);
+
+        assert_eq!(analytics.by_machine.len(), 2);
+        assert_eq!(analytics.by_machine[0].key, "10.0.0.1");
+        assert_eq!(analytics.by_machine[0].raised, 8);
+        assert_eq!(analytics.by_machine[0].resolved, 2);
+        assert_eq!(analytics.by_machine[1].key, "10.0.0.3");
+    }
+}
@@ -0,0 +1,68 @@
+/// dingtalk group robot
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use super::notifier::{self, Notifier};
+use super::Alert;
+use crate::error::MinerError;
+use crate::http;
+use crate::secret::{self, SecretString};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DingTalkNotifier {
+    pub access_token: SecretString,
+    // sign secret, empty when robot uses keyword or ip security
+    pub secret: SecretString,
+}
+
+impl DingTalkNotifier {
+    fn url(&self) -> Result<String, MinerError> {
+        let mut url = format!(
+            "https://oapi.dingtalk.com/robot/send?access_token={}",
+            secret::resolve(self.access_token.expose())?
+        );
+        if !self.secret.is_empty() {
+            let sign_secret = secret::resolve(self.secret.expose())?;
+            let timestamp = chrono::Local::now().timestamp_millis();
+            let mut mac = Hmac::<Sha256>::new_from_slice(sign_secret.as_bytes())
+                .map_err(|_| MinerError::AuthError)?;
+            mac.update(format!("{}\n{}", timestamp, sign_secret).as_bytes());
+            let sign =
+                base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+            let query = serde_urlencoded::to_string([
+                ("timestamp", timestamp.to_string()),
+                ("sign", sign),
+            ])?;
+            url = format!("{}&{}", url, query);
+        }
+        Ok(url)
+    }
+}
+
+impl Notifier for DingTalkNotifier {
+    fn name(&self) -> &str {
+        "dingtalk"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        let client = http::default_client()?;
+        let res: Value = client
+            .post(self.url()?)
+            .json(&json!({
+                "msgtype": "markdown",
+                "markdown": {
+                    "title": alert.title,
+                    "text": alert.to_markdown(),
+                }
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        notifier::check_reply(&res, "errcode", "errmsg")
+    }
+}
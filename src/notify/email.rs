@@ -0,0 +1,170 @@
+/// smtp email channel, alerts rendered as html digest
+use std::collections::BTreeMap;
+
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+
+use super::{notifier::Notifier, Alert, Severity};
+use crate::clock;
+use crate::error::MinerError;
+use crate::secret::{self, SecretString};
+
+const DEFAULT_TEMPLATE: &str = r#"<html><body>
+<h3 style="color:{color}">[{severity}] {title}</h3>
+<p>{content}</p>
+<table border="1" cellpadding="4" cellspacing="0">
+<tr><th>IP</th><th>Detail</th></tr>
+{rows}
+</table>
+<p style="color:#888">{time}</p>
+</body></html>"#;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum EmailTls {
+    None,
+    #[default]
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailNotifier {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: SecretString,
+    pub from: String,
+    pub tls: EmailTls,
+    /// recipients of each severity, an alert goes to the list of its own severity
+    pub recipients: BTreeMap<Severity, Vec<String>>,
+    /// html body, placeholders: {title} {severity} {color} {content} {rows} {time},
+    /// empty to use the built-in template
+    pub html_template: String,
+}
+
+impl EmailNotifier {
+    pub fn render_html(&self, alert: &Alert) -> String {
+        let template = if self.html_template.is_empty() {
+            DEFAULT_TEMPLATE
+        } else {
+            &self.html_template
+        };
+
+        let color = match alert.severity {
+            Severity::Info => "#1f6feb",
+            Severity::Warning => "#d97706",
+            Severity::Critical => "#dc2626",
+        };
+
+        let rows: String = alert
+            .machines
+            .iter()
+            .map(|m| {
+                format!(
+                    "<tr><td><a href=\"http://{ip}\">{ip}</a></td><td>{}</td></tr>",
+                    escape_html(&m.detail),
+                    ip = escape_html(&m.ip)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        template
+            .replace("{title}", &escape_html(&alert.title))
+            .replace("{severity}", &format!("{:?}", alert.severity))
+            .replace("{color}", color)
+            .replace("{content}", &escape_html(&alert.content))
+            .replace("{rows}", &rows)
+            .replace(
+                "{time}",
+                &clock::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            )
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, MinerError> {
+        let builder = match self.tls {
+            EmailTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(self.host.as_str())
+            }
+            EmailTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?,
+            EmailTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)?,
+        };
+
+        let mut builder = builder.port(self.port);
+        if !self.username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                self.username.clone(),
+                secret::resolve(self.password.expose())?,
+            ));
+        }
+        Ok(builder.build())
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        let recipients = match self.recipients.get(&alert.severity) {
+            Some(recipients) if !recipients.is_empty() => recipients,
+            _ => return Ok(()),
+        };
+
+        let mut builder = Message::builder()
+            .from(self.from.parse()?)
+            .subject(format!("[{:?}] {}", alert.severity, alert.title))
+            .header(ContentType::TEXT_HTML);
+        for to in recipients.iter() {
+            builder = builder.to(to.parse()?);
+        }
+        let message = builder.body(self.render_html(alert))?;
+
+        self.transport()?.send(message).await?;
+        Ok(())
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::AlertMachine;
+    use super::*;
+
+    #[test]
+    fn test_render_html() {
+        let notifier = EmailNotifier {
+            host: "smtp.example.com".to_string(),
+            port: 587,
+            username: "".to_string(),
+            password: "".into(),
+            from: "lcd <lcd@example.com>".to_string(),
+            tls: EmailTls::StartTls,
+            recipients: BTreeMap::new(),
+            html_template: "".to_string(),
+        };
+        let alert = Alert {
+            kind: "".to_string(),
+            title: "访问故障".to_string(),
+            severity: Severity::Critical,
+            content: "<b>".to_string(),
+            machines: vec![AlertMachine {
+                ip: "192.168.1.2".to_string(),
+                detail: "A1".to_string(),
+            }],
+        };
+        let html = notifier.render_html(&alert);
+        assert!(html.contains("[Critical] 访问故障"));
+        assert!(html.contains("&lt;b&gt;"));
+        assert!(html.contains("<a href=\"http://192.168.1.2\">192.168.1.2</a>"));
+    }
+}
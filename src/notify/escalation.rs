@@ -0,0 +1,437 @@
+/// escalation of alerts nobody acknowledged: an alert matching a rule opens an incident, its
+/// steps run when due, e.g. a phone webhook after 15 minutes and a reboot after 30. the
+/// incident is resolved once the alert stops firing, acknowledged ones do not escalate.
+/// actions on machines skip those in maintenance, reboots are capped per machine, and every
+/// action, skipped or failed ones included, goes to the event log
+use std::collections::HashMap;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::notifier::{Notifier, NotifierType};
+use super::{template, Alert, Severity};
+use crate::context::{self, Local};
+use crate::error::MinerError;
+use crate::miner::entry::{self, PoolConfig};
+use crate::miner::maintenance;
+use crate::status;
+use crate::store::db;
+
+const DAY_SECONDS: i64 = 24 * 3600;
+
+// event detail of an automatic reboot, counted for the cap
+const REBOOT_DETAIL: &str = "reboot";
+
+static ESCALATION: Local<Escalation> = Local::new(Escalation::default);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationConfig {
+    /// first matching rule of an alert wins
+    pub rules: Vec<EscalationRule>,
+    /// minutes without the alert firing again before its incident is resolved
+    pub resolve_minutes: i64,
+    /// automatic reboots of one machine in 24 hours, 0 for none
+    #[serde(default = "default_max_reboots")]
+    pub max_reboots_per_day: u32,
+}
+
+fn default_max_reboots() -> u32 {
+    3
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        EscalationConfig {
+            rules: vec![],
+            resolve_minutes: 30,
+            max_reboots_per_day: default_max_reboots(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationRule {
+    pub name: String,
+    /// alert kinds, e.g. "switch_failed", empty for every kind
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    pub min_severity: Severity,
+    /// in the order of after_minutes
+    pub steps: Vec<EscalationStep>,
+}
+
+impl EscalationRule {
+    fn matches(&self, alert: &Alert) -> bool {
+        alert.severity >= self.min_severity
+            && (self.kinds.is_empty() || self.kinds.contains(&alert.kind))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationStep {
+    /// minutes after the incident opened
+    pub after_minutes: i64,
+    pub action: EscalationAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EscalationAction {
+    /// the alert through one more channel, e.g. the webhook of an sms or phone gateway
+    Notify(NotifierType),
+    /// reboot the machines of the alert
+    Reboot,
+    /// shell command on the machines of the alert, needs the ssh feature
+    Exec(String),
+    /// run mode for the machines of the alert, e.g. down to 普通 or sleep
+    Mode(String),
+    /// backup pools for the machines of the alert
+    Pools(Vec<PoolConfig>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Incident {
+    pub id: u64,
+    pub rule: String,
+    /// the latest alert of the incident
+    pub alert: Alert,
+    pub opened: i64,
+    pub last_seen: i64,
+    /// steps of the rule already run
+    pub steps_done: usize,
+    pub acked_by: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Escalation {
+    config: EscalationConfig,
+    incidents: Vec<Incident>,
+    next_id: u64,
+    /// ip to the times of its automatic reboots, the event log may be off without a db
+    reboots: HashMap<String, Vec<i64>>,
+}
+
+impl Escalation {
+    fn observe(&mut self, alert: &Alert, now: i64) {
+        let Some(rule) = self.config.rules.iter().find(|r| r.matches(alert)) else {
+            return;
+        };
+        if rule.steps.is_empty() {
+            return;
+        }
+        let key = alert.incident_key();
+        if let Some(incident) = self
+            .incidents
+            .iter_mut()
+            .find(|i| i.rule == rule.name && i.alert.incident_key() == key)
+        {
+            incident.alert = alert.clone();
+            incident.last_seen = now;
+            return;
+        }
+        self.next_id += 1;
+        info!("incident {} opened: {}", self.next_id, alert.title);
+        self.incidents.push(Incident {
+            id: self.next_id,
+            rule: rule.name.clone(),
+            alert: alert.clone(),
+            opened: now,
+            last_seen: now,
+            steps_done: 0,
+            acked_by: None,
+        });
+    }
+
+    // resolve the quiet incidents, then the steps due of the unacknowledged ones
+    fn due(&mut self, now: i64) -> Vec<(Incident, EscalationAction)> {
+        let resolve = self.config.resolve_minutes * 60;
+        self.incidents.retain(|i| {
+            let open = now - i.last_seen < resolve;
+            if !open {
+                info!("incident {} resolved", i.id);
+            }
+            open
+        });
+
+        let mut due = vec![];
+        for incident in self.incidents.iter_mut() {
+            if incident.acked_by.is_some() {
+                continue;
+            }
+            let Some(rule) = self.config.rules.iter().find(|r| r.name == incident.rule) else {
+                continue;
+            };
+            while let Some(step) = rule.steps.get(incident.steps_done) {
+                if now - incident.opened < step.after_minutes * 60 {
+                    break;
+                }
+                incident.steps_done += 1;
+                due.push((incident.clone(), step.action.clone()));
+            }
+        }
+        due
+    }
+
+    // split the ips into those still under the reboot cap and the refused ones, logged counts
+    // from the event log count too
+    fn allow_reboots(
+        &mut self,
+        ips: Vec<String>,
+        now: i64,
+        logged: impl Fn(&str) -> i64,
+    ) -> (Vec<String>, Vec<String>) {
+        let max = self.config.max_reboots_per_day as i64;
+        let mut allowed = vec![];
+        let mut refused = vec![];
+        for ip in ips {
+            let times = self.reboots.entry(ip.clone()).or_default();
+            times.retain(|t| now - t < DAY_SECONDS);
+            if (times.len() as i64).max(logged(&ip)) < max {
+                times.push(now);
+                allowed.push(ip);
+            } else {
+                refused.push(ip);
+            }
+        }
+        (allowed, refused)
+    }
+
+    fn ack_key(&mut self, key: &str, by: &str) {
+        for incident in self.incidents.iter_mut() {
+            if incident.acked_by.is_none() && incident.alert.incident_key() == key {
+                info!("incident {} acknowledged by {}", incident.id, by);
+                incident.acked_by = Some(by.to_string());
+            }
+        }
+    }
+
+    fn ack(&mut self, id: u64, by: &str) -> Result<(), MinerError> {
+        let incident = self
+            .incidents
+            .iter_mut()
+            .find(|i| i.id == id)
+            .ok_or(MinerError::IncidentNotFoundError(id))?;
+        info!("incident {} acknowledged by {}", id, by);
+        incident.acked_by = Some(by.to_string());
+        Ok(())
+    }
+}
+
+/// the open incidents are kept, their rule is looked up again at each step
+pub fn set_config(config: EscalationConfig) {
+    ESCALATION.with(|escalation| escalation.config = config);
+}
+
+/// open or refresh the incident of an alert matching a rule
+pub fn observe(alert: &Alert) {
+    ESCALATION.with(|escalation| escalation.observe(alert, chrono::Local::now().timestamp()));
+}
+
+/// stop the escalation of an incident, it stays listed until resolved
+pub fn ack(id: u64, by: &str) -> Result<(), MinerError> {
+    ESCALATION.with(|escalation| escalation.ack(id, by))
+}
+
+/// stop the escalation of the incidents of an alert, as stored by notify::alerts
+pub fn ack_key(key: &str, by: &str) {
+    ESCALATION.with(|escalation| escalation.ack_key(key, by))
+}
+
+pub fn incidents() -> Vec<Incident> {
+    ESCALATION.with(|escalation| escalation.incidents.clone())
+}
+
+/// run the steps due now
+pub async fn run_due(runtime: &tokio::runtime::Handle) {
+    let now = chrono::Local::now().timestamp();
+    let due = ESCALATION.with(|escalation| escalation.due(now));
+    for (incident, action) in due {
+        info!("incident {} escalates: {:?}", incident.id, action);
+        if let EscalationAction::Notify(notifier) = &action {
+            if let Err(e) = notifier.send(&escalated(&incident, now)).await {
+                error!("notify {} error: {:?}", notifier.name(), e);
+            }
+            continue;
+        }
+
+        let (ips, skipped): (Vec<String>, Vec<String>) = incident
+            .alert
+            .machines
+            .iter()
+            .map(|m| m.ip.clone())
+            .partition(|ip| !maintenance::is_in_maintenance(ip));
+        for ip in skipped.iter() {
+            audit(ip, &format!("{:?} skipped, in maintenance", action));
+        }
+        let failed = match action {
+            EscalationAction::Notify(_) => vec![],
+            EscalationAction::Reboot => reboot(runtime, ips, now).await,
+            EscalationAction::Exec(command) => {
+                entry::exec_ips(runtime, ips, &command, db::EVENT_ESCALATION).await
+            }
+            EscalationAction::Mode(mode) => {
+                let modes = ips.into_iter().map(|ip| (ip, mode.clone())).collect();
+                entry::config_mode_ips(runtime, modes, db::EVENT_ESCALATION).await
+            }
+            EscalationAction::Pools(pools) => {
+                entry::config_pool_ips(runtime, ips, &pools, db::EVENT_ESCALATION).await
+            }
+        };
+        for ip in failed.iter() {
+            audit(ip, &format!("incident {} step failed", incident.id));
+        }
+        if !failed.is_empty() {
+            error!("incident {} escalation failed: {:?}", incident.id, failed);
+        }
+    }
+}
+
+// reboot the machines under the cap, returns the failed ips
+async fn reboot(runtime: &tokio::runtime::Handle, ips: Vec<String>, now: i64) -> Vec<String> {
+    let (ips, refused) = ESCALATION.with(|escalation| {
+        escalation.allow_reboots(ips, now, |ip| {
+            db::count_ip_events(db::EVENT_ESCALATION, ip, REBOOT_DETAIL, now - DAY_SECONDS)
+                .unwrap_or(0)
+        })
+    });
+    for ip in refused.iter() {
+        audit(ip, "reboot skipped, daily cap reached");
+    }
+    if ips.is_empty() {
+        return vec![];
+    }
+
+    let failed = match entry::reboot_batch(runtime.clone(), ips.clone(), 5).await {
+        Ok(()) => vec![],
+        Err(MinerError::BatchError(e)) => e.failed.into_iter().map(|(ip, _)| ip).collect(),
+        Err(e) => {
+            error!("escalation reboot error: {:?}", e);
+            ips.clone()
+        }
+    };
+    for ip in ips.iter().filter(|ip| !failed.contains(ip)) {
+        audit(ip, REBOOT_DETAIL);
+    }
+    failed
+}
+
+fn audit(ip: &str, detail: &str) {
+    info!("escalation {}: {}", ip, detail);
+    if let Err(e) = db::insert_event(db::EVENT_ESCALATION, ip, detail) {
+        error!("insert escalation event error: {:?}", e);
+    }
+}
+
+// the alert with the minutes it is unacknowledged and how to stop it
+fn escalated(incident: &Incident, now: i64) -> Alert {
+    Alert {
+        title: template::render(
+            "escalation.title",
+            &[
+                ("minutes", &((now - incident.opened) / 60)),
+                ("title", &incident.alert.title),
+            ],
+        ),
+        content: template::render(
+            "escalation.content",
+            &[("content", &incident.alert.content), ("id", &incident.id)],
+        ),
+        ..incident.alert.clone()
+    }
+}
+
+/// start the task running the due steps every interval, e.g. every minute
+pub fn schedule_task(
+    runtime: tokio::runtime::Handle,
+    interval_seconds: u64,
+) -> tokio::task::JoinHandle<()> {
+    let handle = runtime.clone();
+    context::spawn(&runtime, async move {
+        loop {
+            run_due(&handle).await;
+            if !status::wait(
+                "escalation",
+                tokio::time::Duration::from_secs(interval_seconds),
+            )
+            .await
+            {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::AlertMachine;
+
+    fn alert(kind: &str, ip: &str) -> Alert {
+        Alert {
+            kind: kind.to_string(),
+            title: "08:00:00 访问故障 1台".to_string(),
+            severity: Severity::Warning,
+            content: "".to_string(),
+            machines: vec![AlertMachine {
+                ip: ip.to_string(),
+                detail: "".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_escalation_steps() {
+        let mut escalation = Escalation {
+            config: EscalationConfig {
+                rules: vec![EscalationRule {
+                    name: "unreachable".to_string(),
+                    kinds: vec!["switch_failed".to_string()],
+                    min_severity: Severity::Warning,
+                    steps: vec![
+                        EscalationStep {
+                            after_minutes: 15,
+                            action: EscalationAction::Exec("reboot".to_string()),
+                        },
+                        EscalationStep {
+                            after_minutes: 30,
+                            action: EscalationAction::Reboot,
+                        },
+                    ],
+                }],
+                resolve_minutes: 30,
+                max_reboots_per_day: 2,
+            },
+            ..Default::default()
+        };
+        escalation.observe(&alert("thermal", "10.0.0.1"), 0);
+        escalation.observe(&alert("switch_failed", "10.0.0.1"), 0);
+        escalation.observe(&alert("switch_failed", "10.0.0.1"), 600);
+        escalation.observe(&alert("switch_failed", "10.0.0.2"), 600);
+        assert_eq!(escalation.incidents.len(), 2);
+
+        // both steps of the first incident are due, the second one acknowledged
+        escalation.observe(&alert("switch_failed", "10.0.0.1"), 1800);
+        escalation.ack(2, "ops").unwrap();
+        let due = escalation.due(1800);
+        assert_eq!(due.len(), 2);
+        assert!(due.iter().all(|(incident, _)| incident.id == 1));
+        assert!(escalation.due(1900).is_empty());
+        assert_eq!(escalation.ack(9, "ops").unwrap_err().code(), 6008);
+
+        // quiet for resolve_minutes
+        escalation.due(600 + 1800);
+        assert_eq!(escalation.incidents.len(), 1);
+        escalation.due(1800 + 1800);
+        assert!(escalation.incidents.is_empty());
+
+        // two reboots a day, the logged ones of an earlier run count too
+        let ips = vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()];
+        let logged = |ip: &str| if ip == "10.0.0.2" { 2 } else { 0 };
+        let (allowed, refused) = escalation.allow_reboots(ips.clone(), 0, logged);
+        assert_eq!((allowed.len(), refused), (1, vec!["10.0.0.2".to_string()]));
+        escalation.allow_reboots(ips.clone(), 3600, logged);
+        let (allowed, _) = escalation.allow_reboots(ips.clone(), 7200, logged);
+        assert!(allowed.is_empty());
+        let (allowed, _) = escalation.allow_reboots(ips, DAY_SECONDS + 1, |_| 0);
+        assert_eq!(allowed.len(), 2);
+    }
+}
@@ -1,38 +1,101 @@
 use serde_json::{json, Value};
 
+use serde::{Deserialize, Serialize};
+
+use super::notifier::{self, Notifier};
+use super::token::{CachedToken, TokenCache};
+use super::{template, Alert, Severity};
+use crate::context;
 use crate::error::MinerError;
+use crate::http;
+use crate::secret::SecretString;
 
 /// feishu api to query sheet
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+
+// feishu codes for an invalid or expired tenant token
+const TOKEN_INVALID_CODES: [i64; 2] = [99991663, 99991668];
 
-lazy_static! {
-    static ref APP_ID: Mutex<Option<String>> = Mutex::new(None);
-    static ref APP_SECRET: Mutex<Option<String>> = Mutex::new(None);
-    static ref BOT: Mutex<Option<String>> = Mutex::new(None);
+/// feishu app of a context
+#[derive(Default)]
+pub struct FeishuApp {
+    app_id: Mutex<Option<String>>,
+    app_secret: Mutex<Option<SecretString>>,
+    bot: Mutex<Option<String>>,
+    // open_id of on-call users, mentioned in critical alerts
+    oncall: Mutex<Vec<String>>,
+    token: Mutex<Arc<TokenCache>>,
 }
 
 pub fn init(app_id: &str, app_secret: &str, bot: &str) {
-    *APP_ID.lock().unwrap() = Some(app_id.to_string());
-    *APP_SECRET.lock().unwrap() = Some(app_secret.to_string());
-    *BOT.lock().unwrap() = Some(bot.to_string());
+    let context = context::current();
+    let app = &context.feishu;
+    *app.app_id.lock().unwrap() = Some(app_id.to_string());
+    *app.app_secret.lock().unwrap() = Some(app_secret.into());
+    *app.bot.lock().unwrap() = Some(bot.to_string());
+    // credentials may changed, drop old token
+    *app.token.lock().unwrap() = Arc::default();
 }
 
+/// get cached tenant token, refresh when missing or about to expire
 async fn get_access_token() -> Result<String, MinerError> {
-    let url = format!("https://open.feishu.cn/open-apis/auth/v3/tenant_access_token/internal/");
-    let client = reqwest::Client::new();
+    let cache = context::current().feishu.token.lock().unwrap().clone();
+    cache.get(request_access_token).await
+}
+
+/// set on-call users (open_id) to mention in critical cards
+pub fn set_oncall(users: Vec<String>) {
+    *context::current().feishu.oncall.lock().unwrap() = users;
+}
+
+/// drop cached token when feishu reports it invalid
+async fn check_token_code(res: &Value) {
+    if let Some(code) = res["code"].as_i64() {
+        if TOKEN_INVALID_CODES.contains(&code) {
+            let cache = context::current().feishu.token.lock().unwrap().clone();
+            cache.clear().await;
+        }
+    }
+}
+
+async fn request_access_token() -> Result<CachedToken, MinerError> {
+    let url = "https://open.feishu.cn/open-apis/auth/v3/tenant_access_token/internal/";
+    let client = http::default_client()?;
+    let context = context::current();
+    let app_id = context
+        .feishu
+        .app_id
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_default();
+    let app_secret = context
+        .feishu
+        .app_secret
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_default();
     let res: Value = client
-        .post(&url)
+        .post(url)
         .header("Content-Type", "application/json")
         .json(&json!({
-            "app_id": APP_ID.lock().unwrap().as_ref().unwrap(),
-            "app_secret": APP_SECRET.lock().unwrap().as_ref().unwrap(),
+            "app_id": app_id,
+            "app_secret": app_secret.expose(),
         })) // Convert JSON body to string
         .send()
         .await?
         .json()
         .await?;
 
-    Ok(res["tenant_access_token"].as_str().unwrap().to_string())
+    let token = res["tenant_access_token"]
+        .as_str()
+        .ok_or(MinerError::AuthError)?
+        .to_string();
+    // expire in seconds, 7200 by default
+    let expire = res["expire"].as_i64().unwrap_or(7200);
+
+    Ok(CachedToken::new(token, expire))
 }
 
 pub async fn query_sheet(sheets_id: &str, sheet_id: &str) -> Result<Value, MinerError> {
@@ -41,7 +104,7 @@ pub async fn query_sheet(sheets_id: &str, sheet_id: &str) -> Result<Value, Miner
         "https://open.feishu.cn/open-apis/sheets/v2/spreadsheets/{}/values/{}",
         sheets_id, sheet_id
     );
-    let client = reqwest::Client::new();
+    let client = http::default_client()?;
     let res = client
         .get(&url)
         .header("Authorization", format!("Bearer {}", token))
@@ -50,15 +113,89 @@ pub async fn query_sheet(sheets_id: &str, sheet_id: &str) -> Result<Value, Miner
         .json()
         .await?;
 
+    check_token_code(&res).await;
     Ok(res)
 }
 
+/// write values into one range, range like "sheetId!A2:C2"
+pub async fn update_sheet_range(
+    sheets_id: &str,
+    range: &str,
+    values: Vec<Vec<Value>>,
+) -> Result<(), MinerError> {
+    let token = get_access_token().await?;
+    let url = format!(
+        "https://open.feishu.cn/open-apis/sheets/v2/spreadsheets/{}/values",
+        sheets_id
+    );
+    let client = http::default_client()?;
+    let res: Value = client
+        .put(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({
+            "valueRange": {
+                "range": range,
+                "values": values,
+            }
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    check_token_code(&res).await;
+    if res["code"] != 0 {
+        return Err(MinerError::FeishuParserJsonError);
+    }
+    Ok(())
+}
+
+/// write multiple ranges in one request
+pub async fn update_sheet_ranges(
+    sheets_id: &str,
+    ranges: Vec<(String, Vec<Vec<Value>>)>,
+) -> Result<(), MinerError> {
+    let token = get_access_token().await?;
+    let url = format!(
+        "https://open.feishu.cn/open-apis/sheets/v2/spreadsheets/{}/values_batch_update",
+        sheets_id
+    );
+    let value_ranges: Vec<Value> = ranges
+        .into_iter()
+        .map(|(range, values)| json!({ "range": range, "values": values }))
+        .collect();
+    let client = http::default_client()?;
+    let res: Value = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "valueRanges": value_ranges }))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    check_token_code(&res).await;
+    if res["code"] != 0 {
+        return Err(MinerError::FeishuParserJsonError);
+    }
+    Ok(())
+}
+
 pub async fn notify(msg: &str) {
     let url = format!(
         "https://open.feishu.cn/open-apis/bot/v2/hook/{}",
-        BOT.lock().unwrap().as_ref().unwrap()
+        context::current()
+            .feishu
+            .bot
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
     );
-    let client = reqwest::Client::new();
+    let client = match http::default_client() {
+        Ok(client) => client,
+        Err(_) => return,
+    };
     let _ = client
         .post(url)
         .header("Content-Type", "application/json")
@@ -72,6 +209,94 @@ pub async fn notify(msg: &str) {
         .await;
 }
 
+/// feishu group bot as notify channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeishuNotifier {
+    pub bot: String,
+}
+
+impl Notifier for FeishuNotifier {
+    fn name(&self) -> &str {
+        "feishu"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        send_card(&self.bot, alert).await
+    }
+}
+
+async fn send_card(bot: &str, alert: &Alert) -> Result<(), MinerError> {
+    let url = format!("https://open.feishu.cn/open-apis/bot/v2/hook/{}", bot);
+    let oncall = context::current().feishu.oncall.lock().unwrap().clone();
+    let client = http::default_client()?;
+    let res: Value = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .json(&build_card(alert, &oncall))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    notifier::check_reply(&res, "code", "msg")
+}
+
+fn build_card(alert: &Alert, oncall: &[String]) -> Value {
+    let template = match alert.severity {
+        Severity::Info => "blue",
+        Severity::Warning => "orange",
+        Severity::Critical => "red",
+    };
+
+    let mut elements = vec![];
+    if !alert.content.is_empty() {
+        elements.push(json!({
+            "tag": "div",
+            "text": { "tag": "lark_md", "content": alert.content }
+        }));
+    }
+
+    // two columns table: ip with link to web ui, detail
+    if !alert.machines.is_empty() {
+        let mut ips = "**IP**".to_string();
+        let mut details = format!("**{}**", template::render("alert.detail", &[]));
+        for machine in alert.machines.iter() {
+            ips.push_str(&format!("\n[{}](http://{})", machine.ip, machine.ip));
+            details.push_str(&format!("\n{}", machine.detail));
+        }
+        elements.push(json!({
+            "tag": "div",
+            "fields": [
+                { "is_short": true, "text": { "tag": "lark_md", "content": ips } },
+                { "is_short": true, "text": { "tag": "lark_md", "content": details } }
+            ]
+        }));
+    }
+
+    if alert.severity == Severity::Critical && !oncall.is_empty() {
+        let mentions: Vec<String> = oncall
+            .iter()
+            .map(|id| format!("<at id={}></at>", id))
+            .collect();
+        elements.push(json!({
+            "tag": "div",
+            "text": { "tag": "lark_md", "content": mentions.join(" ") }
+        }));
+    }
+
+    json!({
+        "msg_type": "interactive",
+        "card": {
+            "config": { "wide_screen_mode": true },
+            "header": {
+                "template": template,
+                "title": { "tag": "plain_text", "content": alert.title }
+            },
+            "elements": elements
+        }
+    })
+}
+
 //test
 #[cfg(test)]
 mod tests {
@@ -89,7 +314,38 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_build_card() {
+        let mut alert = Alert {
+            kind: "".to_string(),
+            title: "访问故障".to_string(),
+            severity: Severity::Warning,
+            content: "".to_string(),
+            machines: vec![super::super::AlertMachine {
+                ip: "192.168.1.2".to_string(),
+                detail: "A1".to_string(),
+            }],
+        };
+        let oncall = vec!["ou_1".to_string()];
+        let card = build_card(&alert, &oncall);
+        assert_eq!(card["card"]["header"]["template"], "orange");
+        assert_eq!(card["card"]["elements"].as_array().unwrap().len(), 1);
+        let ips = card["card"]["elements"][0]["fields"][0]["text"]["content"]
+            .as_str()
+            .unwrap();
+        assert!(ips.contains("[192.168.1.2](http://192.168.1.2)"));
+
+        alert.severity = Severity::Critical;
+        let card = build_card(&alert, &oncall);
+        assert_eq!(card["card"]["header"]["template"], "red");
+        assert_eq!(
+            card["card"]["elements"][1]["text"]["content"],
+            "<at id=ou_1></at>"
+        );
+    }
+
     #[tokio::test]
+    #[ignore = "needs feishu credentials"]
     async fn test_get_access_token() {
         let _ = &*SETUP;
         let token = get_access_token().await.unwrap();
@@ -98,6 +354,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs feishu credentials"]
     async fn test_query_sheet() {
         let _ = &*SETUP;
         let res = query_sheet("PwjYsZoefh6rXZt3mIucC9XmnZb", "IiekOA")
@@ -108,6 +365,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs feishu credentials"]
     async fn test_notify() {
         let _ = &*SETUP;
         notify("hello test").await;
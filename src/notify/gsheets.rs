@@ -0,0 +1,174 @@
+/// google sheets api to query sheet, auth through service account json key
+use std::sync::Arc;
+
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::token::{CachedToken, TokenCache};
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::http;
+
+const SHEETS_SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets";
+const SHEETS_API: &str = "https://sheets.googleapis.com/v4/spreadsheets";
+static SERVICE_ACCOUNT: Local<Option<ServiceAccountKey>> = Local::new(Option::default);
+static TOKEN: Local<Arc<TokenCache>> = Local::new(Arc::default);
+
+/// fields used from the service account json key file
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    pub token_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Claims {
+    iss: String,
+    scope: String,
+    aud: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// load service account key from json file
+pub fn init(key_path: &str) -> Result<(), MinerError> {
+    let content = std::fs::read_to_string(key_path)?;
+    let key: ServiceAccountKey = serde_json::from_str(&content)?;
+    SERVICE_ACCOUNT.set(Some(key));
+    // key may changed, drop the token of the old one
+    TOKEN.set(Arc::default());
+    Ok(())
+}
+
+/// get cached access token, refresh when missing or about to expire
+async fn get_access_token() -> Result<String, MinerError> {
+    TOKEN.get().get(request_access_token).await
+}
+
+async fn request_access_token() -> Result<CachedToken, MinerError> {
+    let key = SERVICE_ACCOUNT.get().ok_or(MinerError::GoogleAuthError)?;
+
+    let now = chrono::Local::now().timestamp();
+    let claims = Claims {
+        iss: key.client_email.clone(),
+        scope: SHEETS_SCOPE.to_string(),
+        aud: key.token_uri.clone(),
+        iat: now,
+        exp: now + 3600,
+    };
+    let assertion = jsonwebtoken::encode(
+        &Header::new(Algorithm::RS256),
+        &claims,
+        &EncodingKey::from_rsa_pem(key.private_key.as_bytes())?,
+    )?;
+
+    let client = http::default_client()?;
+    let res: Value = client
+        .post(&key.token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", &assertion),
+        ])
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    let token = res["access_token"]
+        .as_str()
+        .ok_or(MinerError::GoogleAuthError)?
+        .to_string();
+    // expire in seconds, 3600 by default
+    let expire = res["expires_in"].as_i64().unwrap_or(3600);
+
+    Ok(CachedToken::new(token, expire))
+}
+
+// range as one percent encoded path segment, sheet names may have spaces or cjk
+fn values_url(spreadsheet_id: &str, range: &str) -> String {
+    // the base is a constant http url, it parses and has path segments
+    let mut url = reqwest::Url::parse(SHEETS_API).unwrap();
+    url.path_segments_mut()
+        .unwrap()
+        .extend([spreadsheet_id, "values", range]);
+    url.to_string()
+}
+
+/// query range of spreadsheet, range can be a sheet name like "machines"
+pub async fn query_sheet(spreadsheet_id: &str, range: &str) -> Result<Value, MinerError> {
+    let token = get_access_token().await?;
+    let url = values_url(spreadsheet_id, range);
+    let client = http::default_client()?;
+    let res = client
+        .get(&url)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    Ok(res)
+}
+
+/// write multiple ranges in one request
+pub async fn update_sheet_ranges(
+    spreadsheet_id: &str,
+    ranges: Vec<(String, Vec<Vec<Value>>)>,
+) -> Result<(), MinerError> {
+    let token = get_access_token().await?;
+    let url = format!("{}/{}/values:batchUpdate", SHEETS_API, spreadsheet_id);
+    let data: Vec<Value> = ranges
+        .into_iter()
+        .map(|(range, values)| serde_json::json!({ "range": range, "values": values }))
+        .collect();
+    let client = http::default_client()?;
+    client
+        .post(&url)
+        .bearer_auth(token)
+        .json(&serde_json::json!({ "valueInputOption": "RAW", "data": data }))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+//test
+#[cfg(test)]
+mod tests {
+    use log::info;
+
+    use super::*;
+
+    lazy_static! {
+        static ref SETUP: () = {
+            env_logger::init();
+            let key = std::env::var("GOOGLE_KEY").expect("GOOGLE_KEY is not set in env");
+            init(&key).unwrap();
+        };
+    }
+
+    #[test]
+    fn test_values_url() {
+        assert_eq!(
+            values_url("abc", "machines!A1:C3"),
+            format!("{}/abc/values/machines!A1:C3", SHEETS_API)
+        );
+        assert_eq!(
+            values_url("abc", "矿机 A"),
+            format!("{}/abc/values/%E7%9F%BF%E6%9C%BA%20A", SHEETS_API)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs google credentials"]
+    async fn test_gsheets_query_sheet() {
+        let _ = &*SETUP;
+        let sheet_id = std::env::var("GOOGLE_SHEET").expect("GOOGLE_SHEET is not set in env");
+        let res = query_sheet(&sheet_id, "machines").await.unwrap();
+        info!("res: {:?}", res);
+        assert!(res["values"].is_array());
+    }
+}
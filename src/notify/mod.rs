@@ -1 +1,207 @@
+pub mod alerts;
+pub mod dingtalk;
+#[cfg(feature = "email")]
+pub mod email;
+pub mod escalation;
+#[cfg(feature = "feishu")]
 pub mod feishu;
+#[cfg(feature = "gsheets")]
+pub mod gsheets;
+pub mod notifier;
+pub mod slack;
+pub mod telegram;
+pub mod template;
+pub mod throttle;
+#[cfg(any(feature = "feishu", feature = "gsheets"))]
+mod token;
+pub mod webhook;
+pub mod wecom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::context::Local;
+use crate::error::MinerError;
+
+static SHEET_BACKEND: Local<SheetBackend> = Local::new(SheetBackend::default);
+
+/// alert severity, decides card color and whether on-call users are mentioned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+/// one affected machine listed in an alert
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlertMachine {
+    pub ip: String,
+    pub detail: String,
+}
+
+/// structured alert, rendered as card by chat channels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    /// what fired it, the template key of the title without ".title", e.g. "switch_failed",
+    /// empty for alerts of the embedding app
+    #[serde(default)]
+    pub kind: String,
+    pub title: String,
+    pub severity: Severity,
+    pub content: String,
+    pub machines: Vec<AlertMachine>,
+}
+
+impl Alert {
+    /// plain text for channels without rich format
+    pub fn to_text(&self) -> String {
+        let mut text = format!("[{:?}] {}", self.severity, self.title);
+        if !self.content.is_empty() {
+            text.push_str(&format!("\n{}", self.content));
+        }
+        for machine in self.machines.iter() {
+            text.push_str(&format!("\n{} {}", machine.ip, machine.detail));
+        }
+        text
+    }
+
+    /// the same alert raised again, the title is skipped as it carries the time
+    pub fn incident_key(&self) -> String {
+        let mut ips: Vec<&str> = self.machines.iter().map(|m| m.ip.as_str()).collect();
+        ips.sort();
+        if ips.is_empty() {
+            format!("{}|{}", self.kind, self.content)
+        } else {
+            format!("{}|{}", self.kind, ips.join(","))
+        }
+    }
+
+    /// markdown with links to machine web ui
+    pub fn to_markdown(&self) -> String {
+        let mut text = format!("### [{:?}] {}", self.severity, self.title);
+        if !self.content.is_empty() {
+            text.push_str(&format!("\n\n{}", self.content));
+        }
+        for machine in self.machines.iter() {
+            text.push_str(&format!(
+                "\n- [{}](http://{}) {}",
+                machine.ip, machine.ip, machine.detail
+            ));
+        }
+        text
+    }
+}
+
+/// where config sheets are loaded from
+#[derive(Debug, Clone, Default)]
+pub enum SheetBackend {
+    #[default]
+    Feishu,
+    /// google sheets, with path of the service account json key
+    Google(String),
+}
+
+#[cfg(not(feature = "feishu"))]
+fn feishu_disabled() -> MinerError {
+    MinerError::FeatureDisabledError("feishu".to_string())
+}
+
+#[cfg(not(feature = "gsheets"))]
+fn gsheets_disabled() -> MinerError {
+    MinerError::FeatureDisabledError("gsheets".to_string())
+}
+
+pub fn init_sheet_backend(backend: &SheetBackend) -> Result<(), MinerError> {
+    if let SheetBackend::Google(key_path) = backend {
+        #[cfg(feature = "gsheets")]
+        gsheets::init(key_path)?;
+        #[cfg(not(feature = "gsheets"))]
+        {
+            let _ = key_path;
+            return Err(gsheets_disabled());
+        }
+    }
+    SHEET_BACKEND.set(backend.clone());
+    Ok(())
+}
+
+/// query all rows of a sheet through the selected backend
+#[cfg_attr(
+    not(any(feature = "feishu", feature = "gsheets")),
+    allow(unused_variables, unreachable_code)
+)]
+pub async fn query_sheet_values(excel: &str, sheet: &str) -> Result<Vec<Value>, MinerError> {
+    let backend = SHEET_BACKEND.get();
+    let values = match backend {
+        #[cfg(feature = "feishu")]
+        SheetBackend::Feishu => {
+            let json_result = feishu::query_sheet(excel, sheet).await?;
+            json_result["data"]["valueRange"]["values"].clone()
+        }
+        #[cfg(not(feature = "feishu"))]
+        SheetBackend::Feishu => return Err(feishu_disabled()),
+        #[cfg(feature = "gsheets")]
+        SheetBackend::Google(_) => {
+            let json_result = gsheets::query_sheet(excel, sheet).await?;
+            json_result["values"].clone()
+        }
+        #[cfg(not(feature = "gsheets"))]
+        SheetBackend::Google(_) => return Err(gsheets_disabled()),
+    };
+
+    match values {
+        Value::Array(rows) => Ok(rows),
+        _ => Err(MinerError::FeishuParserJsonError),
+    }
+}
+
+/// write ranges through the selected backend, ranges as (A1 range, rows)
+#[cfg_attr(
+    not(any(feature = "feishu", feature = "gsheets")),
+    allow(unused_variables, unreachable_code)
+)]
+pub async fn update_sheet_ranges(
+    excel: &str,
+    ranges: Vec<(String, Vec<Vec<Value>>)>,
+) -> Result<(), MinerError> {
+    if ranges.is_empty() {
+        return Ok(());
+    }
+    let backend = SHEET_BACKEND.get();
+    match backend {
+        #[cfg(feature = "feishu")]
+        SheetBackend::Feishu => feishu::update_sheet_ranges(excel, ranges).await,
+        #[cfg(not(feature = "feishu"))]
+        SheetBackend::Feishu => Err(feishu_disabled()),
+        #[cfg(feature = "gsheets")]
+        SheetBackend::Google(_) => gsheets::update_sheet_ranges(excel, ranges).await,
+        #[cfg(not(feature = "gsheets"))]
+        SheetBackend::Google(_) => Err(gsheets_disabled()),
+    }
+}
+
+/// write one range through the selected backend
+#[cfg_attr(
+    not(any(feature = "feishu", feature = "gsheets")),
+    allow(unused_variables, unreachable_code)
+)]
+pub async fn update_sheet_range(
+    excel: &str,
+    range: &str,
+    values: Vec<Vec<Value>>,
+) -> Result<(), MinerError> {
+    let backend = SHEET_BACKEND.get();
+    match backend {
+        #[cfg(feature = "feishu")]
+        SheetBackend::Feishu => feishu::update_sheet_range(excel, range, values).await,
+        #[cfg(not(feature = "feishu"))]
+        SheetBackend::Feishu => Err(feishu_disabled()),
+        #[cfg(feature = "gsheets")]
+        SheetBackend::Google(_) => {
+            gsheets::update_sheet_ranges(excel, vec![(range.to_string(), values)]).await
+        }
+        #[cfg(not(feature = "gsheets"))]
+        SheetBackend::Google(_) => Err(gsheets_disabled()),
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[cfg(feature = "email")]
+use super::email::EmailNotifier;
+#[cfg(feature = "feishu")]
+use super::feishu::FeishuNotifier;
+use super::{
+    alerts, dingtalk::DingTalkNotifier, escalation, slack::SlackNotifier,
+    telegram::TelegramNotifier, template, throttle, webhook::WebhookNotifier, wecom::WeComNotifier,
+    Alert, Severity,
+};
+use crate::context;
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::miner::{group, maintenance};
+use crate::store::db;
+
+// failed sends per notifier name
+static ERRORS: Local<BTreeMap<String, u64>> = Local::new(BTreeMap::new);
+
+// define trait for general notify channel
+pub trait Notifier {
+    fn name(&self) -> &str;
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError>;
+}
+
+/// robots answer 200 with the error in the body, e.g. {"errcode":310000,"errmsg":"keywords not in content"}
+pub fn check_reply(res: &Value, code: &str, msg: &str) -> Result<(), MinerError> {
+    match res[code].as_i64() {
+        None | Some(0) => Ok(()),
+        Some(code) => Err(MinerError::WebhookError(format!(
+            "{} {}",
+            code,
+            res[msg].as_str().unwrap_or("")
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NotifierType {
+    #[cfg(feature = "feishu")]
+    Feishu(FeishuNotifier),
+    DingTalk(DingTalkNotifier),
+    WeCom(WeComNotifier),
+    Telegram(TelegramNotifier),
+    Slack(SlackNotifier),
+    Webhook(WebhookNotifier),
+    #[cfg(feature = "email")]
+    Email(EmailNotifier),
+}
+
+impl Notifier for NotifierType {
+    fn name(&self) -> &str {
+        match self {
+            #[cfg(feature = "feishu")]
+            NotifierType::Feishu(n) => n.name(),
+            NotifierType::DingTalk(n) => n.name(),
+            NotifierType::WeCom(n) => n.name(),
+            NotifierType::Telegram(n) => n.name(),
+            NotifierType::Slack(n) => n.name(),
+            NotifierType::Webhook(n) => n.name(),
+            #[cfg(feature = "email")]
+            NotifierType::Email(n) => n.name(),
+        }
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        match self {
+            #[cfg(feature = "feishu")]
+            NotifierType::Feishu(n) => n.send(alert).await,
+            NotifierType::DingTalk(n) => n.send(alert).await,
+            NotifierType::WeCom(n) => n.send(alert).await,
+            NotifierType::Telegram(n) => n.send(alert).await,
+            NotifierType::Slack(n) => n.send(alert).await,
+            NotifierType::Webhook(n) => n.send(alert).await,
+            #[cfg(feature = "email")]
+            NotifierType::Email(n) => n.send(alert).await,
+        }
+    }
+}
+
+/// one notify destination, receives alerts at or above min_severity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotifySink {
+    pub notifier: NotifierType,
+    pub min_severity: Severity,
+}
+
+impl NotifySink {
+    pub fn accepts(&self, severity: Severity) -> bool {
+        severity >= self.min_severity
+    }
+}
+
+pub fn set_sinks(sinks: Vec<NotifySink>) {
+    *context::current().sinks.lock().unwrap() = sinks;
+}
+
+pub fn add_sink(sink: NotifySink) {
+    context::current().sinks.lock().unwrap().push(sink);
+}
+
+/// failed sends per notifier name since start
+pub fn error_counts() -> BTreeMap<String, u64> {
+    ERRORS.get()
+}
+
+/// send alert to every sink routed for its severity, errors are logged per sink
+pub async fn send_alert(alert: &Alert) {
+    let mut alert = match maintenance::filter_alert(alert) {
+        Some(alert) => alert,
+        None => {
+            info!("alert only for machines in maintenance: {}", alert.title);
+            return;
+        }
+    };
+    // repeats refresh the incident even when the throttle drops them
+    escalation::observe(&alert);
+    if let Err(e) = alerts::record(&alert) {
+        error!("record alert error: {:?}", e);
+    }
+    match throttle::allow(&alert) {
+        None => {
+            info!("alert throttled: {}", alert.title);
+            return;
+        }
+        Some(suppressed) if suppressed > 0 => {
+            alert.content.push_str(&format!(
+                "\n{}",
+                template::render("alert.suppressed", &[("count", &suppressed)])
+            ));
+        }
+        _ => {}
+    }
+    let alert = &alert;
+
+    if let Err(e) = db::insert_event(db::EVENT_ALERT, "", &alert.title) {
+        error!("insert alert event error: {:?}", e);
+    }
+
+    let sinks: Vec<NotifySink> = context::current()
+        .sinks
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|sink| sink.accepts(alert.severity))
+        .cloned()
+        .collect();
+
+    let mut routed: Vec<(NotifySink, Alert)> = sinks
+        .into_iter()
+        .map(|sink| (sink, alert.clone()))
+        .collect();
+    // group sinks only see the machines of their group
+    for (group_sinks, group_alert) in group::group_alerts(alert) {
+        routed.extend(
+            group_sinks
+                .into_iter()
+                .filter(|sink| sink.accepts(alert.severity))
+                .map(|sink| (sink, group_alert.clone())),
+        );
+    }
+
+    let sends = routed.iter().map(|(sink, alert)| sink.notifier.send(alert));
+    let results = futures::future::join_all(sends).await;
+    for ((sink, _), result) in routed.iter().zip(results) {
+        if let Err(e) = result {
+            error!("notify {} error: {:?}", sink.notifier.name(), e);
+            ERRORS.with(|errors| *errors.entry(sink.notifier.name().to_string()).or_default() += 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_routing() {
+        let sink = NotifySink {
+            notifier: NotifierType::Slack(SlackNotifier {
+                webhook_url: "https://hooks.slack.com/services/x".to_string(),
+            }),
+            min_severity: Severity::Critical,
+        };
+        assert!(!sink.accepts(Severity::Info));
+        assert!(!sink.accepts(Severity::Warning));
+        assert!(sink.accepts(Severity::Critical));
+
+        let ok = serde_json::json!({"errcode": 0, "errmsg": "ok"});
+        assert!(check_reply(&ok, "errcode", "errmsg").is_ok());
+        let refused = serde_json::json!({"errcode": 310000, "errmsg": "keywords not in content"});
+        assert_eq!(
+            check_reply(&refused, "errcode", "errmsg")
+                .unwrap_err()
+                .code(),
+            8005
+        );
+        let refused = serde_json::json!({"code": 19021, "msg": "sign match fail"});
+        assert!(check_reply(&refused, "code", "msg").is_err());
+    }
+}
@@ -0,0 +1,29 @@
+/// slack incoming webhook
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{notifier::Notifier, Alert};
+use crate::error::MinerError;
+use crate::http;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlackNotifier {
+    pub webhook_url: String,
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        let client = http::default_client()?;
+        client
+            .post(&self.webhook_url)
+            .json(&json!({ "text": alert.to_text() }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+/// telegram bot api
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{notifier::Notifier, Alert};
+use crate::error::MinerError;
+use crate::http;
+use crate::secret::{self, SecretString};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramNotifier {
+    pub bot_token: SecretString,
+    pub chat_id: String,
+}
+
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        let client = http::default_client()?;
+        client
+            .post(format!(
+                "https://api.telegram.org/bot{}/sendMessage",
+                secret::resolve(self.bot_token.expose())?
+            ))
+            .json(&json!({
+                "chat_id": self.chat_id,
+                // plain text, markdown would need escaping of ip dots
+                "text": alert.to_text(),
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
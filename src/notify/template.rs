@@ -0,0 +1,299 @@
+/// texts of the alerts per locale, with "{name}" variables. a configured template replaces the
+/// built in text of its key in every locale
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::context::Local;
+
+static CONFIG: Local<TemplateConfig> = Local::new(TemplateConfig::default);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    Zh,
+    En,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TemplateConfig {
+    #[serde(default)]
+    pub locale: Locale,
+    /// key to template, e.g. "switch_failed.detail": "{position} {error}"
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+// key, zh, en. the variables of a key are the same in every locale
+const TEXTS: &[(&str, &str, &str)] = &[
+    (
+        "switch_failed.title",
+        "{time} 访问故障 {count}台",
+        "{time} unreachable {count} machines",
+    ),
+    ("switch_failed.detail", "{position}", "{position}"),
+    (
+        "crash_loop.title",
+        "{time} 反复重启 {count}台",
+        "{time} restarting over and over {count} machines",
+    ),
+    (
+        "crash_loop.detail",
+        "{polls}次采样内重启{restarts}次{action}",
+        "{restarts} restarts in {polls} polls{action}",
+    ),
+    ("crash_loop.failed", "调整失败", "adjust failed"),
+    (
+        "boards.title",
+        "{time} 算力板掉板 {count}台",
+        "{time} hash boards missing on {count} machines",
+    ),
+    (
+        "boards.detail",
+        "{model} 算力板 {active}/{expected}",
+        "{model} boards {active}/{expected}",
+    ),
+    (
+        "drift.title",
+        "{time} 配置偏离 {count}项",
+        "{time} config drift {count} items",
+    ),
+    ("drift.applied", "已修正 {count}台", "corrected {count} machines"),
+    ("drift.not_applied", "未修正", "not corrected"),
+    (
+        "thermal.title",
+        "{time} 温控调整 {count}台",
+        "{time} thermal adjust {count} machines",
+    ),
+    ("thermal.failed", "失败", "failed"),
+    (
+        "anomaly.title",
+        "{time} 算力低于基线 {count}台",
+        "{time} hashrate below baseline {count} machines",
+    ),
+    (
+        "anomaly.detail",
+        "{model} {current}/{baseline} THS 下降 {drop}%",
+        "{model} {current}/{baseline} THS down {drop}%",
+    ),
+    (
+        "ip_change.title",
+        "{time} 矿机IP变更 {count}台",
+        "{time} machine ip changed {count} machines",
+    ),
+    (
+        "ip_change.detail",
+        "{model} 原IP {from} MAC {mac} 矿工 {worker}",
+        "{model} was {from} mac {mac} worker {worker}",
+    ),
+    (
+        "zone_temp.title",
+        "{time} 区域平均温度过高 {count}处",
+        "{time} zone average temperature high in {count} zones",
+    ),
+    (
+        "zone_temp.detail",
+        "{level} {zone} 平均 {avg}℃ 超过 {threshold}℃ {count}台 最高 {max}℃",
+        "{level} {zone} average {avg}℃ above {threshold}℃ {count} machines max {max}℃",
+    ),
+    ("zone_temp.rack", "机架", "rack"),
+    ("zone_temp.room", "机房", "room"),
+    ("pool_down.title", "矿池不可达 {url}", "pool unreachable {url}"),
+    (
+        "pool_down.content",
+        "已切换到备用矿池",
+        "switched to the backup pools",
+    ),
+    ("pool_up.title", "矿池恢复 {url}", "pool recovered {url}"),
+    (
+        "pool_up.content",
+        "已恢复原矿池顺序",
+        "original pool order restored",
+    ),
+    (
+        "stale.title",
+        "{time} 矿池掉线 {count}台",
+        "{time} missing on the pool {count} machines",
+    ),
+    (
+        "stale.content",
+        "本地有算力但矿池缺失或为0, 请检查矿工名和矿池网络",
+        "hashing locally but missing or 0 on the pool, check the worker names and the pool network",
+    ),
+    (
+        "stale.expired",
+        "矿池记录过期 本地 {hash} THS",
+        "pool record expired, local {hash} THS",
+    ),
+    (
+        "stale.zero",
+        "矿池算力为0 本地 {hash} THS",
+        "0 on the pool, local {hash} THS",
+    ),
+    (
+        "stale.missing",
+        "矿池无此矿工 本地 {hash} THS",
+        "worker not on the pool, local {hash} THS",
+    ),
+    (
+        "proxy.title",
+        "{time} 矿池代理故障 {count}个",
+        "{time} stratum proxies down {count}",
+    ),
+    (
+        "proxy.no_status",
+        "{name} 状态接口无响应",
+        "{name} status api not answering",
+    ),
+    (
+        "proxy.upstreams_down",
+        "{name} 上游矿池全部断开",
+        "{name} all upstream pools disconnected",
+    ),
+    (
+        "report.title",
+        "运行报告 {start} ~ {end}",
+        "report {start} ~ {end}",
+    ),
+    (
+        "report.content",
+        "机器: {machines}台\n平均总算力: {hash} THS\n停机总时长: {downtime} 分钟\n切换次数: {switches}\n告警次数: {alerts}\n表现最差:",
+        "machines: {machines}\naverage fleet hashrate: {hash} THS\ntotal downtime: {downtime} minutes\nswitches: {switches}\nalerts: {alerts}\nworst:",
+    ),
+    (
+        "report.detail",
+        "{model} 均值 {avg}/峰值 {peak} THS 停机 {downtime} 分钟 拒绝 {rejected}%",
+        "{model} avg {avg}/peak {peak} THS down {downtime} minutes rejected {rejected}%",
+    ),
+    (
+        "report.alerts",
+        "{content}\n告警最多: {machines}\n平均恢复: {mttr} 分钟",
+        "{content}\nmost alerts: {machines}\nmean time to recovery: {mttr} minutes",
+    ),
+    (
+        "report_header.machines",
+        "机器",
+        "machines",
+    ),
+    (
+        "report_header.fleet_hash",
+        "平均总算力(THS)",
+        "fleet hashrate (THS)",
+    ),
+    ("report_header.switches", "切换次数", "switches"),
+    ("report_header.alerts", "告警次数", "alerts"),
+    ("report_header.model", "类型", "model"),
+    ("report_header.avg", "均值(THS)", "avg (THS)"),
+    ("report_header.peak", "峰值(THS)", "peak (THS)"),
+    (
+        "report_header.downtime",
+        "停机(分钟)",
+        "downtime (minutes)",
+    ),
+    ("report_header.samples", "采样", "samples"),
+    ("report_header.rejected", "拒绝率(%)", "rejected (%)"),
+    ("report_header.hw_errors", "硬件错误", "hw errors"),
+    ("report_header.alert_ip", "告警IP", "alerting ip"),
+    ("report_header.alert_kinds", "告警", "alerts"),
+    ("report_header.raised", "触发次数", "raised"),
+    ("report_header.resolved", "已恢复", "resolved"),
+    (
+        "report_header.mttr",
+        "平均恢复(分钟)",
+        "mean recovery (minutes)",
+    ),
+    (
+        "alert.suppressed",
+        "(已抑制 {count} 条重复或超限告警)",
+        "({count} repeated or over limit alerts suppressed)",
+    ),
+    ("alert.detail", "信息", "detail"),
+    ("sheet.unreachable", "访问故障", "unreachable"),
+    (
+        "escalation.title",
+        "[{minutes}分钟未处理] {title}",
+        "[unacknowledged {minutes} minutes] {title}",
+    ),
+    (
+        "escalation.content",
+        "{content}\n事件 {id}, 确认后停止升级",
+        "{content}\nincident {id}, acknowledge it to stop the escalation",
+    ),
+];
+
+/// name and value of a variable, Sync so a render can sit in a spawned future
+pub type Var<'a> = (&'a str, &'a (dyn Display + Sync));
+
+pub fn set_config(config: TemplateConfig) {
+    CONFIG.set(config);
+}
+
+/// text of the key in the configured locale, an unknown key is rendered as itself
+pub fn render(key: &str, vars: &[Var]) -> String {
+    CONFIG.with(|config| render_with(config, key, vars))
+}
+
+fn render_with(config: &TemplateConfig, key: &str, vars: &[Var]) -> String {
+    let template = match config.templates.get(key) {
+        Some(template) => template.as_str(),
+        None => TEXTS
+            .iter()
+            .find(|(k, _, _)| *k == key)
+            .map(|(_, zh, en)| match config.locale {
+                Locale::Zh => *zh,
+                Locale::En => *en,
+            })
+            .unwrap_or(key),
+    };
+    vars.iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let mut config = TemplateConfig::default();
+        let vars: &[Var] = &[("time", &"08:00:00"), ("count", &3)];
+        assert_eq!(
+            render_with(&config, "switch_failed.title", vars),
+            "08:00:00 访问故障 3台"
+        );
+
+        config.locale = Locale::En;
+        assert_eq!(
+            render_with(&config, "switch_failed.title", vars),
+            "08:00:00 unreachable 3 machines"
+        );
+
+        config.templates.insert(
+            "switch_failed.detail".to_string(),
+            "{position}: {error}".to_string(),
+        );
+        let vars: &[Var] = &[("position", &"A1"), ("error", &"timeout")];
+        assert_eq!(
+            render_with(&config, "switch_failed.detail", vars),
+            "A1: timeout"
+        );
+        assert_eq!(render_with(&config, "no.such.key", vars), "no.such.key");
+
+        // every key has the same variables in both locales
+        let names = |text: &str| {
+            let mut names: Vec<String> = text
+                .split('{')
+                .skip(1)
+                .filter_map(|s| s.split('}').next().map(|s| s.to_string()))
+                .collect();
+            names.sort();
+            names
+        };
+        for (key, zh, en) in TEXTS {
+            assert_eq!(names(zh), names(en), "{}", key);
+        }
+    }
+}
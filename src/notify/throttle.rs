@@ -0,0 +1,201 @@
+/// dedup and rate limit for outgoing alerts
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Alert, AlertMachine};
+
+use crate::context::Local;
+
+static THROTTLE: Local<Throttle> = Local::new(|| Throttle::new(ThrottleConfig::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottleConfig {
+    /// identical alerts within this window are dropped
+    pub dedup_window_seconds: i64,
+    /// global cap of sent alerts in the last hour, 0 for unlimited
+    pub max_per_hour: usize,
+    /// consecutive failed cycles before a machine shows up in the failure digest
+    pub fail_threshold: u32,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig {
+            dedup_window_seconds: 600,
+            max_per_hour: 30,
+            fail_threshold: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Throttle {
+    config: ThrottleConfig,
+    sent: VecDeque<i64>,
+    recent: HashMap<String, i64>,
+    fail_counts: HashMap<String, u32>,
+    suppressed: usize,
+}
+
+impl Throttle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Throttle {
+            config,
+            sent: VecDeque::new(),
+            recent: HashMap::new(),
+            fail_counts: HashMap::new(),
+            suppressed: 0,
+        }
+    }
+
+    /// check alert against dedup window and hourly cap, record it when allowed.
+    /// returns count of alerts suppressed since the last allowed one.
+    pub fn allow(&mut self, alert: &Alert, now: i64) -> Option<usize> {
+        let window = self.config.dedup_window_seconds;
+        self.recent.retain(|_, time| now - *time < window);
+        while let Some(time) = self.sent.front() {
+            if now - *time >= 3600 {
+                self.sent.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let key = alert.dedup_key();
+        if self.recent.contains_key(&key) {
+            self.suppressed += 1;
+            return None;
+        }
+        if self.config.max_per_hour > 0 && self.sent.len() >= self.config.max_per_hour {
+            self.suppressed += 1;
+            return None;
+        }
+
+        self.recent.insert(key, now);
+        self.sent.push_back(now);
+        let suppressed = self.suppressed;
+        self.suppressed = 0;
+        Some(suppressed)
+    }
+
+    /// count failures per ip across cycles, return machines reaching the threshold.
+    /// ips not failed this cycle are reset.
+    pub fn filter_failures(&mut self, failed: Vec<AlertMachine>) -> Vec<AlertMachine> {
+        let threshold = self.config.fail_threshold.max(1);
+        self.fail_counts
+            .retain(|ip, _| failed.iter().any(|m| &m.ip == ip));
+
+        let mut selected = vec![];
+        for machine in failed {
+            let count = self.fail_counts.entry(machine.ip.clone()).or_insert(0);
+            *count += 1;
+            if *count >= threshold {
+                *count = 0;
+                selected.push(machine);
+            }
+        }
+        selected
+    }
+}
+
+impl Alert {
+    /// identical alerts share the key, the severity and the incident. alerts of the embedding
+    /// app carry no kind and go by their title
+    pub fn dedup_key(&self) -> String {
+        if self.kind.is_empty() {
+            format!("{:?}|{}|{}", self.severity, self.title, self.incident_key())
+        } else {
+            format!("{:?}|{}", self.severity, self.incident_key())
+        }
+    }
+}
+
+pub fn set_config(config: ThrottleConfig) {
+    THROTTLE.set(Throttle::new(config));
+}
+
+pub fn allow(alert: &Alert) -> Option<usize> {
+    THROTTLE.with(|throttle| throttle.allow(alert, chrono::Local::now().timestamp()))
+}
+
+pub fn filter_failures(failed: Vec<AlertMachine>) -> Vec<AlertMachine> {
+    THROTTLE.with(|throttle| throttle.filter_failures(failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Severity;
+    use super::*;
+
+    fn alert(ips: &[&str]) -> Alert {
+        Alert {
+            kind: "thermal".to_string(),
+            title: "t".to_string(),
+            severity: Severity::Warning,
+            content: "".to_string(),
+            machines: ips
+                .iter()
+                .map(|ip| AlertMachine {
+                    ip: ip.to_string(),
+                    detail: "".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_dedup_and_cap() {
+        let mut throttle = Throttle::new(ThrottleConfig {
+            dedup_window_seconds: 600,
+            max_per_hour: 2,
+            fail_threshold: 1,
+        });
+        assert_eq!(throttle.allow(&alert(&["1.1.1.1"]), 0), Some(0));
+        assert_eq!(throttle.allow(&alert(&["1.1.1.1"]), 100), None);
+        assert_eq!(throttle.allow(&alert(&["1.1.1.2"]), 200), Some(1));
+        // hourly cap reached
+        assert_eq!(throttle.allow(&alert(&["1.1.1.3"]), 300), None);
+        // window passed, both dedup and cap free again
+        assert_eq!(throttle.allow(&alert(&["1.1.1.1"]), 3700), Some(1));
+    }
+
+    #[test]
+    fn test_dedup_by_kind() {
+        let mut throttle = Throttle::new(ThrottleConfig::default());
+        let thermal = alert(&["1.1.1.1"]);
+        let boards = Alert {
+            kind: "boards".to_string(),
+            ..alert(&["1.1.1.1"])
+        };
+        assert_eq!(throttle.allow(&thermal, 0), Some(0));
+        assert_eq!(throttle.allow(&boards, 10), Some(0));
+
+        // the time in the title does not make it another alert
+        let proxy = |time: &str| Alert {
+            kind: "proxy".to_string(),
+            title: format!("{} 矿池代理故障 1个", time),
+            content: "proxy-a".to_string(),
+            ..alert(&[])
+        };
+        assert_eq!(throttle.allow(&proxy("08:00:00"), 20), Some(0));
+        assert_eq!(throttle.allow(&proxy("08:01:00"), 80), None);
+    }
+
+    #[test]
+    fn test_filter_failures() {
+        let mut throttle = Throttle::new(ThrottleConfig {
+            fail_threshold: 3,
+            ..Default::default()
+        });
+        let failed = alert(&["1.1.1.1", "1.1.1.2"]).machines;
+        assert!(throttle.filter_failures(failed.clone()).is_empty());
+        assert!(throttle
+            .filter_failures(alert(&["1.1.1.1"]).machines)
+            .is_empty());
+        // 1.1.1.2 recovered in between, only 1.1.1.1 reaches 3
+        let selected = throttle.filter_failures(failed);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].ip, "1.1.1.1");
+    }
+}
@@ -0,0 +1,90 @@
+/// access token cache of the sheet apis, refreshed when missing or about to expire
+use std::future::Future;
+
+use crate::error::MinerError;
+
+// refresh token this many seconds before it expires
+const TOKEN_REFRESH_AHEAD: i64 = 300;
+
+#[derive(Debug, Clone)]
+pub struct CachedToken {
+    pub token: String,
+    pub expire_at: i64,
+}
+
+impl CachedToken {
+    /// token expiring in the seconds from now
+    pub fn new(token: String, expires_in: i64) -> Self {
+        CachedToken {
+            token,
+            expire_at: chrono::Local::now().timestamp() + expires_in,
+        }
+    }
+
+    fn is_fresh(&self, now: i64) -> bool {
+        now + TOKEN_REFRESH_AHEAD < self.expire_at
+    }
+}
+
+/// new credentials replace the whole cache, a refresh of the old ones ends in the dropped one
+#[derive(Default)]
+pub struct TokenCache {
+    // async lock so only one refresh is in flight, others wait for its result
+    token: tokio::sync::Mutex<Option<CachedToken>>,
+}
+
+impl TokenCache {
+    /// cached token, requested when missing or about to expire
+    pub async fn get<F>(&self, request: impl FnOnce() -> F) -> Result<String, MinerError>
+    where
+        F: Future<Output = Result<CachedToken, MinerError>>,
+    {
+        let mut cached = self.token.lock().await;
+        let now = chrono::Local::now().timestamp();
+        if let Some(token) = cached.as_ref() {
+            if token.is_fresh(now) {
+                return Ok(token.token.clone());
+            }
+        }
+
+        let token = request().await?;
+        let value = token.token.clone();
+        *cached = Some(token);
+        Ok(value)
+    }
+
+    /// drop a token the api reported invalid
+    #[cfg_attr(not(feature = "feishu"), allow(dead_code))]
+    pub async fn clear(&self) {
+        *self.token.lock().await = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_cache() {
+        let token = CachedToken {
+            token: "t".to_string(),
+            expire_at: 1000,
+        };
+        assert!(token.is_fresh(1000 - TOKEN_REFRESH_AHEAD - 1));
+        assert!(!token.is_fresh(1000 - TOKEN_REFRESH_AHEAD));
+
+        let cache = TokenCache::default();
+        let request = |token: &'static str| {
+            move || async move { Ok(CachedToken::new(token.to_string(), 3600)) }
+        };
+        assert_eq!(cache.get(request("a")).await.unwrap(), "a");
+        assert_eq!(cache.get(request("b")).await.unwrap(), "a");
+        cache.clear().await;
+        assert_eq!(cache.get(request("b")).await.unwrap(), "b");
+        // about to expire
+        let expiring = || async { Ok(CachedToken::new("c".to_string(), TOKEN_REFRESH_AHEAD)) };
+        cache.clear().await;
+        assert_eq!(cache.get(expiring).await.unwrap(), "c");
+        assert_eq!(cache.get(request("d")).await.unwrap(), "d");
+    }
+}
@@ -0,0 +1,28 @@
+/// generic webhook, posts the alert as json
+use serde::{Deserialize, Serialize};
+
+use super::{notifier::Notifier, Alert};
+use crate::error::MinerError;
+use crate::http;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookNotifier {
+    pub url: String,
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        let client = http::default_client()?;
+        client
+            .post(&self.url)
+            .json(alert)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
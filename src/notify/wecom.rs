@@ -0,0 +1,38 @@
+/// wecom (企业微信) group robot
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::notifier::{self, Notifier};
+use super::Alert;
+use crate::error::MinerError;
+use crate::http;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeComNotifier {
+    pub key: String,
+}
+
+impl Notifier for WeComNotifier {
+    fn name(&self) -> &str {
+        "wecom"
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), MinerError> {
+        let client = http::default_client()?;
+        let res: Value = client
+            .post(format!(
+                "https://qyapi.weixin.qq.com/cgi-bin/webhook/send?key={}",
+                self.key
+            ))
+            .json(&json!({
+                "msgtype": "markdown",
+                "markdown": { "content": alert.to_markdown() }
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        notifier::check_reply(&res, "errcode", "errmsg")
+    }
+}
@@ -0,0 +1,272 @@
+/// antpool.com api query, signed api key or observer link
+use hmac::{Hmac, Mac};
+use log::info;
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+
+use super::pool::{Pool, PoolWorker};
+use crate::error::MinerError;
+use crate::http;
+use crate::secret::SecretString;
+
+/// antpool api key, created in account settings, empty user_id to disable
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AntpoolAccount {
+    pub user_id: String,
+    pub api_key: SecretString,
+    pub api_secret: SecretString,
+}
+
+pub struct Antpool {
+    api_url: String,
+    user_id: String,
+    api_key: String,
+    api_secret: String,
+    // observer access key, used instead of signing when not empty
+    access_key: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AntpoolWorker {
+    pub name: String,
+    /// TH/s, antpool has no 15m window, last 10m is used
+    pub hash_15m: f64,
+    /// TH/s
+    pub hash_24h: f64,
+}
+
+impl From<AntpoolWorker> for PoolWorker {
+    fn from(aw: AntpoolWorker) -> Self {
+        PoolWorker {
+            schema_version: Default::default(),
+            name: aw.name,
+            hash_real: aw.hash_15m,
+            hash_avg: aw.hash_24h,
+            time_stamp: chrono::Local::now().timestamp(),
+            pool_type: "antpool".to_string(),
+            account: "".to_string(),
+        }
+    }
+}
+
+impl Antpool {
+    pub fn from_account(account: &AntpoolAccount) -> Antpool {
+        Antpool {
+            api_url: "https://antpool.com/api/workers.htm".to_string(),
+            user_id: account.user_id.clone(),
+            api_key: account.api_key.expose().to_string(),
+            api_secret: account.api_secret.expose().to_string(),
+            access_key: "".to_string(),
+        }
+    }
+
+    pub fn from_watcher(watcher: &str) -> Result<Antpool, MinerError> {
+        // https://www.antpool.com/observer?accessKey=xxxx&coinType=BTC&observerUserId=yyyy
+        let access_key = Regex::new(r"accessKey=([^&]+)")
+            .unwrap()
+            .captures(watcher)
+            .and_then(|caps| caps.get(1))
+            .ok_or(MinerError::AntpoolApiRegexError)?;
+        let user_id = Regex::new(r"observerUserId=([^&]+)")
+            .unwrap()
+            .captures(watcher)
+            .and_then(|caps| caps.get(1))
+            .ok_or(MinerError::AntpoolApiRegexError)?;
+
+        Ok(Antpool {
+            api_url: "https://www.antpool.com/auth/v3/observer/api/worker/list".to_string(),
+            user_id: user_id.as_str().to_string(),
+            api_key: "".to_string(),
+            api_secret: "".to_string(),
+            access_key: access_key.as_str().to_string(),
+        })
+    }
+
+    /// upper hex of hmac-sha256(user_id + api_key + nonce)
+    fn sign(&self, nonce: i64) -> Result<String, MinerError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.api_secret.as_bytes())
+            .map_err(|_| MinerError::AuthError)?;
+        mac.update(format!("{}{}{}", self.user_id, self.api_key, nonce).as_bytes());
+        Ok(mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect())
+    }
+
+    /// returns workers of the page and total page count
+    async fn query_page(
+        &self,
+        client: &Client,
+        page: i32,
+        page_size: i32,
+    ) -> Result<(Vec<AntpoolWorker>, i32), MinerError> {
+        if !self.access_key.is_empty() {
+            let resp = client
+                .get(&self.api_url)
+                .query(&[
+                    ("accessKey", self.access_key.clone()),
+                    ("coinType", "BTC".to_string()),
+                    ("observerUserId", self.user_id.clone()),
+                    ("workerStatus", "0".to_string()),
+                    ("pageNum", page.to_string()),
+                    ("pageSize", page_size.to_string()),
+                ])
+                .timeout(std::time::Duration::from_secs(10))
+                .send()
+                .await?
+                .json::<Value>()
+                .await?;
+            if resp["code"].as_str() != Some("000000") {
+                return Err(MinerError::AntpoolApiRequestError);
+            }
+            return Ok(parse_observer_page(&resp["data"]));
+        }
+
+        let nonce = chrono::Local::now().timestamp_millis();
+        let resp = client
+            .post(&self.api_url)
+            .form(&[
+                ("key", self.api_key.clone()),
+                ("nonce", nonce.to_string()),
+                ("signature", self.sign(nonce)?),
+                ("coin", "BTC".to_string()),
+                ("pageEnable", "1".to_string()),
+                ("page", page.to_string()),
+                ("pageSize", page_size.to_string()),
+            ])
+            .timeout(std::time::Duration::from_secs(10))
+            .send()
+            .await?
+            .json::<Value>()
+            .await?;
+        if resp["code"].as_i64() != Some(0) {
+            return Err(MinerError::AntpoolApiRequestError);
+        }
+        Ok(parse_api_page(&resp["data"]))
+    }
+}
+
+impl Pool for Antpool {
+    async fn query(&self, proxy: &str) -> Result<Vec<PoolWorker>, MinerError> {
+        let client = http::client(proxy)?;
+
+        let mut workers = vec![];
+        let page_size = 100;
+        let mut page = 1;
+        loop {
+            let (page_workers, page_count) = self.query_page(&client, page, page_size).await?;
+            workers.extend(page_workers.into_iter().map(PoolWorker::from));
+
+            info!("antpool page: {}, page_count: {}", page, page_count);
+            if page >= page_count {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(workers)
+    }
+}
+
+// number or numeric string
+fn value_f64(value: &Value) -> f64 {
+    match value {
+        Value::Number(n) => n.as_f64().unwrap_or(0.0),
+        Value::String(s) => s.trim().parse::<f64>().unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+// "95.12 TH/s" style hashrate to TH/s
+fn hashrate_th(value: &Value) -> f64 {
+    let s = match value {
+        Value::String(s) => s.trim(),
+        _ => return value_f64(value) / 1000000000000.0,
+    };
+    let (num, unit) = s.split_at(
+        s.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len()),
+    );
+    let num = num.parse::<f64>().unwrap_or(0.0);
+    match unit.trim().chars().next() {
+        Some('E') => num * 1000000.0,
+        Some('P') => num * 1000.0,
+        Some('T') => num,
+        Some('G') => num / 1000.0,
+        Some('M') => num / 1000000.0,
+        _ => num / 1000000000000.0,
+    }
+}
+
+/// signed api page, hashrate in MH/s
+fn parse_api_page(data: &Value) -> (Vec<AntpoolWorker>, i32) {
+    let workers = data["rows"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|row| AntpoolWorker {
+            name: row["worker"].as_str().unwrap_or_default().to_string(),
+            hash_15m: value_f64(&row["last10m"]) / 1000000.0,
+            hash_24h: value_f64(&row["last1d"]) / 1000000.0,
+        })
+        .collect();
+    (workers, value_f64(&data["totalPage"]) as i32)
+}
+
+/// observer page, hashrate as text with unit
+fn parse_observer_page(data: &Value) -> (Vec<AntpoolWorker>, i32) {
+    let workers = data["items"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|item| AntpoolWorker {
+            name: item["workerId"].as_str().unwrap_or_default().to_string(),
+            hash_15m: hashrate_th(&item["hsLast10min"]),
+            hash_24h: hashrate_th(&item["hsLast1d"]),
+        })
+        .collect();
+    (workers, value_f64(&data["totalPage"]) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_antpool_from_watcher() {
+        let antpool = Antpool::from_watcher(
+            "https://www.antpool.com/observer?accessKey=abcDEF123&coinType=BTC&observerUserId=lcd01",
+        )
+        .unwrap();
+        assert_eq!(antpool.access_key, "abcDEF123");
+        assert_eq!(antpool.user_id, "lcd01");
+        assert!(Antpool::from_watcher("https://www.antpool.com/observer").is_err());
+    }
+
+    #[test]
+    fn test_antpool_parse_page() {
+        let (workers, pages) = parse_api_page(&json!({
+            "page": 1,
+            "totalPage": 3,
+            "rows": [{"worker": "lcd01.188x41", "last10m": "95000000", "last1d": 90500000.0}]
+        }));
+        assert_eq!(pages, 3);
+        assert_eq!(workers[0].name, "lcd01.188x41");
+        assert_eq!(workers[0].hash_15m, 95.0);
+        assert_eq!(workers[0].hash_24h, 90.5);
+
+        let (workers, pages) = parse_observer_page(&json!({
+            "totalPage": 1,
+            "items": [{"workerId": "lcd01.188x42", "hsLast10min": "1.2 PH/s", "hsLast1d": "850 GH/s"}]
+        }));
+        assert_eq!(pages, 1);
+        assert_eq!(workers[0].hash_15m, 1200.0);
+        assert_eq!(workers[0].hash_24h, 0.85);
+    }
+}
@@ -1,15 +1,17 @@
 use log::info;
-use reqwest::{header, Client};
+use reqwest::header;
 use serde::{Deserialize, Serialize};
 
 use crate::error::MinerError;
+use crate::http;
+use crate::secret::SecretString;
 
-use super::pool::{Pool, PoolWorker};
+use super::pool::{Pool, PoolEarning, PoolWorker, EARNING_PAYOUT, EARNING_REVENUE};
 
 pub struct F2pool {
     api_url: String,
     account: String,
-    secret: String,
+    secret: SecretString,
 }
 
 /**
@@ -72,36 +74,26 @@ impl From<serde_json::Value> for F2poolWorker {
 impl From<F2poolWorker> for PoolWorker {
     fn from(fw: F2poolWorker) -> Self {
         PoolWorker {
+            schema_version: Default::default(),
             name: fw.name,
             hash_real: fw.hash_rate,
             hash_avg: fw.h1_hash_rate,
             time_stamp: fw.time_stamp,
             pool_type: "f2pool".to_string(),
+            account: "".to_string(),
         }
     }
 }
 
 impl Pool for F2pool {
     async fn query(&self, proxy: &str) -> Result<Vec<PoolWorker>, MinerError> {
-        let client: Client;
-        if !proxy.is_empty() {
-            // if proxy not start with http, add it
-            let proxy = if proxy.starts_with("http") {
-                proxy.to_string()
-            } else {
-                format!("http://{}", proxy)
-            };
-            let proxy = reqwest::Proxy::all(proxy).unwrap();
-            client = Client::builder().proxy(proxy).build()?;
-        } else {
-            client = Client::new();
-        }
+        let client = http::client(proxy)?;
 
         info!("query f2pool workers: {}/{}", self.api_url, self.account);
         let resp = client
             .get(format!("{}/{}/{}", self.api_url, "bitcoin", self.account))
             .header(header::CONTENT_TYPE, "application/json")
-            .header("F2P-API-SECRET", &self.secret)
+            .header("F2P-API-SECRET", self.secret.expose())
             .timeout(std::time::Duration::from_secs(10))
             .send()
             .await?;
@@ -121,6 +113,74 @@ impl Pool for F2pool {
 
         Ok(workers)
     }
+
+    async fn query_earnings(
+        &self,
+        proxy: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Vec<PoolEarning>, MinerError> {
+        let client = http::client(proxy)?;
+
+        let mut earnings = vec![];
+        for kind in [EARNING_REVENUE, EARNING_PAYOUT] {
+            let resp = client
+                .post(format!("{}/v2/assets/transactions/list", self.api_url))
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("F2P-API-SECRET", self.secret.expose())
+                .json(&serde_json::json!({
+                    "currency": "bitcoin",
+                    "mining_user_name": self.account,
+                    "type": kind,
+                    "start_time": start_time,
+                    "end_time": end_time,
+                }))
+                .timeout(std::time::Duration::from_secs(10))
+                .send()
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+            earnings.extend(parse_transactions(&resp, kind));
+        }
+
+        info!("f2pool earnings: {}/{}", self.account, earnings.len());
+        Ok(earnings)
+    }
+}
+
+fn format_date(time: i64) -> String {
+    chrono::DateTime::from_timestamp(time, 0)
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// v2 transactions list, revenue dated by mining date, payout by created time
+fn parse_transactions(resp: &serde_json::Value, kind: &str) -> Vec<PoolEarning> {
+    resp.get("transactions")
+        .and_then(|v| v.as_array())
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|t| {
+            let created_at = t["created_at"].as_i64().unwrap_or_default();
+            let date = t["mining_extra"]["mining_date"]
+                .as_i64()
+                .unwrap_or(created_at);
+            PoolEarning {
+                pool_type: "f2pool".to_string(),
+                account: "".to_string(),
+                kind: kind.to_string(),
+                date: format_date(date),
+                amount: t["changed_balance"].as_f64().unwrap_or_default().abs(),
+                hash_rate: t["mining_extra"]["hash_rate"].as_f64().unwrap_or_default()
+                    / 1000000000000.0,
+                tx_id: t["payout_extra"]["tx_id"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                time_stamp: created_at,
+            }
+        })
+        .collect()
 }
 
 impl F2pool {
@@ -132,7 +192,7 @@ impl F2pool {
         F2pool {
             api_url: "https://api.f2pool.com".to_string(),
             account,
-            secret,
+            secret: secret.into(),
         }
     }
 }
@@ -149,7 +209,26 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_f2pool_parse_transactions() {
+        let resp = serde_json::json!({
+            "transactions": [{
+                "id": 1,
+                "type": "revenue",
+                "changed_balance": 0.0123,
+                "created_at": 1715731200,
+                "mining_extra": {"mining_date": 1715644800, "hash_rate": 95000000000000.0}
+            }]
+        });
+        let earnings = parse_transactions(&resp, EARNING_REVENUE);
+        assert_eq!(earnings.len(), 1);
+        assert_eq!(earnings[0].date, "2024-05-14");
+        assert_eq!(earnings[0].amount, 0.0123);
+        assert_eq!(earnings[0].hash_rate, 95.0);
+    }
+
     #[tokio::test]
+    #[ignore = "needs network access to the pool"]
     async fn test_f2pool_query() {
         let _ = *SETUP;
 
@@ -0,0 +1,262 @@
+/// stratum endpoint health, unreachable primaries are moved behind healthy backups
+use std::collections::HashMap;
+use std::time::Duration;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::context::Local;
+use crate::notify::{notifier, template, Alert, Severity};
+
+static HEALTH: Local<PoolHealth> = Local::new(|| PoolHealth::new(PoolHealthConfig::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolHealthConfig {
+    /// probe pools and reorder them on switch, off by default
+    pub enabled: bool,
+    /// endpoint unreachable this long is moved behind backups, seconds
+    pub failover_seconds: i64,
+    /// endpoint reachable again this long is restored to its place, seconds
+    pub recover_seconds: i64,
+    /// connect and subscribe timeout of one probe
+    pub timeout_seconds: u64,
+}
+
+impl Default for PoolHealthConfig {
+    fn default() -> Self {
+        PoolHealthConfig {
+            enabled: false,
+            failover_seconds: 300,
+            recover_seconds: 600,
+            timeout_seconds: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolEndpointStatus {
+    pub url: String,
+    /// used in pool ordering
+    pub healthy: bool,
+    /// result of the last probe
+    pub reachable: bool,
+    /// time the last probe result started
+    pub since: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthChange {
+    Down,
+    Up,
+}
+
+#[derive(Debug)]
+pub struct PoolHealth {
+    config: PoolHealthConfig,
+    endpoints: HashMap<String, PoolEndpointStatus>,
+}
+
+impl PoolHealth {
+    pub fn new(config: PoolHealthConfig) -> Self {
+        PoolHealth {
+            config,
+            endpoints: HashMap::new(),
+        }
+    }
+
+    /// record a probe result, returns the change once it lasted long enough
+    pub fn update(&mut self, url: &str, reachable: bool, now: i64) -> Option<HealthChange> {
+        let status = self
+            .endpoints
+            .entry(url.to_string())
+            .or_insert(PoolEndpointStatus {
+                url: url.to_string(),
+                healthy: true,
+                reachable: true,
+                since: now,
+            });
+        if status.reachable != reachable {
+            status.reachable = reachable;
+            status.since = now;
+        }
+
+        if status.healthy && !reachable && now - status.since >= self.config.failover_seconds {
+            status.healthy = false;
+            return Some(HealthChange::Down);
+        }
+        if !status.healthy && reachable && now - status.since >= self.config.recover_seconds {
+            status.healthy = true;
+            return Some(HealthChange::Up);
+        }
+        None
+    }
+
+    pub fn is_healthy(&self, url: &str) -> bool {
+        self.endpoints.get(url).map(|s| s.healthy).unwrap_or(true)
+    }
+
+    /// healthy pools first, original order kept otherwise
+    pub fn order(&self, pools: &[String]) -> Vec<String> {
+        let mut ordered: Vec<String> = pools
+            .iter()
+            .filter(|p| self.is_healthy(p))
+            .cloned()
+            .collect();
+        ordered.extend(pools.iter().filter(|p| !self.is_healthy(p)).cloned());
+        ordered
+    }
+}
+
+/// same stratum endpoint regardless of scheme prefix, case and trailing slash
+pub fn same_endpoint(a: &str, b: &str) -> bool {
+    endpoint(a).eq_ignore_ascii_case(endpoint(b))
+}
+
+fn endpoint(url: &str) -> &str {
+    let url = url.trim();
+    let url = match url.find("://") {
+        Some(pos) => &url[pos + 3..],
+        None => url,
+    };
+    url.trim_end_matches('/')
+}
+
+/// tcp connect then mining.subscribe, any json reply without error counts as reachable
+pub async fn probe(url: &str, timeout_seconds: u64) -> bool {
+    let addr = endpoint(url).to_string();
+    if addr.is_empty() {
+        return false;
+    }
+
+    let handshake = async {
+        let mut stream = TcpStream::connect(&addr).await?;
+        stream
+            .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[]}\n")
+            .await?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        Ok::<String, std::io::Error>(line)
+    };
+
+    match tokio::time::timeout(Duration::from_secs(timeout_seconds), handshake).await {
+        Ok(Ok(line)) => serde_json::from_str::<Value>(&line)
+            .map(|v| v["error"].is_null())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+pub fn set_config(config: PoolHealthConfig) {
+    HEALTH.set(PoolHealth::new(config));
+}
+
+pub fn status() -> Vec<PoolEndpointStatus> {
+    let mut status: Vec<PoolEndpointStatus> =
+        HEALTH.with(|health| health.endpoints.values().cloned().collect());
+    status.sort_by(|a, b| a.url.cmp(&b.url));
+    status
+}
+
+/// probe every pool of the pool sheet and reorder each pool list by health.
+/// failover and recovery are notified, recovered pools return to their place.
+pub async fn apply(pools_map: &mut HashMap<String, Vec<String>>) {
+    let config = HEALTH.with(|health| health.config.clone());
+    if !config.enabled {
+        return;
+    }
+
+    let mut urls: Vec<String> = pools_map
+        .values()
+        .flatten()
+        .filter(|url| !url.is_empty())
+        .cloned()
+        .collect();
+    urls.sort();
+    urls.dedup();
+
+    let probes = urls.iter().map(|url| probe(url, config.timeout_seconds));
+    let results = futures::future::join_all(probes).await;
+
+    let now = chrono::Local::now().timestamp();
+    let changes = HEALTH.with(|health| {
+        let mut changes = vec![];
+        for (url, reachable) in urls.iter().zip(results) {
+            if let Some(change) = health.update(url, reachable, now) {
+                info!("pool {} health changed: {:?}", url, change);
+                changes.push((url.clone(), change));
+            }
+        }
+        for pools in pools_map.values_mut() {
+            *pools = health.order(pools);
+        }
+        changes
+    });
+
+    for (url, change) in changes {
+        let (key, severity) = match change {
+            HealthChange::Down => ("pool_down", Severity::Warning),
+            HealthChange::Up => ("pool_up", Severity::Info),
+        };
+        let title = template::render(&format!("{}.title", key), &[("url", &url)]);
+        let content = template::render(&format!("{}.content", key), &[]);
+        notifier::send_alert(&Alert {
+            kind: key.to_string(),
+            title,
+            severity,
+            content,
+            machines: vec![],
+        })
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failover_and_recover() {
+        let mut health = PoolHealth::new(PoolHealthConfig {
+            enabled: true,
+            failover_seconds: 300,
+            recover_seconds: 600,
+            timeout_seconds: 5,
+        });
+        let pools = vec![
+            "btc.ss.poolin.com:443".to_string(),
+            "btc.f2pool.com:1314".to_string(),
+            "ss.antpool.com:3333".to_string(),
+        ];
+
+        assert_eq!(health.update(&pools[0], false, 0), None);
+        assert_eq!(health.update(&pools[0], false, 200), None);
+        assert_eq!(health.order(&pools), pools);
+        assert_eq!(
+            health.update(&pools[0], false, 300),
+            Some(HealthChange::Down)
+        );
+        assert_eq!(health.order(&pools)[0], pools[1]);
+        assert_eq!(health.order(&pools)[2], pools[0]);
+
+        // back up, restored only after recover_seconds
+        assert_eq!(health.update(&pools[0], true, 400), None);
+        assert_eq!(health.update(&pools[0], true, 900), None);
+        assert_eq!(health.update(&pools[0], true, 1000), Some(HealthChange::Up));
+        assert_eq!(health.order(&pools), pools);
+    }
+
+    #[test]
+    fn test_same_endpoint() {
+        assert!(same_endpoint(
+            "stratum+tcp://BTC.ss.poolin.com:443/",
+            "btc.ss.poolin.com:443"
+        ));
+        assert!(!same_endpoint(
+            "btc.ss.poolin.com:443",
+            "btc.ss.poolin.com:1883"
+        ));
+    }
+}
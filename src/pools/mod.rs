@@ -1,3 +1,19 @@
+#[cfg(feature = "pools")]
+pub mod antpool;
+#[cfg(feature = "pools")]
 pub mod f2pool;
+#[cfg(feature = "engine")]
+pub mod health;
 pub mod pool;
+#[cfg(feature = "pools")]
 pub mod poolin;
+#[cfg(feature = "engine")]
+pub mod proxy;
+#[cfg(feature = "engine")]
+pub mod reconcile;
+#[cfg(feature = "engine")]
+pub mod stale;
+#[cfg(feature = "engine")]
+pub mod summary;
+#[cfg(feature = "pools")]
+pub mod viabtc;
@@ -1,15 +1,47 @@
+#![cfg_attr(
+    not(all(feature = "pools", feature = "engine")),
+    allow(unused_imports, dead_code)
+)]
+use std::collections::HashMap;
+use std::time::Duration;
+
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 
-use crate::{error::MinerError, store::db};
+#[cfg(feature = "engine")]
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::model::SchemaVersion;
+#[cfg(feature = "pools")]
+use crate::secret::{self, SecretString};
+#[cfg(all(feature = "pools", feature = "engine"))]
+use crate::{clock, context, status, store::db};
+
+#[cfg(all(feature = "pools", feature = "engine"))]
+use super::stale;
+#[cfg(feature = "pools")]
+use super::{
+    antpool::{Antpool, AntpoolAccount},
+    f2pool::F2pool,
+    poolin::Poolin,
+    viabtc::ViaBtc,
+};
 
-use super::{f2pool::F2pool, poolin::Poolin};
+// ip to the pool worker, for machines not named after their ip
+#[cfg(feature = "engine")]
+static WORKER_NAMES: Local<HashMap<String, String>> = Local::new(HashMap::new);
+#[cfg(feature = "engine")]
+static LAST_QUERY: Local<Option<PoolQueryStatus>> = Local::new(Option::default);
 
+#[cfg(feature = "pools")]
 pub enum PoolType {
     Poolin(Poolin),
     F2pool(F2pool),
+    Antpool(Antpool),
+    ViaBtc(ViaBtc),
 }
 
+#[cfg(feature = "pools")]
 impl PoolType {
     pub fn detect(watcher_url: &str) -> Result<PoolType, MinerError> {
         if watcher_url.contains("poolin") {
@@ -18,82 +50,344 @@ impl PoolType {
         if watcher_url.contains("f2pool") {
             return Ok(PoolType::F2pool(F2pool::from_watcher(watcher_url)?));
         }
+        if watcher_url.contains("antpool") {
+            return Ok(PoolType::Antpool(Antpool::from_watcher(watcher_url)?));
+        }
+        if watcher_url.contains("viabtc") {
+            return Ok(PoolType::ViaBtc(ViaBtc::from_watcher(watcher_url)?));
+        }
         Err(MinerError::PoolTypeNotDetected)
     }
 }
 
 /// public data define
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PoolWorker {
+    #[serde(default = "SchemaVersion::unversioned")]
+    pub schema_version: SchemaVersion,
     pub name: String,
     pub hash_real: f64,
     pub hash_avg: f64,
     pub time_stamp: i64,
     pub pool_type: String,
+    /// label of the pool account the worker belongs to
+    #[serde(default)]
+    pub account: String,
+}
+
+/// result of the last pool workers query
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PoolQueryStatus {
+    pub time: i64,
+    pub workers: usize,
+    /// empty when the query succeeded
+    pub error: String,
+}
+
+#[cfg(feature = "engine")]
+pub fn last_query() -> Option<PoolQueryStatus> {
+    LAST_QUERY.get()
+}
+
+/// seconds between two queries of the pool workers
+pub const QUERY_SECONDS: i64 = 300;
+
+// days of earnings refreshed each day, covers late settlement
+#[cfg(all(feature = "pools", feature = "engine"))]
+const EARNINGS_DAYS: i64 = 7;
+
+/// worker name is the suffix itself or ends with it after a non digit,
+/// so "lcd.a188x41" matches "188x41" but "2188x41" does not
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+pub fn is_worker_of(name: &str, suffix: &str) -> bool {
+    match name.strip_suffix(suffix) {
+        Some(prefix) => !prefix.ends_with(|c: char| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// how the pool worker of a machine is looked up
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkerName {
+    /// "acc.rack3-07", the whole worker name
+    Full(String),
+    /// "188x41" or "rack3-07", the worker name ends with it, see is_worker_of
+    Suffix(String),
+}
+
+/// pool worker per ip, a full name with the account or only the suffix after it. other
+/// ips keep the "<ip3>x<ip4>" suffix the switch names workers with
+#[cfg(feature = "engine")]
+pub fn set_worker_names(names: HashMap<String, String>) {
+    WORKER_NAMES.set(names);
+}
+
+#[cfg(feature = "engine")]
+pub fn worker_names() -> HashMap<String, String> {
+    WORKER_NAMES.get()
+}
+
+/// a machine moved to another ip keeps the worker configured for its old one
+#[cfg(feature = "engine")]
+pub fn move_worker_name(from: &str, to: &str) {
+    WORKER_NAMES.with(|names| {
+        if let Some(name) = names.remove(from) {
+            names.insert(to.to_string(), name);
+        }
+    });
+}
+
+#[cfg(feature = "engine")]
+pub fn worker_name(ip: &str) -> Option<WorkerName> {
+    if let Some(name) = WORKER_NAMES.with(|names| names.get(ip).cloned()) {
+        return Some(if name.contains('.') {
+            WorkerName::Full(name)
+        } else {
+            WorkerName::Suffix(name)
+        });
+    }
+    let ip_segs = ip.split('.').collect::<Vec<&str>>();
+    if ip_segs.len() != 4 {
+        return None;
+    }
+    Some(WorkerName::Suffix(format!("{}x{}", ip_segs[2], ip_segs[3])))
+}
+
+pub const EARNING_REVENUE: &str = "revenue";
+pub const EARNING_PAYOUT: &str = "payout";
+
+/// daily mining revenue or a payout transaction of a pool account
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PoolEarning {
+    pub pool_type: String,
+    pub account: String,
+    /// EARNING_REVENUE or EARNING_PAYOUT
+    pub kind: String,
+    /// YYYY-MM-DD, mining date of revenue or paid date of payout
+    pub date: String,
+    /// in coin, e.g. BTC
+    pub amount: f64,
+    /// TH/s average of the mining date, 0 when pool does not report it
+    pub hash_rate: f64,
+    /// payout transaction, empty for revenue
+    pub tx_id: String,
+    pub time_stamp: i64,
 }
 
 // define trait for general pool api query
+#[cfg(feature = "pools")]
 pub trait Pool {
     async fn query(&self, proxy: &str) -> Result<Vec<PoolWorker>, MinerError>;
+
+    /// revenue and payouts between start and end time, empty when pool is not supported
+    async fn query_earnings(
+        &self,
+        _proxy: &str,
+        _start_time: i64,
+        _end_time: i64,
+    ) -> Result<Vec<PoolEarning>, MinerError> {
+        Ok(vec![])
+    }
 }
 
+#[cfg(feature = "pools")]
 impl Pool for PoolType {
     async fn query(&self, proxy: &str) -> Result<Vec<PoolWorker>, MinerError> {
         match self {
             PoolType::Poolin(poolin) => poolin.query(proxy).await,
             PoolType::F2pool(f2pool) => f2pool.query(proxy).await,
+            PoolType::Antpool(antpool) => antpool.query(proxy).await,
+            PoolType::ViaBtc(viabtc) => viabtc.query(proxy).await,
+        }
+    }
+
+    async fn query_earnings(
+        &self,
+        proxy: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Vec<PoolEarning>, MinerError> {
+        match self {
+            PoolType::Poolin(poolin) => poolin.query_earnings(proxy, start_time, end_time).await,
+            PoolType::F2pool(f2pool) => f2pool.query_earnings(proxy, start_time, end_time).await,
+            PoolType::Antpool(antpool) => antpool.query_earnings(proxy, start_time, end_time).await,
+            PoolType::ViaBtc(viabtc) => viabtc.query_earnings(proxy, start_time, end_time).await,
+        }
+    }
+}
+
+/// one pool account to query, workers are tagged with the label
+#[cfg(feature = "pools")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolAccountConfig {
+    pub label: String,
+    pub account: PoolAccount,
+}
+
+#[cfg(feature = "pools")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PoolAccount {
+    /// observer/watcher link, pool detected from url
+    Watcher(SecretString),
+    F2pool {
+        account: String,
+        secret: SecretString,
+    },
+    Antpool(AntpoolAccount),
+    ViaBtc {
+        api_key: SecretString,
+    },
+}
+
+#[cfg(feature = "pools")]
+impl PoolAccountConfig {
+    pub fn to_pool(&self) -> Result<PoolType, MinerError> {
+        match &self.account {
+            // watcher links carry their read token
+            PoolAccount::Watcher(url) => PoolType::detect(&secret::resolve(url.expose())?),
+            PoolAccount::F2pool { account, secret } => Ok(PoolType::F2pool(F2pool::from_account(
+                account.clone(),
+                secret::resolve(secret.expose())?,
+            ))),
+            PoolAccount::Antpool(account) => {
+                let account = AntpoolAccount {
+                    user_id: account.user_id.clone(),
+                    api_key: secret::resolve(account.api_key.expose())?.into(),
+                    api_secret: secret::resolve(account.api_secret.expose())?.into(),
+                };
+                Ok(PoolType::Antpool(Antpool::from_account(&account)))
+            }
+            PoolAccount::ViaBtc { api_key } => Ok(PoolType::ViaBtc(ViaBtc::from_api_key(
+                &secret::resolve(api_key.expose())?,
+            ))),
         }
     }
+
+    /// workers of the account tagged with the label
+    pub async fn query_workers(&self, proxy: &str) -> Result<Vec<PoolWorker>, MinerError> {
+        let mut workers = self.to_pool()?.query(proxy).await?;
+        for worker in workers.iter_mut() {
+            worker.account = self.label.clone();
+        }
+        Ok(workers)
+    }
+
+    /// earnings of the account between start and end time, tagged with the label
+    pub async fn query_earnings(
+        &self,
+        proxy: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Vec<PoolEarning>, MinerError> {
+        let pool = self.to_pool()?;
+        let mut earnings = pool.query_earnings(proxy, start_time, end_time).await?;
+        for earning in earnings.iter_mut() {
+            earning.account = self.label.clone();
+        }
+        Ok(earnings)
+    }
 }
 
+/// query all accounts concurrently, a failed account is logged and skipped
+#[cfg(feature = "pools")]
 pub async fn query_pool_workers(
     proxy: &str,
-    watcher_url: &str,
-    f2p_account: &str,
-    f2p_secret: &str,
+    accounts: &[PoolAccountConfig],
 ) -> Result<Vec<PoolWorker>, MinerError> {
+    let queries = accounts.iter().map(|account| account.query_workers(proxy));
+
     let mut workers = vec![];
-    // detect pool type
-    if watcher_url.contains("poolin") {
-        match PoolType::detect(watcher_url) {
-            Ok(pool) => {
-                // get query result, ignore error, return empty vec
-                let w = match pool.query(proxy).await {
-                    Ok(result) => result,
-                    Err(_) => vec![],
-                };
-                workers.extend(w);
-            }
+    let results = futures::future::join_all(queries).await;
+    for (account, result) in accounts.iter().zip(results) {
+        match result {
+            Ok(w) => workers.extend(w),
             Err(e) => {
-                error!("detect pool type error: {:?}", e);
+                error!("query pool account {} error: {:?}", account.label, e);
             }
         }
     }
 
-    if f2p_account.len() > 0 && f2p_secret.len() > 0 {
-        let f2pool = F2pool::from_account(f2p_account.to_string(), f2p_secret.to_string());
-        let w = match f2pool.query(proxy).await {
-            Ok(result) => result,
-            Err(_) => vec![],
-        };
-        workers.extend(w);
-    }
-
     Ok(workers)
 }
 
+/// query earnings of the accounts, a failed account is logged and its label returned
+#[cfg(feature = "pools")]
+pub async fn query_pool_earnings(
+    proxy: &str,
+    accounts: &[&PoolAccountConfig],
+    start_time: i64,
+    end_time: i64,
+) -> (Vec<PoolEarning>, Vec<String>) {
+    let queries = accounts
+        .iter()
+        .map(|account| account.query_earnings(proxy, start_time, end_time));
+
+    let mut earnings = vec![];
+    let mut failed = vec![];
+    let results = futures::future::join_all(queries).await;
+    for (account, result) in accounts.iter().zip(results) {
+        match result {
+            Ok(e) => earnings.extend(e),
+            Err(e) => {
+                error!("query pool earnings {} error: {:?}", account.label, e);
+                failed.push(account.label.clone());
+            }
+        }
+    }
+    (earnings, failed)
+}
+
+#[cfg(all(feature = "pools", feature = "engine"))]
 pub fn schedule_query_task(
     runtime: tokio::runtime::Handle,
     proxy: String,
-    watcher_url: String,
-    f2p_account: String,
-    f2p_secret: String,
+    accounts: Vec<PoolAccountConfig>,
 ) -> tokio::task::JoinHandle<()> {
     // create tokio runtime context
-    return runtime.spawn(async move {
+    context::spawn(&runtime, async move {
+        // account label to the date its earnings were last fetched
+        let mut earnings_dates: HashMap<String, String> = HashMap::new();
         loop {
+            // earnings settle once a day, refresh last days when date changes, failed
+            // accounts are retried next round
+            let now = clock::now();
+            let today = now.format("%Y-%m-%d").to_string();
+            let due: Vec<&PoolAccountConfig> = accounts
+                .iter()
+                .filter(|account| earnings_dates.get(&account.label) != Some(&today))
+                .collect();
+            if !due.is_empty() {
+                let end_time = now.timestamp();
+                let (earnings, failed) = query_pool_earnings(
+                    &proxy,
+                    &due,
+                    end_time - EARNINGS_DAYS * 24 * 3600,
+                    end_time,
+                )
+                .await;
+                info!("query pool earnings: {}", earnings.len());
+                for earning in earnings {
+                    if let Err(e) = db::insert_pool_earning(&earning) {
+                        error!("insert pool earning error: {:?}", e);
+                    }
+                }
+                for account in due.iter().filter(|a| !failed.contains(&a.label)) {
+                    earnings_dates.insert(account.label.clone(), today.clone());
+                }
+            }
+
             info!("query pool workers task scheduled.");
-            let workers = query_pool_workers(&proxy, &watcher_url, &f2p_account, &f2p_secret).await;
+            let workers = query_pool_workers(&proxy, &accounts).await;
+            LAST_QUERY.set(Some(PoolQueryStatus {
+                time: chrono::Local::now().timestamp(),
+                workers: workers.as_ref().map(|w| w.len()).unwrap_or(0),
+                error: workers
+                    .as_ref()
+                    .err()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default(),
+            }));
             match workers {
                 Ok(workers) => {
                     // update db
@@ -103,6 +397,7 @@ pub fn schedule_query_task(
                             worker.hash_real,
                             worker.hash_avg,
                             &worker.pool_type,
+                            &worker.account,
                             worker.time_stamp,
                         ) {
                             Ok(_) => {}
@@ -117,7 +412,60 @@ pub fn schedule_query_task(
                 }
             }
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
+            stale::check_stale_workers().await;
+
+            if !status::wait("pool_query", Duration::from_secs(QUERY_SECONDS as u64)).await {
+                break;
+            }
         }
-    });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_worker_of() {
+        assert!(is_worker_of("188x41", "188x41"));
+        assert!(is_worker_of("lcd01.a188x41", "188x41"));
+        assert!(!is_worker_of("2188x41", "188x41"));
+        assert!(!is_worker_of("188x412", "188x41"));
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn test_worker_name() {
+        set_worker_names(HashMap::from([
+            ("10.1.0.7".to_string(), "lcd01.rack3-07".to_string()),
+            ("10.1.0.8".to_string(), "rack3-08".to_string()),
+        ]));
+        assert_eq!(
+            worker_name("10.1.0.7"),
+            Some(WorkerName::Full("lcd01.rack3-07".to_string()))
+        );
+        assert_eq!(
+            worker_name("10.1.0.8"),
+            Some(WorkerName::Suffix("rack3-08".to_string()))
+        );
+        assert_eq!(
+            worker_name("192.168.188.41"),
+            Some(WorkerName::Suffix("188x41".to_string()))
+        );
+        assert_eq!(worker_name("miner-41"), None);
+    }
+
+    #[cfg(feature = "pools")]
+    #[test]
+    fn test_pool_account_config() {
+        let accounts: Vec<PoolAccountConfig> = serde_json::from_str(
+            r#"[
+                {"label": "site-a", "account": {"Watcher": "https://www.viabtc.com/observer/worker?access_key=3f9a2c&coin=BTC"}},
+                {"label": "site-b", "account": {"F2pool": {"account": "lcd", "secret": "x"}}}
+            ]"#,
+        )
+        .unwrap();
+        assert!(matches!(accounts[0].to_pool(), Ok(PoolType::ViaBtc(_))));
+        assert!(matches!(accounts[1].to_pool(), Ok(PoolType::F2pool(_))));
+    }
 }
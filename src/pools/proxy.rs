@@ -0,0 +1,215 @@
+/// local stratum proxy status, tells proxy problems apart from miner problems
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::clock;
+use crate::context;
+use crate::error::MinerError;
+use crate::notify::{notifier, template, Alert, Severity};
+use crate::status;
+use crate::store::db;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StratumProxyConfig {
+    /// label, also used for records
+    pub name: String,
+    /// json status endpoint, e.g. "http://192.168.190.8:8080/status"
+    pub status_url: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProxyUpstream {
+    pub url: String,
+    pub alive: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProxyStatus {
+    pub name: String,
+    /// status endpoint answered
+    pub reachable: bool,
+    pub upstreams: Vec<ProxyUpstream>,
+    /// TH/s aggregated over all miners behind the proxy
+    pub hash_real: f64,
+    pub workers: i64,
+    pub time_stamp: i64,
+}
+
+impl ProxyStatus {
+    pub fn alive_upstreams(&self) -> usize {
+        self.upstreams.iter().filter(|u| u.alive).count()
+    }
+}
+
+// first present key of the aliases
+fn field<'a>(value: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+    keys.iter().map(|k| &value[*k]).find(|v| !v.is_null())
+}
+
+fn number(value: Option<&Value>) -> f64 {
+    match value {
+        Some(Value::Number(n)) => n.as_f64().unwrap_or(0.0),
+        Some(Value::String(s)) => s.trim().parse::<f64>().unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+/// tolerant parse of common proxy status json, hashrate in H/s unless a th key is used
+pub fn parse_status(name: &str, json: &Value) -> ProxyStatus {
+    let upstreams = field(json, &["upstreams", "pools", "upstream"])
+        .and_then(|v| v.as_array())
+        .map(|list| {
+            list.iter()
+                .map(|u| ProxyUpstream {
+                    url: field(u, &["url", "host", "address"])
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                    alive: match field(u, &["alive", "connected", "status", "state"]) {
+                        Some(Value::Bool(b)) => *b,
+                        Some(Value::String(s)) => {
+                            matches!(
+                                s.to_lowercase().as_str(),
+                                "alive" | "connected" | "up" | "ok"
+                            )
+                        }
+                        Some(Value::Number(n)) => n.as_i64().unwrap_or(0) != 0,
+                        _ => false,
+                    },
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let hash_real = match field(json, &["hashrate_ths", "hash_rate_ths"]) {
+        Some(v) => number(Some(v)),
+        None => number(field(json, &["hashrate", "hash_rate"])) / 1000000000000.0,
+    };
+
+    ProxyStatus {
+        name: name.to_string(),
+        reachable: true,
+        upstreams,
+        hash_real,
+        workers: number(field(json, &["workers", "miners", "connections"])) as i64,
+        time_stamp: chrono::Local::now().timestamp(),
+    }
+}
+
+pub async fn query_status(config: &StratumProxyConfig) -> Result<ProxyStatus, MinerError> {
+    // proxy is on the local network, never through the outbound proxy
+    let json = reqwest::Client::new()
+        .get(&config.status_url)
+        .timeout(std::time::Duration::from_secs(5))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    Ok(parse_status(&config.name, &json))
+}
+
+/// query every proxy, an unreachable one is returned with reachable false
+pub async fn query_all(proxies: &[StratumProxyConfig]) -> Vec<ProxyStatus> {
+    let queries = proxies.iter().map(query_status);
+    let results = futures::future::join_all(queries).await;
+
+    proxies
+        .iter()
+        .zip(results)
+        .map(|(config, result)| match result {
+            Ok(status) => status,
+            Err(e) => {
+                error!("query proxy {} error: {:?}", config.name, e);
+                ProxyStatus {
+                    name: config.name.clone(),
+                    time_stamp: chrono::Local::now().timestamp(),
+                    ..Default::default()
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn schedule_status_task(
+    runtime: tokio::runtime::Handle,
+    proxies: Vec<StratumProxyConfig>,
+    interval_seconds: u64,
+) -> tokio::task::JoinHandle<()> {
+    context::spawn(&runtime, async move {
+        loop {
+            info!("query proxy status task scheduled.");
+            let statuses = query_all(&proxies).await;
+
+            let mut down = vec![];
+            for status in statuses.iter() {
+                if let Err(e) = db::insert_proxy_record(status) {
+                    error!("insert proxy record error: {:?}", e);
+                }
+                if !status.reachable {
+                    down.push(template::render(
+                        "proxy.no_status",
+                        &[("name", &status.name)],
+                    ));
+                } else if !status.upstreams.is_empty() && status.alive_upstreams() == 0 {
+                    down.push(template::render(
+                        "proxy.upstreams_down",
+                        &[("name", &status.name)],
+                    ));
+                }
+            }
+
+            if !down.is_empty() {
+                notifier::send_alert(&Alert {
+                    kind: "proxy".to_string(),
+                    title: template::render(
+                        "proxy.title",
+                        &[
+                            ("time", &clock::now().format("%H:%M:%S")),
+                            ("count", &down.len()),
+                        ],
+                    ),
+                    severity: Severity::Critical,
+                    content: down.join("\n"),
+                    machines: vec![],
+                })
+                .await;
+            }
+
+            if !status::wait(
+                "proxy_status",
+                tokio::time::Duration::from_secs(interval_seconds),
+            )
+            .await
+            {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_status() {
+        let status = parse_status(
+            "proxy-8",
+            &json!({
+                "hashrate": 2500000000000000.0,
+                "workers": 27,
+                "upstreams": [
+                    {"url": "btc.ss.poolin.com:443", "connected": true},
+                    {"host": "btc.f2pool.com:1314", "status": "down"}
+                ]
+            }),
+        );
+        assert!(status.reachable);
+        assert_eq!(status.hash_real, 2500.0);
+        assert_eq!(status.workers, 27);
+        assert_eq!(status.upstreams[1].url, "btc.f2pool.com:1314");
+        assert_eq!(status.alive_upstreams(), 1);
+    }
+}
@@ -0,0 +1,94 @@
+/// local hashrate against pool hashrate of the same machine
+use serde::{Deserialize, Serialize};
+
+use super::pool::PoolWorker;
+use crate::error::MinerError;
+use crate::miner::entry::MachineRecord;
+use crate::store::db;
+
+// machine records older than this are not reconciled
+const RECORD_MAX_AGE: i64 = 3600;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HashReconcile {
+    pub ip: String,
+    /// TH/s
+    pub local_hash_real: f64,
+    /// TH/s
+    pub local_hash_avg: f64,
+    /// empty when pool has no worker for the machine
+    pub pool_worker: String,
+    /// TH/s, pool short window
+    pub pool_hash_real: Option<f64>,
+    /// TH/s, pool 24h
+    pub pool_hash_avg: Option<f64>,
+    /// (local - pool) / local of the real hashrate in percent, positive when pool sees less
+    pub mismatch_percent: Option<f64>,
+}
+
+pub fn reconcile_record(machine: &MachineRecord, pool: Option<PoolWorker>) -> HashReconcile {
+    let local_hash_real = machine.hash_real / 1000.0;
+    let mut result = HashReconcile {
+        ip: machine.ip.clone(),
+        local_hash_real,
+        local_hash_avg: machine.hash_avg / 1000.0,
+        ..Default::default()
+    };
+
+    if let Some(pool) = pool {
+        result.mismatch_percent = if local_hash_real > 0.0 {
+            Some((local_hash_real - pool.hash_real) / local_hash_real * 100.0)
+        } else {
+            None
+        };
+        result.pool_worker = pool.name;
+        result.pool_hash_real = Some(pool.hash_real);
+        result.pool_hash_avg = Some(pool.hash_avg);
+    }
+    result
+}
+
+/// reconcile latest records of the ips, ips without a recent record are skipped
+pub fn reconcile(ips: &[String]) -> Result<Vec<HashReconcile>, MinerError> {
+    let since = chrono::Local::now().timestamp() - RECORD_MAX_AGE;
+    let machines = db::query_latest_machine_records(since)?;
+
+    let mut results = vec![];
+    for machine in machines.iter().filter(|m| ips.contains(&m.ip)) {
+        let pool = db::get_newest_pool_record(&machine.ip)?;
+        results.push(reconcile_record(machine, pool));
+    }
+    results.sort_by(|a, b| a.ip.cmp(&b.ip));
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconcile_record() {
+        let machine = MachineRecord {
+            ip: "192.168.188.41".to_string(),
+            hash_real: 100000.0,
+            hash_avg: 98000.0,
+            ..Default::default()
+        };
+        let result = reconcile_record(
+            &machine,
+            Some(PoolWorker {
+                name: "lcd.a188x41".to_string(),
+                hash_real: 90.0,
+                hash_avg: 95.0,
+                ..Default::default()
+            }),
+        );
+        assert_eq!(result.local_hash_real, 100.0);
+        assert_eq!(result.pool_hash_avg, Some(95.0));
+        assert!((result.mismatch_percent.unwrap() - 10.0).abs() < 0.001);
+
+        let result = reconcile_record(&machine, None);
+        assert!(result.pool_worker.is_empty());
+        assert_eq!(result.mismatch_percent, None);
+    }
+}
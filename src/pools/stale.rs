@@ -0,0 +1,189 @@
+// without pool queries there are no pool records to check
+#![cfg_attr(not(feature = "pools"), allow(dead_code))]
+/// machines hashing locally but missing or idle on the pool side
+use std::collections::HashMap;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::pool::PoolWorker;
+use crate::clock;
+use crate::context::Local;
+use crate::miner::entry::MachineRecord;
+use crate::notify::{notifier, template, Alert, AlertMachine, Severity};
+use crate::store::db;
+
+static DETECTOR: Local<StaleDetector> =
+    Local::new(|| StaleDetector::new(StaleWorkerConfig::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaleWorkerConfig {
+    /// consecutive pool cycles a worker is missing or zero before alerting, 0 to disable
+    pub cycles: u32,
+    /// local or pool records older than this are ignored, seconds
+    pub record_max_age: i64,
+}
+
+impl Default for StaleWorkerConfig {
+    fn default() -> Self {
+        StaleWorkerConfig {
+            cycles: 3,
+            record_max_age: 900,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StaleDetector {
+    config: StaleWorkerConfig,
+    counts: HashMap<String, u32>,
+}
+
+impl StaleDetector {
+    pub fn new(config: StaleWorkerConfig) -> Self {
+        StaleDetector {
+            config,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// check one cycle, returns machines reaching the cycle count.
+    /// counting restarts once a machine is reported.
+    pub fn check<F>(
+        &mut self,
+        machines: &[MachineRecord],
+        now: i64,
+        pool_record: F,
+    ) -> Vec<AlertMachine>
+    where
+        F: Fn(&str) -> Option<PoolWorker>,
+    {
+        if self.config.cycles == 0 {
+            return vec![];
+        }
+
+        let max_age = self.config.record_max_age;
+        let mut stale = HashMap::new();
+        for machine in machines.iter() {
+            if machine.hash_real <= 0.0 || now - machine.create_time > max_age {
+                continue;
+            }
+            let key = match pool_record(&machine.ip) {
+                Some(worker) if now - worker.time_stamp > max_age => "stale.expired",
+                Some(worker) if worker.hash_real <= 0.0 => "stale.zero",
+                Some(_) => continue,
+                None => "stale.missing",
+            };
+            let hash = format!("{:.2}", machine.hash_real / 1000.0);
+            stale.insert(
+                machine.ip.clone(),
+                template::render(key, &[("hash", &hash)]),
+            );
+        }
+
+        self.counts.retain(|ip, _| stale.contains_key(ip));
+        let mut selected = vec![];
+        for (ip, detail) in stale {
+            let count = self.counts.entry(ip.clone()).or_insert(0);
+            *count += 1;
+            if *count >= self.config.cycles {
+                *count = 0;
+                selected.push(AlertMachine { ip, detail });
+            }
+        }
+        selected.sort_by(|a, b| a.ip.cmp(&b.ip));
+        selected
+    }
+}
+
+pub fn set_config(config: StaleWorkerConfig) {
+    DETECTOR.set(StaleDetector::new(config));
+}
+
+/// cross check latest machine records against pool records, alert on stale workers
+pub async fn check_stale_workers() {
+    let now = chrono::Local::now().timestamp();
+    let max_age = DETECTOR.with(|detector| detector.config.record_max_age);
+    let machines = match db::query_latest_machine_records(now - max_age) {
+        Ok(machines) => machines,
+        Err(e) => {
+            error!("query latest machine records error: {:?}", e);
+            return;
+        }
+    };
+
+    let stale = DETECTOR.with(|detector| {
+        detector.check(&machines, now, |ip| {
+            db::get_newest_pool_record(ip).ok().flatten()
+        })
+    });
+    if stale.is_empty() {
+        return;
+    }
+
+    info!("stale pool workers: {}", stale.len());
+    notifier::send_alert(&Alert {
+        kind: "stale".to_string(),
+        title: template::render(
+            "stale.title",
+            &[
+                ("time", &clock::now().format("%H:%M:%S")),
+                ("count", &stale.len()),
+            ],
+        ),
+        severity: Severity::Warning,
+        content: template::render("stale.content", &[]),
+        machines: stale,
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(ip: &str, hash: f64) -> MachineRecord {
+        MachineRecord {
+            ip: ip.to_string(),
+            hash_real: hash,
+            create_time: 1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stale_detector() {
+        let mut detector = StaleDetector::new(StaleWorkerConfig {
+            cycles: 2,
+            record_max_age: 900,
+        });
+        let machines = vec![
+            machine("192.168.1.2", 90000.0),
+            machine("192.168.1.3", 90000.0),
+            machine("192.168.1.4", 90000.0),
+            // not hashing locally, pool state irrelevant
+            machine("192.168.1.5", 0.0),
+        ];
+        let pool_record = |ip: &str| match ip {
+            "192.168.1.2" => Some(PoolWorker {
+                hash_real: 90.0,
+                time_stamp: 1000,
+                ..Default::default()
+            }),
+            "192.168.1.3" => Some(PoolWorker {
+                hash_real: 0.0,
+                time_stamp: 1000,
+                ..Default::default()
+            }),
+            _ => None,
+        };
+
+        assert!(detector.check(&machines, 1000, pool_record).is_empty());
+        let stale = detector.check(&machines, 1000, pool_record);
+        assert_eq!(stale.len(), 2);
+        assert_eq!(stale[0].ip, "192.168.1.3");
+        assert_eq!(stale[1].ip, "192.168.1.4");
+        // reported machines start counting again
+        assert!(detector.check(&machines, 1000, pool_record).is_empty());
+    }
+}
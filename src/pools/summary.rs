@@ -0,0 +1,198 @@
+/// pool hashrate over a time range per account and for the fleet, from the pool records
+/// written each query cycle
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::pool::{PoolWorker, QUERY_SECONDS};
+use crate::error::MinerError;
+use crate::store::db;
+
+/// hashrate of one query cycle
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HashPoint {
+    /// first record time of the cycle
+    pub time_stamp: i64,
+    pub hash_real: f64,
+    pub workers: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccountSummary {
+    pub pool_type: String,
+    pub account: String,
+    /// average over the cycles the account was queried in
+    pub hash_avg: f64,
+    pub series: Vec<HashPoint>,
+}
+
+/// worker reported earlier in the range but not in the last cycle of its account
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DisappearedWorker {
+    pub pool_type: String,
+    pub account: String,
+    pub name: String,
+    pub last_seen: i64,
+    pub last_hash_real: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PoolSummary {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub accounts: Vec<AccountSummary>,
+    /// every account per cycle
+    pub total: Vec<HashPoint>,
+    pub disappeared: Vec<DisappearedWorker>,
+}
+
+// records of one query cycle are written within seconds of each other, a new cycle
+// starts once the time moves on by more than half the interval
+fn cycles(records: &[PoolWorker]) -> Vec<&[PoolWorker]> {
+    let mut cycles = Vec::new();
+    let mut start = 0;
+    for i in 1..records.len() {
+        if records[i].time_stamp - records[start].time_stamp > QUERY_SECONDS / 2 {
+            cycles.push(&records[start..i]);
+            start = i;
+        }
+    }
+    if !records.is_empty() {
+        cycles.push(&records[start..]);
+    }
+    cycles
+}
+
+/// summarize records sorted by time_stamp
+pub fn summarize(records: &[PoolWorker], start_time: i64, end_time: i64) -> PoolSummary {
+    let mut accounts: BTreeMap<(String, String), AccountSummary> = BTreeMap::new();
+    let mut total = Vec::new();
+    // per account, worker name to last record and the cycle it was seen in
+    let mut seen: HashMap<(String, String), HashMap<String, (usize, PoolWorker)>> = HashMap::new();
+    let mut last_cycle: HashMap<(String, String), usize> = HashMap::new();
+
+    for (index, cycle) in cycles(records).into_iter().enumerate() {
+        let time_stamp = cycle[0].time_stamp;
+        let mut point = HashPoint {
+            time_stamp,
+            ..Default::default()
+        };
+        // a worker once per cycle, the newest record wins
+        let mut workers: BTreeMap<(String, String, String), &PoolWorker> = BTreeMap::new();
+        for worker in cycle {
+            let key = (
+                worker.pool_type.clone(),
+                worker.account.clone(),
+                worker.name.clone(),
+            );
+            workers.insert(key, worker);
+        }
+        for ((pool_type, account, name), worker) in workers {
+            let key = (pool_type.clone(), account.clone());
+            let summary = accounts
+                .entry(key.clone())
+                .or_insert_with(|| AccountSummary {
+                    pool_type,
+                    account,
+                    ..Default::default()
+                });
+            if summary.series.last().map(|p| p.time_stamp) != Some(time_stamp) {
+                summary.series.push(HashPoint {
+                    time_stamp,
+                    ..Default::default()
+                });
+            }
+            let account_point = summary.series.last_mut().unwrap();
+            account_point.hash_real += worker.hash_real;
+            account_point.workers += 1;
+            point.hash_real += worker.hash_real;
+            point.workers += 1;
+
+            seen.entry(key.clone())
+                .or_default()
+                .insert(name, (index, worker.clone()));
+            last_cycle.insert(key, index);
+        }
+        total.push(point);
+    }
+
+    let mut disappeared = Vec::new();
+    for (key, workers) in seen {
+        let last = last_cycle[&key];
+        for (name, (index, worker)) in workers {
+            if index < last {
+                disappeared.push(DisappearedWorker {
+                    pool_type: key.0.clone(),
+                    account: key.1.clone(),
+                    name,
+                    last_seen: worker.time_stamp,
+                    last_hash_real: worker.hash_real,
+                });
+            }
+        }
+    }
+    disappeared.sort_by(|a, b| (&a.account, &a.name).cmp(&(&b.account, &b.name)));
+
+    let accounts = accounts
+        .into_values()
+        .map(|mut summary| {
+            summary.hash_avg = summary.series.iter().map(|p| p.hash_real).sum::<f64>()
+                / summary.series.len() as f64;
+            summary
+        })
+        .collect();
+
+    PoolSummary {
+        start_time,
+        end_time,
+        accounts,
+        total,
+        disappeared,
+    }
+}
+
+pub fn query_pool_summary(start_time: i64, end_time: i64) -> Result<PoolSummary, MinerError> {
+    let records = db::query_all_pool_records_by_time(start_time, end_time)?;
+    Ok(summarize(&records, start_time, end_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(account: &str, name: &str, hash_real: f64, time_stamp: i64) -> PoolWorker {
+        PoolWorker {
+            schema_version: Default::default(),
+            name: name.to_string(),
+            hash_real,
+            hash_avg: hash_real,
+            time_stamp,
+            pool_type: "f2pool".to_string(),
+            account: account.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let records = vec![
+            worker("a", "a.188x41", 100.0, 1000),
+            worker("a", "a.188x42", 90.0, 1001),
+            worker("b", "b.188x51", 80.0, 1003),
+            worker("a", "a.188x41", 110.0, 1300),
+            worker("b", "b.188x51", 70.0, 1302),
+        ];
+        let summary = summarize(&records, 0, 2000);
+
+        assert_eq!(summary.total.len(), 2);
+        assert_eq!(summary.total[0].hash_real, 270.0);
+        assert_eq!(summary.total[1].workers, 2);
+        assert_eq!(summary.accounts.len(), 2);
+        assert_eq!(summary.accounts[0].account, "a");
+        assert_eq!(summary.accounts[0].hash_avg, 150.0);
+        assert_eq!(summary.accounts[1].hash_avg, 75.0);
+
+        assert_eq!(summary.disappeared.len(), 1);
+        assert_eq!(summary.disappeared[0].name, "a.188x42");
+        assert_eq!(summary.disappeared[0].last_seen, 1001);
+    }
+}
@@ -0,0 +1,172 @@
+/// viabtc.com api query, observer link or api key
+use log::info;
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::pool::{Pool, PoolWorker};
+use crate::error::MinerError;
+use crate::http;
+
+pub struct ViaBtc {
+    api_url: String,
+    // observer access key, sent as query param
+    access_key: String,
+    // account api key, sent as X-API-KEY header
+    api_key: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ViaBtcWorker {
+    pub worker_name: String,
+    /// TH/s
+    pub hashrate_10min: f64,
+    /// TH/s
+    pub hashrate_1day: f64,
+}
+
+impl From<ViaBtcWorker> for PoolWorker {
+    fn from(vw: ViaBtcWorker) -> Self {
+        PoolWorker {
+            schema_version: Default::default(),
+            name: vw.worker_name,
+            hash_real: vw.hashrate_10min,
+            hash_avg: vw.hashrate_1day,
+            time_stamp: chrono::Local::now().timestamp(),
+            pool_type: "viabtc".to_string(),
+            account: "".to_string(),
+        }
+    }
+}
+
+impl ViaBtc {
+    pub fn from_watcher(watcher: &str) -> Result<ViaBtc, MinerError> {
+        // https://www.viabtc.com/observer/worker?access_key=xxxx&coin=BTC
+        let access_key = Regex::new(r"access_key=([^&]+)")
+            .unwrap()
+            .captures(watcher)
+            .and_then(|caps| caps.get(1))
+            .ok_or(MinerError::ViaBtcApiRegexError)?;
+
+        Ok(ViaBtc {
+            api_url: "https://www.viabtc.com/res/observer/worker".to_string(),
+            access_key: access_key.as_str().to_string(),
+            api_key: "".to_string(),
+        })
+    }
+
+    pub fn from_api_key(api_key: &str) -> ViaBtc {
+        ViaBtc {
+            api_url: "https://www.viabtc.net/res/openapi/v1/hashrate/worker".to_string(),
+            access_key: "".to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    async fn query_page(
+        &self,
+        client: &Client,
+        page: i32,
+        page_size: i32,
+    ) -> Result<(Vec<ViaBtcWorker>, bool), MinerError> {
+        let mut params = vec![
+            ("coin", "BTC".to_string()),
+            ("page", page.to_string()),
+            ("limit", page_size.to_string()),
+        ];
+        if !self.access_key.is_empty() {
+            params.push(("access_key", self.access_key.clone()));
+        }
+
+        let mut request = client
+            .get(&self.api_url)
+            .query(&params)
+            .timeout(std::time::Duration::from_secs(10));
+        if !self.api_key.is_empty() {
+            request = request.header("X-API-KEY", &self.api_key);
+        }
+        let resp = request.send().await?.json::<Value>().await?;
+        if resp["code"].as_i64() != Some(0) {
+            return Err(MinerError::ViaBtcApiRequestError);
+        }
+        Ok(parse_page(&resp["data"]))
+    }
+}
+
+impl Pool for ViaBtc {
+    async fn query(&self, proxy: &str) -> Result<Vec<PoolWorker>, MinerError> {
+        let client = http::client(proxy)?;
+
+        let mut workers = vec![];
+        let page_size = 100;
+        let mut page = 1;
+        loop {
+            let (page_workers, has_next) = self.query_page(&client, page, page_size).await?;
+            workers.extend(page_workers.into_iter().map(PoolWorker::from));
+
+            info!("viabtc page: {}, has_next: {}", page, has_next);
+            if !has_next {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(workers)
+    }
+}
+
+// hashrate in H/s, number or numeric string, to TH/s
+fn hashrate_th(value: &Value) -> f64 {
+    let hashrate = match value {
+        Value::Number(n) => n.as_f64().unwrap_or(0.0),
+        Value::String(s) => s.trim().parse::<f64>().unwrap_or(0.0),
+        _ => 0.0,
+    };
+    hashrate / 1000000000000.0
+}
+
+/// workers of the page and whether more pages follow
+fn parse_page(data: &Value) -> (Vec<ViaBtcWorker>, bool) {
+    let workers = data["data"]
+        .as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .map(|row| ViaBtcWorker {
+            worker_name: row["worker_name"].as_str().unwrap_or_default().to_string(),
+            hashrate_10min: hashrate_th(&row["hashrate_10min"]),
+            hashrate_1day: hashrate_th(&row["hashrate_1day"]),
+        })
+        .collect();
+    (workers, data["has_next"].as_bool().unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::pool::PoolType;
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_viabtc_parse() {
+        let viabtc = ViaBtc::from_watcher(
+            "https://www.viabtc.com/observer/worker?access_key=3f9a2c&coin=BTC",
+        )
+        .unwrap();
+        assert_eq!(viabtc.access_key, "3f9a2c");
+        assert!(matches!(
+            PoolType::detect("https://www.viabtc.com/observer/worker?access_key=3f9a2c&coin=BTC"),
+            Ok(PoolType::ViaBtc(_))
+        ));
+
+        let (workers, has_next) = parse_page(&json!({
+            "curr_page": 1,
+            "has_next": true,
+            "data": [{"worker_name": "188x41", "hashrate_10min": "95000000000000", "hashrate_1day": 90500000000000.0}]
+        }));
+        assert!(has_next);
+        assert_eq!(workers[0].worker_name, "188x41");
+        assert_eq!(workers[0].hashrate_10min, 95.0);
+        assert_eq!(workers[0].hashrate_1day, 90.5);
+    }
+}
@@ -0,0 +1,317 @@
+/// run mode by profitability: the btc price and the network difficulty give the revenue of a
+/// TH/s, the J/TH of each model and the electricity price its power cost. a model whose margin
+/// stays high even with the extra power of 高功 runs 高功, one losing money sleeps
+use std::collections::{HashMap, HashSet};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::http;
+use crate::miner::mode::RunMode;
+use crate::miner::{catalog, power};
+use crate::store::db;
+use crate::tariff::{self, MODE_HIGH, MODE_NORMAL, MODE_SLEEP};
+
+static CONFIG: Local<Option<ProfitabilityConfig>> = Local::new(Option::default);
+// machines put to sleep for their model while the fleet runs
+static SLEEPING: Local<HashSet<String>> = Local::new(HashSet::new);
+
+// machines with a record this recent are looked at
+const RECORD_SECONDS: i64 = 3600;
+
+/// one number of a json endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarketSource {
+    /// empty to use the fallback only
+    #[serde(default)]
+    pub url: String,
+    /// json pointer of the number, e.g. "/bitcoin/usd", empty when the body is the number
+    #[serde(default)]
+    pub pointer: String,
+    /// used when the url is empty or fails, 0 fails the policy instead
+    #[serde(default)]
+    pub fallback: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfitabilityConfig {
+    /// in the currency of the electricity prices
+    pub btc_price: MarketSource,
+    pub difficulty: MarketSource,
+    /// btc per block, subsidy plus the usual fees
+    #[serde(default = "default_block_reward")]
+    pub block_reward: f64,
+    #[serde(default)]
+    pub pool_fee_pct: f64,
+    /// per kWh, the current tariff price when a tariff is configured
+    #[serde(default)]
+    pub power_price: f64,
+    /// margin over the revenue at or above which a model runs 高功, with its extra power
+    pub high_min_margin: f64,
+    /// margin below which a model sleeps, 0 sleeps once the power costs more than it earns
+    #[serde(default)]
+    pub sleep_below_margin: f64,
+}
+
+fn default_block_reward() -> f64 {
+    3.125
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Market {
+    pub btc_price: f64,
+    pub difficulty: f64,
+    /// per kWh
+    pub power_price: f64,
+}
+
+impl Market {
+    /// btc one TH/s earns a day, after the pool fee
+    pub fn btc_per_th_day(&self, config: &ProfitabilityConfig) -> f64 {
+        if self.difficulty <= 0.0 {
+            return 0.0;
+        }
+        config.block_reward * 86400.0 * 1e12 / (self.difficulty * 2f64.powi(32))
+            * (1.0 - config.pool_fee_pct / 100.0)
+    }
+}
+
+/// per TH/s and day, in the currency of the prices
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelProfit {
+    /// machine type as reported, empty for models without records
+    pub model: String,
+    /// J/TH in 普通
+    pub efficiency: f64,
+    pub revenue: f64,
+    pub cost_normal: f64,
+    pub cost_high: f64,
+    pub mode: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profitability {
+    pub market: Market,
+    pub models: Vec<ModelProfit>,
+    /// ip to its machine type
+    pub machines: HashMap<String, String>,
+}
+
+impl Profitability {
+    /// mode of the model of the machine, None for machines without a recent record
+    pub fn mode_of_ip(&self, ip: &str) -> Option<&str> {
+        let model = self.machines.get(ip)?;
+        self.models
+            .iter()
+            .find(|m| &m.model == model)
+            .map(|m| m.mode.as_str())
+    }
+
+    /// the best mode of any model, the fleet sleeps only when no model pays
+    pub fn fleet_mode(&self) -> &'static str {
+        let has = |mode: &str| self.models.iter().any(|m| m.mode == mode);
+        if has(MODE_HIGH) {
+            MODE_HIGH
+        } else if has(MODE_NORMAL) || self.models.is_empty() {
+            MODE_NORMAL
+        } else {
+            MODE_SLEEP
+        }
+    }
+}
+
+/// revenue against the power cost of the model
+pub fn decide(config: &ProfitabilityConfig, market: &Market, model: &str) -> ModelProfit {
+    let efficiency = power::model_efficiency(model);
+    let revenue = market.btc_per_th_day(config) * market.btc_price;
+    let cost_normal = efficiency * 24.0 / 1000.0 * market.power_price;
+    let cost_high = cost_normal * (1.0 + power::high_extra(model));
+    // models without the mode in the catalog never get it
+    let supports = |mode: RunMode| {
+        catalog::lookup(model)
+            .map(|spec| spec.supports(mode))
+            .unwrap_or(true)
+    };
+    let margin = |cost: f64| {
+        if revenue > 0.0 {
+            (revenue - cost) / revenue
+        } else {
+            -1.0
+        }
+    };
+    let mode = if supports(RunMode::High) && margin(cost_high) >= config.high_min_margin {
+        MODE_HIGH
+    } else if margin(cost_normal) >= config.sleep_below_margin || !supports(RunMode::Sleep) {
+        MODE_NORMAL
+    } else {
+        MODE_SLEEP
+    };
+    ModelProfit {
+        model: model.to_string(),
+        efficiency,
+        revenue,
+        cost_normal,
+        cost_high,
+        mode: mode.to_string(),
+    }
+}
+
+// the number at the pointer, numbers sent as strings too
+fn value_at(value: &Value, pointer: &str) -> Option<f64> {
+    let value = if pointer.is_empty() {
+        value
+    } else {
+        value.pointer(pointer)?
+    };
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+async fn fetch(source: &MarketSource) -> Result<f64, MinerError> {
+    let body = http::default_client()?
+        .get(&source.url)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .await?
+        .text()
+        .await?;
+    let value = serde_json::from_str(&body).unwrap_or(Value::String(body));
+    value_at(&value, &source.pointer)
+        .filter(|v| *v > 0.0)
+        .ok_or_else(|| MinerError::MarketFeedError(source.url.clone()))
+}
+
+// the fetched number, the fallback when the url is empty or fails
+async fn read(source: &MarketSource) -> Result<f64, MinerError> {
+    if !source.url.is_empty() {
+        match fetch(source).await {
+            Ok(value) => return Ok(value),
+            Err(e) if source.fallback > 0.0 => {
+                error!("fetch {} error, use fallback: {:?}", source.url, e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if source.fallback > 0.0 {
+        Ok(source.fallback)
+    } else {
+        Err(MinerError::MarketFeedError(
+            "no url or fallback".to_string(),
+        ))
+    }
+}
+
+pub fn set_config(config: Option<ProfitabilityConfig>) {
+    CONFIG.set(config);
+}
+
+/// market and the mode of every model with a recent record, None when not configured
+pub async fn current() -> Result<Option<Profitability>, MinerError> {
+    let config = match CONFIG.get() {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    let power_price = match tariff::current().await? {
+        Some((price, _)) => price,
+        None => config.power_price,
+    };
+    let market = Market {
+        btc_price: read(&config.btc_price).await?,
+        difficulty: read(&config.difficulty).await?,
+        power_price,
+    };
+
+    let now = chrono::Local::now().timestamp();
+    let machines: HashMap<String, String> = db::query_latest_machine_records(now - RECORD_SECONDS)?
+        .into_iter()
+        .map(|r| (r.ip, r.machine_type))
+        .collect();
+    let mut models: Vec<&String> = machines
+        .values()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    models.sort();
+    let models: Vec<ModelProfit> = models
+        .into_iter()
+        .map(|model| decide(&config, &market, model))
+        .collect();
+    for m in models.iter() {
+        info!(
+            "profitability {}: revenue {:.4} cost {:.4}, mode {}",
+            m.model, m.revenue, m.cost_normal, m.mode
+        );
+    }
+    Ok(Some(Profitability {
+        market,
+        models,
+        machines,
+    }))
+}
+
+/// remember the machines slept for their model
+pub fn set_sleeping(ips: &[String]) {
+    SLEEPING.with(|sleeping| sleeping.extend(ips.iter().cloned()));
+}
+
+/// whether the machine was slept for its model, it is forgotten then
+pub fn take_sleeping(ip: &str) -> bool {
+    SLEEPING.with(|sleeping| sleeping.remove(ip))
+}
+
+/// the whole fleet woke up, the model sleeps are gone with it
+pub fn clear_sleeping() {
+    SLEEPING.with(|sleeping| sleeping.clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profitability_modes() {
+        let config = ProfitabilityConfig {
+            btc_price: MarketSource::default(),
+            difficulty: MarketSource::default(),
+            block_reward: 3.125,
+            pool_fee_pct: 0.0,
+            power_price: 0.05,
+            high_min_margin: 0.5,
+            sleep_below_margin: 0.0,
+        };
+        let market = Market {
+            btc_price: 60000.0,
+            difficulty: 8.6e13,
+            power_price: 0.05,
+        };
+        // about 0.044 a TH/s and day
+        let revenue = market.btc_per_th_day(&config) * market.btc_price;
+        assert!((revenue - 0.0439).abs() < 0.001, "{}", revenue);
+
+        let profit = Profitability {
+            models: ["S21 Pro", "S21", "S17"]
+                .iter()
+                .map(|model| decide(&config, &market, model))
+                .collect(),
+            machines: HashMap::from([("10.0.0.1".to_string(), "Antminer S17".to_string())]),
+            ..Default::default()
+        };
+        let modes: Vec<&str> = profit.models.iter().map(|m| m.mode.as_str()).collect();
+        assert_eq!(modes, [MODE_HIGH, MODE_NORMAL, MODE_SLEEP]);
+        assert_eq!(profit.fleet_mode(), MODE_HIGH);
+        assert_eq!(profit.mode_of_ip("10.0.0.1"), None);
+        assert_eq!(Profitability::default().fleet_mode(), MODE_NORMAL);
+
+        let value: Value = serde_json::from_str(r#"{"bitcoin": {"usd": "61000.5"}}"#).unwrap();
+        assert_eq!(value_at(&value, "/bitcoin/usd"), Some(61000.5));
+        assert_eq!(value_at(&Value::from(8.6e13), ""), Some(8.6e13));
+        assert_eq!(value_at(&value, "/bitcoin/eur"), None);
+    }
+}
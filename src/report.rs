@@ -0,0 +1,539 @@
+/// daily or shift summary report built from db records
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::clock;
+use crate::context;
+use crate::error::MinerError;
+use crate::miner::entry::MachineRecord;
+use crate::miner::group::GroupSelector;
+use crate::miner::thermal;
+use crate::notify::alerts::{self, AlertAnalytics};
+use crate::notify::{self, notifier, template, Alert, AlertMachine, Severity};
+use crate::status;
+use crate::store::db;
+
+// no record for longer than this means machine was unreachable
+const OFFLINE_GAP_SECONDS: i64 = 600;
+
+const WORST_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportConfig {
+    /// cron with seconds, e.g. "0 0 8 * * *" for 08:00 every day
+    pub cron: String,
+    /// report covers this many hours before generation time
+    pub span_hours: i64,
+    /// push report through notify sinks
+    pub notify: bool,
+    /// spreadsheet to write report into, empty to skip
+    pub excel: String,
+    pub sheet: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MachineSummary {
+    pub ip: String,
+    pub machine_type: String,
+    /// TH/s
+    pub hash_avg: f64,
+    /// TH/s
+    pub hash_peak: f64,
+    pub downtime_minutes: i64,
+    pub samples: usize,
+    /// average of the samples
+    #[serde(default)]
+    pub rejected_pct: f64,
+    #[serde(default)]
+    pub stale_pct: f64,
+    /// of the last sample, counted since the miner started
+    #[serde(default)]
+    pub hw_errors: i64,
+    /// watts, average of the samples reporting it
+    #[serde(default)]
+    pub power_avg: f64,
+    /// hottest board, average of the samples reporting it
+    #[serde(default)]
+    pub temp_avg: f64,
+}
+
+impl MachineSummary {
+    /// average against own peak, low value means degraded or often down
+    pub fn hash_ratio(&self) -> f64 {
+        if self.hash_peak > 0.0 {
+            self.hash_avg / self.hash_peak
+        } else {
+            0.0
+        }
+    }
+
+    /// TH/s per kW, 0 without power
+    pub fn efficiency(&self) -> f64 {
+        if self.power_avg > 0.0 {
+            self.hash_avg / (self.power_avg / 1000.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// what machines are ranked by
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RankMetric {
+    /// TH/s per kW, highest first
+    Efficiency,
+    /// average of the hottest board, coolest first
+    Temp,
+    /// downtime minutes, least first
+    Downtime,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DailyReport {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub machine_count: usize,
+    /// sum of per machine average, TH/s
+    pub fleet_hash_avg: f64,
+    pub switches: i64,
+    pub alerts: i64,
+    pub machines: Vec<MachineSummary>,
+    pub worst: Vec<MachineSummary>,
+    /// alerts of the stored alerts per kind and the machines alerting most
+    #[serde(default)]
+    pub alert_stats: AlertAnalytics,
+}
+
+/// ips and models of the inventory and the loaded sheets, listed even without records
+pub fn known_machines() -> Result<Vec<(String, String)>, MinerError> {
+    let mut machines: BTreeMap<String, String> = db::query_inventory()?
+        .into_iter()
+        .filter(|entry| !entry.ip.is_empty())
+        .map(|entry| (entry.ip, entry.machine_type))
+        .collect();
+    for ip in GroupSelector::All.resolve() {
+        machines.entry(ip).or_default();
+    }
+    Ok(machines.into_iter().collect())
+}
+
+/// summarize records per machine, records of one ip must be ordered by time. known machines
+/// without a record were down the whole range
+pub fn summarize(
+    records: &[MachineRecord],
+    known: &[(String, String)],
+    start_time: i64,
+    end_time: i64,
+) -> Vec<MachineSummary> {
+    let mut by_ip: BTreeMap<&str, Vec<&MachineRecord>> = BTreeMap::new();
+    for (ip, _) in known.iter() {
+        by_ip.entry(ip.as_str()).or_default();
+    }
+    for record in records.iter() {
+        by_ip.entry(record.ip.as_str()).or_default().push(record);
+    }
+
+    let mut summaries = vec![];
+    for (ip, records) in by_ip {
+        if records.is_empty() {
+            let machine_type = known
+                .iter()
+                .find(|(known_ip, _)| known_ip == ip)
+                .map(|(_, machine_type)| machine_type.clone())
+                .unwrap_or_default();
+            summaries.push(MachineSummary {
+                ip: ip.to_string(),
+                machine_type,
+                downtime_minutes: (end_time - start_time).max(0) / 60,
+                ..Default::default()
+            });
+            continue;
+        }
+        let mut downtime = 0;
+        let first = records[0].create_time;
+        if first - start_time > OFFLINE_GAP_SECONDS {
+            downtime += first - start_time;
+        }
+        for pair in records.windows(2) {
+            let dt = pair[1].create_time - pair[0].create_time;
+            if pair[0].hash_real <= 0.0 || dt > OFFLINE_GAP_SECONDS {
+                downtime += dt;
+            }
+        }
+        let last = records[records.len() - 1];
+        let tail = end_time - last.create_time;
+        if last.hash_real <= 0.0 || tail > OFFLINE_GAP_SECONDS {
+            downtime += tail.max(0);
+        }
+
+        let hash_sum: f64 = records.iter().map(|r| r.hash_avg).sum();
+        let hash_peak = records.iter().map(|r| r.hash_avg).fold(0.0, f64::max);
+        let average = |pct: fn(&MachineRecord) -> f64| {
+            records.iter().map(|r| pct(r)).sum::<f64>() / records.len() as f64
+        };
+        // samples without the value are left out
+        let reported = |value: fn(&MachineRecord) -> f64| {
+            let values: Vec<f64> = records
+                .iter()
+                .map(|r| value(r))
+                .filter(|v| *v > 0.0)
+                .collect();
+            if values.is_empty() {
+                0.0
+            } else {
+                values.iter().sum::<f64>() / values.len() as f64
+            }
+        };
+        summaries.push(MachineSummary {
+            ip: ip.to_string(),
+            machine_type: last.machine_type.clone(),
+            hash_avg: hash_sum / records.len() as f64 / 1000.0,
+            hash_peak: hash_peak / 1000.0,
+            downtime_minutes: downtime / 60,
+            samples: records.len(),
+            rejected_pct: average(|r| r.rejected_pct),
+            stale_pct: average(|r| r.stale_pct),
+            hw_errors: last.hw_errors,
+            power_avg: reported(|r| r.power as f64),
+            temp_avg: reported(thermal::max_temp),
+        });
+    }
+
+    summaries
+}
+
+/// machines best first by the metric
+pub fn rank(mut machines: Vec<MachineSummary>, metric: RankMetric) -> Vec<MachineSummary> {
+    let key = |m: &MachineSummary| match metric {
+        RankMetric::Efficiency => -m.efficiency(),
+        // machines without temperature go last
+        RankMetric::Temp if m.temp_avg <= 0.0 => f64::MAX,
+        RankMetric::Temp => m.temp_avg,
+        RankMetric::Downtime => m.downtime_minutes as f64,
+    };
+    machines.sort_by(|a, b| {
+        key(a)
+            .partial_cmp(&key(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    machines
+}
+
+/// rank the machines over the time range from db
+pub fn rank_machines(
+    metric: RankMetric,
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<MachineSummary>, MinerError> {
+    let records = db::query_all_records_by_time(start_time, end_time)?;
+    let known = known_machines()?;
+    Ok(rank(
+        summarize(&records, &known, start_time, end_time),
+        metric,
+    ))
+}
+
+pub fn build_report(
+    records: &[MachineRecord],
+    known: &[(String, String)],
+    switches: i64,
+    alerts: i64,
+    start_time: i64,
+    end_time: i64,
+) -> DailyReport {
+    let machines = summarize(records, known, start_time, end_time);
+
+    let mut worst = machines.clone();
+    worst.sort_by(|a, b| {
+        a.hash_ratio()
+            .partial_cmp(&b.hash_ratio())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(b.downtime_minutes.cmp(&a.downtime_minutes))
+    });
+    worst.truncate(WORST_COUNT);
+
+    DailyReport {
+        start_time,
+        end_time,
+        machine_count: machines.len(),
+        fleet_hash_avg: machines.iter().map(|m| m.hash_avg).sum(),
+        switches,
+        alerts,
+        machines,
+        worst,
+        alert_stats: AlertAnalytics::default(),
+    }
+}
+
+/// build report of time range from db
+pub fn generate_report(start_time: i64, end_time: i64) -> Result<DailyReport, MinerError> {
+    let records = db::query_all_records_by_time(start_time, end_time)?;
+    let switches = db::count_events(db::EVENT_SWITCH, start_time, end_time)?;
+    let alerts = db::count_events(db::EVENT_ALERT, start_time, end_time)?;
+    let known = known_machines()?;
+    let mut report = build_report(&records, &known, switches, alerts, start_time, end_time);
+    report.alert_stats = alerts::analytics(start_time, end_time, WORST_COUNT)?;
+    Ok(report)
+}
+
+impl DailyReport {
+    pub fn to_alert(&self) -> Alert {
+        let format_time = |t: i64| {
+            chrono::DateTime::from_timestamp(t, 0)
+                .map(|t| clock::at(t).format("%m-%d %H:%M").to_string())
+                .unwrap_or_default()
+        };
+        let downtime: i64 = self.machines.iter().map(|m| m.downtime_minutes).sum();
+        let mut content = template::render(
+            "report.content",
+            &[
+                ("machines", &self.machine_count),
+                ("hash", &format!("{:.2}", self.fleet_hash_avg)),
+                ("downtime", &downtime),
+                ("switches", &self.switches),
+                ("alerts", &self.alerts),
+            ],
+        );
+        let stats = &self.alert_stats;
+        if !stats.by_machine.is_empty() {
+            let offenders = stats
+                .by_machine
+                .iter()
+                .map(|m| format!("{}×{}", m.key, m.raised))
+                .collect::<Vec<String>>()
+                .join(", ");
+            content = template::render(
+                "report.alerts",
+                &[
+                    ("content", &content),
+                    ("machines", &offenders),
+                    ("mttr", &format!("{:.0}", stats.mttr_minutes)),
+                ],
+            );
+        }
+
+        Alert {
+            kind: "report".to_string(),
+            title: template::render(
+                "report.title",
+                &[
+                    ("start", &format_time(self.start_time)),
+                    ("end", &format_time(self.end_time)),
+                ],
+            ),
+            severity: Severity::Info,
+            content,
+            machines: self
+                .worst
+                .iter()
+                .map(|m| AlertMachine {
+                    ip: m.ip.clone(),
+                    detail: template::render(
+                        "report.detail",
+                        &[
+                            ("model", &m.machine_type),
+                            ("avg", &format!("{:.2}", m.hash_avg)),
+                            ("peak", &format!("{:.2}", m.hash_peak)),
+                            ("downtime", &m.downtime_minutes),
+                            ("rejected", &format!("{:.2}", m.rejected_pct)),
+                        ],
+                    ),
+                })
+                .collect(),
+        }
+    }
+
+    /// summary row, one row per machine, then the machines alerting most, all as text
+    pub fn to_rows(&self) -> Vec<Vec<Value>> {
+        let text = |s: String| Value::String(s);
+        let header = |key: &str| text(template::render(key, &[]));
+        let mut rows = vec![
+            vec![
+                header("report_header.machines"),
+                header("report_header.fleet_hash"),
+                header("report_header.switches"),
+                header("report_header.alerts"),
+                text("".to_string()),
+                text("".to_string()),
+                text("".to_string()),
+                text("".to_string()),
+            ],
+            vec![
+                text(self.machine_count.to_string()),
+                text(format!("{:.2}", self.fleet_hash_avg)),
+                text(self.switches.to_string()),
+                text(self.alerts.to_string()),
+                text("".to_string()),
+                text("".to_string()),
+                text("".to_string()),
+                text("".to_string()),
+            ],
+            vec![
+                text("IP".to_string()),
+                header("report_header.model"),
+                header("report_header.avg"),
+                header("report_header.peak"),
+                header("report_header.downtime"),
+                header("report_header.samples"),
+                header("report_header.rejected"),
+                header("report_header.hw_errors"),
+            ],
+        ];
+        for m in self.machines.iter() {
+            rows.push(vec![
+                text(m.ip.clone()),
+                text(m.machine_type.clone()),
+                text(format!("{:.2}", m.hash_avg)),
+                text(format!("{:.2}", m.hash_peak)),
+                text(m.downtime_minutes.to_string()),
+                text(m.samples.to_string()),
+                text(format!("{:.2}", m.rejected_pct)),
+                text(m.hw_errors.to_string()),
+            ]);
+        }
+        if !self.alert_stats.by_machine.is_empty() {
+            rows.push(vec![
+                header("report_header.alert_ip"),
+                header("report_header.alert_kinds"),
+                header("report_header.raised"),
+                header("report_header.resolved"),
+                header("report_header.mttr"),
+                text("".to_string()),
+                text("".to_string()),
+                text("".to_string()),
+            ]);
+        }
+        for m in self.alert_stats.by_machine.iter() {
+            rows.push(vec![
+                text(m.key.clone()),
+                text(m.alerts.to_string()),
+                text(m.raised.to_string()),
+                text(m.resolved.to_string()),
+                text(format!("{:.0}", m.mttr_minutes)),
+                text("".to_string()),
+                text("".to_string()),
+                text("".to_string()),
+            ]);
+        }
+        rows
+    }
+}
+
+/// generate report for the span ending now, then push and write as configured
+pub async fn run_report(config: &ReportConfig) -> Result<DailyReport, MinerError> {
+    let end_time = chrono::Local::now().timestamp();
+    let report = generate_report(end_time - config.span_hours * 3600, end_time)?;
+
+    if config.notify {
+        notifier::send_alert(&report.to_alert()).await;
+    }
+
+    if !config.excel.is_empty() && !config.sheet.is_empty() {
+        let rows = report.to_rows();
+        let range = format!("{}!A1:H{}", config.sheet, rows.len());
+        notify::update_sheet_range(&config.excel, &range, rows).await?;
+    }
+
+    Ok(report)
+}
+
+pub fn start_report_task(
+    runtime: tokio::runtime::Handle,
+    config: ReportConfig,
+) -> Result<tokio::task::JoinHandle<()>, MinerError> {
+    let schedule = cron::Schedule::from_str(&config.cron)?;
+    Ok(context::spawn(&runtime, async move {
+        while let Some(next) = schedule.after(&clock::now()).next() {
+            let wait = (next - clock::now()).to_std().unwrap_or_default();
+            if !status::wait("report", wait).await {
+                break;
+            }
+
+            info!("report task scheduled.");
+            if let Err(e) = run_report(&config).await {
+                error!("report task error: {:?}", e);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(ip: &str, hash: f64, time: i64) -> MachineRecord {
+        MachineRecord {
+            ip: ip.to_string(),
+            machine_type: "1246".to_string(),
+            hash_real: hash,
+            hash_avg: hash,
+            create_time: time,
+            rejected_pct: if hash > 0.0 { 0.5 } else { 0.0 },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_report() {
+        let records = vec![
+            record("192.168.1.2", 90000.0, 0),
+            record("192.168.1.2", 90000.0, 300),
+            record("192.168.1.2", 90000.0, 600),
+            // down from 300 to 600, then missing until end
+            record("192.168.1.3", 80000.0, 0),
+            record("192.168.1.3", 0.0, 300),
+            record("192.168.1.3", 40000.0, 600),
+        ];
+        let report = build_report(&records, &[], 3, 1, 0, 1500);
+        assert_eq!(report.machine_count, 2);
+        assert_eq!(report.switches, 3);
+        assert_eq!(report.machines[0].downtime_minutes, 15);
+        assert_eq!(report.machines[1].downtime_minutes, 20);
+        assert!((report.fleet_hash_avg - 130.0).abs() < 0.001);
+        assert_eq!(report.worst[0].ip, "192.168.1.3");
+        assert!((report.machines[0].rejected_pct - 0.5).abs() < 0.001);
+        assert!((report.machines[1].rejected_pct - 1.0 / 3.0).abs() < 0.001);
+        assert!(report.to_rows().iter().all(|row| row.len() == 8));
+
+        let mut report = report;
+        report.alert_stats.by_machine.push(alerts::AlertStats {
+            key: "192.168.1.3".to_string(),
+            alerts: 2,
+            raised: 6,
+            ..Default::default()
+        });
+        assert!(report.to_alert().content.contains("192.168.1.3×6"));
+        let rows = report.to_rows();
+        assert_eq!(rows.len(), 3 + 2 + 2);
+        assert!(rows.iter().all(|row| row.len() == 8));
+
+        let mut machines = report.machines.clone();
+        machines[0].power_avg = 3000.0;
+        machines[1].power_avg = 1000.0;
+        machines[0].temp_avg = 70.0;
+        let by_efficiency = rank(machines.clone(), RankMetric::Efficiency);
+        // 40 TH/s per kW against 30
+        assert_eq!(by_efficiency[0].ip, "192.168.1.3");
+        assert_eq!(
+            rank(machines.clone(), RankMetric::Temp)[0].ip,
+            "192.168.1.2"
+        );
+        assert_eq!(rank(machines, RankMetric::Downtime)[0].ip, "192.168.1.2");
+
+        // a known machine without records was down the whole range
+        let known = [
+            ("192.168.1.2".to_string(), "1246".to_string()),
+            ("192.168.1.4".to_string(), "1346".to_string()),
+        ];
+        let machines = summarize(&records, &known, 0, 1500);
+        assert_eq!(machines.len(), 3);
+        assert_eq!(machines[2].ip, "192.168.1.4");
+        assert_eq!(machines[2].machine_type, "1346");
+        assert_eq!(machines[2].downtime_minutes, 25);
+        assert_eq!(machines[2].samples, 0);
+    }
+}
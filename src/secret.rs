@@ -0,0 +1,364 @@
+/// secrets referenced from config as "secret:<name>", read from the os keychain or an
+/// encrypted file instead of being kept in plain config
+use std::fmt;
+#[cfg(feature = "engine")]
+use std::{
+    collections::{BTreeMap, HashMap},
+    process::Command,
+    sync::Mutex,
+};
+
+#[cfg(feature = "engine")]
+use chacha20poly1305::aead::rand_core::RngCore;
+#[cfg(feature = "engine")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+#[cfg(feature = "engine")]
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+#[cfg(feature = "engine")]
+use log::info;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "engine")]
+use sha2::Sha256;
+
+#[cfg(feature = "engine")]
+use crate::context::Local;
+use crate::error::MinerError;
+
+const PREFIX: &str = "secret:";
+#[cfg(feature = "engine")]
+const MASTER_KEY_ENV: &str = "LCD_MASTER_KEY";
+#[cfg(feature = "engine")]
+const SALT_LEN: usize = 16;
+#[cfg(feature = "engine")]
+const NONCE_LEN: usize = 12;
+#[cfg(feature = "engine")]
+const KDF_ROUNDS: u32 = 100_000;
+
+// keychain and file of the site
+#[cfg(feature = "engine")]
+static CONFIG: Local<SecretConfig> = Local::new(SecretConfig::default);
+// resolved secrets by name, dropped when the secret or the config changes
+#[cfg(feature = "engine")]
+static CACHE: Local<HashMap<String, String>> = Local::new(HashMap::new);
+
+#[cfg(feature = "engine")]
+lazy_static! {
+    // master key and salt of the last derived key, pbkdf2 is slow on purpose
+    static ref DERIVED: Mutex<Option<(String, Vec<u8>, Key)>> = Mutex::new(None);
+}
+
+#[cfg(feature = "engine")]
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SecretConfig {
+    /// service name of the keychain entries, empty to skip the keychain
+    pub keychain_service: String,
+    /// encrypted secrets file, empty to skip
+    pub file: String,
+    /// master key of the file, LCD_MASTER_KEY from the environment when empty
+    pub master_key: String,
+}
+
+#[cfg(feature = "engine")]
+impl fmt::Debug for SecretConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretConfig")
+            .field("keychain_service", &self.keychain_service)
+            .field("file", &self.file)
+            .field("master_key", &redact(&self.master_key))
+            .finish()
+    }
+}
+
+#[cfg(feature = "engine")]
+pub fn set_config(config: SecretConfig) {
+    CONFIG.set(config);
+    CACHE.with(|cache| cache.clear());
+}
+
+/// masked value for logs
+pub fn redact(value: &str) -> &'static str {
+    if value.is_empty() {
+        ""
+    } else {
+        "******"
+    }
+}
+
+/// password or token, masked in Debug and Display, serialized as the plain value
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: impl Into<String>) -> Self {
+        SecretString(value.into())
+    }
+
+    /// the real value, only for where it is sent to the miner, pool or api
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        SecretString::new(value)
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        SecretString(value)
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", redact(&self.0))
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(redact(&self.0))
+    }
+}
+
+/// the value itself, or the named secret when it is "secret:<name>"
+pub fn resolve(value: &str) -> Result<String, MinerError> {
+    match value.strip_prefix(PREFIX) {
+        Some(name) => get(name.trim()),
+        None => Ok(value.to_string()),
+    }
+}
+
+#[cfg(feature = "engine")]
+/// keychain first, then the encrypted file. values are read once and kept
+pub fn get(name: &str) -> Result<String, MinerError> {
+    if let Some(value) = CACHE.with(|cache| cache.get(name).cloned()) {
+        return Ok(value);
+    }
+    let value = lookup(name)?;
+    CACHE.with(|cache| cache.insert(name.to_string(), value.clone()));
+    Ok(value)
+}
+
+#[cfg(feature = "engine")]
+fn lookup(name: &str) -> Result<String, MinerError> {
+    let config = CONFIG.get();
+    if !config.keychain_service.is_empty() {
+        if let Some(value) = keychain_get(&config.keychain_service, name) {
+            return Ok(value);
+        }
+    }
+    if !config.file.is_empty() {
+        if let Some(value) = read_file(&config)?.remove(name) {
+            return Ok(value);
+        }
+    }
+    Err(MinerError::SecretNotFoundError(name.to_string()))
+}
+
+/// no keychain nor secrets file without the engine, "secret:" values cannot be resolved
+#[cfg(not(feature = "engine"))]
+pub fn get(_name: &str) -> Result<String, MinerError> {
+    Err(MinerError::FeatureDisabledError("engine".to_string()))
+}
+
+#[cfg(feature = "engine")]
+/// store the secret into the encrypted file
+pub fn set(name: &str, value: &str) -> Result<(), MinerError> {
+    let config = CONFIG.get();
+    let mut secrets = read_file(&config)?;
+    secrets.insert(name.to_string(), value.to_string());
+    write_file(&config, &secrets)?;
+    CACHE.with(|cache| cache.remove(name));
+    info!("secret {} stored", name);
+    Ok(())
+}
+
+#[cfg(feature = "engine")]
+pub fn remove(name: &str) -> Result<(), MinerError> {
+    let config = CONFIG.get();
+    let mut secrets = read_file(&config)?;
+    if secrets.remove(name).is_some() {
+        write_file(&config, &secrets)?;
+    }
+    CACHE.with(|cache| cache.remove(name));
+    Ok(())
+}
+
+#[cfg(feature = "engine")]
+/// names in the encrypted file, values are not returned
+pub fn names() -> Result<Vec<String>, MinerError> {
+    let config = CONFIG.get();
+    Ok(read_file(&config)?.into_keys().collect())
+}
+
+#[cfg(feature = "engine")]
+fn master_key(config: &SecretConfig) -> Result<String, MinerError> {
+    if !config.master_key.is_empty() {
+        return Ok(config.master_key.clone());
+    }
+    std::env::var(MASTER_KEY_ENV)
+        .ok()
+        .filter(|key| !key.is_empty())
+        .ok_or(MinerError::SecretError(format!(
+            "{} not set",
+            MASTER_KEY_ENV
+        )))
+}
+
+#[cfg(feature = "engine")]
+fn derive_key(master_key: &str, salt: &[u8]) -> Key {
+    let mut derived = DERIVED.lock().unwrap();
+    if let Some((master, derived_salt, key)) = derived.as_ref() {
+        if master == master_key && derived_salt == salt {
+            return *key;
+        }
+    }
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(master_key.as_bytes(), salt, KDF_ROUNDS, &mut key);
+    let key = Key::from(key);
+    *derived = Some((master_key.to_string(), salt.to_vec(), key));
+    key
+}
+
+#[cfg(feature = "engine")]
+/// salt, nonce, then the sealed data. the salt of the derived key is kept so a rewrite does
+/// not derive again, the nonce is new every time
+pub fn encrypt(master_key: &str, plain: &[u8]) -> Result<Vec<u8>, MinerError> {
+    let mut salt = [0u8; SALT_LEN];
+    match DERIVED.lock().unwrap().as_ref() {
+        Some((master, derived_salt, _)) if master == master_key => {
+            salt.copy_from_slice(derived_salt)
+        }
+        _ => OsRng.fill_bytes(&mut salt),
+    }
+    let cipher = ChaCha20Poly1305::new(&derive_key(master_key, &salt));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher
+        .encrypt(&nonce, plain)
+        .map_err(|_| MinerError::SecretError("encrypt failed".to_string()))?;
+    Ok([&salt[..], &nonce[..], &sealed[..]].concat())
+}
+
+#[cfg(feature = "engine")]
+pub fn decrypt(master_key: &str, data: &[u8]) -> Result<Vec<u8>, MinerError> {
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(MinerError::SecretError(
+            "secrets file too short".to_string(),
+        ));
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().unwrap_or_default();
+    let cipher = ChaCha20Poly1305::new(&derive_key(master_key, salt));
+    cipher
+        .decrypt(&Nonce::from(nonce), sealed)
+        .map_err(|_| MinerError::SecretError("wrong master key or corrupted file".to_string()))
+}
+
+#[cfg(feature = "engine")]
+fn read_file(config: &SecretConfig) -> Result<BTreeMap<String, String>, MinerError> {
+    if config.file.is_empty() {
+        return Err(MinerError::SecretError("secrets file not set".to_string()));
+    }
+    let data = match std::fs::read(&config.file) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let plain = decrypt(&master_key(config)?, &data)?;
+    Ok(serde_json::from_slice(&plain)?)
+}
+
+#[cfg(feature = "engine")]
+fn write_file(config: &SecretConfig, secrets: &BTreeMap<String, String>) -> Result<(), MinerError> {
+    if config.file.is_empty() {
+        return Err(MinerError::SecretError("secrets file not set".to_string()));
+    }
+    let data = encrypt(&master_key(config)?, &serde_json::to_vec(secrets)?)?;
+    // replace in one step so a crash never leaves a half written file
+    let tmp = format!("{}.tmp", config.file);
+    std::fs::write(&tmp, data)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+    }
+    std::fs::rename(&tmp, &config.file)?;
+    Ok(())
+}
+
+#[cfg(feature = "engine")]
+// macos keychain or the freedesktop secret service, through their command line tools
+fn keychain_get(service: &str, name: &str) -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", service, "-a", name, "-w"])
+            .output()
+    } else {
+        Command::new("secret-tool")
+            .args(["lookup", "service", service, "account", name])
+            .output()
+    };
+    match output {
+        Ok(output) if output.status.success() => {
+            let value = String::from_utf8_lossy(&output.stdout)
+                .trim_end_matches(['\r', '\n'])
+                .to_string();
+            (!value.is_empty()).then_some(value)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_string_masked() {
+        let secret = SecretString::from("hunter2");
+        assert_eq!(format!("{}", secret), "******");
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+        assert_eq!(secret.expose(), "hunter2");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"hunter2\"");
+        assert_eq!(format!("{}", SecretString::default()), "");
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn test_secret_file() {
+        let sealed = encrypt("master", b"{\"f2pool\":\"abc\"}").unwrap();
+        assert_eq!(decrypt("master", &sealed).unwrap(), b"{\"f2pool\":\"abc\"}");
+        assert!(decrypt("other", &sealed).is_err());
+
+        let file = std::env::temp_dir().join(format!("lcd-secrets-{}", std::process::id()));
+        set_config(SecretConfig {
+            keychain_service: "".to_string(),
+            file: file.to_string_lossy().to_string(),
+            master_key: "master".to_string(),
+        });
+        set("f2pool", "abc").unwrap();
+        assert_eq!(resolve("secret:f2pool").unwrap(), "abc");
+        assert_eq!(resolve("plain").unwrap(), "plain");
+        assert!(resolve("secret:missing").is_err());
+        let data = std::fs::read(&file).unwrap();
+        assert!(!data.windows(3).any(|w| w == b"abc"));
+
+        // kept after the first read, dropped when the secret changes
+        let _ = std::fs::remove_file(&file);
+        assert_eq!(resolve("secret:f2pool").unwrap(), "abc");
+        set("f2pool", "def").unwrap();
+        assert_eq!(resolve("secret:f2pool").unwrap(), "def");
+        remove("f2pool").unwrap();
+        assert!(resolve("secret:f2pool").is_err());
+        let _ = std::fs::remove_file(&file);
+    }
+}
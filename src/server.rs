@@ -0,0 +1,329 @@
+/// http + json api over the library operations, every request needs the bearer token of a user
+/// and runs with the role of that user
+use std::sync::Arc;
+
+use axum::extract::{Query, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::access::{self, ApiUser, Role};
+use crate::error::MinerError;
+use crate::miner::entry::PoolConfig;
+use crate::miner::group::GroupSelector;
+use crate::secret::SecretString;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// e.g. "0.0.0.0:8420"
+    pub bind: String,
+    /// bearer token of the admin, empty leaves only the users
+    pub token: SecretString,
+    /// users with their own token and role
+    #[serde(default)]
+    pub users: Vec<ApiUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScanRequest {
+    ip: String,
+    offset: i32,
+    count: i32,
+    timeout_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchingRequest {
+    target: GroupSelector,
+    timeout_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RebootRequest {
+    target: GroupSelector,
+    timeout_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfigRequest {
+    target: GroupSelector,
+    pools: Vec<PoolConfig>,
+    run_mode: String,
+    timeout_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordsQuery {
+    #[serde(default)]
+    ip: String,
+    start_time: i64,
+    end_time: i64,
+    #[serde(default)]
+    limit: u32,
+    #[serde(default)]
+    descending: bool,
+    #[serde(default)]
+    after_time: i64,
+    #[serde(default)]
+    after_id: i32,
+    /// comma separated, e.g. "hash_real,power"
+    #[serde(default)]
+    columns: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AckRequest {
+    id: u64,
+    /// who is on it, shown in the incident list
+    by: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AlertRequest {
+    id: i64,
+    /// who is on it, only for acknowledging
+    #[serde(default)]
+    by: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnalyticsQuery {
+    start_time: i64,
+    end_time: i64,
+    #[serde(default = "default_top")]
+    top: usize,
+}
+
+fn default_top() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeQuery {
+    start_time: i64,
+    end_time: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeatmapQuery {
+    start_time: i64,
+    end_time: i64,
+    /// every room when missing
+    #[serde(default)]
+    room: String,
+}
+
+// MinerError serializes to its code, message and context, a missing role is 403
+fn reply<T: Serialize, E: Serialize>(result: Result<T, E>) -> Response {
+    match result {
+        Ok(data) => Json(json!({"ok": true, "data": data})).into_response(),
+        Err(e) => {
+            let error = json!(e);
+            let denied = MinerError::PermissionDeniedError(String::new()).code();
+            let status = if error["code"] == json!(denied) {
+                StatusCode::FORBIDDEN
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(json!({"ok": false, "error": error}))).into_response()
+        }
+    }
+}
+
+fn bearer(headers: &HeaderMap) -> &str {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("")
+}
+
+/// bearer token check, an empty configured token never matches
+pub fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    access::token_matches(bearer(headers), token)
+}
+
+// the request runs as the user of its token
+async fn auth(State(users): State<Arc<Vec<ApiUser>>>, request: Request, next: Next) -> Response {
+    let Some(caller) = access::caller_of(&users, bearer(request.headers())) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"ok": false, "error": "unauthorized"})),
+        )
+            .into_response();
+    };
+    access::scope(caller, next.run(request)).await
+}
+
+async fn scan(Json(req): Json<ScanRequest>) -> Response {
+    let runtime = tokio::runtime::Handle::current();
+    reply(crate::scan(runtime, &req.ip, req.offset, req.count, req.timeout_seconds).await)
+}
+
+async fn scan_diff(Json(req): Json<ScanRequest>) -> Response {
+    let runtime = tokio::runtime::Handle::current();
+    reply(crate::scan_diff(runtime, &req.ip, req.offset, req.count, req.timeout_seconds).await)
+}
+
+async fn watching(Json(req): Json<WatchingRequest>) -> Response {
+    let runtime = tokio::runtime::Handle::current();
+    reply(crate::watching(runtime, req.target, req.timeout_seconds).await)
+}
+
+async fn reboot(Json(req): Json<RebootRequest>) -> Response {
+    let runtime = tokio::runtime::Handle::current();
+    reply(crate::reboot(runtime, req.target, req.timeout_seconds).await)
+}
+
+async fn config_pools(Json(req): Json<ConfigRequest>) -> Response {
+    let runtime = tokio::runtime::Handle::current();
+    reply(
+        crate::config(
+            runtime,
+            req.target,
+            req.pools,
+            req.run_mode,
+            req.timeout_seconds,
+        )
+        .await,
+    )
+}
+
+async fn records(Query(query): Query<RecordsQuery>) -> Response {
+    let page = crate::RecordQuery {
+        limit: query.limit,
+        descending: query.descending,
+        after_time: query.after_time,
+        after_id: query.after_id,
+        columns: query
+            .columns
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.trim().to_string())
+            .collect(),
+    };
+    reply(crate::query_machine_records(
+        &query.ip,
+        query.start_time,
+        query.end_time,
+        &page,
+    ))
+}
+
+async fn power_usage(Query(query): Query<RangeQuery>) -> Response {
+    reply(crate::query_power_usage(query.start_time, query.end_time))
+}
+
+async fn hashrate_anomalies(Query(query): Query<RangeQuery>) -> Response {
+    reply(crate::query_hashrate_anomalies(
+        query.start_time,
+        query.end_time,
+    ))
+}
+
+async fn position_heatmap(Query(query): Query<HeatmapQuery>) -> Response {
+    reply(crate::query_position_heatmap(
+        query.start_time,
+        query.end_time,
+        &query.room,
+    ))
+}
+
+async fn inventory() -> Response {
+    reply(crate::query_inventory())
+}
+
+async fn incidents() -> Response {
+    reply::<_, MinerError>(Ok(crate::incidents()))
+}
+
+async fn ack_incident(Json(req): Json<AckRequest>) -> Response {
+    reply(crate::ack_incident(req.id, &req.by))
+}
+
+async fn alerts(Query(query): Query<crate::AlertQuery>) -> Response {
+    reply(crate::query_alerts(&query))
+}
+
+async fn alert_analytics(Query(query): Query<AnalyticsQuery>) -> Response {
+    reply(crate::alert_analytics(
+        query.start_time,
+        query.end_time,
+        query.top,
+    ))
+}
+
+async fn ack_alert(Json(req): Json<AlertRequest>) -> Response {
+    reply(crate::ack_alert(req.id, &req.by))
+}
+
+async fn resolve_alert(Json(req): Json<AlertRequest>) -> Response {
+    reply(crate::resolve_alert(req.id))
+}
+
+async fn health() -> Json<Value> {
+    Json(json!({"ok": true}))
+}
+
+async fn status() -> Response {
+    reply::<_, MinerError>(Ok(crate::status()))
+}
+
+pub fn router(server: &ServerConfig) -> Router {
+    // an unresolved token stays empty and matches no request
+    let mut users = server.users.clone();
+    users.push(ApiUser {
+        name: "admin".to_string(),
+        token: server.token.clone(),
+        role: Role::Admin,
+    });
+    let users = Arc::new(access::resolve_users(&users));
+    Router::new()
+        .route("/scan", post(scan))
+        .route("/scan/diff", post(scan_diff))
+        .route("/watching", post(watching))
+        .route("/reboot", post(reboot))
+        .route("/config", post(config_pools))
+        .route("/records", get(records))
+        .route("/power_usage", get(power_usage))
+        .route("/anomalies", get(hashrate_anomalies))
+        .route("/inventory", get(inventory))
+        .route("/heatmap", get(position_heatmap))
+        .route("/incidents", get(incidents))
+        .route("/incidents/ack", post(ack_incident))
+        .route("/alerts", get(alerts))
+        .route("/alerts/analytics", get(alert_analytics))
+        .route("/alerts/ack", post(ack_alert))
+        .route("/alerts/resolve", post(resolve_alert))
+        .route("/health", get(health))
+        .route("/status", get(status))
+        .layer(middleware::from_fn_with_state(users, auth))
+}
+
+/// serve until the listener fails
+pub async fn serve(config: ServerConfig) -> Result<(), MinerError> {
+    let listener = tokio::net::TcpListener::bind(&config.bind).await?;
+    info!("lcd server listening on {}", config.bind);
+    axum::serve(listener, router(&config)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorized() {
+        let mut headers = HeaderMap::new();
+        assert!(!authorized(&headers, "secret"));
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        assert!(authorized(&headers, "secret"));
+        assert!(!authorized(&headers, "secreT"));
+        assert!(!authorized(&headers, ""));
+    }
+}
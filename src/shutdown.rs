@@ -0,0 +1,128 @@
+/// process wide shutdown, the long running tasks stop at their next wait and new batch
+/// operations are refused while the running ones finish
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use log::info;
+use tokio::sync::watch;
+
+use crate::error::MinerError;
+use crate::store::db;
+
+lazy_static! {
+    static ref SHUTDOWN: Shutdown = Shutdown::new();
+}
+
+#[derive(Debug)]
+pub struct Shutdown {
+    signal: watch::Sender<bool>,
+    running: AtomicUsize,
+}
+
+/// a batch operation in flight, shutdown waits until every one is dropped
+#[derive(Debug)]
+pub struct Operation<'a> {
+    running: &'a AtomicUsize,
+}
+
+impl Drop for Operation<'_> {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            signal: watch::channel(false).0,
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_signalled(&self) -> bool {
+        *self.signal.borrow()
+    }
+
+    pub fn begin(&self) -> Result<Operation<'_>, MinerError> {
+        self.running.fetch_add(1, Ordering::SeqCst);
+        let operation = Operation {
+            running: &self.running,
+        };
+        if self.is_signalled() {
+            return Err(MinerError::ShuttingDownError);
+        }
+        Ok(operation)
+    }
+
+    /// false when shutdown came before the duration passed
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        let mut signal = self.signal.subscribe();
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => !self.is_signalled(),
+            _ = signal.wait_for(|s| *s) => false,
+        }
+    }
+
+    /// signal and wait for the running operations, returns the ones left at the timeout
+    pub async fn signal(&self, timeout: Duration) -> usize {
+        self.signal.send_replace(true);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let running = self.running.load(Ordering::SeqCst);
+            if running == 0 || tokio::time::Instant::now() >= deadline {
+                return running;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTDOWN.is_signalled()
+}
+
+/// held for the whole batch, fails once shutdown started
+pub fn begin() -> Result<Operation<'static>, MinerError> {
+    SHUTDOWN.begin()
+}
+
+/// wait of the long running tasks, false when they should stop
+pub async fn sleep(duration: Duration) -> bool {
+    SHUTDOWN.sleep(duration).await
+}
+
+/// stop the tasks, wait for the batches in flight up to the timeout and close the db
+pub async fn shutdown(timeout: Duration) -> Result<(), MinerError> {
+    info!("lcd shutting down");
+    let running = SHUTDOWN.signal(timeout).await;
+    db::close();
+    crate::miner::route::close();
+    if running > 0 {
+        return Err(MinerError::ShutdownTimeoutError(running));
+    }
+    info!("lcd shut down");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let shutdown = Shutdown::new();
+        assert!(shutdown.sleep(Duration::from_millis(1)).await);
+
+        let operation = shutdown.begin().unwrap();
+        let (sleep, left) = tokio::join!(
+            shutdown.sleep(Duration::from_secs(60)),
+            shutdown.signal(Duration::from_millis(200))
+        );
+        assert!(!sleep);
+        assert_eq!(left, 1);
+        assert!(shutdown.begin().is_err());
+
+        drop(operation);
+        assert_eq!(shutdown.signal(Duration::from_millis(200)).await, 0);
+    }
+}
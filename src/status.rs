@@ -0,0 +1,119 @@
+/// engine health for the embedding app, liveness of the long running tasks and the last
+/// result of the main subsystems
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::context::Local;
+use crate::miner::schedule::{self, SwitchRun};
+use crate::notify::notifier;
+use crate::pools::pool::{self, PoolQueryStatus};
+use crate::shutdown;
+use crate::store::db;
+
+// a task not back to its wait this long after the planned wake up is not alive, the
+// work in between, e.g. a staggered switch, takes a while
+const GRACE_SECONDS: i64 = 900;
+
+static TASKS: Local<BTreeMap<String, TaskStatus>> = Local::new(BTreeMap::new);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskStatus {
+    pub name: String,
+    /// last time the task went to wait
+    pub last_beat: i64,
+    /// when it should wake up next
+    pub next_beat: i64,
+    /// left its loop, e.g. on shutdown
+    pub stopped: bool,
+    pub alive: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EngineStatus {
+    pub time: i64,
+    pub shutting_down: bool,
+    /// None before the first pool query of this process
+    pub pool_query: Option<PoolQueryStatus>,
+    pub last_switch: Option<SwitchRun>,
+    /// 0 without db
+    pub db_bytes: i64,
+    /// failed sends per notifier name since start
+    pub notify_errors: BTreeMap<String, u64>,
+    pub tasks: Vec<TaskStatus>,
+}
+
+/// the wait of a long running task loop, false when the task should stop
+pub async fn wait(task: &str, duration: Duration) -> bool {
+    let now = chrono::Local::now().timestamp();
+    beat(task, now, now + duration.as_secs() as i64);
+    let running = shutdown::sleep(duration).await;
+    if !running {
+        TASKS.with(|tasks| {
+            if let Some(status) = tasks.get_mut(task) {
+                status.stopped = true;
+            }
+        });
+    }
+    running
+}
+
+fn beat(task: &str, now: i64, next: i64) {
+    TASKS.with(|tasks| {
+        tasks.insert(
+            task.to_string(),
+            TaskStatus {
+                name: task.to_string(),
+                last_beat: now,
+                next_beat: next,
+                ..Default::default()
+            },
+        )
+    });
+}
+
+fn task_status(mut status: TaskStatus, now: i64) -> TaskStatus {
+    status.alive = !status.stopped && now <= status.next_beat + GRACE_SECONDS;
+    status
+}
+
+pub fn status() -> EngineStatus {
+    let now = chrono::Local::now().timestamp();
+    EngineStatus {
+        time: now,
+        shutting_down: shutdown::is_shutting_down(),
+        pool_query: pool::last_query(),
+        last_switch: schedule::last_run(),
+        db_bytes: db::size().unwrap_or(0),
+        notify_errors: notifier::error_counts(),
+        tasks: TASKS
+            .get()
+            .into_values()
+            .map(|status| task_status(status, now))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_alive() {
+        let status = TaskStatus {
+            name: "pool_query".to_string(),
+            last_beat: 1000,
+            next_beat: 1300,
+            ..Default::default()
+        };
+        assert!(task_status(status.clone(), 1310).alive);
+        // missed its wake up, e.g. the task panicked
+        assert!(!task_status(status.clone(), 1300 + GRACE_SECONDS + 1).alive);
+        let stopped = TaskStatus {
+            stopped: true,
+            ..status
+        };
+        assert!(!task_status(stopped, 1000).alive);
+    }
+}
@@ -0,0 +1,64 @@
+/// per-site caches of db tables, loaded on first use so values survive restarts
+use crate::context::Local;
+
+pub struct DbCache<T> {
+    value: Local<Option<T>>,
+    load: fn() -> T,
+}
+
+impl<T: Send + 'static> DbCache<T> {
+    pub const fn new(load: fn() -> T) -> Self {
+        DbCache {
+            value: Local::new(Option::default),
+            load,
+        }
+    }
+
+    pub fn with<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.value
+            .with(|cache| f(cache.get_or_insert_with(self.load)))
+    }
+
+    /// load the db just opened, in case the cache was used before
+    pub fn reload(&'static self) {
+        self.value.set(Some((self.load)()));
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::context::{self, Context};
+    use crate::store::db;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    static MAINTENANCE: DbCache<HashMap<String, i64>> =
+        DbCache::new(|| db::query_maintenance(0).unwrap().into_iter().collect());
+
+    #[test]
+    fn test_cache_survives_restart() {
+        let path = std::env::temp_dir().join(format!("lcd-cache-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        context::enter(Arc::new(Context::default()), || {
+            // used before the db is opened
+            assert!(MAINTENANCE.with(|entries| entries.is_empty()));
+            db::init(&path).unwrap();
+            MAINTENANCE.reload();
+            db::set_maintenance("10.9.0.1", 3600).unwrap();
+            assert!(MAINTENANCE.with(|entries| entries.is_empty()));
+
+            // reopened as on a restart
+            db::init(&path).unwrap();
+            MAINTENANCE.reload();
+            assert_eq!(
+                MAINTENANCE.with(|entries| entries.get("10.9.0.1").copied()),
+                Some(3600)
+            );
+        });
+        context::enter(Arc::new(Context::default()), || {
+            assert!(MAINTENANCE.with(|entries| entries.is_empty()));
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,32 +1,130 @@
-use std::{path::Path, sync::Mutex};
+#![cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+#[cfg(feature = "sqlite")]
+use std::path::Path;
 
-use crate::{miner::entry::MachineRecord, pools::pool::PoolWorker};
-use log::info;
-use rusqlite::{params, Connection};
+use crate::miner::inventory::{InventoryEntry, ScannedMachine};
+use crate::notify::alerts::{AlertEntry, AlertQuery, AlertState};
+use crate::notify::Alert;
+#[cfg(feature = "sqlite")]
+use crate::pools::pool::{is_worker_of, WorkerName};
+use crate::{
+    miner::entry::{MachineRecord, SwitchState},
+    pools::pool::{worker_name, PoolEarning, PoolWorker},
+    pools::proxy::ProxyStatus,
+};
+use log::{error, info};
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, DatabaseName};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sqlite")]
 use std::fs;
 
+use super::retention::RetentionConfig;
+use crate::context;
 use crate::error::MinerError;
+use crate::status;
 
-lazy_static! {
-    static ref LCD_DB: Mutex<Option<DB>> = Mutex::new(None);
+// t_event types
+pub const EVENT_SWITCH: &str = "switch";
+pub const EVENT_ALERT: &str = "alert";
+pub const EVENT_CURTAIL: &str = "curtail";
+pub const EVENT_THERMAL: &str = "thermal";
+pub const EVENT_RECONCILE: &str = "reconcile";
+pub const EVENT_SWITCH_RUN: &str = "switch_run";
+pub const EVENT_CRASH_LOOP: &str = "crash_loop";
+pub const EVENT_ESCALATION: &str = "escalation";
+pub const EVENT_PROFITABILITY: &str = "profitability";
+pub const EVENT_IP_CHANGE: &str = "ip_change";
+pub const EVENT_OPERATION: &str = "operation";
+pub const EVENT_IMPLAUSIBLE_HASH: &str = "implausible_hash";
+
+/// db_path of a db kept in memory, for tests
+#[cfg(feature = "sqlite")]
+pub const MEMORY: &str = ":memory:";
+
+// columns of t_machine_record in MachineRecord order
+#[cfg(feature = "sqlite")]
+const RECORD_COLUMNS: [&str; 16] = [
+    "id",
+    "ip",
+    "machine_type",
+    "work_mode",
+    "hash_real",
+    "hash_avg",
+    "temp_0",
+    "temp_1",
+    "temp_2",
+    "power",
+    "create_time",
+    "hw_errors",
+    "accepted_pct",
+    "rejected_pct",
+    "stale_pct",
+    "elapsed",
+];
+
+/// paging, order and columns of a machine record query, the default reads every row
+/// oldest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordQuery {
+    /// rows of one page, 0 for every row
+    #[serde(default)]
+    pub limit: u32,
+    /// newest first
+    #[serde(default)]
+    pub descending: bool,
+    /// create_time and id of the last row of the previous page, after_id 0 for the first page
+    #[serde(default)]
+    pub after_time: i64,
+    #[serde(default)]
+    pub after_id: i32,
+    /// MachineRecord fields read, empty for all. id, ip and create_time are always read,
+    /// the others stay default
+    #[serde(default)]
+    pub columns: Vec<String>,
+}
+
+// run on the db of the current context, the none value without db
+#[cfg(feature = "sqlite")]
+macro_rules! with_db {
+    (|$db:ident| $some:expr, $none:expr) => {{
+        let context = context::current();
+        let db = context.db.lock().unwrap();
+        match &*db {
+            Some($db) => $some,
+            None => $none,
+        }
+    }};
+}
+
+// built without sqlite there never is a db
+#[cfg(not(feature = "sqlite"))]
+macro_rules! with_db {
+    (|$db:ident| $some:expr, $none:expr) => {
+        $none
+    };
 }
 
 /// Sqlite DB
+#[cfg(feature = "sqlite")]
 pub struct DB {
     conn: Connection,
 }
 
+#[cfg(feature = "sqlite")]
 impl DB {
-    pub fn new(app_path: &str) -> Result<Self, MinerError> {
-        info!("init sqlite db {}", app_path);
-
-        let db_path = get_db_path(app_path);
-
-        if !db_file_exists(&db_path) {
-            create_db_file(app_path);
-        }
+    /// open the sqlite file, creating its directory, or an in-memory db for MEMORY
+    pub fn new(db_path: &str) -> Result<Self, MinerError> {
+        info!("init sqlite db {}", db_path);
 
-        let conn = Connection::open(&db_path).unwrap();
+        let conn = if db_path == MEMORY {
+            Connection::open_in_memory()?
+        } else {
+            if let Some(db_dir) = Path::new(db_path).parent() {
+                fs::create_dir_all(db_dir)?;
+            }
+            Connection::open(db_path)?
+        };
 
         // main table of miners
         conn.execute(
@@ -41,10 +139,20 @@ impl DB {
                   temp_1          REAL,
                   temp_2          REAL,
                   power           INTEGER,
-                  create_time     INTEGER
+                  create_time     INTEGER,
+                  hw_errors       INTEGER,
+                  accepted_pct    REAL,
+                  rejected_pct    REAL,
+                  stale_pct       REAL,
+                  elapsed         INTEGER
                   )",
             [],
         )?;
+        add_column_if_missing(&conn, "t_machine_record", "hw_errors", "INTEGER")?;
+        add_column_if_missing(&conn, "t_machine_record", "accepted_pct", "REAL")?;
+        add_column_if_missing(&conn, "t_machine_record", "rejected_pct", "REAL")?;
+        add_column_if_missing(&conn, "t_machine_record", "stale_pct", "REAL")?;
+        add_column_if_missing(&conn, "t_machine_record", "elapsed", "INTEGER")?;
 
         // pool record
         conn.execute(
@@ -54,56 +162,1134 @@ impl DB {
                   hash_real       REAL,
                   hash_avg        REAL,
                   pool_type       TEXT,
+                  time_stamp      INTEGER,
+                  account         TEXT
+                  )",
+            [],
+        )?;
+        add_column_if_missing(&conn, "t_pool_record", "account", "TEXT")?;
+
+        // pool daily revenue and payouts, refetched rows replace old ones
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_pool_earnings (
+                  id              INTEGER PRIMARY KEY,
+                  pool_type       TEXT NOT NULL,
+                  account         TEXT NOT NULL,
+                  kind            TEXT NOT NULL,
+                  date            TEXT NOT NULL,
+                  amount          REAL,
+                  hash_rate       REAL,
+                  tx_id           TEXT NOT NULL,
+                  time_stamp      INTEGER,
+                  UNIQUE (pool_type, account, kind, date, tx_id)
+                  )",
+            [],
+        )?;
+
+        // local stratum proxy status, upstreams as json
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_proxy_record (
+                  id              INTEGER PRIMARY KEY,
+                  name            TEXT NOT NULL,
+                  reachable       INTEGER,
+                  upstreams       TEXT,
+                  hash_real       REAL,
+                  workers         INTEGER,
                   time_stamp      INTEGER
                   )",
             [],
         )?;
 
-        Ok(Self { conn })
+        // events for reports, like switch and alert
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_event (
+                  id              INTEGER PRIMARY KEY,
+                  event_type      TEXT NOT NULL,
+                  ip              TEXT,
+                  detail          TEXT,
+                  create_time     INTEGER
+                  )",
+            [],
+        )?;
+
+        // machines under maintenance until the time, skipped by switch and alerts
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_maintenance (
+                  ip              TEXT PRIMARY KEY,
+                  until           INTEGER,
+                  create_time     INTEGER
+                  )",
+            [],
+        )?;
+
+        // last applied account per machine, error is empty on success
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_switch_state (
+                  ip              TEXT PRIMARY KEY,
+                  account         TEXT,
+                  run_mode        TEXT,
+                  error           TEXT,
+                  update_time     INTEGER
+                  )",
+            [],
+        )?;
+
+        // fleet mode last applied per machine by the tariff or profitability policy
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_applied_mode (
+                  ip              TEXT PRIMARY KEY,
+                  mode            TEXT,
+                  update_time     INTEGER
+                  )",
+            [],
+        )?;
+
+        // machines curtailed until restored, step as json
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_curtail (
+                  ip              TEXT PRIMARY KEY,
+                  body            TEXT NOT NULL,
+                  create_time     INTEGER
+                  )",
+            [],
+        )?;
+
+        // named config profiles, body as json
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_profile (
+                  name            TEXT PRIMARY KEY,
+                  body            TEXT NOT NULL,
+                  update_time     INTEGER
+                  )",
+            [],
+        )?;
+
+        // batch operations while they run, finish_time is null until every target is done
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_job (
+                  id              INTEGER PRIMARY KEY,
+                  kind            TEXT NOT NULL,
+                  create_time     INTEGER,
+                  finish_time     INTEGER
+                  )",
+            [],
+        )?;
+
+        // one row per machine of a job, action as json
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_job_target (
+                  job_id          INTEGER NOT NULL,
+                  ip              TEXT NOT NULL,
+                  action          TEXT NOT NULL,
+                  done            INTEGER NOT NULL DEFAULT 0,
+                  error           TEXT,
+                  PRIMARY KEY (job_id, ip)
+                  )",
+            [],
+        )?;
+
+        // raised alerts, one row per alert until it is resolved, machines as json
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_alert (
+                  id              INTEGER PRIMARY KEY,
+                  dedup_key       TEXT NOT NULL,
+                  kind            TEXT,
+                  severity        TEXT,
+                  title           TEXT,
+                  content         TEXT,
+                  machines        TEXT,
+                  state           TEXT NOT NULL,
+                  count           INTEGER NOT NULL DEFAULT 1,
+                  first_time      INTEGER,
+                  last_time       INTEGER,
+                  ack_by          TEXT,
+                  ack_time        INTEGER,
+                  resolve_time    INTEGER
+                  )",
+            [],
+        )?;
+
+        // machines the last scan of each range found
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_scan (
+                  scan_range      TEXT NOT NULL,
+                  ip              TEXT NOT NULL,
+                  miner           TEXT,
+                  machine_type    TEXT,
+                  worker          TEXT,
+                  scan_time       INTEGER,
+                  mac             TEXT,
+                  serial          TEXT,
+                  PRIMARY KEY (scan_range, ip)
+                  )",
+            [],
+        )?;
+        add_column_if_missing(&conn, "t_scan", "mac", "TEXT")?;
+        add_column_if_missing(&conn, "t_scan", "serial", "TEXT")?;
+
+        // machines by mac, where each was seen last
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS t_inventory (
+                  mac             TEXT PRIMARY KEY,
+                  serial          TEXT,
+                  ip              TEXT,
+                  machine_type    TEXT,
+                  first_seen      INTEGER,
+                  last_seen       INTEGER
+                  )",
+            [],
+        )?;
+
+        migrate(&conn)?;
+        // every query of the db layer stays prepared
+        conn.set_prepared_statement_cache_capacity(32);
+
+        Ok(Self { conn })
+    }
+
+    pub fn save_profile(&self, name: &str, body: &str, update_time: i64) -> Result<(), MinerError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO t_profile (name, body, update_time)
+                  VALUES (?1, ?2, ?3)",
+            params![name, body, update_time],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete_profile(&self, name: &str) -> Result<(), MinerError> {
+        self.conn
+            .execute("DELETE FROM t_profile WHERE name == ?1", params![name])?;
+
+        Ok(())
+    }
+
+    pub fn query_profiles(&self) -> Result<Vec<String>, MinerError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT body FROM t_profile ORDER BY name")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+
+        let mut bodies = Vec::new();
+        for body in rows {
+            bodies.push(body?);
+        }
+
+        Ok(bodies)
+    }
+
+    pub fn save_curtail_step(
+        &self,
+        ip: &str,
+        body: &str,
+        create_time: i64,
+    ) -> Result<(), MinerError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO t_curtail (ip, body, create_time) VALUES (?1, ?2, ?3)",
+            params![ip, body, create_time],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete_curtail_step(&self, ip: &str) -> Result<(), MinerError> {
+        self.conn
+            .execute("DELETE FROM t_curtail WHERE ip == ?1", params![ip])?;
+
+        Ok(())
+    }
+
+    pub fn query_curtail_steps(&self) -> Result<Vec<String>, MinerError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT body FROM t_curtail ORDER BY create_time, ip")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+
+        let mut bodies = Vec::new();
+        for body in rows {
+            bodies.push(body?);
+        }
+
+        Ok(bodies)
+    }
+
+    pub fn set_switch_state(&self, state: &SwitchState) -> Result<(), MinerError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO t_switch_state (ip, account, run_mode, error, update_time)
+                  VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                state.ip,
+                state.account,
+                state.run_mode,
+                state.error,
+                state.update_time
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn query_switch_state(&self) -> Result<Vec<SwitchState>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT ip, account, run_mode, error, update_time FROM t_switch_state ORDER BY ip",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SwitchState {
+                ip: row.get(0)?,
+                account: row.get(1)?,
+                run_mode: row.get(2)?,
+                error: row.get(3)?,
+                update_time: row.get(4)?,
+            })
+        })?;
+
+        let mut states = Vec::new();
+        for state in rows {
+            states.push(state?);
+        }
+
+        Ok(states)
+    }
+
+    pub fn set_maintenance(
+        &self,
+        ip: &str,
+        until: i64,
+        create_time: i64,
+    ) -> Result<(), MinerError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO t_maintenance (ip, until, create_time)
+                  VALUES (?1, ?2, ?3)",
+            params![ip, until, create_time],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_maintenance(&self, ip: &str) -> Result<(), MinerError> {
+        self.conn
+            .execute("DELETE FROM t_maintenance WHERE ip == ?1", params![ip])?;
+
+        Ok(())
+    }
+
+    pub fn query_maintenance(&self, now: i64) -> Result<Vec<(String, i64)>, MinerError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT ip, until FROM t_maintenance WHERE until > ?1 ORDER BY ip")?;
+        let rows = stmt.query_map(params![now], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut entries = Vec::new();
+        for entry in rows {
+            entries.push(entry?);
+        }
+
+        Ok(entries)
+    }
+
+    pub fn set_applied_mode(
+        &self,
+        ip: &str,
+        mode: &str,
+        update_time: i64,
+    ) -> Result<(), MinerError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO t_applied_mode (ip, mode, update_time) VALUES (?1, ?2, ?3)",
+            params![ip, mode, update_time],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn query_applied_modes(&self) -> Result<Vec<(String, String)>, MinerError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT ip, mode FROM t_applied_mode ORDER BY ip")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut modes = Vec::new();
+        for mode in rows {
+            modes.push(mode?);
+        }
+
+        Ok(modes)
+    }
+
+    pub fn insert_machine_record(&self, machine: &MachineRecord) -> Result<i32, MinerError> {
+        // insert miner
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO t_machine_record (ip, machine_type, work_mode, hash_real, hash_avg, temp_0, temp_1, temp_2, power, create_time,
+                  hw_errors, accepted_pct, rejected_pct, stale_pct, elapsed)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        )?;
+        stmt.execute(params![
+            machine.ip,
+            machine.machine_type,
+            machine.work_mode,
+            machine.hash_real,
+            machine.hash_avg,
+            machine.temp_0,
+            machine.temp_1,
+            machine.temp_2,
+            machine.power,
+            machine.create_time,
+            machine.hw_errors,
+            machine.accepted_pct,
+            machine.rejected_pct,
+            machine.stale_pct,
+            machine.elapsed
+        ])?;
+
+        // return miner id
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    pub fn query_machine_records_by_time(
+        &self,
+        ip: String,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Vec<MachineRecord>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, ip, machine_type, work_mode, hash_real, hash_avg, temp_0, temp_1, temp_2, power, create_time,
+                  hw_errors, accepted_pct, rejected_pct, stale_pct, elapsed
+                  FROM t_machine_record
+                  WHERE ip == ?1 AND create_time >= ?2 AND create_time <= ?3",
+        )?;
+
+        info!(
+            "query machine records by time: {} {} {}",
+            ip, start_time, end_time
+        );
+
+        let rows = stmt.query_map(params![ip, start_time, end_time], |row| {
+            Ok(MachineRecord {
+                schema_version: Default::default(),
+                id: row.get(0)?,
+                ip: row.get(1)?,
+                machine_type: row.get(2)?,
+                work_mode: row.get(3)?,
+                hash_real: row.get(4)?,
+                hash_avg: row.get(5)?,
+                temp_0: row.get(6)?,
+                temp_1: row.get(7)?,
+                temp_2: row.get(8)?,
+                power: row.get(9)?,
+                create_time: row.get(10)?,
+                // null on rows written before the share columns
+                hw_errors: row.get::<_, Option<i64>>(11)?.unwrap_or(0),
+                accepted_pct: row.get::<_, Option<f64>>(12)?.unwrap_or(0.0),
+                rejected_pct: row.get::<_, Option<f64>>(13)?.unwrap_or(0.0),
+                stale_pct: row.get::<_, Option<f64>>(14)?.unwrap_or(0.0),
+                elapsed: row.get::<_, Option<i64>>(15)?.unwrap_or(0),
+            })
+        })?;
+
+        let mut machines = Vec::new();
+        for machine in rows {
+            machines.push(machine?);
+        }
+
+        info!("query machine records by time: {:?}", machines.len());
+        Ok(machines)
+    }
+
+    /// records of one ip, or of every machine when ip is empty, paged by create_time and id
+    pub fn query_machine_records(
+        &self,
+        ip: &str,
+        start_time: i64,
+        end_time: i64,
+        query: &RecordQuery,
+    ) -> Result<Vec<MachineRecord>, MinerError> {
+        if let Some(column) = query
+            .columns
+            .iter()
+            .find(|c| !RECORD_COLUMNS.contains(&c.as_str()))
+        {
+            return Err(MinerError::UnknownColumnError(column.clone()));
+        }
+        let select = RECORD_COLUMNS
+            .iter()
+            .map(|c| {
+                if query.columns.is_empty()
+                    || ["id", "ip", "create_time"].contains(c)
+                    || query.columns.iter().any(|q| q == c)
+                {
+                    c
+                } else {
+                    "NULL"
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let (order, after) = if query.descending {
+            ("DESC", "<")
+        } else {
+            ("ASC", ">")
+        };
+        // no ip condition at all for every machine, so one ip keeps the (ip, create_time) index
+        let by_ip = if ip.is_empty() {
+            "?1 == ''"
+        } else {
+            "ip == ?1"
+        };
+        let sql = format!(
+            "SELECT {} FROM t_machine_record
+                  WHERE {} AND create_time >= ?2 AND create_time <= ?3
+                  AND (?4 == 0 OR (create_time, id) {} (?5, ?4))
+                  ORDER BY create_time {}, id {} LIMIT ?6",
+            select, by_ip, after, order, order
+        );
+        // LIMIT -1 is no limit
+        let limit = if query.limit == 0 {
+            -1
+        } else {
+            query.limit as i64
+        };
+
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(
+            params![
+                ip,
+                start_time,
+                end_time,
+                query.after_id,
+                query.after_time,
+                limit
+            ],
+            |row| {
+                // projected out columns come back null
+                Ok(MachineRecord {
+                    schema_version: Default::default(),
+                    id: row.get(0)?,
+                    ip: row.get(1)?,
+                    machine_type: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    work_mode: row.get::<_, Option<i32>>(3)?.unwrap_or(0),
+                    hash_real: row.get::<_, Option<f64>>(4)?.unwrap_or(0.0),
+                    hash_avg: row.get::<_, Option<f64>>(5)?.unwrap_or(0.0),
+                    temp_0: row.get(6)?,
+                    temp_1: row.get(7)?,
+                    temp_2: row.get(8)?,
+                    power: row.get::<_, Option<i32>>(9)?.unwrap_or(0),
+                    create_time: row.get(10)?,
+                    hw_errors: row.get::<_, Option<i64>>(11)?.unwrap_or(0),
+                    accepted_pct: row.get::<_, Option<f64>>(12)?.unwrap_or(0.0),
+                    rejected_pct: row.get::<_, Option<f64>>(13)?.unwrap_or(0.0),
+                    stale_pct: row.get::<_, Option<f64>>(14)?.unwrap_or(0.0),
+                    elapsed: row.get::<_, Option<i64>>(15)?.unwrap_or(0),
+                })
+            },
+        )?;
+
+        let mut machines = Vec::new();
+        for machine in rows {
+            machines.push(machine?);
+        }
+        Ok(machines)
+    }
+
+    pub fn query_all_machine_records_by_time(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Vec<MachineRecord>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, ip, machine_type, work_mode, hash_real, hash_avg, temp_0, temp_1, temp_2, power, create_time,
+                  hw_errors, accepted_pct, rejected_pct, stale_pct, elapsed
+                  FROM t_machine_record
+                  WHERE create_time >= ?1 AND create_time <= ?2
+                  ORDER BY ip, create_time",
+        )?;
+
+        let rows = stmt.query_map(params![start_time, end_time], |row| {
+            Ok(MachineRecord {
+                schema_version: Default::default(),
+                id: row.get(0)?,
+                ip: row.get(1)?,
+                machine_type: row.get(2)?,
+                work_mode: row.get(3)?,
+                hash_real: row.get(4)?,
+                hash_avg: row.get(5)?,
+                temp_0: row.get(6)?,
+                temp_1: row.get(7)?,
+                temp_2: row.get(8)?,
+                power: row.get(9)?,
+                create_time: row.get(10)?,
+                // null on rows written before the share columns
+                hw_errors: row.get::<_, Option<i64>>(11)?.unwrap_or(0),
+                accepted_pct: row.get::<_, Option<f64>>(12)?.unwrap_or(0.0),
+                rejected_pct: row.get::<_, Option<f64>>(13)?.unwrap_or(0.0),
+                stale_pct: row.get::<_, Option<f64>>(14)?.unwrap_or(0.0),
+                elapsed: row.get::<_, Option<i64>>(15)?.unwrap_or(0),
+            })
+        })?;
+
+        let mut machines = Vec::new();
+        for machine in rows {
+            machines.push(machine?);
+        }
+
+        Ok(machines)
+    }
+
+    pub fn insert_event(
+        &self,
+        event_type: &str,
+        ip: &str,
+        detail: &str,
+        create_time: i64,
+    ) -> Result<i32, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO t_event (event_type, ip, detail, create_time)
+                  VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        stmt.execute(params![event_type, ip, detail, create_time])?;
+
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    pub fn count_events(
+        &self,
+        event_type: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<i64, MinerError> {
+        let count = self.conn.query_row(
+            "SELECT COUNT(*) FROM t_event
+                  WHERE event_type == ?1 AND create_time >= ?2 AND create_time <= ?3",
+            params![event_type, start_time, end_time],
+            |row| row.get(0),
+        )?;
+
+        Ok(count)
+    }
+
+    /// events of the type on the machine with the detail
+    pub fn count_ip_events(
+        &self,
+        event_type: &str,
+        ip: &str,
+        detail: &str,
+        start_time: i64,
+    ) -> Result<i64, MinerError> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM t_event
+                  WHERE event_type == ?1 AND ip == ?2 AND detail == ?3 AND create_time >= ?4",
+            params![event_type, ip, detail, start_time],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// detail and time of the newest event of the type
+    pub fn query_last_event(&self, event_type: &str) -> Result<Option<(String, i64)>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT detail, create_time FROM t_event
+                  WHERE event_type == ?1 ORDER BY create_time DESC, id DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query_map(params![event_type], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        Ok(rows.next().transpose()?)
+    }
+
+    // clear specified records before specified time
+    /// online copy of the db to a file, consistent while other connections write
+    pub fn backup(&self, to_path: &str) -> Result<(), MinerError> {
+        if let Some(dir) = Path::new(to_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        self.conn.backup(DatabaseName::Main, to_path, None)?;
+        Ok(())
+    }
+
+    /// rebuild the file to give the pages of cleared records back, then refresh the
+    /// statistics of the query planner
+    /// bytes of the db file
+    pub fn size(&self) -> Result<i64, MinerError> {
+        let pages: i64 = self
+            .conn
+            .query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: i64 = self
+            .conn
+            .query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok(pages * page_size)
+    }
+
+    pub fn vacuum(&self) -> Result<(), MinerError> {
+        self.conn.execute_batch("VACUUM; ANALYZE;")?;
+        Ok(())
+    }
+
+    pub fn clear_records_before_time(&self, time: i64) -> Result<(), MinerError> {
+        self.conn.execute(
+            "DELETE FROM t_machine_record WHERE create_time < ?1",
+            params![time],
+        )?;
+
+        self.conn.execute(
+            "DELETE FROM t_pool_record WHERE time_stamp < ?1",
+            params![time],
+        )?;
+
+        self.conn
+            .execute("DELETE FROM t_event WHERE create_time < ?1", params![time])?;
+
+        self.conn.execute(
+            "DELETE FROM t_proxy_record WHERE time_stamp < ?1",
+            params![time],
+        )?;
+
+        self.conn
+            .execute("DELETE FROM t_maintenance WHERE until < ?1", params![time])?;
+
+        Ok(())
+    }
+
+    pub fn clear_machine_records_before(&self, time: i64) -> Result<usize, MinerError> {
+        Ok(self.conn.execute(
+            "DELETE FROM t_machine_record WHERE create_time < ?1",
+            params![time],
+        )?)
+    }
+
+    /// pool and stratum proxy records
+    pub fn clear_pool_records_before(&self, time: i64) -> Result<usize, MinerError> {
+        let pools = self.conn.execute(
+            "DELETE FROM t_pool_record WHERE time_stamp < ?1",
+            params![time],
+        )?;
+        let proxies = self.conn.execute(
+            "DELETE FROM t_proxy_record WHERE time_stamp < ?1",
+            params![time],
+        )?;
+        Ok(pools + proxies)
+    }
+
+    /// alert events when alerts, else every other event
+    pub fn clear_events_before(&self, time: i64, alerts: bool) -> Result<usize, MinerError> {
+        let sql = if alerts {
+            "DELETE FROM t_event WHERE create_time < ?1 AND event_type == ?2"
+        } else {
+            "DELETE FROM t_event WHERE create_time < ?1 AND event_type != ?2"
+        };
+        Ok(self.conn.execute(sql, params![time, EVENT_ALERT])?)
+    }
+
+    /// count the repeat on the unresolved row of the key, or a new open row
+    pub fn record_alert(&self, alert: &Alert, key: &str, now: i64) -> Result<i64, MinerError> {
+        let machines = serde_json::to_string(&alert.machines)?;
+        let severity = format!("{:?}", alert.severity);
+        let updated = self.conn.execute(
+            "UPDATE t_alert SET severity = ?2, title = ?3, content = ?4, machines = ?5,
+                  count = count + 1, last_time = ?6
+                  WHERE dedup_key == ?1 AND state != ?7",
+            params![
+                key,
+                severity,
+                alert.title,
+                alert.content,
+                machines,
+                now,
+                AlertState::Resolved.as_str()
+            ],
+        )?;
+        if updated > 0 {
+            return Ok(self.conn.query_row(
+                "SELECT id FROM t_alert WHERE dedup_key == ?1 AND state != ?2",
+                params![key, AlertState::Resolved.as_str()],
+                |row| row.get(0),
+            )?);
+        }
+        self.conn.execute(
+            "INSERT INTO t_alert (dedup_key, kind, severity, title, content, machines, state,
+                  first_time, last_time)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+            params![
+                key,
+                alert.kind,
+                severity,
+                alert.title,
+                alert.content,
+                machines,
+                AlertState::Open.as_str(),
+                now
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// acknowledge or resolve an unresolved alert, its dedup key, None when there is none
+    pub fn set_alert_state(
+        &self,
+        id: i64,
+        state: AlertState,
+        by: &str,
+        now: i64,
+    ) -> Result<Option<String>, MinerError> {
+        let resolved = AlertState::Resolved.as_str();
+        let updated = match state {
+            AlertState::Acknowledged => self.conn.execute(
+                "UPDATE t_alert SET state = ?2, ack_by = ?3, ack_time = ?4
+                      WHERE id == ?1 AND state != ?5",
+                params![id, state.as_str(), by, now, resolved],
+            )?,
+            AlertState::Resolved => self.conn.execute(
+                "UPDATE t_alert SET state = ?2, resolve_time = ?3 WHERE id == ?1 AND state != ?2",
+                params![id, resolved, now],
+            )?,
+            AlertState::Open => 0,
+        };
+        if updated == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.conn.query_row(
+            "SELECT dedup_key FROM t_alert WHERE id == ?1",
+            params![id],
+            |row| row.get(0),
+        )?))
+    }
+
+    /// resolve the unresolved alerts not raised since the time
+    pub fn resolve_quiet_alerts(&self, before: i64, now: i64) -> Result<usize, MinerError> {
+        Ok(self.conn.execute(
+            "UPDATE t_alert SET state = ?3, resolve_time = ?2 WHERE last_time < ?1 AND state != ?3",
+            params![before, now, AlertState::Resolved.as_str()],
+        )?)
+    }
+
+    pub fn query_alerts(&self, query: &AlertQuery) -> Result<Vec<AlertEntry>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, dedup_key, kind, severity, title, content, machines, state, count,
+                  first_time, last_time, ack_by, ack_time, resolve_time FROM t_alert
+                  WHERE last_time >= ?1 AND last_time <= ?2 AND (?3 == '' OR state == ?3)
+                  ORDER BY last_time DESC, id DESC LIMIT ?4",
+        )?;
+        let state = query.state.map(|s| s.as_str()).unwrap_or("");
+        // a negative limit is no limit in sqlite
+        let limit = if query.limit == 0 {
+            -1
+        } else {
+            query.limit as i64
+        };
+        let rows = stmt.query_map(
+            params![query.start_time, query.end_time, state, limit],
+            |row| {
+                let severity: String = row.get(3)?;
+                let machines: String = row.get(6)?;
+                let state: String = row.get(7)?;
+                Ok(AlertEntry {
+                    id: row.get(0)?,
+                    dedup_key: row.get(1)?,
+                    kind: row.get(2)?,
+                    severity: serde_json::from_value(serde_json::Value::String(severity))
+                        .unwrap_or(crate::notify::Severity::Info),
+                    title: row.get(4)?,
+                    content: row.get(5)?,
+                    machines: serde_json::from_str(&machines).unwrap_or_default(),
+                    state: AlertState::parse(&state).unwrap_or(AlertState::Open),
+                    count: row.get(8)?,
+                    first_time: row.get(9)?,
+                    last_time: row.get(10)?,
+                    ack_by: row.get(11)?,
+                    ack_time: row.get(12)?,
+                    resolve_time: row.get(13)?,
+                })
+            },
+        )?;
+
+        let mut alerts = Vec::new();
+        for alert in rows {
+            alerts.push(alert?);
+        }
+        Ok(alerts)
+    }
+
+    /// resolved alerts last raised before the time
+    pub fn clear_alerts_before(&self, time: i64) -> Result<usize, MinerError> {
+        Ok(self.conn.execute(
+            "DELETE FROM t_alert WHERE last_time < ?1 AND state == ?2",
+            params![time, AlertState::Resolved.as_str()],
+        )?)
+    }
+
+    /// machines of the last scan of the range and its time, None when nothing was found yet
+    pub fn query_scan(
+        &self,
+        range: &str,
+    ) -> Result<(Vec<ScannedMachine>, Option<i64>), MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT ip, miner, machine_type, worker, scan_time, mac, serial FROM t_scan
+                  WHERE scan_range == ?1 ORDER BY ip",
+        )?;
+        let mut scan_time = None;
+        let rows = stmt.query_map(params![range], |row| {
+            Ok((
+                ScannedMachine {
+                    ip: row.get(0)?,
+                    miner: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    machine_type: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    worker: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                    mac: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                    serial: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                },
+                row.get::<_, Option<i64>>(4)?,
+            ))
+        })?;
+
+        let mut machines = vec![];
+        for row in rows {
+            let (machine, time) = row?;
+            scan_time = scan_time.max(time);
+            machines.push(machine);
+        }
+        Ok((machines, scan_time))
+    }
+
+    /// replace the machines of the range with the ones of this scan
+    pub fn save_scan(
+        &self,
+        range: &str,
+        machines: &[ScannedMachine],
+        scan_time: i64,
+    ) -> Result<(), MinerError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM t_scan WHERE scan_range == ?1", params![range])?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO t_scan
+                  (scan_range, ip, miner, machine_type, worker, scan_time, mac, serial)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for m in machines {
+                stmt.execute(params![
+                    range,
+                    m.ip,
+                    m.miner,
+                    m.machine_type,
+                    m.worker,
+                    scan_time,
+                    m.mac,
+                    m.serial
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// insert or refresh the machine of the mac, returns the ip it had before
+    pub fn upsert_inventory(&self, entry: &InventoryEntry) -> Result<Option<String>, MinerError> {
+        let previous = {
+            let mut stmt = self
+                .conn
+                .prepare_cached("SELECT ip FROM t_inventory WHERE mac == ?1")?;
+            let mut rows = stmt.query_map(params![entry.mac], |row| row.get(0))?;
+            rows.next().transpose()?
+        };
+        self.conn.execute(
+            "INSERT INTO t_inventory (mac, serial, ip, machine_type, first_seen, last_seen)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                  ON CONFLICT (mac) DO UPDATE SET serial = ?2, ip = ?3, machine_type = ?4,
+                  last_seen = ?6",
+            params![
+                entry.mac,
+                entry.serial,
+                entry.ip,
+                entry.machine_type,
+                entry.first_seen,
+                entry.last_seen
+            ],
+        )?;
+        Ok(previous)
+    }
+
+    /// the ip went to another machine, the machines last seen there lose it
+    pub fn release_inventory_ip(&self, ip: &str, mac: &str) -> Result<usize, MinerError> {
+        Ok(self.conn.execute(
+            "UPDATE t_inventory SET ip = '' WHERE ip == ?1 AND mac != ?2",
+            params![ip, mac],
+        )?)
+    }
+
+    pub fn query_inventory(&self) -> Result<Vec<InventoryEntry>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT mac, serial, ip, machine_type, first_seen, last_seen FROM t_inventory
+                  ORDER BY last_seen DESC, mac",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(InventoryEntry {
+                mac: row.get(0)?,
+                serial: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                ip: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                machine_type: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                first_seen: row.get::<_, Option<i64>>(4)?.unwrap_or(0),
+                last_seen: row.get::<_, Option<i64>>(5)?.unwrap_or(0),
+                position: None,
+            })
+        })?;
+
+        let mut entries = vec![];
+        for entry in rows {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    pub fn clear_expired_maintenance(&self, now: i64) -> Result<usize, MinerError> {
+        Ok(self
+            .conn
+            .execute("DELETE FROM t_maintenance WHERE until < ?1", params![now])?)
+    }
+
+    /// job with its targets as (ip, action json), returns the job id
+    pub fn insert_job(
+        &self,
+        kind: &str,
+        targets: &[(String, String)],
+        create_time: i64,
+    ) -> Result<i64, MinerError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO t_job (kind, create_time) VALUES (?1, ?2)",
+            params![kind, create_time],
+        )?;
+        let job_id = tx.last_insert_rowid();
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO t_job_target (job_id, ip, action) VALUES (?1, ?2, ?3)",
+            )?;
+            for (ip, action) in targets {
+                stmt.execute(params![job_id, ip, action])?;
+            }
+        }
+        tx.commit()?;
+        Ok(job_id)
+    }
+
+    pub fn set_job_target_done(
+        &self,
+        job_id: i64,
+        ip: &str,
+        error: &str,
+    ) -> Result<(), MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "UPDATE t_job_target SET done = 1, error = ?3 WHERE job_id == ?1 AND ip == ?2",
+        )?;
+        stmt.execute(params![job_id, ip, error])?;
+        Ok(())
+    }
+
+    pub fn finish_job(&self, job_id: i64, finish_time: i64) -> Result<(), MinerError> {
+        self.conn.execute(
+            "UPDATE t_job SET finish_time = ?2 WHERE id == ?1",
+            params![job_id, finish_time],
+        )?;
+        Ok(())
+    }
+
+    /// (job id, ip, action json) not done yet of the unfinished jobs, oldest job first
+    pub fn query_pending_job_targets(&self) -> Result<Vec<(i64, String, String)>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT t.job_id, t.ip, t.action FROM t_job_target t
+                  JOIN t_job j ON j.id == t.job_id
+                  WHERE j.finish_time IS NULL AND t.done == 0
+                  ORDER BY t.job_id, t.ip",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+        let mut targets = Vec::new();
+        for target in rows {
+            targets.push(target?);
+        }
+        Ok(targets)
     }
 
-    pub fn insert_machine_record(&self, machine: &MachineRecord) -> Result<i32, MinerError> {
-        // insert miner
+    /// every job left unfinished, e.g. after the pending targets were resumed
+    pub fn finish_pending_jobs(&self, finish_time: i64) -> Result<(), MinerError> {
         self.conn.execute(
-            "INSERT INTO t_machine_record (ip, machine_type, work_mode, hash_real, hash_avg, temp_0, temp_1, temp_2, power, create_time)
-                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
-            params![
-                machine.ip,
-                machine.machine_type,
-                machine.work_mode,
-                machine.hash_real,
-                machine.hash_avg,
-                machine.temp_0,
-                machine.temp_1,
-                machine.temp_2,
-                machine.power,
-                machine.create_time
-            ],
+            "UPDATE t_job SET finish_time = ?1 WHERE finish_time IS NULL",
+            params![finish_time],
         )?;
+        Ok(())
+    }
+
+    /// finished jobs created before the time with their targets
+    pub fn clear_jobs_before(&self, time: i64) -> Result<usize, MinerError> {
+        self.conn.execute(
+            "DELETE FROM t_job_target WHERE job_id IN
+                  (SELECT id FROM t_job WHERE create_time < ?1 AND finish_time IS NOT NULL)",
+            params![time],
+        )?;
+        Ok(self.conn.execute(
+            "DELETE FROM t_job WHERE create_time < ?1 AND finish_time IS NOT NULL",
+            params![time],
+        )?)
+    }
+
+    pub fn insert_proxy_record(&self, status: &ProxyStatus) -> Result<i32, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO t_proxy_record (name, reachable, upstreams, hash_real, workers, time_stamp)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        stmt.execute(params![
+            status.name,
+            status.reachable,
+            serde_json::to_string(&status.upstreams)?,
+            status.hash_real,
+            status.workers,
+            status.time_stamp
+        ])?;
 
-        // return miner id
         Ok(self.conn.last_insert_rowid() as i32)
     }
 
-    pub fn query_machine_records_by_time(
+    pub fn query_proxy_records_by_time(
         &self,
-        ip: String,
+        name: &str,
         start_time: i64,
         end_time: i64,
+    ) -> Result<Vec<ProxyStatus>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT name, reachable, upstreams, hash_real, workers, time_stamp
+                  FROM t_proxy_record
+                  WHERE name == ?1 AND time_stamp >= ?2 AND time_stamp <= ?3
+                  ORDER BY time_stamp",
+        )?;
+
+        let rows = stmt.query_map(params![name, start_time, end_time], |row| {
+            let upstreams: String = row.get(2)?;
+            Ok(ProxyStatus {
+                name: row.get(0)?,
+                reachable: row.get(1)?,
+                upstreams: serde_json::from_str(&upstreams).unwrap_or_default(),
+                hash_real: row.get(3)?,
+                workers: row.get(4)?,
+                time_stamp: row.get(5)?,
+            })
+        })?;
+
+        let mut records = vec![];
+        for record in rows {
+            records.push(record?);
+        }
+        Ok(records)
+    }
+
+    /// newest record of every ip reported since the time
+    pub fn query_latest_machine_records(
+        &self,
+        since: i64,
     ) -> Result<Vec<MachineRecord>, MinerError> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, ip, machine_type, work_mode, hash_real, hash_avg, temp_0, temp_1, temp_2, power, create_time
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, ip, machine_type, work_mode, hash_real, hash_avg, temp_0, temp_1, temp_2, power, create_time,
+                  hw_errors, accepted_pct, rejected_pct, stale_pct, elapsed
                   FROM t_machine_record
-                  WHERE ip == ?1 AND create_time >= ?2 AND create_time <= ?3",
+                  WHERE id IN (SELECT MAX(id) FROM t_machine_record WHERE create_time >= ?1 GROUP BY ip)",
         )?;
 
-        info!(
-            "query machine records by time: {} {} {}",
-            ip, start_time, end_time
-        );
-
-        let rows = stmt.query_map(params![ip, start_time, end_time], |row| {
+        let rows = stmt.query_map(params![since], |row| {
             Ok(MachineRecord {
+                schema_version: Default::default(),
                 id: row.get(0)?,
                 ip: row.get(1)?,
                 machine_type: row.get(2)?,
@@ -115,6 +1301,12 @@ impl DB {
                 temp_2: row.get(8)?,
                 power: row.get(9)?,
                 create_time: row.get(10)?,
+                // null on rows written before the share columns
+                hw_errors: row.get::<_, Option<i64>>(11)?.unwrap_or(0),
+                accepted_pct: row.get::<_, Option<f64>>(12)?.unwrap_or(0.0),
+                rejected_pct: row.get::<_, Option<f64>>(13)?.unwrap_or(0.0),
+                stale_pct: row.get::<_, Option<f64>>(14)?.unwrap_or(0.0),
+                elapsed: row.get::<_, Option<i64>>(15)?.unwrap_or(0),
             })
         })?;
 
@@ -122,53 +1314,94 @@ impl DB {
         for machine in rows {
             machines.push(machine?);
         }
-
-        info!("query machine records by time: {:?}", machines.len());
         Ok(machines)
     }
 
-    // clear specified records before specified time
-    pub fn clear_records_before_time(&self, time: i64) -> Result<(), MinerError> {
-        self.conn.execute(
-            "DELETE FROM t_machine_record WHERE create_time < ?1",
-            params![time],
-        )?;
-
-        self.conn.execute(
-            "DELETE FROM t_pool_record WHERE time_stamp < ?1",
-            params![time],
-        )?;
-
-        Ok(())
-    }
-
     pub fn insert_pool_record(
         &self,
         name: &str,
         hash_real: f64,
         hash_avg: f64,
         pool_type: &str,
+        account: &str,
         time_stamp: i64,
     ) -> Result<i32, MinerError> {
         // insert pool record
-        self.conn.execute(
-            "INSERT INTO t_pool_record (name, hash_real, hash_avg, pool_type, time_stamp)
-                  VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![name, hash_real, hash_avg, pool_type, time_stamp],
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO t_pool_record (name, hash_real, hash_avg, pool_type, time_stamp, account)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
         )?;
+        stmt.execute(params![
+            name, hash_real, hash_avg, pool_type, time_stamp, account
+        ])?;
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO t_pool_worker (name) VALUES (?1)")?
+            .execute(params![name])?;
 
         // return pool record id
         Ok(self.conn.last_insert_rowid() as i32)
     }
 
+    pub fn insert_pool_earning(&self, earning: &PoolEarning) -> Result<i32, MinerError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO t_pool_earnings (pool_type, account, kind, date, amount, hash_rate, tx_id, time_stamp)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                earning.pool_type,
+                earning.account,
+                earning.kind,
+                earning.date,
+                earning.amount,
+                earning.hash_rate,
+                earning.tx_id,
+                earning.time_stamp
+            ],
+        )?;
+
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    /// dates are YYYY-MM-DD, both inclusive
+    pub fn query_pool_earnings(
+        &self,
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<Vec<PoolEarning>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT pool_type, account, kind, date, amount, hash_rate, tx_id, time_stamp
+                  FROM t_pool_earnings
+                  WHERE date >= ?1 AND date <= ?2
+                  ORDER BY date, account, kind",
+        )?;
+
+        let rows = stmt.query_map(params![start_date, end_date], |row| {
+            Ok(PoolEarning {
+                pool_type: row.get(0)?,
+                account: row.get(1)?,
+                kind: row.get(2)?,
+                date: row.get(3)?,
+                amount: row.get(4)?,
+                hash_rate: row.get(5)?,
+                tx_id: row.get(6)?,
+                time_stamp: row.get(7)?,
+            })
+        })?;
+
+        let mut earnings = vec![];
+        for earning in rows {
+            earnings.push(earning?);
+        }
+        Ok(earnings)
+    }
+
     pub fn _query_pool_records_by_time(
         &self,
         name: String,
         start_time: i64,
         end_time: i64,
     ) -> Result<Vec<PoolWorker>, MinerError> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, name, hash_real, hash_avg, pool_type, time_stamp
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, name, hash_real, hash_avg, pool_type, time_stamp, account
                   FROM t_pool_record
                   WHERE name == ?1 AND time_stamp >= ?2 AND time_stamp <= ?3",
         )?;
@@ -180,11 +1413,13 @@ impl DB {
 
         let rows = stmt.query_map(params![name, start_time, end_time], |row| {
             Ok(PoolWorker {
+                schema_version: Default::default(),
                 name: row.get(1)?,
                 hash_real: row.get(2)?,
                 hash_avg: row.get(3)?,
                 pool_type: row.get(4)?,
                 time_stamp: row.get(5)?,
+                account: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
             })
         })?;
 
@@ -197,71 +1432,209 @@ impl DB {
         Ok(workers)
     }
 
-    fn get_newest_pool_record(&self, name: &str) -> Result<Option<PoolWorker>, MinerError> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, name, hash_real, hash_avg, pool_type, time_stamp
+    /// records of every worker in time order
+    pub fn query_all_pool_records_by_time(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<Vec<PoolWorker>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, name, hash_real, hash_avg, pool_type, time_stamp, account
+                  FROM t_pool_record
+                  WHERE time_stamp >= ?1 AND time_stamp <= ?2
+                  ORDER BY time_stamp, id",
+        )?;
+
+        let rows = stmt.query_map(params![start_time, end_time], |row| {
+            Ok(PoolWorker {
+                schema_version: Default::default(),
+                name: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                hash_real: row.get::<_, Option<f64>>(2)?.unwrap_or(0.0),
+                hash_avg: row.get::<_, Option<f64>>(3)?.unwrap_or(0.0),
+                pool_type: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                time_stamp: row.get(5)?,
+                account: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+            })
+        })?;
+
+        let mut workers = vec![];
+        for worker in rows {
+            workers.push(worker?);
+        }
+        Ok(workers)
+    }
+
+    fn get_newest_pool_record_by_name(&self, name: &str) -> Result<Option<PoolWorker>, MinerError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, name, hash_real, hash_avg, pool_type, time_stamp, account
                   FROM t_pool_record
                   WHERE name == ?1
-                  ORDER BY time_stamp DESC
-                  LIMIT 1",
+                  ORDER BY time_stamp DESC LIMIT 1",
         )?;
 
         let mut rows = stmt.query_map(params![name], |row| {
             Ok(PoolWorker {
+                schema_version: Default::default(),
                 name: row.get(1)?,
                 hash_real: row.get(2)?,
                 hash_avg: row.get(3)?,
                 pool_type: row.get(4)?,
                 time_stamp: row.get(5)?,
+                account: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
             })
         })?;
+        Ok(rows.next().transpose()?)
+    }
 
-        if let Some(row) = rows.next() {
-            Ok(Some(row?))
-        } else {
-            Ok(None)
+    /// newest record whose worker name ends with the suffix, like "acc.188x41" for "188x41".
+    /// the suffix is matched against the known worker names, not the whole history
+    fn get_newest_pool_record(&self, suffix: &str) -> Result<Option<PoolWorker>, MinerError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name FROM t_pool_worker WHERE name LIKE '%' || ?1")?;
+        let names = stmt
+            .query_map(params![suffix], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        let mut newest: Option<PoolWorker> = None;
+        for name in names.iter().filter(|name| is_worker_of(name, suffix)) {
+            if let Some(worker) = self.get_newest_pool_record_by_name(name)? {
+                if newest
+                    .as_ref()
+                    .is_none_or(|n| worker.time_stamp > n.time_stamp)
+                {
+                    newest = Some(worker);
+                }
+            }
         }
+        Ok(newest)
     }
 }
 
-fn create_db_file(app_path: &str) {
-    let db_path = get_db_path(app_path);
-    let db_dir = Path::new(&db_path).parent().unwrap();
+// schema changes applied once in order, the db keeps how many ran in user_version.
+// only append, a released step must not change
+#[cfg(feature = "sqlite")]
+const MIGRATIONS: &[&str] = &[
+    // time range queries of one machine or one pool worker
+    "CREATE INDEX IF NOT EXISTS i_machine_record_ip_time ON t_machine_record (ip, create_time);
+     CREATE INDEX IF NOT EXISTS i_pool_record_name_time ON t_pool_record (name, time_stamp);",
+    // the unresolved row of an alert and the incident list
+    "CREATE INDEX IF NOT EXISTS i_alert_key_state ON t_alert (dedup_key, state);
+     CREATE INDEX IF NOT EXISTS i_alert_last_time ON t_alert (last_time);",
+    // worker names seen, pool records of a machine are looked up by worker suffix
+    "CREATE TABLE IF NOT EXISTS t_pool_worker (name TEXT PRIMARY KEY);
+     INSERT OR IGNORE INTO t_pool_worker (name)
+       SELECT DISTINCT name FROM t_pool_record WHERE name IS NOT NULL;",
+];
+
+#[cfg(feature = "sqlite")]
+fn migrate(conn: &Connection) -> Result<(), MinerError> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        info!("db migration {}", i + 1);
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
 
-    if !db_dir.exists() {
-        fs::create_dir_all(db_dir).unwrap();
+// tables created by older versions lack newer columns
+#[cfg(feature = "sqlite")]
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    column_type: &str,
+) -> Result<(), MinerError> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, _>>()?;
+    if !columns.iter().any(|c| c == column) {
+        conn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, column_type
+            ),
+            [],
+        )?;
     }
+    Ok(())
+}
 
-    fs::File::create(db_path).unwrap();
+/// the configured db path, "<app_path>/db/lcd.sqlite" when empty
+pub fn get_db_path(app_path: &str, db_path: &str) -> String {
+    if db_path.is_empty() {
+        app_path.to_owned() + "/db/lcd.sqlite"
+    } else {
+        db_path.to_string()
+    }
 }
 
-fn db_file_exists(db_path: &str) -> bool {
-    Path::new(&db_path).exists()
+#[cfg(feature = "sqlite")]
+pub fn init(db_path: &str) -> Result<(), MinerError> {
+    let context = context::current();
+    let mut db = context.db.lock().unwrap();
+    *db = Some(DB::new(db_path)?);
+    info!("lcd db initialized.");
+    Ok(())
 }
 
-fn get_db_path(app_path: &str) -> String {
-    app_path.to_owned() + "/db/lcd.sqlite"
+#[cfg(not(feature = "sqlite"))]
+pub fn init(db_path: &str) -> Result<(), MinerError> {
+    info!("built without sqlite, {} not opened", db_path);
+    Ok(())
 }
 
-pub fn init(app_path: &str, data_keep_days: i64) {
-    let mut db = LCD_DB.lock().unwrap();
-    let db_inst = DB::new(app_path).unwrap();
+/// copy the db to a file, e.g. a dated backup next to it
+pub fn backup(to_path: &str) -> Result<(), MinerError> {
+    with_db!(|db| db.backup(to_path), Err(MinerError::DbNotInitError))
+}
 
-    // try to clear old data
-    let now = chrono::Local::now().timestamp();
-    db_inst
-        .clear_records_before_time(now - data_keep_days * 24 * 3600)
-        .unwrap();
-    *db = Some(db_inst);
-    info!("lcd db initialized.");
+pub fn size() -> Result<i64, MinerError> {
+    with_db!(|db| db.size(), Ok(0))
 }
 
-pub fn insert_machine_record(machine: &MachineRecord) -> Result<i32, MinerError> {
-    let db = LCD_DB.lock().unwrap();
-    match &*db {
-        Some(db) => db.insert_machine_record(machine),
-        None => Ok(-1),
+pub fn vacuum() -> Result<(), MinerError> {
+    with_db!(|db| db.vacuum(), Ok(()))
+}
+
+/// VACUUM and ANALYZE every interval, the first run after one interval
+pub fn schedule_maintenance_task(
+    runtime: tokio::runtime::Handle,
+    interval_seconds: u64,
+) -> tokio::task::JoinHandle<()> {
+    context::spawn(&runtime, async move {
+        while status::wait(
+            "db_maintenance",
+            tokio::time::Duration::from_secs(interval_seconds),
+        )
+        .await
+        {
+            info!("db maintenance task scheduled.");
+            // the db lock is held for the whole vacuum, keep it off the async workers
+            match context::spawn_blocking(vacuum).await {
+                Ok(Err(e)) => error!("db vacuum error: {:?}", e),
+                Err(e) => error!("db vacuum task error: {:?}", e),
+                Ok(Ok(())) => info!("db vacuum done."),
+            }
+        }
+    })
+}
+
+/// close the connection, later calls act as without db until init
+pub fn close() {
+    #[cfg(feature = "sqlite")]
+    {
+        *context::current().db.lock().unwrap() = None;
     }
+    info!("lcd db closed.");
+}
+
+pub fn insert_machine_record(machine: &MachineRecord) -> Result<i32, MinerError> {
+    with_db!(|db| db.insert_machine_record(machine), Ok(-1))
 }
 
 pub fn query_records_by_time(
@@ -269,25 +1642,56 @@ pub fn query_records_by_time(
     start_time: i64,
     end_time: i64,
 ) -> Result<Vec<MachineRecord>, MinerError> {
-    let db = LCD_DB.lock().unwrap();
-    match &*db {
-        Some(db) => db.query_machine_records_by_time(ip, start_time, end_time),
-        None => Ok(Vec::new()),
-    }
+    with_db!(
+        |db| db.query_machine_records_by_time(ip, start_time, end_time),
+        Ok(Vec::new())
+    )
 }
 
+pub fn query_machine_records(
+    ip: &str,
+    start_time: i64,
+    end_time: i64,
+    query: &RecordQuery,
+) -> Result<Vec<MachineRecord>, MinerError> {
+    with_db!(
+        |db| db.query_machine_records(ip, start_time, end_time, query),
+        Ok(Vec::new())
+    )
+}
+
+pub fn query_latest_machine_records(since: i64) -> Result<Vec<MachineRecord>, MinerError> {
+    with_db!(|db| db.query_latest_machine_records(since), Ok(Vec::new()))
+}
+
+#[cfg_attr(not(feature = "pools"), allow(dead_code))]
 pub fn insert_pool_record(
     name: &str,
     hash_real: f64,
     hash_avg: f64,
     pool_type: &str,
+    account: &str,
     time_stamp: i64,
 ) -> Result<i32, MinerError> {
-    let db = LCD_DB.lock().unwrap();
-    match &*db {
-        Some(db) => db.insert_pool_record(name, hash_real, hash_avg, pool_type, time_stamp),
-        None => Ok(-1),
-    }
+    with_db!(
+        |db| db.insert_pool_record(name, hash_real, hash_avg, pool_type, account, time_stamp),
+        Ok(-1)
+    )
+}
+
+#[cfg_attr(not(feature = "pools"), allow(dead_code))]
+pub fn insert_pool_earning(earning: &PoolEarning) -> Result<i32, MinerError> {
+    with_db!(|db| db.insert_pool_earning(earning), Ok(-1))
+}
+
+pub fn query_pool_earnings(
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<PoolEarning>, MinerError> {
+    with_db!(
+        |db| db.query_pool_earnings(start_date, end_date),
+        Ok(Vec::new())
+    )
 }
 
 pub fn _query_pool_records_by_time(
@@ -295,27 +1699,457 @@ pub fn _query_pool_records_by_time(
     start_time: i64,
     end_time: i64,
 ) -> Result<Vec<PoolWorker>, MinerError> {
-    let db = LCD_DB.lock().unwrap();
-    match &*db {
-        Some(db) => db._query_pool_records_by_time(name, start_time, end_time),
-        None => Ok(Vec::new()),
-    }
+    with_db!(
+        |db| db._query_pool_records_by_time(name, start_time, end_time),
+        Ok(Vec::new())
+    )
+}
+
+pub fn query_all_pool_records_by_time(
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<PoolWorker>, MinerError> {
+    with_db!(
+        |db| db.query_all_pool_records_by_time(start_time, end_time),
+        Ok(Vec::new())
+    )
 }
 
+/// newest pool record of the machine, by its mapped worker name or its ip
 pub fn get_newest_pool_record(ip: &str) -> Result<Option<PoolWorker>, MinerError> {
-    let db = LCD_DB.lock().unwrap();
-    let ip_segs = ip.split(".").collect::<Vec<&str>>();
-    let name = format!("{}x{}", ip_segs[2], ip_segs[3]);
-    match &*db {
-        Some(db) => db.get_newest_pool_record(&name),
-        None => Ok(None),
-    }
+    let Some(worker) = worker_name(ip) else {
+        return Ok(None);
+    };
+    with_db!(
+        |db| match worker {
+            WorkerName::Full(name) => db.get_newest_pool_record_by_name(&name),
+            WorkerName::Suffix(suffix) => db.get_newest_pool_record(&suffix),
+        },
+        Ok(None)
+    )
+}
+
+pub fn insert_job(kind: &str, targets: &[(String, String)]) -> Result<i64, MinerError> {
+    with_db!(
+        |db| db.insert_job(kind, targets, chrono::Local::now().timestamp()),
+        Ok(-1)
+    )
+}
+
+pub fn set_job_target_done(job_id: i64, ip: &str, error: &str) -> Result<(), MinerError> {
+    with_db!(|db| db.set_job_target_done(job_id, ip, error), Ok(()))
+}
+
+pub fn finish_job(job_id: i64) -> Result<(), MinerError> {
+    with_db!(
+        |db| db.finish_job(job_id, chrono::Local::now().timestamp()),
+        Ok(())
+    )
+}
+
+pub fn query_pending_job_targets() -> Result<Vec<(i64, String, String)>, MinerError> {
+    with_db!(|db| db.query_pending_job_targets(), Ok(Vec::new()))
+}
+
+pub fn finish_pending_jobs() -> Result<(), MinerError> {
+    with_db!(
+        |db| db.finish_pending_jobs(chrono::Local::now().timestamp()),
+        Ok(())
+    )
+}
+
+pub fn insert_proxy_record(status: &ProxyStatus) -> Result<i32, MinerError> {
+    with_db!(|db| db.insert_proxy_record(status), Ok(-1))
+}
+
+pub fn query_proxy_records_by_time(
+    name: &str,
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<ProxyStatus>, MinerError> {
+    with_db!(
+        |db| db.query_proxy_records_by_time(name, start_time, end_time),
+        Ok(Vec::new())
+    )
 }
 
 pub fn clear_records_before_time(time: i64) -> Result<(), MinerError> {
-    let db = LCD_DB.lock().unwrap();
-    match &*db {
-        Some(db) => db.clear_records_before_time(time),
-        None => Ok(()),
+    with_db!(|db| db.clear_records_before_time(time), Ok(()))
+}
+
+pub fn clear_retention(config: &RetentionConfig, now: i64) -> Result<usize, MinerError> {
+    with_db!(|db| db.clear_retention(config, now), Ok(0))
+}
+
+pub fn query_all_records_by_time(
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<MachineRecord>, MinerError> {
+    with_db!(
+        |db| db.query_all_machine_records_by_time(start_time, end_time),
+        Ok(Vec::new())
+    )
+}
+
+pub fn insert_event(event_type: &str, ip: &str, detail: &str) -> Result<i32, MinerError> {
+    with_db!(
+        |db| db.insert_event(event_type, ip, detail, chrono::Local::now().timestamp()),
+        Ok(-1)
+    )
+}
+
+pub fn count_events(event_type: &str, start_time: i64, end_time: i64) -> Result<i64, MinerError> {
+    with_db!(
+        |db| db.count_events(event_type, start_time, end_time),
+        Ok(0)
+    )
+}
+
+pub fn count_ip_events(
+    event_type: &str,
+    ip: &str,
+    detail: &str,
+    start_time: i64,
+) -> Result<i64, MinerError> {
+    with_db!(
+        |db| db.count_ip_events(event_type, ip, detail, start_time),
+        Ok(0)
+    )
+}
+
+pub fn query_last_event(event_type: &str) -> Result<Option<(String, i64)>, MinerError> {
+    with_db!(|db| db.query_last_event(event_type), Ok(None))
+}
+
+pub fn record_alert(alert: &Alert, key: &str, now: i64) -> Result<i64, MinerError> {
+    with_db!(|db| db.record_alert(alert, key, now), Ok(-1))
+}
+
+pub fn set_alert_state(id: i64, state: AlertState, by: &str) -> Result<Option<String>, MinerError> {
+    with_db!(
+        |db| db.set_alert_state(id, state, by, chrono::Local::now().timestamp()),
+        Err(MinerError::DbNotInitError)
+    )
+}
+
+pub fn resolve_quiet_alerts(before: i64, now: i64) -> Result<usize, MinerError> {
+    with_db!(|db| db.resolve_quiet_alerts(before, now), Ok(0))
+}
+
+pub fn query_alerts(query: &AlertQuery) -> Result<Vec<AlertEntry>, MinerError> {
+    with_db!(|db| db.query_alerts(query), Ok(Vec::new()))
+}
+
+pub fn query_scan(range: &str) -> Result<(Vec<ScannedMachine>, Option<i64>), MinerError> {
+    with_db!(|db| db.query_scan(range), Ok((Vec::new(), None)))
+}
+
+pub fn save_scan(
+    range: &str,
+    machines: &[ScannedMachine],
+    scan_time: i64,
+) -> Result<(), MinerError> {
+    with_db!(|db| db.save_scan(range, machines, scan_time), Ok(()))
+}
+
+pub fn upsert_inventory(entry: &InventoryEntry) -> Result<Option<String>, MinerError> {
+    with_db!(|db| db.upsert_inventory(entry), Ok(None))
+}
+
+pub fn release_inventory_ip(ip: &str, mac: &str) -> Result<usize, MinerError> {
+    with_db!(|db| db.release_inventory_ip(ip, mac), Ok(0))
+}
+
+pub fn query_inventory() -> Result<Vec<InventoryEntry>, MinerError> {
+    with_db!(|db| db.query_inventory(), Ok(Vec::new()))
+}
+
+pub fn set_switch_state(state: &SwitchState) -> Result<(), MinerError> {
+    with_db!(|db| db.set_switch_state(state), Ok(()))
+}
+
+pub fn query_switch_state() -> Result<Vec<SwitchState>, MinerError> {
+    with_db!(|db| db.query_switch_state(), Ok(Vec::new()))
+}
+
+pub fn set_maintenance(ip: &str, until: i64) -> Result<(), MinerError> {
+    with_db!(
+        |db| db.set_maintenance(ip, until, chrono::Local::now().timestamp()),
+        Ok(())
+    )
+}
+
+pub fn clear_maintenance(ip: &str) -> Result<(), MinerError> {
+    with_db!(|db| db.clear_maintenance(ip), Ok(()))
+}
+
+pub fn query_maintenance(now: i64) -> Result<Vec<(String, i64)>, MinerError> {
+    with_db!(|db| db.query_maintenance(now), Ok(Vec::new()))
+}
+
+pub fn set_applied_mode(ip: &str, mode: &str) -> Result<(), MinerError> {
+    with_db!(
+        |db| db.set_applied_mode(ip, mode, chrono::Local::now().timestamp()),
+        Ok(())
+    )
+}
+
+pub fn query_applied_modes() -> Result<Vec<(String, String)>, MinerError> {
+    with_db!(|db| db.query_applied_modes(), Ok(Vec::new()))
+}
+
+pub fn save_profile(name: &str, body: &str) -> Result<(), MinerError> {
+    with_db!(
+        |db| db.save_profile(name, body, chrono::Local::now().timestamp()),
+        Ok(())
+    )
+}
+
+pub fn delete_profile(name: &str) -> Result<(), MinerError> {
+    with_db!(|db| db.delete_profile(name), Ok(()))
+}
+
+pub fn query_profiles() -> Result<Vec<String>, MinerError> {
+    with_db!(|db| db.query_profiles(), Ok(Vec::new()))
+}
+
+pub fn save_curtail_step(ip: &str, body: &str) -> Result<(), MinerError> {
+    with_db!(
+        |db| db.save_curtail_step(ip, body, chrono::Local::now().timestamp()),
+        Ok(())
+    )
+}
+
+pub fn delete_curtail_step(ip: &str) -> Result<(), MinerError> {
+    with_db!(|db| db.delete_curtail_step(ip), Ok(()))
+}
+
+pub fn query_curtail_steps() -> Result<Vec<String>, MinerError> {
+    with_db!(|db| db.query_curtail_steps(), Ok(Vec::new()))
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_db() {
+        assert_eq!(get_db_path("/opt/lcd", ""), "/opt/lcd/db/lcd.sqlite");
+        assert_eq!(get_db_path("/opt/lcd", MEMORY), MEMORY);
+
+        let db = DB::new(MEMORY).unwrap();
+        let record = MachineRecord {
+            ip: "192.168.188.41".to_string(),
+            machine_type: "S19".to_string(),
+            hash_real: 95.0,
+            temp_0: Some(70.0),
+            create_time: 1709018811,
+            ..Default::default()
+        };
+        db.insert_machine_record(&record).unwrap();
+        let records = db.query_latest_machine_records(1709018800).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].ip, record.ip);
+        assert_eq!(records[0].temp_0, Some(70.0));
+
+        db.vacuum().unwrap();
+        let path = std::env::temp_dir().join(format!("lcd-backup-{}.sqlite", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        db.backup(&path).unwrap();
+        let copy = DB {
+            conn: Connection::open(&path).unwrap(),
+        };
+        assert_eq!(copy.query_latest_machine_records(0).unwrap().len(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_migrations() {
+        let db = DB::new(MEMORY).unwrap();
+        let version: usize = db
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        // applied steps are skipped
+        migrate(&db.conn).unwrap();
+
+        let plan: String = db
+            .conn
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT * FROM t_machine_record
+                  WHERE ip == ?1 AND create_time >= ?2 AND create_time <= ?3",
+                params!["192.168.188.41", 0, 1],
+                |row| row.get(3),
+            )
+            .unwrap();
+        assert!(plan.contains("i_machine_record_ip_time"), "{}", plan);
+    }
+
+    #[test]
+    fn test_newest_pool_record() {
+        let db = DB::new(MEMORY).unwrap();
+        for (name, time) in [
+            ("acc.188x41", 1),
+            ("acc.188x41", 3),
+            ("lcd.a188x41", 2),
+            ("acc.2188x41", 4),
+        ] {
+            db.insert_pool_record(name, 1.0, 1.0, "antpool", "acc", time)
+                .unwrap();
+        }
+        let newest = db.get_newest_pool_record("188x41").unwrap().unwrap();
+        assert_eq!((newest.name.as_str(), newest.time_stamp), ("acc.188x41", 3));
+        assert!(db.get_newest_pool_record("88x4").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_record_pages() {
+        let db = DB::new(MEMORY).unwrap();
+        for (ip, create_time) in [("10.0.0.1", 10), ("10.0.0.2", 10), ("10.0.0.1", 20)] {
+            db.insert_machine_record(&MachineRecord {
+                ip: ip.to_string(),
+                machine_type: "S19".to_string(),
+                power: 3250,
+                create_time,
+                ..Default::default()
+            })
+            .unwrap();
+        }
+
+        let mut query = RecordQuery {
+            limit: 2,
+            descending: true,
+            columns: vec!["power".to_string()],
+            ..Default::default()
+        };
+        let page = db.query_machine_records("", 0, 30, &query).unwrap();
+        let ids = page.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids, [3, 2]);
+        assert_eq!(page[0].power, 3250);
+        assert_eq!(page[0].machine_type, "");
+
+        query.after_time = page[1].create_time;
+        query.after_id = page[1].id;
+        let page = db.query_machine_records("", 0, 30, &query).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, 1);
+
+        let all = db
+            .query_machine_records("10.0.0.1", 0, 30, &RecordQuery::default())
+            .unwrap();
+        assert_eq!(all.iter().map(|r| r.id).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(all[0].machine_type, "S19");
+
+        query.columns = vec!["password".to_string()];
+        assert!(db.query_machine_records("", 0, 30, &query).is_err());
+    }
+
+    #[test]
+    fn test_inventory_and_scans() {
+        let db = DB::new(MEMORY).unwrap();
+        let entry = InventoryEntry {
+            mac: "b4:a2:eb:3a:1f:22".to_string(),
+            ip: "10.0.0.1".to_string(),
+            first_seen: 100,
+            last_seen: 100,
+            ..Default::default()
+        };
+        assert_eq!(db.upsert_inventory(&entry).unwrap(), None);
+        let moved = InventoryEntry {
+            ip: "10.0.0.7".to_string(),
+            first_seen: 200,
+            last_seen: 200,
+            ..entry
+        };
+        assert_eq!(
+            db.upsert_inventory(&moved).unwrap(),
+            Some("10.0.0.1".to_string())
+        );
+        let entries = db.query_inventory().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].first_seen, entries[0].last_seen), (100, 200));
+        assert_eq!(entries[0].ip, "10.0.0.7");
+        // the lease went to another machine
+        assert_eq!(
+            db.release_inventory_ip("10.0.0.7", "e0:a5:09:2b:7d:15")
+                .unwrap(),
+            1
+        );
+        assert_eq!(db.query_inventory().unwrap()[0].ip, "");
+
+        // a scan replaces the last one of its range
+        let machine = ScannedMachine {
+            ip: "10.0.0.7".to_string(),
+            mac: moved.mac.clone(),
+            ..Default::default()
+        };
+        db.save_scan("10.0.0.1-254", std::slice::from_ref(&machine), 300)
+            .unwrap();
+        db.save_scan("10.0.0.1-254", std::slice::from_ref(&machine), 400)
+            .unwrap();
+        assert_eq!(
+            db.query_scan("10.0.0.1-254").unwrap(),
+            (vec![machine], Some(400))
+        );
+        assert_eq!(db.query_scan("10.0.1.1-254").unwrap(), (vec![], None));
+    }
+
+    #[test]
+    fn test_alert_states() {
+        let db = DB::new(MEMORY).unwrap();
+        let alert = Alert {
+            kind: "switch_failed".to_string(),
+            title: "08:00:00 访问故障 1台".to_string(),
+            severity: crate::notify::Severity::Warning,
+            content: "".to_string(),
+            machines: vec![crate::notify::AlertMachine {
+                ip: "10.0.0.1".to_string(),
+                detail: "A1".to_string(),
+            }],
+        };
+        let key = alert.incident_key();
+        let id = db.record_alert(&alert, &key, 100).unwrap();
+        assert_eq!(db.record_alert(&alert, &key, 200).unwrap(), id);
+
+        let query = AlertQuery {
+            state: Some(AlertState::Open),
+            start_time: 0,
+            end_time: 1000,
+            limit: 0,
+        };
+        let open = db.query_alerts(&query).unwrap();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].count, 2);
+        assert_eq!((open[0].first_time, open[0].last_time), (100, 200));
+        assert_eq!(open[0].machines[0].detail, "A1");
+        assert_eq!(open[0].severity, crate::notify::Severity::Warning);
+
+        assert_eq!(
+            db.set_alert_state(id, AlertState::Acknowledged, "ops", 300)
+                .unwrap(),
+            Some(key.clone())
+        );
+        assert!(db.query_alerts(&query).unwrap().is_empty());
+
+        // resolved ones are not counted again, the next raise opens a new row
+        assert_eq!(db.resolve_quiet_alerts(150, 300).unwrap(), 0);
+        assert_eq!(db.resolve_quiet_alerts(250, 300).unwrap(), 1);
+        assert_eq!(
+            db.set_alert_state(id, AlertState::Resolved, "", 400)
+                .unwrap(),
+            None
+        );
+        assert_ne!(db.record_alert(&alert, &key, 500).unwrap(), id);
+        assert_eq!(db.clear_alerts_before(1000).unwrap(), 1);
+        let all = db
+            .query_alerts(&AlertQuery {
+                end_time: 1000,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].state, AlertState::Open);
+        assert_eq!(all[0].ack_by, None);
     }
 }
@@ -1 +1,3 @@
+pub mod cache;
 pub mod db;
+pub mod retention;
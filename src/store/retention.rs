@@ -0,0 +1,137 @@
+/// how long each kind of data stays in the db, cleared at init and by the retention task
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::db;
+#[cfg(feature = "sqlite")]
+use super::db::DB;
+use crate::context::{self, Local};
+use crate::error::MinerError;
+use crate::status;
+
+#[cfg(feature = "sqlite")]
+const DAY: i64 = 24 * 3600;
+
+static RETENTION: Local<RetentionConfig> = Local::new(|| RetentionConfig::days(30));
+
+/// days kept per table, 0 keeps the data forever
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// polled machine records
+    pub machine_days: i64,
+    /// pool and stratum proxy records
+    pub pool_days: i64,
+    /// switch, curtail, thermal and the other events of the audit log, finished batch jobs
+    pub audit_days: i64,
+    /// alerts sent and the resolved alerts of the incident list
+    pub alert_days: i64,
+}
+
+impl RetentionConfig {
+    /// the same window for every table
+    pub fn days(days: i64) -> Self {
+        RetentionConfig {
+            machine_days: days,
+            pool_days: days,
+            audit_days: days,
+            alert_days: days,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn cutoff(now: i64, days: i64) -> Option<i64> {
+    (days > 0).then(|| now - days * DAY)
+}
+
+#[cfg(feature = "sqlite")]
+impl DB {
+    /// delete what is older than the windows, returns the deleted rows
+    pub fn clear_retention(&self, config: &RetentionConfig, now: i64) -> Result<usize, MinerError> {
+        let mut deleted = self.clear_expired_maintenance(now)?;
+        if let Some(time) = cutoff(now, config.machine_days) {
+            deleted += self.clear_machine_records_before(time)?;
+        }
+        if let Some(time) = cutoff(now, config.pool_days) {
+            deleted += self.clear_pool_records_before(time)?;
+        }
+        if let Some(time) = cutoff(now, config.audit_days) {
+            deleted += self.clear_events_before(time, false)?;
+            deleted += self.clear_jobs_before(time)?;
+        }
+        if let Some(time) = cutoff(now, config.alert_days) {
+            deleted += self.clear_events_before(time, true)?;
+            deleted += self.clear_alerts_before(time)?;
+        }
+        Ok(deleted)
+    }
+}
+
+pub fn set_config(config: RetentionConfig) {
+    RETENTION.set(config);
+}
+
+/// clear by the configured windows now, returns the deleted rows
+pub fn run_now() -> Result<usize, MinerError> {
+    let config = RETENTION.get();
+    let deleted = db::clear_retention(&config, chrono::Local::now().timestamp())?;
+    info!("retention cleared {} rows", deleted);
+    Ok(deleted)
+}
+
+pub fn schedule_task(
+    runtime: tokio::runtime::Handle,
+    interval_seconds: u64,
+) -> tokio::task::JoinHandle<()> {
+    context::spawn(&runtime, async move {
+        while status::wait(
+            "retention",
+            tokio::time::Duration::from_secs(interval_seconds),
+        )
+        .await
+        {
+            info!("retention task scheduled.");
+            match context::spawn_blocking(run_now).await {
+                Ok(Err(e)) => error!("retention error: {:?}", e),
+                Err(e) => error!("retention task error: {:?}", e),
+                Ok(Ok(_)) => {}
+            }
+        }
+    })
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::miner::entry::MachineRecord;
+
+    #[test]
+    fn test_clear_retention() {
+        let db = DB::new(db::MEMORY).unwrap();
+        let now = 1709018811;
+        for days in [1, 10] {
+            let time = now - days * DAY - 60;
+            db.insert_machine_record(&MachineRecord {
+                ip: "192.168.188.41".to_string(),
+                create_time: time,
+                ..Default::default()
+            })
+            .unwrap();
+            db.insert_event(db::EVENT_ALERT, "", "算力板掉板", time)
+                .unwrap();
+            db.insert_event(db::EVENT_SWITCH, "192.168.188.41", "", time)
+                .unwrap();
+        }
+
+        let config = RetentionConfig {
+            machine_days: 5,
+            pool_days: 5,
+            audit_days: 0,
+            alert_days: 1,
+        };
+        assert_eq!(db.clear_retention(&config, now).unwrap(), 3);
+        assert_eq!(db.query_latest_machine_records(0).unwrap().len(), 1);
+        assert_eq!(db.count_events(db::EVENT_ALERT, 0, now).unwrap(), 0);
+        assert_eq!(db.count_events(db::EVENT_SWITCH, 0, now).unwrap(), 2);
+    }
+}
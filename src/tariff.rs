@@ -0,0 +1,192 @@
+/// time-of-use electricity prices and the run mode policy driven by them
+use std::collections::HashMap;
+
+use chrono::NaiveTime;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::context::Local;
+use crate::error::MinerError;
+use crate::http;
+use crate::miner::window::TimeWindow;
+use crate::store::cache::DbCache;
+use crate::store::db;
+
+pub const MODE_HIGH: &str = "高功";
+pub const MODE_NORMAL: &str = "普通";
+pub const MODE_SLEEP: &str = "sleep";
+
+static TARIFF: Local<Option<TariffConfig>> = Local::new(Option::default);
+// fleet mode last applied to each machine, failed machines keep their previous one. kept in
+// the db so machines slept before a restart are woken after it
+static APPLIED: DbCache<HashMap<String, String>> = DbCache::new(load_applied);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TariffPeriod {
+    /// HH:MM:SS, end excluded, end before start wraps midnight, end equal to start is the
+    /// whole day
+    pub start: String,
+    pub end: String,
+    /// per kWh
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TariffPolicy {
+    /// price at or below runs 高功
+    pub high_perf_max_price: f64,
+    /// price at or above sleeps the fleet
+    pub sleep_min_price: f64,
+}
+
+impl TariffPolicy {
+    pub fn mode(&self, price: f64) -> &'static str {
+        if price >= self.sleep_min_price {
+            MODE_SLEEP
+        } else if price <= self.high_perf_max_price {
+            MODE_HIGH
+        } else {
+            MODE_NORMAL
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TariffConfig {
+    pub periods: Vec<TariffPeriod>,
+    /// json endpoint returning periods, overrides periods when reachable, empty to skip
+    pub url: String,
+    /// price outside every period
+    pub default_price: f64,
+    pub policy: TariffPolicy,
+}
+
+/// price of the first period covering the time
+pub fn price_at(
+    periods: &[TariffPeriod],
+    default_price: f64,
+    now: NaiveTime,
+) -> Result<f64, MinerError> {
+    for period in periods.iter() {
+        if TimeWindow::from_str(&period.start, &period.end, "")?.contains(now) {
+            return Ok(period.price);
+        }
+    }
+    Ok(default_price)
+}
+
+async fn fetch_periods(url: &str) -> Result<Vec<TariffPeriod>, MinerError> {
+    let periods = http::default_client()?
+        .get(url)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .await?
+        .json::<Vec<TariffPeriod>>()
+        .await?;
+    Ok(periods)
+}
+
+pub fn set_config(config: Option<TariffConfig>) {
+    TARIFF.set(config);
+}
+
+/// current price and run mode, None when no tariff is configured
+pub async fn current() -> Result<Option<(f64, &'static str)>, MinerError> {
+    let config = match TARIFF.get() {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    let mut periods = config.periods.clone();
+    if !config.url.is_empty() {
+        match fetch_periods(&config.url).await {
+            Ok(fetched) => periods = fetched,
+            Err(e) => error!("fetch tariff error, use configured periods: {:?}", e),
+        }
+    }
+
+    let price = price_at(&periods, config.default_price, clock::now().time())?;
+    let mode = config.policy.mode(price);
+    info!("tariff price: {}, mode: {}", price, mode);
+    Ok(Some((price, mode)))
+}
+
+fn load_applied() -> HashMap<String, String> {
+    match db::query_applied_modes() {
+        Ok(modes) => modes.into_iter().collect(),
+        Err(e) => {
+            error!("load applied modes error: {:?}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// load the applied modes of the db just opened
+pub fn reload() {
+    APPLIED.reload();
+}
+
+/// fleet mode last applied to the machine, None before the first
+pub fn applied_mode(ip: &str) -> Option<String> {
+    APPLIED.with(|applied| applied.get(ip).cloned())
+}
+
+/// remember the fleet mode once it is applied to the machine
+pub fn record_mode(ip: &str, mode: &str) {
+    if let Err(e) = db::set_applied_mode(ip, mode) {
+        error!("save applied mode of {} error: {:?}", ip, e);
+    }
+    APPLIED.with(|applied| applied.insert(ip.to_string(), mode.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_and_mode() {
+        let periods = vec![
+            TariffPeriod {
+                start: "08:00:00".to_string(),
+                end: "12:00:00".to_string(),
+                price: 0.9,
+            },
+            TariffPeriod {
+                start: "23:00:00".to_string(),
+                end: "07:00:00".to_string(),
+                price: 0.3,
+            },
+        ];
+        let at = |t: &str| {
+            price_at(
+                &periods,
+                0.6,
+                NaiveTime::parse_from_str(t, "%H:%M:%S").unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(at("09:30:00"), 0.9);
+        assert_eq!(at("02:00:00"), 0.3);
+        assert_eq!(at("15:00:00"), 0.6);
+        // end excluded
+        assert_eq!(at("12:00:00"), 0.6);
+        assert_eq!(at("11:59:59"), 0.9);
+        assert_eq!(at("07:00:00"), 0.6);
+        let whole_day = [TariffPeriod {
+            start: "00:00:00".to_string(),
+            end: "00:00:00".to_string(),
+            price: 0.5,
+        }];
+        let noon = NaiveTime::parse_from_str("12:00:00", "%H:%M:%S").unwrap();
+        assert_eq!(price_at(&whole_day, 0.6, noon).unwrap(), 0.5);
+
+        let policy = TariffPolicy {
+            high_perf_max_price: 0.35,
+            sleep_min_price: 0.85,
+        };
+        assert_eq!(policy.mode(0.3), MODE_HIGH);
+        assert_eq!(policy.mode(0.6), MODE_NORMAL);
+        assert_eq!(policy.mode(0.9), MODE_SLEEP);
+    }
+}